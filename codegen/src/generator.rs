@@ -40,9 +40,9 @@ fn eval_getter(s: &str, b: usize) -> String {
     } else if s == "cf" {
         format!("cpu.get_cf()")
     } else if s == "d8" || s == "a8" || s == "r8" {
-        format!("mmu.get8(cpu.get_pc().wrapping_add(arg))")
+        format!("mmu.cpu_get8(cpu.get_pc().wrapping_add(arg))")
     } else if s == "d16" || s == "a16" {
-        format!("mmu.get16(cpu.get_pc().wrapping_add(arg))")
+        format!("mmu.cpu_get16(cpu.get_pc().wrapping_add(arg))")
     } else if s.starts_with("0x") {
         let mut expr = s.split("+");
         let offset = expr.next().expect("No offset");
@@ -51,7 +51,13 @@ fn eval_getter(s: &str, b: usize) -> String {
     } else if is_num(s) {
         format!("{}", s)
     } else if s.starts_with("(") {
-        format!("mmu.get{}({})", b, eval_getter(&s[1..s.len() - 1], b))
+        let addr = eval_getter(&s[1..s.len() - 1], b);
+        if addr.contains("mmu.") {
+            // The address is read from the memory first, which is a separate memory cycle.
+            format!("{{ let addr = {}; mmu.cpu_get{}(addr) }}", addr, b)
+        } else {
+            format!("mmu.cpu_get{}({})", b, addr)
+        }
     } else {
         format!("cpu.get_{}()", s)
     }
@@ -65,7 +71,12 @@ pub fn getter(value: Value, map: HashMap<String, Value>) -> tera::Result<Value>
 
 fn eval_setter(s: &str, b: usize) -> String {
     if s.starts_with("(") {
-        format!("mmu.set{}({}, ", b, eval_getter(&s[1..s.len() - 1], b))
+        let addr = eval_getter(&s[1..s.len() - 1], b);
+        if addr.contains("mmu.") {
+            format!("let addr = {};\n    mmu.cpu_set{}(addr, ", addr, b)
+        } else {
+            format!("mmu.cpu_set{}({}, ", b, addr)
+        }
     } else {
         format!("cpu.set_{}(", s)
     }
@@ -162,7 +162,7 @@
 {% endmacro %}
 
 {% macro ei(i) %}
-  cpu.enable_interrupt();
+  cpu.enable_interrupt_delayed();
 {% endmacro %}
 
 {% macro rlc(i) %}
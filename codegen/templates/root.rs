@@ -1,6 +1,7 @@
 {% import "ops.rs" as macros %}
 
 use crate::cpu::Cpu;
+use crate::error::Error;
 use crate::mmu::Mmu;
 use crate::alu;
 use hashbrown::HashMap;
@@ -207,13 +208,16 @@ pub fn mnem(code: u16) -> &'static str {
 }
 
 /// Decodes the opecode and actually executes one instruction.
-pub fn decode(code: u16, arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
+pub fn decode(code: u16, arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> Result<(usize, usize), Error> {
     trace!("{:04x}: {:04x}: {}", cpu.get_pc(), code, mnem(code));
 
     match code {
         {%- for i in insts -%}
-        0x{{i.code | hex}} => op_{{i.code | hex}}(arg, cpu, mmu),
+        0x{{i.code | hex}} => Ok(op_{{i.code | hex}}(arg, cpu, mmu)),
         {%- endfor -%}
-        _ => panic!("Invalid opcode: {:04x}: {:04x}", cpu.get_pc(), code),
+        _ => Err(Error::InvalidOpcode {
+            pc: cpu.get_pc(),
+            code,
+        }),
     }
 }
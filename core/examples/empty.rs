@@ -13,7 +13,7 @@ impl Hardware {
     }
 }
 
-impl rgy::Hardware for Hardware {
+impl rgy::Screen for Hardware {
     fn vram_update(&mut self, line: usize, buffer: &[u32]) {
         // `line` corresponds to the y coordinate.
         let y = line;
@@ -22,17 +22,23 @@ impl rgy::Hardware for Hardware {
             self.display[x][y] = *col;
         }
     }
+}
 
+impl rgy::Input for Hardware {
     fn joypad_pressed(&mut self, key: Key) -> bool {
         // Read a keyboard device and check if the `key` is pressed or not.
         println!("Check if {:?} is pressed", key);
         false
     }
+}
 
+impl rgy::Speaker for Hardware {
     fn sound_play(&mut self, _stream: Box<dyn Stream>) {
         // Play the wave provided `Stream`.
     }
+}
 
+impl rgy::Clock for Hardware {
     fn clock(&mut self) -> u64 {
         // Return the epoch in microseconds.
         let epoch = std::time::SystemTime::now()
@@ -41,6 +47,14 @@ impl rgy::Hardware for Hardware {
         epoch.as_micros() as u64
     }
 
+    fn sched(&mut self) -> bool {
+        // `true` to continue, `false` to stop the emulator.
+        println!("It's running!");
+        true
+    }
+}
+
+impl rgy::Link for Hardware {
     fn send_byte(&mut self, _b: u8) {
         // Send a byte to a serial port.
     }
@@ -49,13 +63,9 @@ impl rgy::Hardware for Hardware {
         // Try to read a byte from a serial port.
         None
     }
+}
 
-    fn sched(&mut self) -> bool {
-        // `true` to continue, `false` to stop the emulator.
-        println!("It's running!");
-        true
-    }
-
+impl rgy::Persistence for Hardware {
     fn load_ram(&mut self, size: usize) -> Vec<u8> {
         // Return save data.
         vec![0; size]
@@ -77,5 +87,7 @@ fn main() {
     let rom = vec![0u8; 1024];
 
     // Run the emulator.
-    rgy::run(cfg, &rom, hw);
+    if let Err(e) = rgy::run(cfg, &rom, hw) {
+        println!("Emulator stopped: {}", e);
+    }
 }
@@ -11,7 +11,7 @@ use std::sync::{
 };
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use rgy::{Key, Stream, VRAM_HEIGHT, VRAM_WIDTH};
+use rgy::{Key, Resampler, Stream, VRAM_HEIGHT, VRAM_WIDTH};
 
 #[derive(Clone)]
 pub struct Hardware {
@@ -153,7 +153,7 @@ impl Hardware {
     }
 }
 
-impl rgy::Hardware for Hardware {
+impl rgy::Screen for Hardware {
     fn vram_update(&mut self, line: usize, buf: &[u32]) {
         let mut vram = self.vram.lock().unwrap();
         for i in 0..buf.len() {
@@ -161,7 +161,9 @@ impl rgy::Hardware for Hardware {
             vram[base + i] = buf[i];
         }
     }
+}
 
+impl rgy::Input for Hardware {
     fn joypad_pressed(&mut self, key: Key) -> bool {
         *self
             .keystate
@@ -170,11 +172,28 @@ impl rgy::Hardware for Hardware {
             .get(&key)
             .expect("Logic error in keystate map")
     }
+}
 
+impl rgy::Speaker for Hardware {
     fn sound_play(&mut self, stream: Box<dyn Stream>) {
         self.pcm.play(stream)
     }
+}
+
+impl rgy::Clock for Hardware {
+    fn clock(&mut self) -> u64 {
+        let epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Couldn't get epoch");
+        epoch.as_micros() as u64
+    }
 
+    fn sched(&mut self) -> bool {
+        !self.escape.load(Ordering::Relaxed)
+    }
+}
+
+impl rgy::Link for Hardware {
     fn send_byte(&mut self, b: u8) {
         info!("Send byte: {:02x}", b);
     }
@@ -182,14 +201,9 @@ impl rgy::Hardware for Hardware {
     fn recv_byte(&mut self) -> Option<u8> {
         None
     }
+}
 
-    fn clock(&mut self) -> u64 {
-        let epoch = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Couldn't get epoch");
-        epoch.as_micros() as u64
-    }
-
+impl rgy::Persistence for Hardware {
     fn load_ram(&mut self, size: usize) -> Vec<u8> {
         let mut ram = vec![0; size];
 
@@ -217,10 +231,6 @@ impl rgy::Hardware for Hardware {
             None => {}
         }
     }
-
-    fn sched(&mut self) -> bool {
-        !self.escape.load(Ordering::Relaxed)
-    }
 }
 
 pub struct Pcm {
@@ -262,7 +272,7 @@ impl Pcm {
         event_loop.run(move |_, data| {
             match self.rx.try_recv() {
                 Ok(SpeakerCmd::Play(s)) => {
-                    stream = Some(s);
+                    stream = Some(Resampler::new(s));
                 }
                 Ok(SpeakerCmd::Stop) => {
                     stream = None;
@@ -282,9 +292,7 @@ impl Pcm {
                 } => {
                     for sample in buffer.chunks_mut(format.channels as usize) {
                         let value = match &mut stream {
-                            Some(s) => {
-                                ((s.next(sample_rate) as u64 * 100 / s.max() as u64) as f32 / 100.0)
-                            }
+                            Some(s) => s.next(sample_rate) as f32 / i16::MAX as f32,
                             None => 0.0,
                         };
 
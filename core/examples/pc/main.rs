@@ -77,10 +77,14 @@ fn main() {
 
         set_affinity();
 
-        if opt.debug {
-            rgy::run_debug(to_cfg(opt), &rom, hw1, Debugger::new());
+        let res = if opt.debug {
+            rgy::run_debug(to_cfg(opt), &rom, hw1, Debugger::new())
         } else {
-            rgy::run(to_cfg(opt), &rom, hw1);
+            rgy::run(to_cfg(opt), &rom, hw1)
+        };
+
+        if let Err(e) = res {
+            error!("Emulator stopped: {}", e);
         }
     });
 
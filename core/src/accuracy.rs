@@ -0,0 +1,258 @@
+use crate::error::Error;
+use crate::state::fnv1a;
+use crate::system::Config;
+use crate::testing::{run_rom_with, TestResult, Verdict};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt;
+use hashbrown::HashMap;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The reference hashes of the final frames, keyed by the path of the ROM relative to the suite directory.
+///
+/// The text format is a line of the name and the hash in hex per ROM, as written by [`Report::references`][].
+/// Empty lines and lines starting with `#` are ignored.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct References(HashMap<String, u64>);
+
+impl References {
+    /// Create an empty set of references.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse the references from the text format.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut refs = HashMap::new();
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (name, hash) = line
+                .rsplit_once(char::is_whitespace)
+                .ok_or_else(|| Error::InvalidReference(line.to_string()))?;
+            let hash = u64::from_str_radix(hash, 16)
+                .map_err(|_| Error::InvalidReference(line.to_string()))?;
+            refs.insert(name.trim_end().to_string(), hash);
+        }
+
+        Ok(Self(refs))
+    }
+
+    /// Returns the reference hash of the ROM.
+    pub fn get(&self, name: &str) -> Option<u64> {
+        self.0.get(name).copied()
+    }
+
+    /// Set the reference hash of the ROM.
+    pub fn insert(&mut self, name: &str, hash: u64) {
+        self.0.insert(name.to_string(), hash);
+    }
+}
+
+/// The outcome of a ROM in the suite.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Case {
+    /// The path of the ROM relative to the suite directory, separated by `/`.
+    pub name: String,
+    /// The result of the run, or the error which stopped it.
+    pub result: Result<TestResult, Error>,
+    /// The hash of the final frame.
+    pub frame_hash: u64,
+    /// The reference hash of the final frame, if any.
+    pub expected_hash: Option<u64>,
+}
+
+impl Case {
+    /// Check if the ROM passed.
+    ///
+    /// With a reference hash, the final frame needs to match it and the ROM mustn't report failure,
+    /// which covers the ROMs only drawing the result on the screen. Otherwise the ROM needs to report success.
+    pub fn passed(&self) -> bool {
+        let res = match &self.result {
+            Ok(res) => res,
+            Err(_) => return false,
+        };
+
+        match self.expected_hash {
+            Some(hash) => hash == self.frame_hash && res.verdict != Verdict::Failed,
+            None => res.verdict == Verdict::Passed,
+        }
+    }
+}
+
+/// The structured report of a suite.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// The outcome of each ROM, sorted by the name.
+    pub cases: Vec<Case>,
+}
+
+impl Report {
+    /// Returns the number of the ROMs passed.
+    pub fn passed(&self) -> usize {
+        self.cases.iter().filter(|c| c.passed()).count()
+    }
+
+    /// Returns the number of the ROMs failed.
+    pub fn failed(&self) -> usize {
+        self.cases.len() - self.passed()
+    }
+
+    /// Returns the hashes of the final frames of this run in the text format of [`References`][],
+    /// to record the references from a known-good build.
+    pub fn references(&self) -> String {
+        self.cases
+            .iter()
+            .map(|c| format!("{} {:016x}\n", c.name, c.frame_hash))
+            .collect()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for c in &self.cases {
+            let mark = if c.passed() { "PASS" } else { "FAIL" };
+            match &c.result {
+                Ok(res) => write!(f, "{} {}: {:?}", mark, c.name, res.verdict)?,
+                Err(e) => write!(f, "{} {}: {}", mark, c.name, e)?,
+            }
+            match c.expected_hash {
+                Some(hash) if hash != c.frame_hash => {
+                    writeln!(f, ", frame {:016x} != {:016x}", c.frame_hash, hash)?
+                }
+                _ => writeln!(f)?,
+            }
+        }
+
+        write!(
+            f,
+            "{} passed, {} failed, {} total",
+            self.passed(),
+            self.failed(),
+            self.cases.len()
+        )
+    }
+}
+
+/// Run all the ROMs (`.gb` and `.gbc`) under the directory headlessly for up to `limit_cycles` each,
+/// comparing the final frames with `refs`.
+///
+/// Each ROM is judged by [`run_rom_with`][], so the suites following the Blargg or Mooneye conventions
+/// pass without references; the ROMs only drawing the result need a reference hash of the final frame.
+///
+/// ```rust,no_run
+/// use rgy::accuracy::{run_suite, References};
+///
+/// let refs = References::parse(&std::fs::read_to_string("tests/refs.txt").unwrap()).unwrap();
+/// let report = run_suite("tests/roms".as_ref(), &refs, 100_000_000).unwrap();
+/// println!("{}", report);
+/// assert_eq!(report.failed(), 0);
+/// ```
+pub fn run_suite(dir: &Path, refs: &References, limit_cycles: u64) -> io::Result<Report> {
+    let mut roms = Vec::new();
+    find_roms(dir, &mut roms)?;
+    roms.sort();
+
+    let mut cases = Vec::with_capacity(roms.len());
+    for path in roms {
+        let rom = fs::read(&path)?;
+        let name = path
+            .strip_prefix(dir)
+            .unwrap_or(&path)
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        let cfg = Config::new().frame_buffer(true);
+        let result = run_rom_with(cfg, &rom, limit_cycles);
+        let frame_hash = result.as_ref().map(|r| hash_frame(&r.frame)).unwrap_or(0);
+        let expected_hash = refs.get(&name);
+
+        cases.push(Case {
+            name,
+            result,
+            frame_hash,
+            expected_hash,
+        });
+    }
+
+    Ok(Report { cases })
+}
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            find_roms(&path, roms)?;
+        } else if matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("gb") | Some("gbc")
+        ) {
+            roms.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+fn hash_frame(frame: &[u32]) -> u64 {
+    let bytes: Vec<u8> = frame.iter().flat_map(|p| p.to_le_bytes()).collect();
+    fnv1a(&bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::{rom, MOONEYE_PROGRAM};
+
+    #[test]
+    fn suite() {
+        let dir = std::env::temp_dir().join(format!("rgy-accuracy-{}", std::process::id()));
+        let mut fail = MOONEYE_PROGRAM.to_vec();
+        fail[1..12].iter_mut().step_by(2).for_each(|r| *r = 0x42);
+        fs::create_dir_all(dir.join("mooneye")).unwrap();
+        fs::write(dir.join("mooneye/pass.gb"), rom(MOONEYE_PROGRAM)).unwrap();
+        fs::write(dir.join("fail.gb"), rom(&fail)).unwrap();
+        fs::write(dir.join("notes.txt"), b"not a ROM").unwrap();
+
+        let report = run_suite(&dir, &References::new(), 30_000_000).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let names: Vec<_> = report.cases.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["fail.gb", "mooneye/pass.gb"]);
+        assert_eq!((report.passed(), report.failed()), (1, 1), "{}", report);
+        assert!(report.to_string().ends_with("1 passed, 1 failed, 2 total"));
+
+        // The frame is compared with the recorded reference
+        let refs = References::parse(&report.references()).unwrap();
+        let mut case = report.cases[1].clone();
+        case.expected_hash = refs.get(&case.name);
+        assert!(case.passed());
+        case.expected_hash = Some(!case.frame_hash);
+        assert!(!case.passed());
+
+        // Failure is reported even if the frame matches
+        let mut case = report.cases[0].clone();
+        case.expected_hash = Some(case.frame_hash);
+        assert!(!case.passed());
+    }
+
+    #[test]
+    fn references() {
+        let refs = References::parse("# comment\n\na.gb 00000000000000ff\n").unwrap();
+        assert_eq!(refs.get("a.gb"), Some(0xff));
+        assert_eq!(refs.get("b.gb"), None);
+        assert!(References::parse("a.gb xyz").is_err());
+    }
+}
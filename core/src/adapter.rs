@@ -0,0 +1,271 @@
+use crate::link::LinkCable;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::{vec, vec::Vec};
+use log::*;
+use spin::Mutex;
+
+/// The number of ports of the adapter.
+const PLAYERS: usize = 4;
+
+/// The bytes player 1 answers to a whole ping packet to start the transmission.
+const START: u8 = 0xaa;
+
+/// The phase of the adapter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    /// Sending ping packets to find the connected players.
+    Ping,
+    /// Relaying the data of all the players.
+    Transmission,
+}
+
+/// The state of the adapter shared by the ports.
+struct Hub {
+    phase: Phase,
+    connected: [bool; PLAYERS],
+    /// The bytes exchanged by each player in the current phase.
+    pos: [usize; PLAYERS],
+    /// The bytes sent to each player before the transmission starts.
+    start: [VecDeque<u8>; PLAYERS],
+    /// The count of consecutive start bytes from player 1.
+    starting: usize,
+    /// The bytes each player sends per transmission cycle.
+    size: usize,
+    /// The data of all the players per transmission cycle, and the number of bytes filled.
+    cycles: BTreeMap<usize, (Vec<u8>, usize)>,
+}
+
+impl Hub {
+    fn status(&self, player: usize) -> u8 {
+        let connected = self
+            .connected
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| **c)
+            .fold(0, |s, (i, _)| s | 0x10 << i);
+
+        connected | (player as u8 + 1)
+    }
+
+    fn exchange(&mut self, player: usize, data: u8) -> Option<u8> {
+        if let Some(b) = self.start[player].pop_front() {
+            return Some(b);
+        }
+
+        match self.phase {
+            Phase::Ping => Some(self.ping(player, data)),
+            Phase::Transmission => self.transmit(player, data),
+        }
+    }
+
+    /// Send the ping packet `FE`, followed by the status three times.
+    ///
+    /// The players answer with two acknowledgements, then the transmission rate and the packet size.
+    fn ping(&mut self, player: usize, data: u8) -> u8 {
+        let pos = self.pos[player];
+        self.pos[player] = (pos + 1) % 4;
+
+        if player == 0 {
+            if pos == 3 && data != START && data > 0 {
+                self.size = data as usize;
+            }
+
+            self.starting = if data == START { self.starting + 1 } else { 0 };
+            if self.starting == 4 {
+                debug!("Four player adapter: transmission with {} bytes", self.size);
+                self.starting = 0;
+                self.phase = Phase::Transmission;
+                self.pos = [0; PLAYERS];
+                self.cycles.clear();
+                for start in self.start.iter_mut() {
+                    start.extend(&[0xcc; 4]);
+                }
+            }
+        }
+
+        if pos == 0 {
+            0xfe
+        } else {
+            self.status(player)
+        }
+    }
+
+    /// Exchange the byte of a transmission cycle, where each player sends its packet
+    /// in the first bytes and receives the packets of all the players of the previous cycle.
+    fn transmit(&mut self, player: usize, data: u8) -> Option<u8> {
+        let len = self.size * PLAYERS;
+        let pos = self.pos[player];
+        let (cycle, i) = (pos / len, pos % len);
+        let players = self.connected.iter().filter(|c| **c).count();
+
+        // Wait for the other players to send the packets of the previous cycle
+        let out = match cycle.checked_sub(1) {
+            Some(prev) => match self.cycles.get(&prev) {
+                Some((buf, filled)) if *filled == self.size * players => buf[i],
+                _ => return None,
+            },
+            None => 0x00,
+        };
+
+        if i < self.size {
+            let size = self.size;
+            let (buf, filled) = self
+                .cycles
+                .entry(cycle)
+                .or_insert_with(|| (vec![0; size * PLAYERS], 0));
+            buf[player * size + i] = data;
+            *filled += 1;
+
+            // Player 1 sending `FF` for the whole packet goes back to the ping phase
+            if player == 0 && i == size - 1 && buf[..size].iter().all(|b| *b == 0xff) {
+                debug!("Four player adapter: back to ping");
+                self.phase = Phase::Ping;
+                self.pos = [0; PLAYERS];
+                return Some(out);
+            }
+        }
+
+        self.pos[player] += 1;
+
+        // Drop the cycles all the players have passed
+        let oldest = (0..PLAYERS)
+            .filter(|p| self.connected[*p])
+            .map(|p| self.pos[p] / len)
+            .min()
+            .unwrap_or(0);
+        self.cycles = self.cycles.split_off(&oldest.saturating_sub(1));
+
+        Some(out)
+    }
+}
+
+/// The DMG-07 adapter which connects up to four Game Boys for multi-player link play.
+///
+/// The adapter supplies the clock; connect [`FourPlayerAdapter::port`][] to each instance
+/// by [`System::connect_link`][crate::System::connect_link].
+///
+/// ```rust,no_run
+/// # fn link<'a, D: rgy::debug::Debugger + rgy::MaybeSend + 'a>(systems: &mut [rgy::System<'a, D>]) {
+/// let adapter = rgy::FourPlayerAdapter::new();
+/// for (player, sys) in systems.iter_mut().enumerate() {
+///     sys.connect_link(adapter.port(player));
+/// }
+/// # }
+/// ```
+pub struct FourPlayerAdapter {
+    hub: Arc<Mutex<Hub>>,
+}
+
+impl FourPlayerAdapter {
+    /// Create a new adapter with nothing connected.
+    pub fn new() -> Self {
+        Self {
+            hub: Arc::new(Mutex::new(Hub {
+                phase: Phase::Ping,
+                connected: [false; PLAYERS],
+                pos: [0; PLAYERS],
+                start: Default::default(),
+                starting: 0,
+                size: 4,
+                cycles: BTreeMap::new(),
+            })),
+        }
+    }
+
+    /// Connect the port of the player from 0 (player 1) to 3 (player 4).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the player is out of range.
+    pub fn port(&self, player: usize) -> AdapterPort {
+        assert!(player < PLAYERS, "no port for player {}", player);
+
+        self.hub.lock().connected[player] = true;
+
+        AdapterPort {
+            hub: self.hub.clone(),
+            player,
+        }
+    }
+}
+
+impl Default for FourPlayerAdapter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The port of [`FourPlayerAdapter`][] for a player.
+pub struct AdapterPort {
+    hub: Arc<Mutex<Hub>>,
+    player: usize,
+}
+
+impl LinkCable for AdapterPort {
+    fn send(&mut self, _data: u8) -> Option<u8> {
+        // The players need to use the external clock from the adapter
+        None
+    }
+
+    fn recv(&mut self, data: u8) -> Option<u8> {
+        self.hub.lock().exchange(self.player, data)
+    }
+}
+
+impl Drop for AdapterPort {
+    fn drop(&mut self) {
+        self.hub.lock().connected[self.player] = false;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ping_and_transmission() {
+        let adapter = FourPlayerAdapter::new();
+        let mut p1 = adapter.port(0);
+        let mut p2 = adapter.port(1);
+
+        // Ping with the status of the connected players
+        let ping: Vec<_> = [0x88, 0x88, 0x10, 0x01]
+            .iter()
+            .map(|b| p1.recv(*b).unwrap())
+            .collect();
+        assert_eq!(ping, vec![0xfe, 0x31, 0x31, 0x31]);
+        assert_eq!(p2.recv(0x88), Some(0xfe));
+        assert_eq!(p2.recv(0x88), Some(0x32));
+
+        // Player 1 starts the transmission with 1-byte packets
+        for _ in 0..4 {
+            p1.recv(START).unwrap();
+        }
+        for _ in 0..4 {
+            assert_eq!(p1.recv(0x00), Some(0xcc));
+            assert_eq!(p2.recv(0x00), Some(0xcc));
+        }
+
+        // The first cycle receives nothing
+        let c1: Vec<_> = [0x11, 0, 0, 0]
+            .iter()
+            .map(|b| p1.recv(*b).unwrap())
+            .collect();
+        assert_eq!(c1, vec![0; 4]);
+
+        // Player 1 waits for the packet of player 2
+        assert_eq!(p1.recv(0x12), None);
+        let c2: Vec<_> = [0x21, 0, 0, 0]
+            .iter()
+            .map(|b| p2.recv(*b).unwrap())
+            .collect();
+        assert_eq!(c2, vec![0; 4]);
+
+        let c1: Vec<_> = [0x12, 0, 0, 0]
+            .iter()
+            .map(|b| p1.recv(*b).unwrap())
+            .collect();
+        assert_eq!(c1, vec![0x11, 0x21, 0x00, 0x00]);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::cart::Header;
+use crate::cpu::CpuState;
+
+/// The I/O registers as the boot ROMs leave them, written in order.
+///
+/// NR14, NR24, NR34 and NR44 are written without the trigger bit,
+/// as the beep of the boot ROM has faded out and the channels are silent.
+const IO: &[(u16, u8)] = &[
+    (0xff26, 0xf1),
+    (0xff10, 0x80),
+    (0xff11, 0xbf),
+    (0xff12, 0xf3),
+    (0xff13, 0xff),
+    (0xff14, 0x3f),
+    (0xff16, 0x3f),
+    (0xff17, 0x00),
+    (0xff18, 0xff),
+    (0xff19, 0x3f),
+    (0xff1a, 0x7f),
+    (0xff1b, 0xff),
+    (0xff1c, 0x9f),
+    (0xff1d, 0xff),
+    (0xff1e, 0x3f),
+    (0xff20, 0xff),
+    (0xff21, 0x00),
+    (0xff22, 0x00),
+    (0xff23, 0x3f),
+    (0xff24, 0x77),
+    (0xff25, 0xf3),
+    (0xff40, 0x91),
+    (0xff47, 0xfc),
+    (0xff0f, 0xe1),
+];
+
+/// The hardware models, whose boot ROMs leave the CPU registers and the I/O ports differently.
+///
+/// Games tell the models apart by the register A at 0100, e.g. to enable the colors on CGB.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Model {
+    /// The early Game Boy with the first revision of the boot ROM.
+    Dmg0,
+    /// Game Boy.
+    Dmg,
+    /// Game Boy Pocket.
+    Mgb,
+    /// Game Boy Color.
+    Cgb,
+}
+
+impl Default for Model {
+    /// [`Model::Cgb`][] with the `color` feature, otherwise [`Model::Dmg`][].
+    fn default() -> Self {
+        if cfg!(feature = "color") {
+            Model::Cgb
+        } else {
+            Model::Dmg
+        }
+    }
+}
+
+impl Model {
+    /// Returns the CPU registers at 0100 after the boot ROM runs the cartridge.
+    ///
+    /// `title` is the 16 bytes of the title area (0134-0143), which the CGB boot ROM sums up
+    /// for the cartridges made for the DMG.
+    pub(crate) fn cpu_state(self, header: &Header, title: &[u8]) -> CpuState {
+        // The boot ROM leaves H and C set unless the header checksum is zero
+        let flags = if header.header_checksum == 0 {
+            0x80
+        } else {
+            0xb0
+        };
+
+        let (af, bc, de, hl) = match self {
+            Model::Dmg0 => (0x0100, 0xff13, 0x00c1, 0x8403),
+            Model::Dmg => (0x0100 | flags, 0x0013, 0x00d8, 0x014d),
+            Model::Mgb => (0xff00 | flags, 0x0013, 0x00d8, 0x014d),
+            Model::Cgb if header.cgb => (0x1180, 0x0000, 0xff56, 0x000d),
+            Model::Cgb => {
+                let nintendo = header.old_licensee == 0x01
+                    || (header.old_licensee == 0x33 && header.new_licensee == "01");
+                let b = if nintendo {
+                    title.iter().fold(0u8, |s, b| s.wrapping_add(*b))
+                } else {
+                    0
+                };
+                let hl = if b == 0x43 || b == 0x58 {
+                    0x991a
+                } else {
+                    0x007c
+                };
+                (0x1180, (b as u16) << 8, 0x0008, hl)
+            }
+        };
+
+        CpuState {
+            af,
+            bc,
+            de,
+            hl,
+            sp: 0xfffe,
+            pc: 0x0100,
+            ime: false,
+            halted: false,
+        }
+    }
+
+    /// Returns the 16-bit internal divider at 0100, of which DIV is the upper byte.
+    pub(crate) fn div(self) -> u16 {
+        match self {
+            Model::Dmg0 => 0x1830,
+            Model::Dmg | Model::Mgb => 0xabcc,
+            // Varies with the time the logo animation takes
+            Model::Cgb => 0x1ea0,
+        }
+    }
+
+    /// Returns the I/O registers at 0100, to be written in order.
+    pub(crate) fn io(self) -> impl Iterator<Item = (u16, u8)> {
+        let sc = match self {
+            Model::Cgb => 0x7f,
+            _ => 0x7e,
+        };
+
+        IO.iter().copied().chain(core::iter::once((0xff02, sc)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn cpu_state() {
+        let mut rom = vec![0u8; 0x150];
+        rom[0x134..0x138].copy_from_slice(b"TEST");
+        rom[0x14d] = 0x12;
+        let mut h = Header::parse(&rom).unwrap();
+        let title = &rom[0x134..0x144];
+
+        assert_eq!(Model::Dmg.cpu_state(&h, title).af, 0x01b0);
+        assert_eq!(Model::Mgb.cpu_state(&h, title).af, 0xffb0);
+        assert_eq!(Model::Dmg0.cpu_state(&h, title).af, 0x0100);
+
+        // The title is summed only for the cartridges licensed by Nintendo
+        assert_eq!(Model::Cgb.cpu_state(&h, title).bc, 0x0000);
+        h.old_licensee = 0x01;
+        let s = Model::Cgb.cpu_state(&h, title);
+        assert_eq!((s.af, s.bc, s.hl), (0x1180, 0x4000, 0x007c));
+
+        h.cgb = true;
+        let s = Model::Cgb.cpu_state(&h, title);
+        assert_eq!((s.bc, s.de, s.hl), (0x0000, 0xff56, 0x000d));
+
+        h.header_checksum = 0;
+        assert_eq!(Model::Dmg.cpu_state(&h, title).af, 0x0180);
+    }
+}
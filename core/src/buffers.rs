@@ -0,0 +1,277 @@
+use crate::error::Error;
+use crate::hardware::{VRAM_HEIGHT, VRAM_WIDTH};
+use crate::state::{Reader, State, Writer};
+use crate::system::Config;
+use alloc::{format, vec, vec::Vec};
+use core::ops::{Deref, DerefMut};
+
+/// The size of the address space backing the RAM and the I/O registers.
+pub const MEMORY_SIZE: usize = 0x10000;
+
+/// The size of a bank of the video RAM.
+pub(crate) const VRAM_BANK_SIZE: usize = 0x2000;
+
+/// The size of the video RAM, two banks of 8 KiB.
+pub const VRAM_SIZE: usize = VRAM_BANK_SIZE * 2;
+
+/// The size of a bank of the CGB work RAM.
+pub(crate) const WRAM_BANK_SIZE: usize = 0x1000;
+
+/// The size of the CGB work RAM, eight banks of 4 KiB.
+pub const WRAM_SIZE: usize = WRAM_BANK_SIZE * 8;
+
+/// The number of the pixels in a frame.
+pub const FRAME_SIZE: usize = VRAM_WIDTH * VRAM_HEIGHT;
+
+/// The memory either allocated by the emulator or provided by the caller.
+pub(crate) enum Buf<'a, T> {
+    Heap(Vec<T>),
+    Borrowed(&'a mut [T]),
+}
+
+impl<'a, T> Buf<'a, T> {
+    /// The empty buffer, which doesn't allocate.
+    pub fn empty() -> Self {
+        Buf::Borrowed(&mut [])
+    }
+
+    /// Move the memory out, leaving the empty buffer.
+    pub fn take(&mut self) -> Self {
+        core::mem::replace(self, Self::empty())
+    }
+}
+
+impl<'a, T> Deref for Buf<'a, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self {
+            Buf::Heap(v) => v,
+            Buf::Borrowed(s) => s,
+        }
+    }
+}
+
+impl<'a, T> DerefMut for Buf<'a, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        match self {
+            Buf::Heap(v) => v,
+            Buf::Borrowed(s) => s,
+        }
+    }
+}
+
+impl<'a, T> From<Vec<T>> for Buf<'a, T> {
+    fn from(v: Vec<T>) -> Self {
+        Buf::Heap(v)
+    }
+}
+
+impl<'a, T> From<&'a mut [T]> for Buf<'a, T> {
+    fn from(s: &'a mut [T]) -> Self {
+        Buf::Borrowed(s)
+    }
+}
+
+/// The memory, which has to have the same size as the saved one, encoded as `Vec<u8>` is.
+impl<'a> State for Buf<'a, u8> {
+    fn save(&self, w: &mut Writer) {
+        self.len().save(w);
+        w.put(self);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        let mut len = 0usize;
+        len.load(r)?;
+
+        if len != self.len() {
+            return Err(Error::InvalidState(format!(
+                "memory size {} while expecting {}",
+                len,
+                self.len()
+            )));
+        }
+
+        self.copy_from_slice(r.take(len)?);
+        Ok(())
+    }
+}
+
+/// The banked memory in a contiguous buffer, encoded as the banks of `Vec<Vec<u8>>` are.
+pub(crate) struct Banks<'a> {
+    buf: Buf<'a, u8>,
+    size: usize,
+}
+
+impl<'a> Banks<'a> {
+    pub fn new(buf: Buf<'a, u8>, size: usize) -> Self {
+        Self { buf, size }
+    }
+
+    /// Allocate `count` banks of `size` bytes.
+    pub fn alloc(count: usize, size: usize) -> Self {
+        Self::new(vec![0; count * size].into(), size)
+    }
+
+    pub fn bank(&self, bank: usize) -> &[u8] {
+        &self.buf[bank * self.size..][..self.size]
+    }
+
+    pub fn bank_mut(&mut self, bank: usize) -> &mut [u8] {
+        &mut self.buf[bank * self.size..][..self.size]
+    }
+}
+
+impl<'a> State for Banks<'a> {
+    fn save(&self, w: &mut Writer) {
+        for bank in self.buf.chunks(self.size) {
+            bank.len().save(w);
+            w.put(bank);
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        for bank in self.buf.chunks_mut(self.size) {
+            let mut len = 0usize;
+            len.load(r)?;
+
+            if len != bank.len() {
+                return Err(Error::InvalidState(format!(
+                    "memory size {} while expecting {}",
+                    len,
+                    bank.len()
+                )));
+            }
+
+            bank.copy_from_slice(r.take(len)?);
+        }
+        Ok(())
+    }
+}
+
+/// The memory of the emulator provided by the caller, to run with a tiny or no heap,
+/// e.g. on microcontrollers. Pass it to [`System::with_buffers`][crate::System::with_buffers].
+///
+/// The memory is cleared when the system is created and reset, and keeps the last state
+/// of the emulation after the system is dropped.
+///
+/// ```rust,no_run
+/// use rgy::{Buffers, MEMORY_SIZE, VRAM_SIZE, WRAM_SIZE};
+///
+/// static mut MEMORY: [u8; MEMORY_SIZE] = [0; MEMORY_SIZE];
+/// static mut VRAM: [u8; VRAM_SIZE] = [0; VRAM_SIZE];
+/// static mut WRAM: [u8; WRAM_SIZE] = [0; WRAM_SIZE];
+///
+/// // Only borrowed once at the start of the program
+/// let bufs = unsafe {
+///     Buffers::new(
+///         &mut *core::ptr::addr_of_mut!(MEMORY),
+///         &mut *core::ptr::addr_of_mut!(VRAM),
+///     )
+///     .wram(&mut *core::ptr::addr_of_mut!(WRAM))
+/// };
+/// ```
+pub struct Buffers<'a> {
+    pub(crate) memory: Buf<'a, u8>,
+    pub(crate) vram: Banks<'a>,
+    /// The CGB work RAM, or empty to allocate it.
+    #[cfg(feature = "cgb")]
+    pub(crate) wram: Banks<'a>,
+    /// The frame being drawn by the GPU.
+    pub(crate) drawn: Buf<'a, u32>,
+    /// The last completed frame returned by [`System::frame`][crate::System::frame].
+    pub(crate) frame: Buf<'a, u32>,
+    /// The previous frame blended into the current one.
+    pub(crate) prev: Buf<'a, u32>,
+}
+
+impl<'a> Buffers<'a> {
+    /// Create the buffers from the memory and the video RAM.
+    ///
+    /// The frame buffer and the frame blending are disabled unless their buffers are given
+    /// by [`Buffers::frame_buffer`][] and [`Buffers::frame_blending`][].
+    pub fn new(memory: &'a mut [u8; MEMORY_SIZE], vram: &'a mut [u8; VRAM_SIZE]) -> Self {
+        Self {
+            memory: Buf::Borrowed(memory),
+            vram: Banks::new(Buf::Borrowed(vram), VRAM_BANK_SIZE),
+            #[cfg(feature = "cgb")]
+            wram: Banks::new(Buf::empty(), WRAM_BANK_SIZE),
+            drawn: Buf::empty(),
+            frame: Buf::empty(),
+            prev: Buf::empty(),
+        }
+    }
+
+    /// Provide the CGB work RAM, which is allocated on the heap otherwise.
+    ///
+    /// Without the `cgb` feature, the work RAM is part of the memory and the buffer is unused.
+    #[allow(unused_mut, unused_variables)]
+    pub fn wram(mut self, wram: &'a mut [u8; WRAM_SIZE]) -> Self {
+        #[cfg(feature = "cgb")]
+        {
+            self.wram = Banks::new(Buf::Borrowed(wram), WRAM_BANK_SIZE);
+        }
+        self
+    }
+
+    /// Enable the frame buffer of [`Config::frame_buffer`][crate::Config::frame_buffer]
+    /// with the frame being drawn and the completed frame returned by [`System::frame`][crate::System::frame].
+    pub fn frame_buffer(
+        mut self,
+        drawn: &'a mut [u32; FRAME_SIZE],
+        frame: &'a mut [u32; FRAME_SIZE],
+    ) -> Self {
+        self.drawn = Buf::Borrowed(drawn);
+        self.frame = Buf::Borrowed(frame);
+        self
+    }
+
+    /// Enable [`Config::frame_blending`][crate::Config::frame_blending] with the buffer keeping the previous frame.
+    pub fn frame_blending(mut self, prev: &'a mut [u32; FRAME_SIZE]) -> Self {
+        self.prev = Buf::Borrowed(prev);
+        self
+    }
+
+    /// Allocate the buffers on the heap as the configuration requires, with the given memory.
+    pub(crate) fn alloc(memory: Vec<u8>, cfg: &Config) -> Self {
+        let frame = || {
+            if cfg.frame_buffer {
+                vec![0; FRAME_SIZE].into()
+            } else {
+                Buf::empty()
+            }
+        };
+
+        Self {
+            memory: memory.into(),
+            vram: Banks::alloc(2, VRAM_BANK_SIZE),
+            #[cfg(feature = "cgb")]
+            wram: Banks::alloc(8, WRAM_BANK_SIZE),
+            drawn: frame(),
+            frame: frame(),
+            prev: if cfg.frame_blending > 0 {
+                vec![0; FRAME_SIZE].into()
+            } else {
+                Buf::empty()
+            },
+        }
+    }
+
+    /// Allocate the CGB work RAM if not provided.
+    #[cfg(feature = "cgb")]
+    pub(crate) fn alloc_wram(&mut self) {
+        if self.wram.buf.is_empty() {
+            self.wram = Banks::alloc(8, WRAM_BANK_SIZE);
+        }
+    }
+
+    /// Clear the memory for the power on, except the completed frame.
+    pub(crate) fn clear(&mut self) {
+        self.memory.iter_mut().for_each(|b| *b = 0);
+        self.vram.buf.iter_mut().for_each(|b| *b = 0);
+        #[cfg(feature = "cgb")]
+        self.wram.buf.iter_mut().for_each(|b| *b = 0);
+        self.drawn.iter_mut().for_each(|p| *p = 0);
+        self.prev.iter_mut().for_each(|p| *p = 0);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::error::Error;
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use log::*;
+
+/// The size of the ROM area which contains the cartridge header.
+pub const HEADER_END: usize = 0x150;
+
+/// The Nintendo logo stored at 0104-0133 in the ROM, which the boot ROM compares.
+pub const LOGO: [u8; 48] = [
+    0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0c, 0x00, 0x0d,
+    0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e, 0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99,
+    0xbb, 0xbb, 0x67, 0x63, 0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+];
+
+/// How the cartridge header is checked when the ROM is loaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Validation {
+    /// Refuse to load the ROM with [`Error::InvalidHeader`][] if [`Header::bootable`][] fails.
+    Reject,
+    /// Log a warning and load the ROM anyway (default).
+    Warn,
+    /// Don't check the header.
+    Ignore,
+}
+
+/// Cartridge header information stored at 0100-014f in the ROM.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    /// Title of the game in upper case ASCII.
+    pub title: String,
+    /// The game supports CGB functions.
+    pub cgb: bool,
+    /// The game works only on CGB.
+    pub cgb_only: bool,
+    /// The game supports SGB functions.
+    pub sgb: bool,
+    /// Cartridge type, which indicates the memory bank controller and the external hardware.
+    pub cart_type: u8,
+    /// ROM size in bytes.
+    pub rom_size: usize,
+    /// External RAM size in bytes.
+    pub ram_size: usize,
+    /// The game is sold in Japan.
+    pub japanese: bool,
+    /// New licensee code, which is used when `old_licensee` is 0x33.
+    pub new_licensee: String,
+    /// Old licensee code.
+    pub old_licensee: u8,
+    /// Version number of the game.
+    pub version: u8,
+    /// Header checksum stored in the ROM.
+    pub header_checksum: u8,
+    /// Global checksum stored in the ROM.
+    pub global_checksum: u16,
+    /// The Nintendo logo matches [`LOGO`][].
+    pub logo_valid: bool,
+    /// The header checksum matches the content of the header.
+    pub header_checksum_valid: bool,
+    /// The global checksum matches the content of the ROM.
+    pub global_checksum_valid: bool,
+}
+
+impl Header {
+    /// Parse the cartridge header of the ROM.
+    pub fn parse(rom: &[u8]) -> Result<Self, Error> {
+        if rom.len() < HEADER_END {
+            return Err(Error::RomTooShort(rom.len()));
+        }
+
+        let header_checksum = rom[0x14d];
+        let global_checksum = (rom[0x14e] as u16) << 8 | rom[0x14f] as u16;
+
+        Ok(Self {
+            title: parse_str(&rom[0x134..0x144]),
+            cgb: rom[0x143] & 0x80 != 0,
+            cgb_only: rom[0x143] == 0xc0,
+            sgb: rom[0x146] == 0x03,
+            cart_type: rom[0x147],
+            rom_size: rom_size(rom[0x148]),
+            ram_size: ram_size(rom[0x147], rom[0x149]),
+            japanese: rom[0x14a] == 0x00,
+            new_licensee: parse_str(&rom[0x144..0x146]),
+            old_licensee: rom[0x14b],
+            version: rom[0x14c],
+            header_checksum,
+            global_checksum,
+            logo_valid: rom[0x104..0x134] == LOGO[..],
+            header_checksum_valid: calc_header_checksum(rom) == header_checksum,
+            global_checksum_valid: calc_global_checksum(rom) == global_checksum,
+        })
+    }
+
+    /// Check if the header passes the checks of the boot ROM, i.e. the logo and the header checksum.
+    ///
+    /// The real hardware locks up on the cartridges failing this; the global checksum isn't checked.
+    pub fn bootable(&self) -> bool {
+        self.logo_valid && self.header_checksum_valid
+    }
+
+    /// Check the header as configured, returning an error for a rejected ROM.
+    pub(crate) fn validate(&self, validation: Validation) -> Result<(), Error> {
+        if validation == Validation::Ignore || self.bootable() {
+            return Ok(());
+        }
+
+        let reason = if !self.logo_valid {
+            "Nintendo logo mismatch"
+        } else {
+            "header checksum mismatch"
+        };
+
+        if validation == Validation::Reject {
+            Err(Error::InvalidHeader(reason.to_string()))
+        } else {
+            warn!("Invalid cartridge header: {}", reason);
+            Ok(())
+        }
+    }
+
+    /// Check if the cartridge has a battery to keep the external RAM content.
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self.cart_type,
+            0x03 | 0x06 | 0x09 | 0x0d | 0x0f | 0x10 | 0x13 | 0x1b | 0x1e | 0x22 | 0xfc | 0xff
+        )
+    }
+}
+
+fn parse_str(b: &[u8]) -> String {
+    let b: Vec<u8> = b
+        .iter()
+        .take_while(|b| *b & 0x80 == 0)
+        .map(|b| if *b == 0x00 { b' ' } else { *b })
+        .collect();
+    String::from_utf8_lossy(&b).trim_end().to_string()
+}
+
+fn rom_size(code: u8) -> usize {
+    match code {
+        0x00..=0x08 => 0x8000 << code,
+        0x52 => 72 * 0x4000,
+        0x53 => 80 * 0x4000,
+        0x54 => 96 * 0x4000,
+        _ => 0,
+    }
+}
+
+fn ram_size(cart_type: u8, code: u8) -> usize {
+    match cart_type {
+        // MBC2 has 512 x 4 bits RAM inside the controller.
+        0x05 | 0x06 => 0x200,
+        // MBC7 has 256 bytes EEPROM.
+        0x22 => 0x100,
+        _ => match code {
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            _ => 0,
+        },
+    }
+}
+
+fn calc_header_checksum(rom: &[u8]) -> u8 {
+    rom[0x134..0x14d]
+        .iter()
+        .fold(0u8, |x, b| x.wrapping_sub(*b).wrapping_sub(1))
+}
+
+fn calc_global_checksum(rom: &[u8]) -> u16 {
+    rom.iter()
+        .enumerate()
+        .filter(|(i, _)| *i != 0x14e && *i != 0x14f)
+        .fold(0u16, |sum, (_, b)| sum.wrapping_add(*b as u16))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x104..0x134].copy_from_slice(&LOGO);
+        rom[0x134..0x139].copy_from_slice(b"TETRA");
+        rom[0x143] = 0x80;
+        rom[0x147] = 0x03;
+        rom[0x148] = 0x01;
+        rom[0x149] = 0x03;
+        rom[0x14d] = calc_header_checksum(&rom);
+        let sum = calc_global_checksum(&rom);
+        rom[0x14e] = (sum >> 8) as u8;
+        rom[0x14f] = sum as u8;
+        rom
+    }
+
+    #[test]
+    fn parse_header() {
+        let h = Header::parse(&rom()).unwrap();
+
+        assert_eq!(h.title, "TETRA");
+        assert!(h.cgb);
+        assert!(!h.cgb_only);
+        assert_eq!(h.cart_type, 0x03);
+        assert_eq!(h.rom_size, 0x10000);
+        assert_eq!(h.ram_size, 0x8000);
+        assert!(h.has_battery());
+        assert!(h.header_checksum_valid);
+        assert!(h.global_checksum_valid);
+        assert!(h.logo_valid);
+        assert!(h.bootable());
+    }
+
+    #[test]
+    fn parse_bad_checksum() {
+        let mut rom = rom();
+        rom[0x134] = b'X';
+
+        let h = Header::parse(&rom).unwrap();
+
+        assert!(!h.header_checksum_valid);
+        assert!(!h.global_checksum_valid);
+        assert!(!h.bootable());
+    }
+
+    #[test]
+    fn validate() {
+        let mut rom = rom();
+        assert!(Header::parse(&rom)
+            .unwrap()
+            .validate(Validation::Reject)
+            .is_ok());
+
+        rom[0x104] = 0;
+        let h = Header::parse(&rom).unwrap();
+        assert!(!h.logo_valid);
+        assert_eq!(
+            h.validate(Validation::Reject),
+            Err(Error::InvalidHeader("Nintendo logo mismatch".into()))
+        );
+        assert!(h.validate(Validation::Warn).is_ok());
+        assert!(h.validate(Validation::Ignore).is_ok());
+    }
+
+    #[test]
+    fn parse_short_rom() {
+        assert_eq!(Header::parse(&[0; 0x100]), Err(Error::RomTooShort(0x100)));
+    }
+}
@@ -1,56 +1,112 @@
 use crate::{
-    device::IoHandler,
+    buffers::{Banks, Buf, WRAM_BANK_SIZE},
+    device::{Banked, IoHandler},
+    error::Error,
+    hardware::HardwareHandle,
     mmu::{MemRead, MemWrite, Mmu},
+    state::{Reader, State, Writer},
 };
-use alloc::{vec, vec::Vec};
 use log::*;
 
-pub struct Cgb {
+pub struct Cgb<'a> {
+    hw: HardwareHandle<'a>,
+    /// The writable bits of RP: the LED in bit 0 and the read enable in bits 6-7.
+    rp: u8,
     double_speed: bool,
     speed_switch: bool,
     wram_select: usize,
-    wram_bank: Vec<Vec<u8>>,
+    wram_bank: Banks<'a>,
 }
 
 #[allow(unused)]
-impl Cgb {
-    pub fn new() -> Self {
+impl<'a> Cgb<'a> {
+    pub fn new(hw: HardwareHandle<'a>) -> Self {
+        Self::with_wram(hw, Banks::alloc(8, WRAM_BANK_SIZE))
+    }
+
+    /// Create the CGB registers with the eight banks of the work RAM.
+    pub(crate) fn with_wram(hw: HardwareHandle<'a>, wram: Banks<'a>) -> Self {
         Self {
+            hw,
+            rp: 0,
             double_speed: false,
             speed_switch: false,
             wram_select: 1,
-            wram_bank: (0..8).map(|_| vec![0; 0x1000]).collect(),
+            wram_bank: wram,
         }
     }
 
-    pub fn try_switch_speed(&mut self) {
+    /// Switch the CPU speed if armed through KEY1. Returns `true` if switched.
+    pub fn try_switch_speed(&mut self) -> bool {
         if self.speed_switch {
             self.double_speed = !self.double_speed;
             self.speed_switch = false;
+            true
+        } else {
+            false
         }
     }
 
     pub fn double_speed(&self) -> bool {
         self.double_speed
     }
+
+    /// Move out the work RAM to reuse it on reset.
+    pub(crate) fn take_wram(&mut self) -> Banks<'a> {
+        core::mem::replace(
+            &mut self.wram_bank,
+            Banks::new(Buf::empty(), WRAM_BANK_SIZE),
+        )
+    }
 }
 
-impl IoHandler for Cgb {
+impl<'a> State for Cgb<'a> {
+    fn save(&self, w: &mut Writer) {
+        self.rp.save(w);
+        self.double_speed.save(w);
+        self.speed_switch.save(w);
+        self.wram_select.save(w);
+        self.wram_bank.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.rp.load(r)?;
+        self.double_speed.load(r)?;
+        self.speed_switch.load(r)?;
+        // Bank 0 is always mapped at c000-cfff, so d000-dfff selects from 1
+        self.wram_select = match r.bounded("WRAM bank", 7)? {
+            0 => return Err(Error::InvalidState("WRAM bank: 0".into())),
+            bank => bank,
+        };
+        self.wram_bank.load(r)?;
+        Ok(())
+    }
+}
+
+impl<'a> Banked for Cgb<'a> {
+    fn bank(&self, bank: usize) -> &[u8] {
+        self.wram_bank.bank(bank)
+    }
+}
+
+impl<'a> IoHandler for Cgb<'a> {
     fn on_read(&mut self, _mmu: &Mmu, addr: u16) -> MemRead {
         if addr >= 0xc000 && addr <= 0xcfff {
             let off = addr as usize - 0xc000;
-            MemRead::Replace(self.wram_bank[0][off])
+            MemRead::Replace(self.wram_bank.bank(0)[off])
         } else if addr >= 0xd000 && addr <= 0xdfff {
             let off = addr as usize - 0xd000;
-            MemRead::Replace(self.wram_bank[self.wram_select][off])
+            MemRead::Replace(self.wram_bank.bank(self.wram_select)[off])
         } else if addr == 0xff4d {
             let mut v = 0;
             v |= if self.double_speed { 0x80 } else { 0x00 };
             v |= if self.speed_switch { 0x01 } else { 0x00 };
             MemRead::Replace(v)
         } else if addr == 0xff56 {
-            warn!("Infrared read");
-            MemRead::PassThrough
+            // Bit 1 is cleared while the enabled receiver detects light
+            let light = self.rp & 0xc0 == 0xc0 && self.hw.get().lock().ir_recv();
+            let v = self.rp | 0x3c | if light { 0x00 } else { 0x02 };
+            MemRead::Replace(v)
         } else if addr == 0xff70 {
             MemRead::Replace(self.wram_select as u8)
         } else {
@@ -61,18 +117,81 @@ impl IoHandler for Cgb {
     fn on_write(&mut self, _mmu: &Mmu, addr: u16, value: u8) -> MemWrite {
         if addr >= 0xc000 && addr <= 0xcfff {
             let off = addr as usize - 0xc000;
-            self.wram_bank[0][off] = value;
+            self.wram_bank.bank_mut(0)[off] = value;
         } else if addr >= 0xd000 && addr <= 0xdfff {
             let off = addr as usize - 0xd000;
-            self.wram_bank[self.wram_select][off] = value;
+            self.wram_bank.bank_mut(self.wram_select)[off] = value;
         } else if addr == 0xff4d {
             self.speed_switch = value & 0x01 != 0;
         } else if addr == 0xff56 {
-            warn!("Infrared read");
+            let rp = value & 0xc1;
+            if (rp ^ self.rp) & 0x01 != 0 {
+                debug!("Infrared LED: {}", rp & 0x01 != 0);
+                self.hw.get().lock().ir_send(rp & 0x01 != 0);
+            }
+            self.rp = rp;
         } else if addr == 0xff70 {
-            self.wram_select = (value as usize & 0xf).max(1);
+            self.wram_select = (value as usize & 0x7).max(1);
         }
 
         MemWrite::PassThrough
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::hardware::{Clock, Input, Link, Persistence, Screen, Speaker};
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    /// The hardware which reflects the infrared LED back to the receiver.
+    struct Mirror(Arc<AtomicBool>);
+
+    impl Screen for Mirror {}
+    impl Speaker for Mirror {}
+    impl Input for Mirror {}
+    impl Persistence for Mirror {}
+
+    impl Clock for Mirror {
+        fn clock(&mut self) -> u64 {
+            0
+        }
+    }
+
+    impl Link for Mirror {
+        fn ir_send(&mut self, on: bool) {
+            self.0.store(on, Ordering::Relaxed);
+        }
+
+        fn ir_recv(&mut self) -> bool {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    #[test]
+    fn infrared() {
+        let led = Arc::new(AtomicBool::new(false));
+        let mut cgb = Cgb::new(HardwareHandle::new(Mirror(led.clone())));
+        let mmu = Mmu::new(vec![0; 0x10000]);
+        let rp = |cgb: &mut Cgb| match cgb.on_read(&mmu, 0xff56) {
+            MemRead::Replace(v) => v,
+            MemRead::PassThrough => unreachable!(),
+        };
+
+        assert_eq!(rp(&mut cgb), 0x3e);
+
+        cgb.on_write(&mmu, 0xff56, 0x01);
+        assert!(led.load(Ordering::Relaxed));
+
+        // No light is detected unless reading is enabled
+        assert_eq!(rp(&mut cgb), 0x3f);
+        cgb.on_write(&mmu, 0xff56, 0xc1);
+        assert_eq!(rp(&mut cgb), 0xfd);
+
+        cgb.on_write(&mmu, 0xff56, 0xc0);
+        assert!(!led.load(Ordering::Relaxed));
+        assert_eq!(rp(&mut cgb), 0xfe);
+    }
+}
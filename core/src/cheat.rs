@@ -0,0 +1,294 @@
+use crate::debug::Debugger;
+use crate::device::IoHandler;
+use crate::error::Error;
+use crate::hardware::MaybeSend;
+use crate::mmu::{MemRead, MemWrite, Mmu};
+use crate::system::System;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::str::FromStr;
+use log::*;
+
+/// Cheat code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cheat {
+    /// GameShark code `BBVVLLHH`, which writes the value to RAM every frame.
+    ///
+    /// The value is written to the bank currently mapped at the address.
+    GameShark {
+        /// RAM bank.
+        bank: u8,
+        /// Address to write.
+        addr: u16,
+        /// Value to write.
+        value: u8,
+    },
+    /// Game Genie code `ABC-DEF` or `ABC-DEF-GHI`, which replaces the value read from ROM.
+    GameGenie {
+        /// Address to replace.
+        addr: u16,
+        /// New value.
+        value: u8,
+        /// The value is replaced only if the original value matches.
+        compare: Option<u8>,
+    },
+}
+
+impl FromStr for Cheat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let digits: Vec<u8> = s
+            .trim()
+            .chars()
+            .filter(|c| *c != '-')
+            .map(|c| c.to_digit(16).map(|d| d as u8))
+            .collect::<Option<_>>()
+            .ok_or_else(|| Error::InvalidCheat(s.into()))?;
+
+        let byte = |i: usize| digits[i] << 4 | digits[i + 1];
+
+        match (s.contains('-'), digits.len()) {
+            (false, 8) => Ok(Cheat::GameShark {
+                bank: byte(0),
+                value: byte(2),
+                addr: (byte(6) as u16) << 8 | byte(4) as u16,
+            }),
+            (true, 6) | (true, 9) => {
+                let d = |i: usize| digits[i] as u16;
+                Ok(Cheat::GameGenie {
+                    addr: ((d(5) ^ 0xf) << 12) | d(2) << 8 | d(3) << 4 | d(4),
+                    value: byte(0),
+                    compare: if digits.len() == 9 {
+                        Some((digits[6] << 4 | digits[8]).rotate_right(2) ^ 0xba)
+                    } else {
+                        None
+                    },
+                })
+            }
+            _ => Err(Error::InvalidCheat(String::from(s))),
+        }
+    }
+}
+
+/// Registered cheat codes.
+pub(crate) struct Cheats {
+    codes: Vec<Option<(Cheat, bool)>>,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Self { codes: Vec::new() }
+    }
+
+    pub fn add(&mut self, cheat: Cheat) -> usize {
+        self.codes.push(Some((cheat, true)));
+        self.codes.len() - 1
+    }
+
+    pub fn remove(&mut self, id: usize) {
+        if let Some(code) = self.codes.get_mut(id) {
+            *code = None;
+        }
+    }
+
+    pub fn enable(&mut self, id: usize, enabled: bool) {
+        match self.codes.get_mut(id) {
+            Some(Some((_, e))) => *e = enabled,
+            _ => warn!("No such cheat: {}", id),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.codes.clear();
+    }
+
+    fn enabled(&self) -> impl Iterator<Item = &Cheat> {
+        self.codes
+            .iter()
+            .flatten()
+            .filter(|(_, e)| *e)
+            .map(|(c, _)| c)
+    }
+
+    /// Return the RAM writes by GameShark codes, which is applied every frame.
+    pub fn writes(&self) -> Vec<(u16, u8)> {
+        self.enabled()
+            .filter_map(|c| match c {
+                Cheat::GameShark { addr, value, .. } => Some((*addr, *value)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl IoHandler for Cheats {
+    fn on_read(&mut self, mmu: &Mmu, addr: u16) -> MemRead {
+        for c in self.enabled() {
+            if let Cheat::GameGenie {
+                addr: a,
+                value,
+                compare,
+            } = c
+            {
+                if *a != addr {
+                    continue;
+                }
+                match compare {
+                    Some(v) if mmu.get8(addr) != *v => {}
+                    _ => return MemRead::Replace(*value),
+                }
+            }
+        }
+
+        MemRead::PassThrough
+    }
+
+    fn on_write(&mut self, _: &Mmu, _: u16, _: u8) -> MemWrite {
+        MemWrite::PassThrough
+    }
+}
+
+/// The first address of the work RAM searched by [`RamSearch`][].
+const WRAM_START: u16 = 0xc000;
+
+/// The size of the work RAM searched by [`RamSearch`][].
+const WRAM_SIZE: usize = 0x2000;
+
+/// Filter to narrow down the candidates of [`RamSearch`][].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    /// The value is equal to the given value.
+    Equal(u8),
+    /// The value is different from the given value.
+    NotEqual(u8),
+    /// The value changed since the last snapshot.
+    Changed,
+    /// The value didn't change since the last snapshot.
+    Unchanged,
+    /// The value increased since the last snapshot.
+    Increased,
+    /// The value decreased since the last snapshot.
+    Decreased,
+    /// The value increased exactly by the given amount since the last snapshot.
+    IncreasedBy(u8),
+    /// The value decreased exactly by the given amount since the last snapshot.
+    DecreasedBy(u8),
+}
+
+impl Filter {
+    fn check(&self, prev: u8, cur: u8) -> bool {
+        match *self {
+            Filter::Equal(v) => cur == v,
+            Filter::NotEqual(v) => cur != v,
+            Filter::Changed => cur != prev,
+            Filter::Unchanged => cur == prev,
+            Filter::Increased => cur > prev,
+            Filter::Decreased => cur < prev,
+            Filter::IncreasedBy(n) => cur == prev.wrapping_add(n),
+            Filter::DecreasedBy(n) => cur == prev.wrapping_sub(n),
+        }
+    }
+}
+
+/// Iterative search of the work RAM (c000-dfff) to find the addresses for cheat codes.
+///
+/// Created by [`System::ram_search`][]. Each call of [`RamSearch::filter`][] takes a new snapshot
+/// and keeps the addresses which satisfy the filter against the previous snapshot.
+pub struct RamSearch {
+    snapshot: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+impl RamSearch {
+    pub(crate) fn new<'a, D: Debugger + MaybeSend + 'a>(sys: &System<'a, D>) -> Self {
+        Self {
+            snapshot: Self::take_snapshot(sys),
+            candidates: (0..WRAM_SIZE as u16).map(|i| WRAM_START + i).collect(),
+        }
+    }
+
+    fn take_snapshot<'a, D: Debugger + MaybeSend + 'a>(sys: &System<'a, D>) -> Vec<u8> {
+        (0..WRAM_SIZE as u16)
+            .map(|i| sys.mmu_get8(WRAM_START + i))
+            .collect()
+    }
+
+    /// Take a new snapshot and drop the candidates which don't satisfy the filter.
+    pub fn filter<'a, D: Debugger + MaybeSend + 'a>(
+        &mut self,
+        sys: &System<'a, D>,
+        filter: Filter,
+    ) {
+        let snapshot = Self::take_snapshot(sys);
+        let prev = &self.snapshot;
+
+        self.candidates.retain(|addr| {
+            let i = (addr - WRAM_START) as usize;
+            filter.check(prev[i], snapshot[i])
+        });
+        self.snapshot = snapshot;
+    }
+
+    /// Return the remaining candidate addresses.
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+
+    /// Return the value of the address in the last snapshot.
+    pub fn value(&self, addr: u16) -> Option<u8> {
+        if addr >= WRAM_START && (addr as usize) < WRAM_START as usize + WRAM_SIZE {
+            Some(self.snapshot[(addr - WRAM_START) as usize])
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_gameshark() {
+        assert_eq!(
+            "01FF16D0".parse(),
+            Ok(Cheat::GameShark {
+                bank: 0x01,
+                addr: 0xd016,
+                value: 0xff,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_gamegenie() {
+        assert_eq!(
+            "00A-17B-C49".parse(),
+            Ok(Cheat::GameGenie {
+                addr: 0x4a17,
+                value: 0x00,
+                compare: Some(0xc8),
+            })
+        );
+        assert_eq!(
+            "3EA-17B".parse(),
+            Ok(Cheat::GameGenie {
+                addr: 0x4a17,
+                value: 0x3e,
+                compare: None,
+            })
+        );
+        assert!("00A-17B-C4".parse::<Cheat>().is_err());
+        assert!("XYZ".parse::<Cheat>().is_err());
+    }
+
+    #[test]
+    fn filter_values() {
+        assert!(Filter::IncreasedBy(2).check(0xff, 0x01));
+        assert!(!Filter::IncreasedBy(2).check(0x01, 0x02));
+        assert!(Filter::DecreasedBy(1).check(0x05, 0x04));
+        assert!(Filter::Changed.check(0x05, 0x04));
+        assert!(!Filter::Unchanged.check(0x05, 0x04));
+    }
+}
@@ -1,11 +1,34 @@
 use crate::device::Device;
+use crate::error::Error;
 use crate::ic::Ic;
 use crate::inst::decode;
 use crate::mmu::Mmu;
+use crate::state::{Reader, State, Writer};
 use log::*;
 
 use alloc::fmt;
 
+/// The registers of the CPU returned by [`System::cpu_state`][crate::System::cpu_state].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CpuState {
+    /// `af` register, whose lower 4 bits are always zero.
+    pub af: u16,
+    /// `bc` register.
+    pub bc: u16,
+    /// `de` register.
+    pub de: u16,
+    /// `hl` register.
+    pub hl: u16,
+    /// Stack pointer.
+    pub sp: u16,
+    /// Program counter.
+    pub pc: u16,
+    /// Interrupt master enable flag.
+    pub ime: bool,
+    /// The CPU is halted. Not written by [`System::set_cpu_state`][crate::System::set_cpu_state].
+    pub halted: bool,
+}
+
 /// Represents CPU state.
 #[derive(Clone)]
 pub struct Cpu {
@@ -21,6 +44,13 @@ pub struct Cpu {
     sp: u16,
     ime: bool,
     halt: bool,
+    halting: bool,
+    halt_bug: bool,
+    stop: bool,
+    stopping: bool,
+    ei: bool,
+    ei_now: bool,
+    locked: bool,
 }
 
 impl fmt::Display for Cpu {
@@ -69,13 +99,34 @@ impl Cpu {
             sp: 0,
             ime: true,
             halt: false,
+            halting: false,
+            halt_bug: false,
+            stop: false,
+            stopping: false,
+            ei: false,
+            ei_now: false,
+            locked: false,
         }
     }
 
     /// Switch the CPU state to halting.
     pub fn halt(&mut self) {
         debug!("Halted");
-        // TODO: self.halt = true;
+        self.halt = true;
+        self.halting = true;
+    }
+
+    /// Lock up the CPU, as the hardware does when it executes an invalid opcode.
+    ///
+    /// The locked CPU doesn't execute instructions nor handle interrupts any more.
+    pub fn lock(&mut self) {
+        warn!("CPU locked up at {:04x}", self.pc);
+        self.locked = true;
+    }
+
+    /// Check if the CPU is locked up.
+    pub fn is_locked(&self) -> bool {
+        self.locked
     }
 
     /// Execute a single instruction.
@@ -83,15 +134,37 @@ impl Cpu {
     /// The function fetches an instruction code from the memory,
     /// decodes it, and updates the CPU/memory state accordingly.
     /// The return value is the number of clock cycles consumed by the instruction.
-    /// If the CPU is in the halt state, the function does nothing but returns a fixed clock cycle.
-    pub fn execute(&mut self, mmu: &mut Mmu) -> usize {
-        if self.halt {
-            4
+    /// Each memory access spends a memory cycle through [`Mmu::tick`][] as it happens,
+    /// and the rest of the cycles are left to the caller.
+    /// If the CPU is in the halt or stop state or locked up, the function does nothing but returns a fixed clock cycle.
+    pub fn execute(&mut self, mmu: &mut Mmu) -> Result<usize, Error> {
+        if self.halt || self.stop || self.locked {
+            Ok(4)
         } else {
-            let (code, arg) = self.fetch(mmu);
-            let (time, size) = decode(code, arg, self, mmu);
-            self.set_pc(self.get_pc().wrapping_add(size as u16));
-            time
+            let (mut code, mut arg) = self.fetch(mmu);
+            for _ in 0..arg {
+                mmu.tick();
+            }
+
+            // EI takes effect after the following instruction, so DI right after EI wins.
+            self.ei_now = core::mem::replace(&mut self.ei, false);
+            if self.ei_now {
+                self.ime = true;
+            }
+
+            // The HALT bug: PC fails to increment once, so the byte after HALT is read twice.
+            let bug = self.halt_bug;
+            if bug {
+                self.halt_bug = false;
+                if arg == 2 {
+                    code = 0xcbcb;
+                }
+                arg -= 1;
+            }
+
+            let (time, size) = decode(code, arg, self, mmu)?;
+            self.set_pc(self.get_pc().wrapping_add(size as u16 - bug as u16));
+            Ok(time)
         }
     }
 
@@ -99,6 +172,7 @@ impl Cpu {
     pub fn disable_interrupt(&mut self) {
         debug!("Disable interrupt");
         self.ime = false;
+        self.ei = false;
     }
 
     /// Enable interrupts to this CPU.
@@ -107,46 +181,111 @@ impl Cpu {
         self.ime = true;
     }
 
+    /// Enable interrupts to this CPU after the next instruction, as EI does.
+    pub fn enable_interrupt_delayed(&mut self) {
+        debug!("Enable interrupt after the next instruction");
+        self.ei = true;
+    }
+
     /// Check if pending interrupts in the interrupt controller,
     /// and process them if any.
     pub fn check_interrupt(&mut self, mmu: &mut Mmu, ic: &Device<Ic>) -> usize {
-        if !self.ime {
+        // Whether the last instruction was HALT itself
+        let halting = core::mem::replace(&mut self.halting, false);
+
+        if self.locked || self.stop {
+            0
+        } else if !self.ime {
             if self.halt {
                 // If HALT is executed while interrupt is disabled,
                 // the interrupt wakes up CPU without being consumed.
                 if let Some(value) = ic.borrow_mut().peek() {
                     debug!("Interrupted on halt + ime=0: {:02x}", value);
                     self.halt = false;
+
+                    // If the interrupt is already pending, HALT doesn't halt the CPU
+                    // but triggers the HALT bug instead.
+                    if halting {
+                        debug!("HALT bug at {:04x}", self.pc);
+                        self.halt_bug = true;
+                    }
                 }
             }
 
             0
         } else {
-            let value = match ic.borrow_mut().poll() {
-                Some(value) => value,
-                None => return 0,
-            };
+            if ic.borrow_mut().peek().is_none() {
+                return 0;
+            }
 
-            debug!("Interrupted: {:02x}", value);
+            // HALT right after EI returns to HALT itself from the interrupt.
+            if halting && self.ei_now {
+                debug!("HALT bug after EI at {:04x}", self.pc);
+                self.pc = self.pc.wrapping_sub(1);
+            }
 
-            self.interrupted(mmu, value);
+            self.interrupted(mmu, ic);
 
+            // Waking up from the halt state takes an extra memory cycle.
+            let wake = if self.halt && !halting { 4 } else { 0 };
             self.halt = false;
 
-            16
+            20 + wake
         }
     }
 
-    fn interrupted(&mut self, mmu: &mut Mmu, value: u8) {
+    /// Dispatch the interrupt, which takes 5 memory cycles.
+    fn interrupted(&mut self, mmu: &mut Mmu, ic: &Device<Ic>) {
         self.disable_interrupt();
 
-        self.push(mmu, self.get_pc());
-        self.set_pc(value as u16);
+        mmu.tick();
+        mmu.tick();
+
+        let pc = self.get_pc();
+        let sp = self.get_sp().wrapping_sub(1);
+        mmu.cpu_set8(sp, (pc >> 8) as u8);
+
+        // The interrupt to dispatch is decided after pushing the upper byte,
+        // which may overwrite IE and cancel the interrupt, jumping to 0x0000.
+        let value = ic.borrow_mut().poll();
+        debug!("Interrupted: {:02x?}", value);
+
+        let sp = sp.wrapping_sub(1);
+        mmu.cpu_set8(sp, pc as u8);
+        self.set_sp(sp);
+
+        mmu.tick();
+        self.set_pc(value.unwrap_or(0) as u16);
+    }
+
+    /// Check if the CPU is in the halt state.
+    pub fn is_halted(&self) -> bool {
+        self.halt
     }
 
     /// Stop the CPU.
-    pub fn stop(&self) {
-        // TODO: Stop.
+    ///
+    /// The stopped CPU doesn't execute instructions nor handle interrupts until [`Cpu::resume`][] is called.
+    pub fn stop(&mut self) {
+        debug!("Stopped");
+        self.stop = true;
+        self.stopping = true;
+    }
+
+    /// Returns `true` once after the CPU executes STOP.
+    pub(crate) fn take_stop(&mut self) -> bool {
+        core::mem::replace(&mut self.stopping, false)
+    }
+
+    /// Check if the CPU is in the stop state.
+    pub fn is_stopped(&self) -> bool {
+        self.stop
+    }
+
+    /// Resume the CPU from the stop state.
+    pub fn resume(&mut self) {
+        debug!("Resumed");
+        self.stop = false;
     }
 
     /// Gets the value of `z` flag in the flag register.
@@ -339,18 +478,50 @@ impl Cpu {
         self.sp = v
     }
 
+    /// Gets the value of the interrupt master enable flag.
+    pub fn get_ime(&self) -> bool {
+        self.ime
+    }
+
+    /// Gets all the registers at once.
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            af: self.get_af(),
+            bc: self.get_bc(),
+            de: self.get_de(),
+            hl: self.get_hl(),
+            sp: self.sp,
+            pc: self.pc,
+            ime: self.ime,
+            halted: self.halt,
+        }
+    }
+
+    /// Updates all the registers at once, cancelling the pending `ei`.
+    pub fn set_state(&mut self, state: &CpuState) {
+        self.set_af(state.af);
+        self.set_bc(state.bc);
+        self.set_de(state.de);
+        self.set_hl(state.hl);
+        self.sp = state.sp;
+        self.pc = state.pc;
+        self.ime = state.ime;
+        self.ei = false;
+        self.ei_now = false;
+    }
+
     /// Pushes a 16-bit value to the stack, updating the stack pointer register.
     pub fn push(&mut self, mmu: &mut Mmu, v: u16) {
         let p = self.get_sp().wrapping_sub(2);
         self.set_sp(self.get_sp().wrapping_sub(2));
-        mmu.set16(p, v)
+        mmu.cpu_set16(p, v)
     }
 
     /// Pops a 16-bit value from the stack, updating the stack pointer register.
     pub fn pop(&mut self, mmu: &mut Mmu) -> u16 {
         let p = self.get_sp();
         self.set_sp(self.get_sp().wrapping_add(2));
-        mmu.get16(p)
+        mmu.cpu_get16(p)
     }
 
     /// Fetches an opcode from the memory and returns it with its length.
@@ -368,11 +539,61 @@ impl Cpu {
     }
 }
 
+impl State for Cpu {
+    fn save(&self, w: &mut Writer) {
+        self.a.save(w);
+        self.b.save(w);
+        self.c.save(w);
+        self.d.save(w);
+        self.e.save(w);
+        self.f.save(w);
+        self.h.save(w);
+        self.l.save(w);
+        self.pc.save(w);
+        self.sp.save(w);
+        self.ime.save(w);
+        self.halt.save(w);
+        self.halting.save(w);
+        self.halt_bug.save(w);
+        self.stop.save(w);
+        self.stopping.save(w);
+        self.ei.save(w);
+        self.ei_now.save(w);
+        self.locked.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.a.load(r)?;
+        self.b.load(r)?;
+        self.c.load(r)?;
+        self.d.load(r)?;
+        self.e.load(r)?;
+        self.f.load(r)?;
+        self.h.load(r)?;
+        self.l.load(r)?;
+        self.pc.load(r)?;
+        self.sp.load(r)?;
+        self.ime.load(r)?;
+        self.halt.load(r)?;
+        self.halting.load(r)?;
+        self.halt_bug.load(r)?;
+        self.stop.load(r)?;
+        self.stopping.load(r)?;
+        self.ei.load(r)?;
+        self.ei_now.load(r)?;
+        self.locked.load(r)?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::inst::decode;
+    use crate::mmu::Clock;
+    use alloc::sync::Arc;
     use alloc::{vec, vec::Vec};
+    use spin::Mutex;
 
     fn write(mmu: &mut Mmu, m: Vec<u8>) {
         for i in 0..m.len() {
@@ -383,15 +604,41 @@ mod test {
     fn exec(cpu: &mut Cpu, mmu: &mut Mmu) {
         let (code, arg) = cpu.fetch(&mmu);
 
-        let (_, size) = decode(code, arg, cpu, mmu);
+        let (_, size) = decode(code, arg, cpu, mmu).unwrap();
 
         cpu.set_pc(cpu.get_pc().wrapping_add(size as u16));
     }
 
+    #[test]
+    fn state() {
+        let mut cpu = Cpu::new();
+        let state = CpuState {
+            af: 0x12ff,
+            bc: 0x3456,
+            de: 0x789a,
+            hl: 0xbcde,
+            sp: 0xfffe,
+            pc: 0x0150,
+            ime: false,
+            halted: true,
+        };
+
+        cpu.set_state(&state);
+        assert_eq!(
+            cpu.state(),
+            CpuState {
+                af: 0x12f0,
+                halted: false,
+                ..state
+            }
+        );
+        assert!(!cpu.get_ime());
+    }
+
     #[test]
     fn op_00af() {
         // xor a
-        let mut mmu = Mmu::new();
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
         let mut cpu = Cpu::new();
 
         cpu.set_a(0x32);
@@ -405,7 +652,7 @@ mod test {
     #[test]
     fn op_00f1() {
         // pop af
-        let mut mmu = Mmu::new();
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
         let mut cpu = Cpu::new();
 
         cpu.set_bc(0x1301);
@@ -430,4 +677,146 @@ mod test {
         exec(&mut cpu, &mut mmu); // cp e
         assert_eq!(cpu.get_zf(), true);
     }
+
+    /// Counts the memory cycles at `C000`.
+    struct Counter;
+
+    impl Clock for Counter {
+        fn tick(&mut self, _: usize, mmu: &mut Mmu) {
+            mmu.ram_mut()[0xc000] += 1;
+        }
+    }
+
+    #[test]
+    fn memory_cycles() {
+        // ld a,(0xc000)
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut cpu = Cpu::new();
+
+        write(&mut mmu, vec![0xfa, 0x00, 0xc0]);
+        mmu.set_clock(Some(Arc::new(Mutex::new(Counter))));
+
+        let time = cpu.execute(&mut mmu).unwrap();
+
+        // The read happens in the last memory cycle after the opcode and the address are fetched
+        assert_eq!(cpu.get_a(), 4);
+        assert_eq!(mmu.take_ticked(), time);
+    }
+
+    #[test]
+    fn halt_bug() {
+        // halt; ld a,0x14 with a pending interrupt and ime=0
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut cpu = Cpu::new();
+        let ic = Device::new(Ic::new());
+        mmu.add_handler((0xff0f, 0xff0f), ic.handler());
+        mmu.add_handler((0xffff, 0xffff), ic.handler());
+
+        write(&mut mmu, vec![0x76, 0x3e, 0x14]);
+        mmu.set8(0xffff, 0x01);
+        mmu.set8(0xff0f, 0x01);
+        cpu.disable_interrupt();
+
+        cpu.execute(&mut mmu).unwrap();
+        assert_eq!(cpu.check_interrupt(&mut mmu, &ic), 0);
+        assert!(!cpu.is_halted());
+
+        // `3e 14` is executed as `3e 3e` and `14`
+        cpu.execute(&mut mmu).unwrap();
+        assert_eq!(cpu.get_a(), 0x3e);
+        assert_eq!(cpu.get_pc(), 0x0002);
+        cpu.execute(&mut mmu).unwrap();
+        assert_eq!(cpu.get_d(), 0x01);
+        assert_eq!(cpu.get_pc(), 0x0003);
+    }
+
+    #[test]
+    fn stop() {
+        // stop 0; inc a
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut cpu = Cpu::new();
+
+        write(&mut mmu, vec![0x10, 0x00, 0x3c]);
+
+        cpu.execute(&mut mmu).unwrap();
+        assert!(cpu.take_stop());
+        assert!(!cpu.take_stop());
+        assert_eq!(cpu.get_pc(), 0x0002);
+
+        // The stopped CPU doesn't proceed until resumed
+        assert_eq!(cpu.execute(&mut mmu).unwrap(), 4);
+        assert_eq!(cpu.get_pc(), 0x0002);
+
+        cpu.resume();
+        cpu.execute(&mut mmu).unwrap();
+        assert_eq!(cpu.get_a(), 0x01);
+    }
+
+    #[test]
+    fn ei_delay() {
+        // ei; inc a; ei; di; inc a
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut cpu = Cpu::new();
+        let ic = Device::new(Ic::new());
+        mmu.add_handler((0xff0f, 0xff0f), ic.handler());
+        mmu.add_handler((0xffff, 0xffff), ic.handler());
+
+        write(&mut mmu, vec![0xfb, 0x3c, 0xfb, 0xf3, 0x3c]);
+        mmu.set8(0xffff, 0x04);
+        mmu.set8(0xff0f, 0x04);
+        cpu.set_sp(0xd000);
+        cpu.disable_interrupt();
+
+        // Interrupts are enabled after the instruction following EI
+        cpu.execute(&mut mmu).unwrap();
+        assert_eq!(cpu.check_interrupt(&mut mmu, &ic), 0);
+        cpu.execute(&mut mmu).unwrap();
+        assert_eq!(cpu.check_interrupt(&mut mmu, &ic), 20);
+        assert_eq!(cpu.get_pc(), 0x0050);
+        assert_eq!(mmu.get16(0xcffe), 0x0002);
+
+        // DI right after EI keeps interrupts disabled
+        cpu.set_pc(0x0002);
+        mmu.set8(0xff0f, 0x04);
+        cpu.execute(&mut mmu).unwrap();
+        cpu.execute(&mut mmu).unwrap();
+        cpu.execute(&mut mmu).unwrap();
+        assert_eq!(cpu.check_interrupt(&mut mmu, &ic), 0);
+        assert_eq!(cpu.get_pc(), 0x0005);
+    }
+
+    #[test]
+    fn ie_push() {
+        // The upper byte of PC pushed onto IE cancels the interrupt
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut cpu = Cpu::new();
+        let ic = Device::new(Ic::new());
+        mmu.add_handler((0xff0f, 0xff0f), ic.handler());
+        mmu.add_handler((0xffff, 0xffff), ic.handler());
+
+        mmu.set8(0xffff, 0x01);
+        mmu.set8(0xff0f, 0x01);
+        cpu.set_sp(0x0000);
+        cpu.set_pc(0x0200);
+
+        assert_eq!(cpu.check_interrupt(&mut mmu, &ic), 20);
+        assert_eq!(cpu.get_pc(), 0x0000);
+        assert_eq!(cpu.get_sp(), 0xfffe);
+        assert_eq!(mmu.get8(0xfffe), 0x00);
+        // The cancelled interrupt is still requested
+        assert_eq!(ic.borrow().peek(), None);
+        assert_eq!(mmu.get8(0xff0f) & 0x1f, 0x01);
+
+        // Redirected to the interrupt enabled by the push, leaving the original one requested
+        mmu.set8(0xffff, 0x01);
+        mmu.set8(0xff0f, 0x03);
+        cpu.enable_interrupt();
+        cpu.set_sp(0x0000);
+        cpu.set_pc(0x0234);
+
+        assert_eq!(cpu.check_interrupt(&mut mmu, &ic), 20);
+        assert_eq!(cpu.get_pc(), 0x0048);
+        assert_eq!(mmu.get8(0xfffe), 0x34);
+        assert_eq!(mmu.get8(0xff0f) & 0x1f, 0x01);
+    }
 }
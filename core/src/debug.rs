@@ -1,11 +1,32 @@
 use crate::cpu::Cpu;
 use crate::device::IoHandler;
+#[cfg(feature = "debugger")]
+pub use crate::expr::Condition;
 use crate::mmu::{MemRead, MemWrite, Mmu};
+#[cfg(feature = "debugger")]
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "debugger")]
+use hashbrown::HashMap;
+use spin::Mutex;
 
 /// Debugger interface.
 ///
 /// The users of this library can implement this interface to inspect the state of the emulator.
 pub trait Debugger: IoHandler {
+    /// Whether the emulator calls the debugger at all.
+    ///
+    /// The emulator skips the per-instruction hooks and the memory hooks of the debugger
+    /// which returns false here, so they compile away for [`NullDebugger`][].
+    fn enabled() -> bool
+    where
+        Self: Sized,
+    {
+        true
+    }
+
     /// The function is called on the initialization phase.
     fn init(&mut self, mmu: &Mmu);
 
@@ -17,6 +38,181 @@ pub trait Debugger: IoHandler {
 
     /// Check if the external signal is triggered. Deprecated.
     fn check_signal(&mut self);
+
+    /// The function is called right after [`Debugger::on_decode`][] to write the registers and the memory
+    /// changed by the debugger, e.g. by the client of a remote debugger, before the instruction runs.
+    fn write_back(&mut self, _cpu: &mut Cpu, _mmu: &mut Mmu) {}
+
+    /// The function is called when the CPU fetches an invalid opcode.
+    fn on_invalid_opcode(&mut self, _pc: u16, _code: u16) {}
+
+    /// The function is called right before the CPU executes an instruction if tracing is enabled.
+    ///
+    /// See [`Config::trace`][crate::Config::trace].
+    fn trace(&mut self, _trace: &Trace) {}
+
+    /// The function is called when the CPU enters a subroutine if call stack tracking is enabled.
+    ///
+    /// See [`Config::call_stack`][crate::Config::call_stack].
+    fn on_call(&mut self, _frame: &Frame) {}
+
+    /// The function is called when the CPU returns from a subroutine if call stack tracking is enabled.
+    fn on_return(&mut self, _frame: &Frame) {}
+
+    /// The function is called for each hardware event if the events are enabled,
+    /// with the CPU cycles elapsed at the end of the instruction during which it happened.
+    ///
+    /// See [`Config::events`][crate::Config::events].
+    fn on_event(&mut self, _event: &HwEvent, _cycles: u64) {}
+}
+
+impl<D: Debugger> Debugger for &mut D {
+    fn enabled() -> bool {
+        D::enabled()
+    }
+
+    fn init(&mut self, mmu: &Mmu) {
+        (**self).init(mmu)
+    }
+
+    fn take_cpu_snapshot(&mut self, cpu: Cpu) {
+        (**self).take_cpu_snapshot(cpu)
+    }
+
+    fn on_decode(&mut self, mmu: &Mmu) {
+        (**self).on_decode(mmu)
+    }
+
+    fn check_signal(&mut self) {
+        (**self).check_signal()
+    }
+
+    fn write_back(&mut self, cpu: &mut Cpu, mmu: &mut Mmu) {
+        (**self).write_back(cpu, mmu)
+    }
+
+    fn on_invalid_opcode(&mut self, pc: u16, code: u16) {
+        (**self).on_invalid_opcode(pc, code)
+    }
+
+    fn trace(&mut self, trace: &Trace) {
+        (**self).trace(trace)
+    }
+
+    fn on_call(&mut self, frame: &Frame) {
+        (**self).on_call(frame)
+    }
+
+    fn on_return(&mut self, frame: &Frame) {
+        (**self).on_return(frame)
+    }
+
+    fn on_event(&mut self, event: &HwEvent, cycles: u64) {
+        (**self).on_event(event, cycles)
+    }
+}
+
+/// A hardware event reported to [`Debugger::on_event`][], for timelines and visualizations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HwEvent {
+    /// The interrupt of the vector (0x40, 0x48, 0x50, 0x58 or 0x60) is requested by the peripheral.
+    InterruptRaised(u16),
+    /// The CPU jumps to the interrupt vector.
+    InterruptServiced(u16),
+    /// The LCD enters the mode (0: H-blank, 1: V-blank, 2: OAM scan, 3: drawing) on the line.
+    ModeChange {
+        /// The new mode.
+        mode: u8,
+        /// The line, i.e. LY.
+        line: u8,
+    },
+    /// The memory bank controller maps another ROM bank at 4000-7fff.
+    BankSwitch(usize),
+    /// The OAM DMA starts copying from the address.
+    DmaStart(u16),
+    /// The OAM DMA completes.
+    DmaEnd,
+    /// The CPU switches the speed, to the double speed if true.
+    SpeedSwitch(bool),
+}
+
+/// The queue of the hardware events shared with the peripherals, which drops the events unless enabled.
+#[derive(Clone, Default)]
+pub(crate) struct Events(Option<Arc<Mutex<Vec<HwEvent>>>>);
+
+impl Events {
+    pub fn new(enabled: bool) -> Self {
+        Self(if enabled {
+            Some(Arc::new(Mutex::new(Vec::new())))
+        } else {
+            None
+        })
+    }
+
+    pub fn push(&self, event: HwEvent) {
+        if let Some(q) = &self.0 {
+            q.lock().push(event);
+        }
+    }
+
+    /// Pass the queued events to `f` in order, emptying the queue.
+    pub fn drain(&self, mut f: impl FnMut(&HwEvent)) {
+        if let Some(q) = &self.0 {
+            q.lock().drain(..).for_each(|e| f(&e));
+        }
+    }
+}
+
+impl fmt::Debug for Events {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Events({})", self.0.is_some())
+    }
+}
+
+/// The CPU state right before an instruction is executed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Trace {
+    /// Program counter.
+    pub pc: u16,
+    /// The opcode, which has 0xcb in the upper byte for the prefixed instructions.
+    pub code: u16,
+    /// AF register.
+    pub af: u16,
+    /// BC register.
+    pub bc: u16,
+    /// DE register.
+    pub de: u16,
+    /// HL register.
+    pub hl: u16,
+    /// Stack pointer.
+    pub sp: u16,
+    /// CPU cycles elapsed since the emulator started.
+    pub cycles: u64,
+}
+
+impl Trace {
+    pub(crate) fn new(cpu: &Cpu, mmu: &Mmu, cycles: u64) -> Self {
+        Self {
+            pc: cpu.get_pc(),
+            code: cpu.fetch(mmu).0,
+            af: cpu.get_af(),
+            bc: cpu.get_bc(),
+            de: cpu.get_de(),
+            hl: cpu.get_hl(),
+            sp: cpu.get_sp(),
+            cycles,
+        }
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "PC:{:04X} OP:{:04X} AF:{:04X} BC:{:04X} DE:{:04X} HL:{:04X} SP:{:04X} CY:{}",
+            self.pc, self.code, self.af, self.bc, self.de, self.hl, self.sp, self.cycles
+        )
+    }
 }
 
 impl dyn Debugger {
@@ -30,6 +226,10 @@ impl dyn Debugger {
 pub struct NullDebugger;
 
 impl Debugger for NullDebugger {
+    fn enabled() -> bool {
+        false
+    }
+
     fn init(&mut self, _: &Mmu) {}
 
     fn take_cpu_snapshot(&mut self, _: Cpu) {}
@@ -48,3 +248,567 @@ impl IoHandler for NullDebugger {
         MemWrite::PassThrough
     }
 }
+
+/// The way the CPU entered a subroutine.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameKind {
+    /// CALL instruction.
+    Call,
+    /// RST instruction.
+    Rst,
+    /// Interrupt dispatch.
+    Interrupt,
+}
+
+/// An entry of the shadow call stack.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// The way the CPU entered the subroutine.
+    pub kind: FrameKind,
+    /// The address of the instruction which called the subroutine.
+    pub caller: u16,
+    /// The address of the subroutine.
+    pub callee: u16,
+    /// The address to return to.
+    pub ret: u16,
+    /// The stack pointer right after the return address is pushed.
+    pub sp: u16,
+}
+
+#[cfg(feature = "debugger")]
+/// Shadow call stack which follows CALL, RST, RET and interrupts.
+#[derive(Default)]
+pub(crate) struct CallStack {
+    frames: Vec<Frame>,
+}
+
+#[cfg(feature = "debugger")]
+impl CallStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Update the stack after the CPU executed `code` at `pc` with the stack pointer `sp`.
+    pub fn exec(&mut self, code: u16, pc: u16, sp: u16, cpu: &Cpu, dbg: &mut dyn Debugger) {
+        let (kind, len) = match code {
+            0xc4 | 0xcc | 0xcd | 0xd4 | 0xdc => (FrameKind::Call, 3),
+            0xc7 | 0xcf | 0xd7 | 0xdf | 0xe7 | 0xef | 0xf7 | 0xff => (FrameKind::Rst, 1),
+            0xc0 | 0xc8 | 0xc9 | 0xd0 | 0xd8 | 0xd9 => {
+                if cpu.get_sp() == sp.wrapping_add(2) {
+                    self.ret(sp, dbg);
+                }
+                return;
+            }
+            _ => return,
+        };
+
+        // Conditional calls which aren't taken don't push anything.
+        if cpu.get_sp() == sp.wrapping_sub(2) {
+            self.call(kind, pc, pc.wrapping_add(len), cpu, dbg);
+        }
+    }
+
+    /// Update the stack after the CPU dispatched an interrupt at `pc`.
+    pub fn interrupt(&mut self, pc: u16, cpu: &Cpu, dbg: &mut dyn Debugger) {
+        self.call(FrameKind::Interrupt, pc, pc, cpu, dbg);
+    }
+
+    fn call(&mut self, kind: FrameKind, caller: u16, ret: u16, cpu: &Cpu, dbg: &mut dyn Debugger) {
+        let frame = Frame {
+            kind,
+            caller,
+            callee: cpu.get_pc(),
+            ret,
+            sp: cpu.get_sp(),
+        };
+        dbg.on_call(&frame);
+        self.frames.push(frame);
+    }
+
+    fn ret(&mut self, sp: u16, dbg: &mut dyn Debugger) {
+        // Drop the frames whose return address is popped, which also cleans up
+        // the frames abandoned by code manipulating the stack pointer directly.
+        while let Some(frame) = self.frames.last() {
+            if frame.sp > sp {
+                break;
+            }
+            dbg.on_return(frame);
+            self.frames.pop();
+        }
+    }
+}
+
+/// A memory access from the CPU recorded by the access log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemAccess {
+    /// The accessed address.
+    pub addr: u16,
+    /// The value read from or written to the address.
+    pub value: u8,
+    /// The access is a write.
+    pub write: bool,
+    /// The address of the instruction which accessed the memory.
+    pub pc: u16,
+    /// CPU cycles elapsed since the emulator started, at the beginning of the instruction.
+    pub cycles: u64,
+}
+
+#[cfg(feature = "debugger")]
+/// Ring buffer of the memory accesses to the configured address ranges.
+pub(crate) struct AccessLog {
+    ranges: Vec<(u16, u16, Access)>,
+    ring: VecDeque<MemAccess>,
+    capacity: usize,
+    pc: u16,
+    cycles: u64,
+    active: bool,
+}
+
+#[cfg(feature = "debugger")]
+impl AccessLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            ranges: Vec::new(),
+            ring: VecDeque::new(),
+            capacity,
+            pc: 0,
+            cycles: 0,
+            active: false,
+        }
+    }
+
+    pub fn add_range(&mut self, range: (u16, u16), access: Access) {
+        self.ranges.push((range.0, range.1, access));
+    }
+
+    /// Start recording the accesses by the instruction at `pc`.
+    pub fn begin(&mut self, pc: u16, cycles: u64) {
+        self.pc = pc;
+        self.cycles = cycles;
+        self.active = true;
+    }
+
+    /// Stop recording until the next instruction.
+    pub fn end(&mut self) {
+        self.active = false;
+    }
+
+    pub fn record(&mut self, addr: u16, value: u8, write: bool) {
+        if !self.active {
+            return;
+        }
+
+        let hit = self.ranges.iter().any(|(lo, hi, access)| {
+            addr >= *lo
+                && addr <= *hi
+                && match access {
+                    Access::Read => !write,
+                    Access::Write => write,
+                    Access::ReadWrite => true,
+                }
+        });
+        if !hit {
+            return;
+        }
+
+        if self.ring.len() >= self.capacity {
+            self.ring.pop_front();
+        }
+        self.ring.push_back(MemAccess {
+            addr,
+            value,
+            write,
+            pc: self.pc,
+            cycles: self.cycles,
+        });
+    }
+
+    pub fn drain(&mut self) -> Vec<MemAccess> {
+        self.ring.drain(..).collect()
+    }
+}
+
+/// The kind of memory access to watch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// Reads from the address.
+    Read,
+    /// Writes to the address.
+    Write,
+    /// Both reads and writes.
+    ReadWrite,
+}
+
+/// The reason why the emulator stopped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Break {
+    /// The CPU is about to execute the instruction at the breakpoint.
+    Breakpoint(u16),
+    /// The CPU read from the watched address.
+    Read(u16),
+    /// The CPU wrote the value to the watched address.
+    Write(u16, u8),
+}
+
+#[cfg(feature = "debugger")]
+/// Breakpoints and watchpoints checked by the emulator.
+#[derive(Default)]
+pub(crate) struct Breakpoints {
+    breaks: HashMap<u16, Option<Condition>>,
+    rd_watches: HashMap<u16, Option<Condition>>,
+    wr_watches: HashMap<u16, Option<Condition>>,
+    hit: Option<Break>,
+    resume: Option<u16>,
+    active: bool,
+}
+
+#[cfg(feature = "debugger")]
+impl Breakpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_breakpoint(&mut self, pc: u16, cond: Option<Condition>) {
+        self.breaks.insert(pc, cond);
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: u16) {
+        self.breaks.remove(&pc);
+    }
+
+    pub fn add_watchpoint(&mut self, addr: u16, access: Access, cond: Option<Condition>) {
+        if access != Access::Write {
+            self.rd_watches.insert(addr, cond.clone());
+        }
+        if access != Access::Read {
+            self.wr_watches.insert(addr, cond);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, addr: u16, access: Access) {
+        if access != Access::Write {
+            self.rd_watches.remove(&addr);
+        }
+        if access != Access::Read {
+            self.wr_watches.remove(&addr);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.breaks.clear();
+        self.rd_watches.clear();
+        self.wr_watches.clear();
+    }
+
+    /// Check if the CPU should stop before executing the instruction at `pc`.
+    ///
+    /// The breakpoint doesn't hit again right after resuming from it.
+    pub fn check_pc(&mut self, cpu: &Cpu, mmu: &Mmu) -> bool {
+        let pc = cpu.get_pc();
+
+        if self.resume.take() == Some(pc) {
+            return false;
+        }
+
+        match self.breaks.get(&pc) {
+            Some(Some(cond)) if !cond.eval(cpu, mmu) => return false,
+            Some(_) => {}
+            None => return false,
+        }
+
+        self.hit = Some(Break::Breakpoint(pc));
+        self.resume = Some(pc);
+        true
+    }
+
+    /// Drop the watchpoint hit by the last instruction if its condition doesn't hold.
+    pub fn check_watch(&mut self, cpu: &Cpu, mmu: &Mmu) {
+        let cond = match self.hit {
+            Some(Break::Read(addr)) => self.rd_watches.get(&addr),
+            Some(Break::Write(addr, _)) => self.wr_watches.get(&addr),
+            _ => return,
+        };
+
+        if let Some(Some(cond)) = cond {
+            if !cond.eval(cpu, mmu) {
+                self.hit = None;
+            }
+        }
+    }
+
+    /// Watch memory accesses only while the CPU executes an instruction,
+    /// ignoring the accesses from the frontend and the debugger.
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+    }
+
+    pub fn take_hit(&mut self) -> Option<Break> {
+        self.hit.take()
+    }
+}
+
+#[cfg(feature = "debugger")]
+impl IoHandler for Breakpoints {
+    fn on_read(&mut self, _: &Mmu, addr: u16) -> MemRead {
+        if self.active && self.hit.is_none() && self.rd_watches.contains_key(&addr) {
+            self.hit = Some(Break::Read(addr));
+        }
+        MemRead::PassThrough
+    }
+
+    fn on_write(&mut self, _: &Mmu, addr: u16, value: u8) -> MemWrite {
+        if self.active && self.hit.is_none() && self.wr_watches.contains_key(&addr) {
+            self.hit = Some(Break::Write(addr, value));
+        }
+        MemWrite::PassThrough
+    }
+}
+
+/// The cycles spent at an instruction address, reported by the profiler.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProfileEntry {
+    /// The ROM bank mapped at 4000-7fff for the addresses in the switchable bank, or 0.
+    pub bank: usize,
+    /// The address of the instruction.
+    pub pc: u16,
+    /// The clock cycles spent, including the interrupt dispatch after the instruction.
+    /// The cycles waiting in HALT are counted at the address following the HALT.
+    pub cycles: u64,
+    /// The number of times the instruction was executed.
+    pub count: u64,
+}
+
+#[cfg(feature = "debugger")]
+/// Accumulates the cycles of the executed instructions per bank and address.
+pub(crate) struct Profiler {
+    hits: HashMap<(usize, u16), (u64, u64)>,
+}
+
+#[cfg(feature = "debugger")]
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            hits: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, bank: usize, pc: u16, cycles: u64) {
+        let hit = self.hits.entry((bank, pc)).or_insert((0, 0));
+        hit.0 += cycles;
+        hit.1 += 1;
+    }
+
+    /// Returns the entries, the hottest first.
+    pub fn entries(&self) -> Vec<ProfileEntry> {
+        let mut entries: Vec<_> = self
+            .hits
+            .iter()
+            .map(|(&(bank, pc), &(cycles, count))| ProfileEntry {
+                bank,
+                pc,
+                cycles,
+                count,
+            })
+            .collect();
+        entries.sort_by(|a, b| {
+            b.cycles
+                .cmp(&a.cycles)
+                .then((a.bank, a.pc).cmp(&(b.bank, b.pc)))
+        });
+        entries
+    }
+}
+
+/// The size of a ROM bank.
+#[cfg(feature = "debugger")]
+const BANK_SIZE: usize = 0x4000;
+
+/// The ROM bytes executed by the CPU, as a bitmap per ROM bank.
+///
+/// All the bytes of the executed instructions, i.e. the opcodes and the operands, are marked,
+/// so the bytes never marked are data or the code not reached yet. Bank 0 is the ROM at 0000-3fff,
+/// and the other banks are the ones selected by the memory bank controller at 4000-7fff.
+#[cfg(feature = "debugger")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Coverage {
+    banks: HashMap<usize, Vec<u8>>,
+}
+
+#[cfg(feature = "debugger")]
+impl Coverage {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mark the instruction of `len` bytes at `pc`, with `bank` mapped at 4000-7fff.
+    pub(crate) fn mark(&mut self, bank: usize, pc: u16, len: usize) {
+        for addr in (pc as usize..pc as usize + len).take_while(|a| *a < 2 * BANK_SIZE) {
+            let bank = if addr < BANK_SIZE { 0 } else { bank };
+            let off = addr % BANK_SIZE;
+            let bitmap = self
+                .banks
+                .entry(bank)
+                .or_insert_with(|| alloc::vec![0; BANK_SIZE / 8]);
+            bitmap[off / 8] |= 1 << (off % 8);
+        }
+    }
+
+    /// Check if the byte at the offset in the bank has been executed.
+    pub fn executed(&self, bank: usize, offset: usize) -> bool {
+        self.bitmap(bank)
+            .and_then(|b| b.get(offset / 8))
+            .is_some_and(|b| b & (1 << (offset % 8)) != 0)
+    }
+
+    /// Returns the bitmap of the bank, where bit `n % 8` of byte `n / 8` is set
+    /// if the byte at offset `n` has been executed, or `None` if nothing in the bank has been executed.
+    pub fn bitmap(&self, bank: usize) -> Option<&[u8]> {
+        self.banks.get(&bank).map(|b| &b[..])
+    }
+
+    /// Returns the banks with any executed byte in ascending order.
+    pub fn banks(&self) -> Vec<usize> {
+        let mut banks: Vec<_> = self.banks.keys().copied().collect();
+        banks.sort_unstable();
+        banks
+    }
+
+    /// Returns the total number of the executed bytes.
+    pub fn count(&self) -> usize {
+        self.banks
+            .values()
+            .flat_map(|b| b.iter())
+            .map(|b| b.count_ones() as usize)
+            .sum()
+    }
+}
+
+#[cfg(all(test, feature = "debugger"))]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn coverage() {
+        let mut c = Coverage::new();
+        // The instruction crossing into the switchable bank
+        c.mark(5, 0x3ffe, 3);
+        c.mark(5, 0x7fff, 3);
+
+        assert_eq!(c.banks(), [0, 5]);
+        assert!(c.executed(0, 0x3ffe) && c.executed(0, 0x3fff));
+        assert!(c.executed(5, 0x0000) && c.executed(5, 0x3fff));
+        assert!(!c.executed(5, 0x0001));
+        assert!(!c.executed(1, 0x0000));
+        assert_eq!(c.count(), 4);
+        assert_eq!(c.bitmap(5).unwrap()[0], 0x01);
+    }
+
+    #[test]
+    fn breakpoint_resumes() {
+        let mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut cpu = Cpu::new();
+        let mut b = Breakpoints::new();
+        b.add_breakpoint(0x150, None);
+
+        cpu.set_pc(0x100);
+        assert!(!b.check_pc(&cpu, &mmu));
+        cpu.set_pc(0x150);
+        assert!(b.check_pc(&cpu, &mmu));
+        assert_eq!(b.take_hit(), Some(Break::Breakpoint(0x150)));
+        assert!(!b.check_pc(&cpu, &mmu));
+        assert!(b.check_pc(&cpu, &mmu));
+    }
+
+    #[test]
+    fn conditional_breakpoint() {
+        let mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut cpu = Cpu::new();
+        let mut b = Breakpoints::new();
+        b.add_breakpoint(0x150, Some("a == 0x3c".parse().unwrap()));
+
+        cpu.set_pc(0x150);
+        cpu.set_a(0x00);
+        assert!(!b.check_pc(&cpu, &mmu));
+        cpu.set_a(0x3c);
+        assert!(b.check_pc(&cpu, &mmu));
+    }
+
+    #[test]
+    fn call_stack_follows_call_and_ret() {
+        let mut dbg = NullDebugger;
+        let mut calls = CallStack::new();
+        let mut cpu = Cpu::new();
+
+        // call 0x2000 at 0x0150
+        cpu.set_pc(0x2000);
+        cpu.set_sp(0xfffc);
+        calls.exec(0xcd, 0x0150, 0xfffe, &cpu, &mut dbg);
+
+        // rst 0x38 at 0x2000
+        cpu.set_pc(0x0038);
+        cpu.set_sp(0xfffa);
+        calls.exec(0xff, 0x2000, 0xfffc, &cpu, &mut dbg);
+
+        assert_eq!(calls.frames().len(), 2);
+        assert_eq!(calls.frames()[0].ret, 0x0153);
+        assert_eq!(calls.frames()[1].ret, 0x2001);
+
+        // ret at 0x0038
+        cpu.set_pc(0x2001);
+        cpu.set_sp(0xfffc);
+        calls.exec(0xc9, 0x0038, 0xfffa, &cpu, &mut dbg);
+
+        assert_eq!(calls.frames().len(), 1);
+        assert_eq!(calls.frames()[0].callee, 0x2000);
+    }
+
+    #[test]
+    fn access_log_ring() {
+        let log = Arc::new(Mutex::new(AccessLog::new(2)));
+        log.lock().add_range((0xc000, 0xc0ff), Access::Write);
+
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        mmu.set_access_log(Some(log.clone()));
+
+        mmu.set8(0xc000, 1);
+        log.lock().begin(0x150, 100);
+        mmu.get8(0xc000);
+        mmu.set8(0xc001, 2);
+        mmu.set8(0xc100, 3);
+        mmu.set8(0xc002, 4);
+        mmu.set8(0xc003, 5);
+        log.lock().end();
+
+        let entries = log.lock().drain();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[0],
+            MemAccess {
+                addr: 0xc002,
+                value: 4,
+                write: true,
+                pc: 0x150,
+                cycles: 100,
+            }
+        );
+        assert_eq!(entries[1].addr, 0xc003);
+    }
+
+    #[test]
+    fn watchpoint_hits() {
+        let mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut b = Breakpoints::new();
+        b.add_watchpoint(0xc000, Access::Write, None);
+        b.set_active(true);
+
+        b.on_read(&mmu, 0xc000);
+        assert_eq!(b.take_hit(), None);
+        b.on_write(&mmu, 0xc000, 0x12);
+        assert_eq!(b.take_hit(), Some(Break::Write(0xc000, 0x12)));
+    }
+}
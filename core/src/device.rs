@@ -1,10 +1,14 @@
-use alloc::rc::Rc;
-use core::cell::{Ref, RefCell, RefMut};
+use alloc::sync::Arc;
+use core::ops::Deref;
+use spin::{Mutex, MutexGuard};
 
+use crate::hardware::MaybeSend;
 use crate::mmu::{MemHandler, MemRead, MemWrite, Mmu};
 
 /// The wrapper type for I/O handlers to register to MMU.
-pub struct Device<T>(Rc<RefCell<T>>, bool);
+///
+/// The device is shared by the system and the MMU, and is `Send` if the handler is.
+pub struct Device<T>(Arc<Mutex<T>>, bool);
 
 impl<T> Device<T> {
     /// Create a new device.
@@ -18,17 +22,45 @@ impl<T> Device<T> {
     }
 
     fn inner(inner: T, debug: bool) -> Self {
-        Self(Rc::new(RefCell::new(inner)), debug)
+        Self(Arc::new(Mutex::new(inner)), debug)
     }
 
     /// Immutably borrow the underlying I/O handler.
-    pub fn borrow<'a>(&'a self) -> Ref<'a, T> {
-        self.0.borrow()
+    ///
+    /// # Panics
+    ///
+    /// Panics if the handler is already borrowed.
+    pub fn borrow<'a>(&'a self) -> MutexGuard<'a, T> {
+        self.borrow_mut()
     }
 
     /// Mutabully borrow the underlying I/O handler.
-    pub fn borrow_mut<'a>(&'a self) -> RefMut<'a, T> {
-        self.0.borrow_mut()
+    ///
+    /// # Panics
+    ///
+    /// Panics if the handler is already borrowed.
+    pub fn borrow_mut<'a>(&'a self) -> MutexGuard<'a, T> {
+        self.0.try_lock().expect("device already borrowed")
+    }
+
+    /// Borrow a memory bank of the device.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the handler is already borrowed.
+    pub(crate) fn bank<'r>(&'r self, bank: usize) -> Region<'r>
+    where
+        T: Banked + 'r,
+    {
+        let mutex: &'r Mutex<dyn Banked + 'r> = &*self.0;
+        let guard = mutex.try_lock().expect("device already borrowed");
+        Region(Inner::Bank(guard, bank))
+    }
+}
+
+impl<T> Clone for Device<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone(), self.1)
     }
 }
 
@@ -39,6 +71,46 @@ impl<T: IoHandler> Device<T> {
     }
 }
 
+/// The devices which have banked memory.
+pub(crate) trait Banked {
+    /// Return the bank.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bank doesn't exist.
+    fn bank(&self, bank: usize) -> &[u8];
+}
+
+/// A memory region borrowed from the emulator, e.g. by [`System::vram`][crate::System::vram].
+///
+/// The device owning the memory is locked until the region is dropped.
+pub struct Region<'r>(Inner<'r>);
+
+enum Inner<'r> {
+    #[cfg(not(feature = "cgb"))]
+    Ram(&'r [u8]),
+    Bank(MutexGuard<'r, dyn Banked + 'r>, usize),
+}
+
+#[cfg(not(feature = "cgb"))]
+impl<'r> Region<'r> {
+    pub(crate) fn ram(ram: &'r [u8]) -> Self {
+        Region(Inner::Ram(ram))
+    }
+}
+
+impl<'r> Deref for Region<'r> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.0 {
+            #[cfg(not(feature = "cgb"))]
+            Inner::Ram(ram) => ram,
+            Inner::Bank(guard, bank) => guard.bank(*bank),
+        }
+    }
+}
+
 /// The trait which allows to hook I/O access from the CPU.
 pub trait IoHandler {
     /// The function is called when the CPU attempts to read the memory-mapped I/O.
@@ -48,20 +120,36 @@ pub trait IoHandler {
     fn on_write(&mut self, mmu: &Mmu, addr: u16, value: u8) -> MemWrite;
 }
 
+impl<T: IoHandler + ?Sized> IoHandler for &mut T {
+    fn on_read(&mut self, mmu: &Mmu, addr: u16) -> MemRead {
+        (**self).on_read(mmu, addr)
+    }
+
+    fn on_write(&mut self, mmu: &Mmu, addr: u16, value: u8) -> MemWrite {
+        (**self).on_write(mmu, addr, value)
+    }
+}
+
 /// The handler to intercept memory-mapped I/O.
-pub struct IoMemHandler<T>(Rc<RefCell<T>>, bool);
+pub struct IoMemHandler<T>(Arc<Mutex<T>>, bool);
+
+impl<T> Clone for IoMemHandler<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone(), self.1)
+    }
+}
 
-impl<T: IoHandler> MemHandler for IoMemHandler<T> {
+impl<T: IoHandler + MaybeSend> MemHandler for IoMemHandler<T> {
     fn on_read(&self, mmu: &Mmu, addr: u16) -> MemRead {
         // Don't hook if it's already hooked
-        match self.0.try_borrow_mut() {
-            Ok(mut inner) => inner.on_read(mmu, addr),
-            Err(e) => {
+        match self.0.try_lock() {
+            Some(mut inner) => inner.on_read(mmu, addr),
+            None => {
                 if self.1 {
                     // In mediator mode, allow to recursive read
                     MemRead::PassThrough
                 } else {
-                    panic!("Recursive read from {:04x}: {}", addr, e)
+                    panic!("Recursive read from {:04x}", addr)
                 }
             }
         }
@@ -69,14 +157,14 @@ impl<T: IoHandler> MemHandler for IoMemHandler<T> {
 
     fn on_write(&self, mmu: &Mmu, addr: u16, value: u8) -> MemWrite {
         // Don't hook if it's already hooked
-        match self.0.try_borrow_mut() {
-            Ok(mut inner) => inner.on_write(mmu, addr, value),
-            Err(e) => {
+        match self.0.try_lock() {
+            Some(mut inner) => inner.on_write(mmu, addr, value),
+            None => {
                 if self.1 {
                     // In mediator mode, allow to recursive write
                     MemWrite::PassThrough
                 } else {
-                    panic!("Recursive write to {:04x}: {}", addr, e)
+                    panic!("Recursive write to {:04x}", addr)
                 }
             }
         }
@@ -0,0 +1,172 @@
+use crate::inst::mnem;
+use crate::mmu::Mmu;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// The opcodes which don't exist in the CPU.
+const INVALID_OPCODES: [u8; 11] = [
+    0xd3, 0xdb, 0xdd, 0xe3, 0xe4, 0xeb, 0xec, 0xed, 0xf4, 0xfc, 0xfd,
+];
+
+/// Disassembled instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    /// The address of the instruction.
+    pub addr: u16,
+    /// The opcode, which has 0xcb in the upper byte for the prefixed instructions.
+    pub code: u16,
+    /// The length of the instruction in bytes including the operands.
+    pub len: usize,
+    /// The mnemonic with the decoded operands, e.g. `ld bc,0x1234`.
+    pub text: String,
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04x}: {}", self.addr, self.text)
+    }
+}
+
+/// Disassemble the instruction at the beginning of `bytes`, which is located at `addr`.
+///
+/// Returns `None` if `bytes` is shorter than the instruction.
+pub fn disasm(addr: u16, bytes: &[u8]) -> Option<Instruction> {
+    let fb = *bytes.first()?;
+
+    if INVALID_OPCODES.contains(&fb) {
+        return Some(Instruction {
+            addr,
+            code: fb as u16,
+            len: 1,
+            text: format!("db 0x{:02x}", fb),
+        });
+    }
+
+    let (code, oplen) = if fb == 0xcb {
+        (0xcb00 | *bytes.get(1)? as u16, 2)
+    } else {
+        (fb as u16, 1)
+    };
+
+    let m = mnem(code).trim_end();
+    let len = inst_len(code);
+    let arglen = len - oplen;
+    let args = bytes.get(oplen..len)?;
+
+    let text = if arglen == 2 {
+        let v = (args[1] as u16) << 8 | args[0] as u16;
+        m.replace("d16", &format!("0x{:04x}", v))
+            .replace("a16", &format!("0x{:04x}", v))
+    } else if m.contains("r8") {
+        let v = args[0] as i8;
+        if m.starts_with("jr") {
+            let target = addr.wrapping_add(len as u16).wrapping_add(v as u16);
+            m.replace("r8", &format!("0x{:04x}", target))
+        } else if v < 0 {
+            m.replace("r8", &format!("-0x{:02x}", -(v as i16)))
+        } else {
+            m.replace("r8", &format!("0x{:02x}", v))
+        }
+    } else if arglen == 1 {
+        m.replace("d8", &format!("0x{:02x}", args[0]))
+            .replace("a8", &format!("0x{:02x}", args[0]))
+    } else {
+        m.into()
+    };
+
+    Some(Instruction {
+        addr,
+        code,
+        len,
+        text,
+    })
+}
+
+/// Returns the length of the instruction in bytes including the operands.
+pub(crate) fn inst_len(code: u16) -> usize {
+    if code & 0xff00 == 0xcb00 {
+        return 2;
+    }
+    if INVALID_OPCODES.contains(&(code as u8)) {
+        return 1;
+    }
+
+    let m = mnem(code);
+    if m.contains("d16") || m.contains("a16") {
+        3
+    } else if m.contains("d8") || m.contains("a8") || m.contains("r8") || m.starts_with("stop") {
+        2
+    } else {
+        1
+    }
+}
+
+/// Disassemble all the instructions in `bytes`, which is located at `addr`.
+///
+/// The trailing bytes which are shorter than an instruction are ignored.
+pub fn disasm_all(addr: u16, bytes: &[u8]) -> Vec<Instruction> {
+    let mut insts = Vec::new();
+    let mut off = 0;
+
+    while let Some(inst) = disasm(addr.wrapping_add(off as u16), &bytes[off..]) {
+        off += inst.len;
+        insts.push(inst);
+    }
+
+    insts
+}
+
+/// Disassemble the instruction at `addr` in the memory.
+pub fn disasm_mmu(mmu: &Mmu, addr: u16) -> Instruction {
+    let bytes = [
+        mmu.get8(addr),
+        mmu.get8(addr.wrapping_add(1)),
+        mmu.get8(addr.wrapping_add(2)),
+    ];
+    disasm(addr, &bytes).expect("instructions are at most 3 bytes")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_operands() {
+        let code = [
+            0x00, // nop
+            0x01, 0x34, 0x12, // ld bc,0x1234
+            0x18, 0xfe, // jr 0x0104
+            0xe0, 0x40, // ld (0xff00+0x40),a
+            0xe8, 0xfe, // add sp,-0x02
+            0xcb, 0x7c, // bit 7,h
+            0xd3, // db 0xd3
+        ];
+
+        let texts: Vec<String> = disasm_all(0x100, &code)
+            .into_iter()
+            .map(|i| i.text)
+            .collect();
+
+        assert_eq!(
+            texts,
+            [
+                "nop",
+                "ld bc,0x1234",
+                "jr 0x0104",
+                "ld (0xff00+0x40),a",
+                "add sp,-0x02",
+                "bit 7,h",
+                "db 0xd3",
+            ]
+        );
+    }
+
+    #[test]
+    fn truncated_instruction() {
+        assert_eq!(disasm(0, &[0xc3, 0x00]), None);
+        assert_eq!(disasm(0, &[0xcb]), None);
+        assert_eq!(disasm(0, &[]), None);
+    }
+}
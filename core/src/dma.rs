@@ -1,42 +1,264 @@
+use crate::debug::{Events, HwEvent};
 use crate::device::IoHandler;
+use crate::error::Error;
 use crate::mmu::{MemRead, MemWrite, Mmu};
+use crate::state::{Reader, State, Writer};
 use log::*;
 
+/// The number of bytes transferred to OAM.
+const DMA_LEN: u16 = 0xa0;
+
 pub struct Dma {
     on: bool,
     src: u8,
+    /// The source of the transfer written to FF46, which takes over after the setup cycle.
+    start: Option<u8>,
+    pos: u16,
+    clocks: usize,
+    last: u8,
+    events: Events,
 }
 
 impl Dma {
     pub fn new() -> Self {
-        Self { on: false, src: 0 }
+        Self {
+            on: false,
+            src: 0,
+            start: None,
+            pos: 0,
+            clocks: 0,
+            last: 0xff,
+            events: Events::default(),
+        }
+    }
+
+    /// Report the start and the end of the transfers to the hardware events.
+    pub fn set_events(&mut self, events: Events) {
+        self.events = events;
+    }
+
+    /// Transfer one byte every 4 clocks, which takes 640 clocks in total after the setup cycle.
+    /// Returns the clock cycles the transfer can be run at once, or `None` if not running.
+    pub fn next_event(&self) -> Option<usize> {
+        if self.on || self.start.is_some() {
+            Some(4)
+        } else {
+            None
+        }
     }
 
-    pub fn step(&mut self, mmu: &mut Mmu) {
-        if self.on {
-            assert!(self.src <= 0x80 || self.src >= 0x9f);
-            debug!("Perform DMA transfer: {:02x}", self.src);
+    pub fn step(&mut self, time: usize, mmu: &mut Mmu) {
+        if !self.on && self.start.is_none() {
+            return;
+        }
+
+        self.clocks += time;
+
+        while (self.on || self.start.is_some()) && self.clocks >= 4 {
+            self.clocks -= 4;
+
+            if self.on {
+                // Bypass the OAM handler as the transfer isn't locked out by the GPU mode
+                let v = mmu.get8(self.source() + self.pos);
+                mmu.ram_mut()[0xfe00 + self.pos as usize] = v;
+                self.last = v;
+                self.pos += 1;
+
+                if self.pos == DMA_LEN {
+                    debug!("DMA transfer completed: {:02x}", self.src);
+                    self.on = false;
+                    self.events.push(HwEvent::DmaEnd);
+                }
+            }
 
-            let src = (self.src as u16) << 8;
-            for i in 0..0xa0 {
-                mmu.set8(0xfe00 + i, mmu.get8(src + i));
+            // The running transfer goes on during the setup cycle of the new one,
+            // which then restarts from the beginning
+            if let Some(src) = self.start.take() {
+                self.on = true;
+                self.src = src;
+                self.pos = 0;
             }
+        }
+    }
+
+    /// The source address of the transfer.
+    ///
+    /// The addresses above DFFF read the work RAM like the echo area, including FE00-FFFF.
+    fn source(&self) -> u16 {
+        let src = if self.src >= 0xe0 {
+            self.src - 0x20
+        } else {
+            self.src
+        };
+        (src as u16) << 8
+    }
+
+    /// Check if the CPU access to `addr` conflicts with the running transfer.
+    ///
+    /// The transfer occupies OAM and the bus of the source, i.e. either the VRAM bus or the
+    /// external bus, while HRAM, I/O registers and the other bus are still accessible.
+    fn conflict(&self, addr: u16) -> bool {
+        if !self.on {
+            return false;
+        }
+
+        let vram = |addr: u16| (0x8000..=0x9fff).contains(&addr);
+        let src = self.source();
+
+        if addr >= 0xfe00 {
+            addr <= 0xfe9f
+        } else {
+            vram(addr) == vram(src)
+        }
+    }
+}
+
+impl State for Dma {
+    fn save(&self, w: &mut Writer) {
+        self.on.save(w);
+        self.src.save(w);
+        self.pos.save(w);
+        self.clocks.save(w);
+        self.last.save(w);
+        // Always save both the flag and the source to keep the length of the savestates constant
+        self.start.is_some().save(w);
+        self.start.unwrap_or(0).save(w);
+    }
 
-            self.on = false;
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.on.load(r)?;
+        self.src.load(r)?;
+        self.pos = r.bounded("position", DMA_LEN)?;
+        self.clocks.load(r)?;
+        self.last.load(r)?;
+        if r.version() >= 3 {
+            let (mut pending, mut src) = (false, 0u8);
+            pending.load(r)?;
+            src.load(r)?;
+            self.start = if pending { Some(src) } else { None };
+        } else {
+            self.start = None;
         }
+        Ok(())
     }
 }
 
 impl IoHandler for Dma {
     fn on_write(&mut self, _mmu: &Mmu, addr: u16, value: u8) -> MemWrite {
-        assert_eq!(addr, 0xff46);
-        debug!("Start DMA transfer: {:02x}", self.src);
-        self.on = true;
-        self.src = value;
+        if addr != 0xff46 {
+            return if self.conflict(addr) {
+                MemWrite::Block
+            } else {
+                MemWrite::PassThrough
+            };
+        }
+
+        if (0x80..=0x9f).contains(&value) {
+            warn!("DMA transfer from VRAM: {:02x}", value);
+        }
+        debug!("Start DMA transfer: {:02x}", value);
+        if !self.on && self.start.is_none() {
+            self.clocks = 0;
+        }
+        self.start = Some(value);
+        self.events.push(HwEvent::DmaStart((value as u16) << 8));
         MemWrite::Block
     }
 
-    fn on_read(&mut self, _mmu: &Mmu, _addr: u16) -> MemRead {
-        MemRead::Replace(0)
+    fn on_read(&mut self, _mmu: &Mmu, addr: u16) -> MemRead {
+        if addr == 0xff46 {
+            MemRead::Replace(self.start.unwrap_or(self.src))
+        } else if addr >= 0xfe00 && self.conflict(addr) {
+            MemRead::Replace(0xff)
+        } else if self.conflict(addr) {
+            // The CPU sees the byte being transferred on the bus
+            MemRead::Replace(self.last)
+        } else {
+            MemRead::PassThrough
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn dma_bus_conflict() {
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        for i in 0..0xa0 {
+            mmu.set8(0xc000 + i, i as u8 + 1);
+        }
+
+        let mut dma = Dma::new();
+        dma.on_write(&mmu, 0xff46, 0xc0);
+
+        // Nothing is blocked nor transferred during the setup cycle
+        assert!(matches!(dma.on_read(&mmu, 0xfe00), MemRead::PassThrough));
+        dma.step(4, &mut mmu);
+        assert_eq!(mmu.ram()[0xfe00], 0);
+
+        dma.step(8, &mut mmu);
+        assert_eq!(mmu.ram()[0xfe01], 2);
+        assert_eq!(mmu.ram()[0xfe02], 0);
+
+        // External bus returns the byte being transferred, OAM is inaccessible
+        assert!(matches!(dma.on_read(&mmu, 0x0150), MemRead::Replace(2)));
+        assert!(matches!(dma.on_read(&mmu, 0xfe00), MemRead::Replace(0xff)));
+        assert!(matches!(dma.on_write(&mmu, 0xc000, 0), MemWrite::Block));
+        // VRAM, I/O and HRAM are accessible
+        assert!(matches!(dma.on_read(&mmu, 0x8000), MemRead::PassThrough));
+        assert!(matches!(dma.on_read(&mmu, 0xff80), MemRead::PassThrough));
+
+        dma.step(640, &mut mmu);
+        assert_eq!(mmu.ram()[0xfe9f], 0xa0);
+        assert!(matches!(dma.on_read(&mmu, 0x0150), MemRead::PassThrough));
+    }
+
+    #[test]
+    fn dma_restart() {
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        for i in 0..0xa0 {
+            mmu.set8(0xc000 + i, 0x10);
+            mmu.set8(0xd000 + i, 0x20);
+            mmu.set8(0xde00 + i, 0x30);
+        }
+
+        let mut dma = Dma::new();
+        let mut w = Writer::new();
+        dma.save(&mut w);
+        let idle = w.finish();
+
+        dma.on_write(&mmu, 0xff46, 0xc0);
+        // The length of the state doesn't change during the setup cycle
+        let mut w = Writer::new();
+        dma.save(&mut w);
+        assert_eq!(w.finish().len(), idle.len());
+
+        dma.step(4 + 4 * 10, &mut mmu);
+        assert_eq!(mmu.ram()[0xfe09], 0x10);
+
+        // The old transfer goes on with OAM blocked during the setup of the new one
+        dma.on_write(&mmu, 0xff46, 0xd0);
+        assert!(matches!(dma.on_read(&mmu, 0xff46), MemRead::Replace(0xd0)));
+        dma.step(4, &mut mmu);
+        assert_eq!(mmu.ram()[0xfe0a], 0x10);
+        assert!(matches!(dma.on_read(&mmu, 0xfe00), MemRead::Replace(0xff)));
+
+        // Then the new one restarts from the beginning, taking 160 cycles
+        dma.step(4, &mut mmu);
+        assert_eq!(mmu.ram()[0xfe00], 0x20);
+        assert_eq!(mmu.ram()[0xfe0b], 0);
+        dma.step(4 * 158, &mut mmu);
+        assert!(dma.next_event().is_some());
+        dma.step(4, &mut mmu);
+        assert!(dma.next_event().is_none());
+        assert!(mmu.ram()[0xfe00..0xfea0].iter().all(|v| *v == 0x20));
+
+        // FE00 reads the work RAM at DE00
+        dma.on_write(&mmu, 0xff46, 0xfe);
+        dma.step(4 * 161, &mut mmu);
+        assert!(mmu.ram()[0xfe00..0xfea0].iter().all(|v| *v == 0x30));
     }
 }
@@ -0,0 +1,64 @@
+use alloc::fmt;
+use alloc::string::String;
+
+/// Errors reported by the emulator.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The ROM is too short to contain the cartridge header.
+    RomTooShort(usize),
+    /// The cartridge header fails the checks of the boot ROM.
+    InvalidHeader(String),
+    /// The cartridge type in the header is not supported.
+    UnsupportedCartridge(u8),
+    /// The CPU fetched an opcode which doesn't exist.
+    InvalidOpcode {
+        /// The address of the opcode.
+        pc: u16,
+        /// The opcode.
+        code: u16,
+    },
+    /// The condition of a breakpoint can't be parsed.
+    InvalidCondition(String),
+    /// The cheat code can't be parsed.
+    InvalidCheat(String),
+    /// The netplay message from the peer can't be decoded.
+    InvalidMessage(String),
+    /// The movie can't be decoded.
+    InvalidMovie(String),
+    /// The ROM patch can't be applied.
+    InvalidPatch(String),
+    /// The line of the reference hashes of the accuracy harness can't be parsed.
+    InvalidReference(String),
+    /// The savestate can't be loaded.
+    InvalidState(String),
+    /// The savestate has a format version newer than this build supports.
+    StateVersion(u16),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::RomTooShort(len) => write!(f, "ROM is too short: {} bytes", len),
+            Error::InvalidHeader(msg) => write!(f, "Invalid cartridge header: {}", msg),
+            Error::UnsupportedCartridge(code) => {
+                write!(f, "Unsupported cartridge type: {:02x}", code)
+            }
+            Error::InvalidOpcode { pc, code } => {
+                write!(f, "Invalid opcode: {:04x}: {:04x}", pc, code)
+            }
+            Error::InvalidCondition(msg) => write!(f, "Invalid condition: {}", msg),
+            Error::InvalidCheat(code) => write!(f, "Invalid cheat code: {}", code),
+            Error::InvalidMessage(msg) => write!(f, "Invalid netplay message: {}", msg),
+            Error::InvalidMovie(msg) => write!(f, "Invalid movie: {}", msg),
+            Error::InvalidPatch(msg) => write!(f, "Invalid patch: {}", msg),
+            Error::InvalidReference(line) => write!(f, "Invalid reference: {}", line),
+            Error::InvalidState(msg) => write!(f, "Invalid savestate: {}", msg),
+            Error::StateVersion(v) => write!(
+                f,
+                "Unsupported savestate version: {} (up to {} supported)",
+                v,
+                crate::state::STATE_VERSION
+            ),
+        }
+    }
+}
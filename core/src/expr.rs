@@ -0,0 +1,275 @@
+use crate::cpu::Cpu;
+use crate::error::Error;
+use crate::mmu::Mmu;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use core::str::FromStr;
+
+/// Condition of a breakpoint or a watchpoint, e.g. `a == 0x3c && [0xc123] != 0`.
+///
+/// The operands are numbers (`0x3c`, `$3c` or `60`), registers (`a`, `f`, `b`, `c`, `d`, `e`, `h`, `l`,
+/// `af`, `bc`, `de`, `hl`, `sp` and `pc`) and memory bytes (`[0xc123]`, `[hl]`).
+/// They are combined with `==`, `!=`, `<`, `<=`, `>`, `>=`, `&&`, `||` and parentheses.
+/// The condition holds if the result is non-zero.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Condition(Expr);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Expr {
+    Num(u16),
+    Reg(Reg),
+    Mem(Box<Expr>),
+    Cmp(Box<Expr>, Cmp, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Reg {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    Af,
+    Bc,
+    De,
+    Hl,
+    Sp,
+    Pc,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Condition {
+    /// Evaluate the condition with the current CPU state and memory.
+    pub fn eval(&self, cpu: &Cpu, mmu: &Mmu) -> bool {
+        self.0.eval(cpu, mmu) != 0
+    }
+}
+
+impl FromStr for Condition {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut p = Parser {
+            s: s.as_bytes(),
+            pos: 0,
+        };
+        let e = p.or()?;
+        p.skip_ws();
+        if p.pos != p.s.len() {
+            return Err(p.error("unexpected character"));
+        }
+        Ok(Condition(e))
+    }
+}
+
+impl Expr {
+    fn eval(&self, cpu: &Cpu, mmu: &Mmu) -> u16 {
+        match self {
+            Expr::Num(v) => *v,
+            Expr::Reg(r) => match r {
+                Reg::A => cpu.get_a() as u16,
+                Reg::F => cpu.get_af() & 0xff,
+                Reg::B => cpu.get_b() as u16,
+                Reg::C => cpu.get_c() as u16,
+                Reg::D => cpu.get_d() as u16,
+                Reg::E => cpu.get_e() as u16,
+                Reg::H => cpu.get_h() as u16,
+                Reg::L => cpu.get_l() as u16,
+                Reg::Af => cpu.get_af(),
+                Reg::Bc => cpu.get_bc(),
+                Reg::De => cpu.get_de(),
+                Reg::Hl => cpu.get_hl(),
+                Reg::Sp => cpu.get_sp(),
+                Reg::Pc => cpu.get_pc(),
+            },
+            Expr::Mem(addr) => mmu.get8(addr.eval(cpu, mmu)) as u16,
+            Expr::Cmp(l, op, r) => {
+                let l = l.eval(cpu, mmu);
+                let r = r.eval(cpu, mmu);
+                let v = match op {
+                    Cmp::Eq => l == r,
+                    Cmp::Ne => l != r,
+                    Cmp::Lt => l < r,
+                    Cmp::Le => l <= r,
+                    Cmp::Gt => l > r,
+                    Cmp::Ge => l >= r,
+                };
+                v as u16
+            }
+            Expr::And(l, r) => (l.eval(cpu, mmu) != 0 && r.eval(cpu, mmu) != 0) as u16,
+            Expr::Or(l, r) => (l.eval(cpu, mmu) != 0 || r.eval(cpu, mmu) != 0) as u16,
+        }
+    }
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, msg: &str) -> Error {
+        Error::InvalidCondition(format!("{}: {}", msg, String::from_utf8_lossy(self.s)))
+    }
+
+    fn skip_ws(&mut self) {
+        while self.pos < self.s.len() && self.s[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, tok: &str) -> bool {
+        self.skip_ws();
+        if self.s[self.pos..].starts_with(tok.as_bytes()) {
+            self.pos += tok.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, tok: &str) -> Result<(), Error> {
+        if self.eat(tok) {
+            Ok(())
+        } else {
+            Err(self.error("unbalanced brackets"))
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, Error> {
+        let mut e = self.and()?;
+        while self.eat("||") {
+            e = Expr::Or(Box::new(e), Box::new(self.and()?));
+        }
+        Ok(e)
+    }
+
+    fn and(&mut self) -> Result<Expr, Error> {
+        let mut e = self.cmp()?;
+        while self.eat("&&") {
+            e = Expr::And(Box::new(e), Box::new(self.cmp()?));
+        }
+        Ok(e)
+    }
+
+    fn cmp(&mut self) -> Result<Expr, Error> {
+        let e = self.atom()?;
+
+        let ops = [
+            ("==", Cmp::Eq),
+            ("!=", Cmp::Ne),
+            ("<=", Cmp::Le),
+            (">=", Cmp::Ge),
+            ("<", Cmp::Lt),
+            (">", Cmp::Gt),
+        ];
+        for (tok, op) in &ops {
+            if self.eat(tok) {
+                return Ok(Expr::Cmp(Box::new(e), *op, Box::new(self.atom()?)));
+            }
+        }
+
+        Ok(e)
+    }
+
+    fn atom(&mut self) -> Result<Expr, Error> {
+        if self.eat("(") {
+            let e = self.or()?;
+            self.expect(")")?;
+            return Ok(e);
+        }
+
+        if self.eat("[") {
+            let e = self.or()?;
+            self.expect("]")?;
+            return Ok(Expr::Mem(Box::new(e)));
+        }
+
+        let start = self.pos;
+        while self.pos < self.s.len()
+            && (self.s[self.pos].is_ascii_alphanumeric() || self.s[self.pos] == b'$')
+        {
+            self.pos += 1;
+        }
+        let word = core::str::from_utf8(&self.s[start..self.pos])
+            .unwrap_or("")
+            .to_ascii_lowercase();
+
+        let reg = match word.as_str() {
+            "a" => Reg::A,
+            "f" => Reg::F,
+            "b" => Reg::B,
+            "c" => Reg::C,
+            "d" => Reg::D,
+            "e" => Reg::E,
+            "h" => Reg::H,
+            "l" => Reg::L,
+            "af" => Reg::Af,
+            "bc" => Reg::Bc,
+            "de" => Reg::De,
+            "hl" => Reg::Hl,
+            "sp" => Reg::Sp,
+            "pc" => Reg::Pc,
+            _ => {
+                let num = if let Some(hex) = word.strip_prefix("0x") {
+                    u16::from_str_radix(hex, 16)
+                } else if let Some(hex) = word.strip_prefix('$') {
+                    u16::from_str_radix(hex, 16)
+                } else {
+                    word.parse()
+                };
+                return num
+                    .map(Expr::Num)
+                    .map_err(|_| self.error("invalid operand"));
+            }
+        };
+
+        Ok(Expr::Reg(reg))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn eval_condition() {
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut cpu = Cpu::new();
+        cpu.set_a(0x3c);
+        cpu.set_hl(0xc123);
+
+        let cond: Condition = "A == 0x3C && [0xC123] != 0".parse().unwrap();
+        assert!(!cond.eval(&cpu, &mmu));
+
+        mmu.set8(0xc123, 1);
+        assert!(cond.eval(&cpu, &mmu));
+
+        let cond: Condition = "(a < $10 || [hl] == 1) && hl >= 49443".parse().unwrap();
+        assert!(cond.eval(&cpu, &mmu));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!("a == ".parse::<Condition>().is_err());
+        assert!("[0xc000".parse::<Condition>().is_err());
+        assert!("a == 1 b".parse::<Condition>().is_err());
+        assert!("x == 1".parse::<Condition>().is_err());
+    }
+}
@@ -2,34 +2,112 @@ use crate::hardware::HardwareHandle;
 use crate::system::Config;
 use log::*;
 
-pub struct FreqControl {
-    hw: HardwareHandle,
+/// The longest lag in microseconds the emulation catches up with.
+const MAX_LAG_US: i64 = 100_000;
+
+/// How the emulator keeps the CPU frequency set by [`Config::freq`][crate::Config::freq].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pacing {
+    /// Measure the frequency every [`Config::sample`][crate::Config::sample] cycles and tune the
+    /// busy loop run on every instruction (default).
+    DelayLoop,
+    /// Keep the emulated time in step with [`Clock::clock`][crate::Clock::clock] by an error accumulator,
+    /// waiting for the clock every [`Config::sample`][crate::Config::sample] cycles if the emulation is ahead.
+    ///
+    /// Only integer additions and multiplications are used, for the targets without an FPU
+    /// or 64-bit division, e.g. Cortex-M0.
+    Accumulator,
+    /// Leave the pacing to the frontend, e.g. [`System::run_frame_async`][crate::System::run_frame_async].
+    External,
+}
+
+pub struct FreqControl<'a> {
+    hw: HardwareHandle<'a>,
+    pacing: Pacing,
     last: u64,
     cycles: u64,
     sample: u64,
     delay: u64,
     delay_unit: u64,
     target_freq: u64,
+    /// How far the emulation is ahead of the clock, in microseconds multiplied by the frequency.
+    ahead: i64,
 }
 
-impl FreqControl {
-    pub fn new(hw: HardwareHandle, cfg: &Config) -> Self {
+impl<'a> FreqControl<'a> {
+    pub fn new(hw: HardwareHandle<'a>, cfg: &Config) -> Self {
         Self {
             hw,
+            pacing: cfg.pacing,
             last: 0,
             cycles: 0,
             delay: 0,
             sample: cfg.sample,
             delay_unit: cfg.delay_unit,
             target_freq: cfg.freq,
+            ahead: 0,
         }
     }
 
     pub fn reset(&mut self) {
-        self.last = self.hw.get().borrow_mut().clock();
+        self.last = self.hw.get().lock().clock();
+        self.ahead = 0;
     }
 
     pub fn adjust(&mut self, time: usize) {
+        match self.pacing {
+            Pacing::DelayLoop => self.delay_loop(time),
+            Pacing::Accumulator => self.accumulate(time),
+            Pacing::External => {}
+        }
+    }
+
+    /// Count the cycles run since the last call, returning how many microseconds
+    /// the emulation is ahead of the clock.
+    pub fn lead(&mut self, time: u64) -> u64 {
+        let freq = self.target_freq as i64;
+        let now = self.hw.get().lock().clock();
+
+        self.ahead += time as i64 * 1_000_000;
+        self.ahead -= now.wrapping_sub(self.last) as i64 * freq;
+        self.ahead = self.ahead.max(-MAX_LAG_US * freq);
+        self.last = now;
+
+        (self.ahead.max(0) / freq) as u64
+    }
+
+    fn accumulate(&mut self, time: usize) {
+        self.cycles += time as u64;
+        if self.cycles < self.sample {
+            return;
+        }
+        self.ahead += self.cycles as i64 * 1_000_000;
+        self.cycles = 0;
+
+        let freq = self.target_freq as i64;
+
+        loop {
+            let now = self.hw.get().lock().clock();
+            let (diff, of) = now.overflowing_sub(self.last);
+            if of {
+                warn!("Overflow: {} - {}", self.last, now);
+                self.last = now;
+                self.ahead = 0;
+                return;
+            }
+            self.last = now;
+
+            self.ahead -= diff as i64 * freq;
+            if self.ahead <= 0 {
+                break;
+            }
+        }
+
+        // Don't rush to catch up with a long stall, e.g. while the debugger stops the emulation
+        self.ahead = self.ahead.max(-MAX_LAG_US * freq);
+    }
+
+    fn delay_loop(&mut self, time: usize) {
         self.cycles += time as u64;
 
         for _ in 0..self.delay {
@@ -39,7 +117,7 @@ impl FreqControl {
         if self.cycles > self.sample {
             self.cycles -= self.sample;
 
-            let now = self.hw.get().borrow_mut().clock();
+            let now = self.hw.get().lock().clock();
             let (diff, of) = now.overflowing_sub(self.last);
             if of || diff == 0 {
                 warn!("Overflow: {} - {}", self.last, now);
@@ -62,3 +140,45 @@ impl FreqControl {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::hardware::{Clock, Input, Link, Persistence, Screen, Speaker};
+    use alloc::sync::Arc;
+    use spin::Mutex;
+
+    /// The clock which advances by 10 us every time it's read.
+    struct Ticking(Arc<Mutex<u64>>);
+
+    impl Screen for Ticking {}
+    impl Speaker for Ticking {}
+    impl Input for Ticking {}
+    impl Persistence for Ticking {}
+    impl Link for Ticking {}
+
+    impl Clock for Ticking {
+        fn clock(&mut self) -> u64 {
+            let mut now = self.0.lock();
+            *now += 10;
+            *now
+        }
+    }
+
+    #[test]
+    fn accumulator_waits_for_clock() {
+        let now = Arc::new(Mutex::new(0));
+        let hw = HardwareHandle::new(Ticking(now.clone()));
+        let cfg = Config::new().pacing(Pacing::Accumulator);
+        let mut fc = FreqControl::new(hw, &cfg);
+        fc.reset();
+
+        // 1 second of the emulated time
+        for _ in 0..cfg.freq / 4 {
+            fc.adjust(4);
+        }
+
+        let elapsed = *now.lock();
+        assert!((999_000..=1_001_000).contains(&elapsed), "{}", elapsed);
+    }
+}
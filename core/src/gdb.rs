@@ -0,0 +1,561 @@
+use crate::cpu::Cpu;
+use crate::debug::Debugger;
+use crate::device::IoHandler;
+use crate::mmu::{MemRead, MemWrite, Mmu};
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use log::*;
+
+/// The maximum size of the packets exchanged with the client.
+const PACKET_SIZE: usize = 0x400;
+
+/// Byte stream between the stub and the GDB client, e.g. a TCP socket or a serial port.
+pub trait Transport {
+    /// Send bytes to the client.
+    fn send(&mut self, data: &[u8]);
+
+    /// Receive a byte from the client if any. The function shouldn't block.
+    fn recv(&mut self) -> Option<u8>;
+
+    /// Check if the client is still connected, e.g. the socket isn't closed.
+    ///
+    /// The stub stops waiting for the client, and detaches as if the client did,
+    /// once this returns `false`.
+    fn connected(&mut self) -> bool {
+        true
+    }
+}
+
+/// Debugger which serves the GDB remote serial protocol over a [`Transport`][].
+///
+/// The registers are exposed to the client in the order of AF, BC, DE, HL, SP and PC,
+/// each of which is 16-bit little endian.
+///
+/// The registers and the memory written by the client are written to the emulator
+/// when it resumes, the memory through the bus as the CPU writes it, so writes to 0000-7fff
+/// go to the memory bank controller and don't patch the ROM.
+///
+/// The emulator stops at the first instruction until the client resumes it.
+/// While running, the client can stop it by sending Ctrl-C.
+pub struct GdbStub<T> {
+    transport: T,
+    cpu: Cpu,
+    breaks: BTreeSet<u16>,
+    rd_watches: BTreeSet<u16>,
+    wr_watches: BTreeSet<u16>,
+    /// The memory written by the client until the emulator resumes.
+    writes: BTreeMap<u16, u8>,
+    /// The registers were written by the client.
+    regs_written: bool,
+    stop: Option<String>,
+    last_stop: String,
+    stepping: bool,
+    attached: bool,
+}
+
+impl<T: Transport> GdbStub<T> {
+    /// Create a new stub which talks to the client over the transport.
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            cpu: Cpu::new(),
+            breaks: BTreeSet::new(),
+            rd_watches: BTreeSet::new(),
+            wr_watches: BTreeSet::new(),
+            writes: BTreeMap::new(),
+            regs_written: false,
+            stop: None,
+            last_stop: "S05".into(),
+            stepping: true,
+            attached: false,
+        }
+    }
+
+    /// Return the underlying transport.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Wait for a byte from the client. Returns `None` if the client is disconnected.
+    fn recv(&mut self) -> Option<u8> {
+        loop {
+            if let Some(b) = self.transport.recv() {
+                return Some(b);
+            }
+            if !self.transport.connected() {
+                return None;
+            }
+        }
+    }
+
+    fn recv_packet(&mut self) -> Option<Vec<u8>> {
+        loop {
+            while self.recv()? != b'$' {}
+
+            let mut data = Vec::new();
+            loop {
+                match self.recv()? {
+                    b'#' => break,
+                    b => data.push(b),
+                }
+            }
+
+            let sum = [self.recv()?, self.recv()?];
+            if parse_hex(&sum) == Some(checksum(&data) as usize) {
+                self.transport.send(b"+");
+                return Some(data);
+            }
+
+            warn!("GDB packet checksum mismatch");
+            self.transport.send(b"-");
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) {
+        let packet = format!("${}#{:02x}", data, checksum(data.as_bytes()));
+        self.transport.send(packet.as_bytes());
+    }
+
+    fn serve(&mut self, mmu: &Mmu) {
+        loop {
+            let packet = match self.recv_packet() {
+                Some(packet) => packet,
+                None => {
+                    info!("GDB disconnected");
+                    self.detach();
+                    return;
+                }
+            };
+
+            match self.handle(mmu, &packet) {
+                Some(reply) => self.send_packet(&reply),
+                None => return,
+            }
+        }
+    }
+
+    /// Handle a packet from the client. Returns `None` to resume the execution.
+    fn handle(&mut self, mmu: &Mmu, packet: &[u8]) -> Option<String> {
+        let (cmd, args) = match packet.split_first() {
+            Some((cmd, args)) => (*cmd, args),
+            None => return Some(String::new()),
+        };
+
+        let reply = match cmd {
+            b'?' => self.last_stop.clone(),
+            b'g' => {
+                let mut s = String::new();
+                while let Some(v) = self.reg(s.len() / 4) {
+                    put16(&mut s, v);
+                }
+                s
+            }
+            b'p' => match parse_hex(args).and_then(|n| self.reg(n)) {
+                Some(v) => {
+                    let mut s = String::new();
+                    put16(&mut s, v);
+                    s
+                }
+                None => "E01".into(),
+            },
+            b'm' => match parse_range(args) {
+                Some((addr, len)) => {
+                    let mut s = String::new();
+                    for i in 0..len.min(PACKET_SIZE / 2) {
+                        let addr = addr.wrapping_add(i as u16);
+                        let v = match self.writes.get(&addr) {
+                            Some(v) => *v,
+                            None => mmu.get8(addr),
+                        };
+                        let _ = write!(s, "{:02x}", v);
+                    }
+                    s
+                }
+                None => "E01".into(),
+            },
+            b'M' => {
+                let mut parts = args.splitn(2, |b| *b == b':');
+                match (parts.next().and_then(parse_range), parts.next()) {
+                    (Some((addr, len)), Some(data)) if data.len() == len * 2 => {
+                        for (i, b) in data.chunks(2).enumerate() {
+                            match parse_hex(b) {
+                                Some(v) => {
+                                    self.writes.insert(addr.wrapping_add(i as u16), v as u8);
+                                }
+                                None => return Some("E01".into()),
+                            }
+                        }
+                        "OK".into()
+                    }
+                    _ => "E01".into(),
+                }
+            }
+            b'G' => {
+                let regs: Option<Vec<_>> = args.chunks(4).map(parse16).collect();
+                match regs {
+                    Some(regs) if regs.len() == 6 => {
+                        for (n, v) in regs.into_iter().enumerate() {
+                            self.set_reg(n, v);
+                        }
+                        "OK".into()
+                    }
+                    _ => "E01".into(),
+                }
+            }
+            b'P' => {
+                let mut parts = args.splitn(2, |b| *b == b'=');
+                match (
+                    parts.next().and_then(parse_hex),
+                    parts.next().and_then(parse16),
+                ) {
+                    (Some(n), Some(v)) if n < 6 => {
+                        self.set_reg(n, v);
+                        "OK".into()
+                    }
+                    _ => "E01".into(),
+                }
+            }
+            b'c' => {
+                self.stepping = false;
+                return None;
+            }
+            b's' => {
+                self.stepping = true;
+                return None;
+            }
+            b'Z' | b'z' => self.set_point(cmd == b'Z', args),
+            b'q' => {
+                if args.starts_with(b"Supported") {
+                    format!("PacketSize={:x}", PACKET_SIZE)
+                } else if args.starts_with(b"Attached") {
+                    "1".into()
+                } else {
+                    String::new()
+                }
+            }
+            b'D' | b'k' => {
+                info!("GDB detached");
+                if cmd == b'D' {
+                    self.send_packet("OK");
+                }
+                self.detach();
+                return None;
+            }
+            _ => String::new(),
+        };
+
+        Some(reply)
+    }
+
+    fn reg(&self, n: usize) -> Option<u16> {
+        let cpu = &self.cpu;
+        match n {
+            0 => Some(cpu.get_af()),
+            1 => Some(cpu.get_bc()),
+            2 => Some(cpu.get_de()),
+            3 => Some(cpu.get_hl()),
+            4 => Some(cpu.get_sp()),
+            5 => Some(cpu.get_pc()),
+            _ => None,
+        }
+    }
+
+    fn set_reg(&mut self, n: usize, v: u16) {
+        let cpu = &mut self.cpu;
+        match n {
+            0 => cpu.set_af(v),
+            1 => cpu.set_bc(v),
+            2 => cpu.set_de(v),
+            3 => cpu.set_hl(v),
+            4 => cpu.set_sp(v),
+            _ => cpu.set_pc(v),
+        }
+        self.regs_written = true;
+    }
+
+    /// Remove the breakpoints and the watchpoints, and run until the next client stops the emulator.
+    fn detach(&mut self) {
+        self.breaks.clear();
+        self.rd_watches.clear();
+        self.wr_watches.clear();
+        self.stepping = false;
+        self.attached = false;
+    }
+
+    fn set_point(&mut self, insert: bool, args: &[u8]) -> String {
+        let mut parts = args.split(|b| *b == b',');
+        let kind = parts.next().and_then(parse_hex);
+        let addr = match parts.next().and_then(parse_hex) {
+            Some(addr) => addr as u16,
+            None => return "E01".into(),
+        };
+
+        let sets: &mut [&mut BTreeSet<u16>] = match kind {
+            Some(0) | Some(1) => &mut [&mut self.breaks],
+            Some(2) => &mut [&mut self.wr_watches],
+            Some(3) => &mut [&mut self.rd_watches],
+            Some(4) => &mut [&mut self.rd_watches, &mut self.wr_watches],
+            _ => return String::new(),
+        };
+
+        for set in sets.iter_mut() {
+            if insert {
+                set.insert(addr);
+            } else {
+                set.remove(&addr);
+            }
+        }
+
+        "OK".into()
+    }
+}
+
+impl<T: Transport> Debugger for GdbStub<T> {
+    fn init(&mut self, _: &Mmu) {
+        info!("Waiting for GDB");
+    }
+
+    fn take_cpu_snapshot(&mut self, cpu: Cpu) {
+        self.cpu = cpu;
+    }
+
+    fn on_decode(&mut self, mmu: &Mmu) {
+        if self.stop.is_none() && (self.stepping || self.breaks.contains(&self.cpu.get_pc())) {
+            self.stop = Some("S05".into());
+        }
+
+        let stop = match self.stop.take() {
+            Some(stop) => stop,
+            None => return,
+        };
+
+        if self.attached {
+            self.send_packet(&stop);
+        } else {
+            self.attached = true;
+        }
+        self.last_stop = stop;
+
+        self.serve(mmu);
+    }
+
+    fn write_back(&mut self, cpu: &mut Cpu, mmu: &mut Mmu) {
+        if core::mem::take(&mut self.regs_written) {
+            cpu.set_state(&self.cpu.state());
+        }
+        for (addr, v) in core::mem::take(&mut self.writes) {
+            mmu.set8(addr, v);
+        }
+    }
+
+    fn check_signal(&mut self) {
+        while let Some(b) = self.transport.recv() {
+            if b == 0x03 {
+                self.stop = Some("S02".into());
+            }
+        }
+    }
+
+    fn on_invalid_opcode(&mut self, _: u16, _: u16) {
+        self.stop = Some("S04".into());
+    }
+}
+
+impl<T: Transport> IoHandler for GdbStub<T> {
+    fn on_read(&mut self, _: &Mmu, addr: u16) -> MemRead {
+        if self.stop.is_none() && self.rd_watches.contains(&addr) {
+            let kind = if self.wr_watches.contains(&addr) {
+                "awatch"
+            } else {
+                "rwatch"
+            };
+            self.stop = Some(format!("T05{}:{:04x};", kind, addr));
+        }
+
+        MemRead::PassThrough
+    }
+
+    fn on_write(&mut self, _: &Mmu, addr: u16, _: u8) -> MemWrite {
+        if self.stop.is_none() && self.wr_watches.contains(&addr) {
+            let kind = if self.rd_watches.contains(&addr) {
+                "awatch"
+            } else {
+                "watch"
+            };
+            self.stop = Some(format!("T05{}:{:04x};", kind, addr));
+        }
+
+        MemWrite::PassThrough
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn put16(s: &mut String, v: u16) {
+    let _ = write!(s, "{:02x}{:02x}", v as u8, v >> 8);
+}
+
+fn parse_hex(s: &[u8]) -> Option<usize> {
+    let s = core::str::from_utf8(s).ok()?;
+    usize::from_str_radix(s, 16).ok()
+}
+
+/// Parse a 16-bit little endian register value.
+fn parse16(s: &[u8]) -> Option<u16> {
+    if s.len() != 4 {
+        return None;
+    }
+    Some((parse_hex(s)? as u16).swap_bytes())
+}
+
+fn parse_range(s: &[u8]) -> Option<(u16, usize)> {
+    let mut parts = s.split(|b| *b == b',');
+    let addr = parts.next().and_then(parse_hex)?;
+    let len = parts.next().and_then(parse_hex)?;
+    Some((addr as u16, len))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::collections::VecDeque;
+    use alloc::vec;
+
+    struct Pipe {
+        input: VecDeque<u8>,
+        output: Vec<u8>,
+        closed: bool,
+    }
+
+    impl Transport for Pipe {
+        fn send(&mut self, data: &[u8]) {
+            self.output.extend_from_slice(data);
+        }
+
+        fn recv(&mut self) -> Option<u8> {
+            self.input.pop_front()
+        }
+
+        fn connected(&mut self) -> bool {
+            !self.closed
+        }
+    }
+
+    fn stub(packets: &[&str]) -> GdbStub<Pipe> {
+        let mut input = VecDeque::new();
+        for p in packets {
+            input.extend(reply(p).bytes());
+        }
+        GdbStub::new(Pipe {
+            input,
+            output: Vec::new(),
+            closed: false,
+        })
+    }
+
+    fn reply(s: &str) -> String {
+        format!("${}#{:02x}", s, checksum(s.as_bytes()))
+    }
+
+    fn output(stub: &mut GdbStub<Pipe>) -> String {
+        String::from_utf8(core::mem::take(&mut stub.transport.output)).unwrap()
+    }
+
+    #[test]
+    fn read_registers_and_memory() {
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        mmu.set8(0xc000, 0x12);
+        mmu.set8(0xc001, 0x34);
+
+        let mut cpu = Cpu::new();
+        cpu.set_pc(0x0150);
+        cpu.set_hl(0xc000);
+
+        let mut stub = stub(&["g", "mc000,2", "c"]);
+        stub.take_cpu_snapshot(cpu);
+        stub.on_decode(&mmu);
+
+        assert_eq!(
+            output(&mut stub),
+            format!("+{}+{}+", reply("00000000000000c000005001"), reply("1234"))
+        );
+    }
+
+    #[test]
+    fn stop_at_breakpoint() {
+        let mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut cpu = Cpu::new();
+
+        let mut stub = stub(&["Z0,200,1", "c", "c"]);
+        stub.on_decode(&mmu);
+        assert_eq!(output(&mut stub), format!("+{}+", reply("OK")));
+
+        cpu.set_pc(0x100);
+        stub.take_cpu_snapshot(cpu.clone());
+        stub.on_decode(&mmu);
+        assert_eq!(output(&mut stub), "");
+
+        cpu.set_pc(0x200);
+        stub.take_cpu_snapshot(cpu);
+        stub.on_decode(&mmu);
+        assert_eq!(output(&mut stub), format!("{}+", reply("S05")));
+    }
+
+    #[test]
+    fn write_memory() {
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut cpu = Cpu::new();
+
+        let mut stub = stub(&["Mc000,1:ab", "mc000,1", "c"]);
+        stub.on_decode(&mmu);
+        assert_eq!(
+            output(&mut stub),
+            format!("+{}+{}+", reply("OK"), reply("ab"))
+        );
+        assert_eq!(mmu.get8(0xc000), 0x00);
+
+        stub.write_back(&mut cpu, &mut mmu);
+        assert_eq!(mmu.get8(0xc000), 0xab);
+    }
+
+    #[test]
+    fn write_registers() {
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut cpu = Cpu::new();
+
+        let mut stub = stub(&["G00000000000000c0feff5001", "P5=0002", "P6=0000", "c"]);
+        stub.take_cpu_snapshot(cpu.clone());
+        stub.on_decode(&mmu);
+        assert_eq!(
+            output(&mut stub),
+            format!("+{}+{}+{}+", reply("OK"), reply("OK"), reply("E01"))
+        );
+
+        stub.write_back(&mut cpu, &mut mmu);
+        assert_eq!(cpu.get_hl(), 0xc000);
+        assert_eq!(cpu.get_sp(), 0xfffe);
+        assert_eq!(cpu.get_pc(), 0x0200);
+    }
+
+    #[test]
+    fn disconnect() {
+        let mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut cpu = Cpu::new();
+
+        // Runs instead of waiting for the closed connection
+        let mut stub = stub(&["Z0,200,1"]);
+        stub.transport.closed = true;
+        stub.on_decode(&mmu);
+        assert_eq!(output(&mut stub), format!("+{}", reply("OK")));
+
+        cpu.set_pc(0x200);
+        stub.take_cpu_snapshot(cpu);
+        stub.on_decode(&mmu);
+        assert_eq!(output(&mut stub), "");
+    }
+}
@@ -1,11 +1,86 @@
-use crate::device::IoHandler;
-use crate::hardware::{HardwareHandle, VRAM_HEIGHT, VRAM_WIDTH};
+use crate::buffers::{Banks, Buf, FRAME_SIZE, VRAM_BANK_SIZE};
+use crate::debug::{Events, HwEvent};
+use crate::device::{Banked, IoHandler};
+use crate::error::Error;
+use crate::hardware::{HardwareHandle, LineSink, PixelFormat, VRAM_HEIGHT, VRAM_WIDTH};
 use crate::ic::Irq;
 use crate::mmu::{MemRead, MemWrite, Mmu};
-use alloc::{vec, vec::Vec};
+use crate::state::{Reader, State, Writer};
+use crate::system::Config;
+use alloc::{collections::VecDeque, format, vec, vec::Vec};
 use log::*;
 
-#[derive(Debug, Clone)]
+/// Width of the tiles decoded by [`System::debug_tiles`][crate::System::debug_tiles],
+/// which has 16 tiles per row for each of the two VRAM banks.
+pub const TILES_WIDTH: usize = 8 * 16 * 2;
+
+/// Height of the tiles decoded by [`System::debug_tiles`][crate::System::debug_tiles].
+pub const TILES_HEIGHT: usize = 8 * 24;
+
+/// Width and height of a background map decoded by [`System::debug_bg_map`][crate::System::debug_bg_map].
+pub const MAP_SIZE: usize = 256;
+
+/// The background maps in VRAM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TileMap {
+    /// The map at 0x9800-0x9bff.
+    Low,
+    /// The map at 0x9c00-0x9fff.
+    High,
+}
+
+/// An OAM entry decoded for a sprite viewer by [`System::debug_sprites`][crate::System::debug_sprites].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sprite {
+    /// Index of the entry in OAM.
+    pub index: usize,
+    /// X coordinate on the screen plus 8.
+    pub x: u8,
+    /// Y coordinate on the screen plus 16.
+    pub y: u8,
+    /// Tile number, whose lowest bit is ignored for 8x16 sprites.
+    pub tile: u8,
+    /// Raw attribute byte.
+    pub attr: u8,
+    /// Palette number, `OBP0`/`OBP1` on DMG and the color palette on CGB.
+    pub palette: usize,
+    /// VRAM bank of the tile.
+    pub bank: usize,
+    /// Flipped horizontally.
+    pub xflip: bool,
+    /// Flipped vertically.
+    pub yflip: bool,
+    /// Drawn behind the BG colors 1-3.
+    pub behind_bg: bool,
+    /// Selected by the OAM scan of any line in the last frame, so at least partially drawn
+    /// unless it's off the screen horizontally.
+    pub visible: bool,
+    /// Pixels of the sprite in `0xRRGGBB` and row-major order, flipped as drawn,
+    /// which is 8 pixels wide and 8 or 16 pixels high as selected by LCDC.
+    /// The transparent pixels are `None`.
+    pub pixels: Vec<Option<u32>>,
+}
+
+/// The current palettes returned by [`System::palettes`][crate::System::palettes].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palettes {
+    /// `BGP` register.
+    pub bgp: u8,
+    /// `OBP0` register.
+    pub obp0: u8,
+    /// `OBP1` register.
+    pub obp1: u8,
+    /// The eight BG palettes of CGB in `0xRRGGBB`.
+    ///
+    /// On DMG, the first one holds `BGP` decoded with the DMG shades.
+    pub bg: [[u32; 4]; 8],
+    /// The eight sprite palettes of CGB in `0xRRGGBB`.
+    ///
+    /// On DMG, the first two hold `OBP0` and `OBP1` decoded with the DMG shades.
+    pub obj: [[u32; 4]; 8],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 enum Mode {
     OAM,
     VRAM,
@@ -38,8 +113,9 @@ impl From<u8> for Mode {
     }
 }
 
-pub struct Gpu {
+pub struct Gpu<'a> {
     irq: Irq,
+    events: Events,
 
     clocks: usize,
 
@@ -47,6 +123,7 @@ pub struct Gpu {
     oam_interrupt: bool,
     vblank_interrupt: bool,
     hblank_interrupt: bool,
+    stat_line: bool,
     mode: Mode,
 
     ly: u8,
@@ -65,21 +142,53 @@ pub struct Gpu {
     spsize: u16,
     spenable: bool,
     bgenable: bool,
-    hw: HardwareHandle,
+    hw: HardwareHandle<'a>,
 
-    bg_palette: Vec<Color>,
-    obj_palette0: Vec<Color>,
-    obj_palette1: Vec<Color>,
+    bg_palette: [Color; 4],
+    obj_palette0: [Color; 4],
+    obj_palette1: [Color; 4],
     bg_color_palette: ColorPalette,
     obj_color_palette: ColorPalette,
-    vram: Vec<Vec<u8>>,
+    vram: Banks<'a>,
     vram_select: usize,
 
     hdma: Hdma,
+    format: PixelFormat,
+    shades: [u32; 4],
+    headless: bool,
+    /// Skip rendering the current frame, keeping the timing.
+    skip: bool,
+    blank: bool,
+    pending: Option<Output>,
+    frame: Buf<'a, u32>,
+    line: Vec<u32>,
+    bgline: Vec<BgPixel>,
+    sprites: Vec<LineSprite>,
+    persistence: u8,
+    prev: Buf<'a, u32>,
+
+    fifo: Option<Fifo>,
+    /// The granularity in pixels of the register writes in mode 3, or zero to latch them per line.
+    split: usize,
+    /// The pixels of the current line drawn before the register writes in mode 3.
+    drawn: usize,
+    oam_bug: bool,
+    oam_corrupt: bool,
+    hblank_len: usize,
+    /// WY matched LY on a line of the current frame, which enables the window until the next frame.
+    wy_hit: bool,
+    /// The internal line counter of the window, only advanced on the lines the window is shown.
+    wline: u16,
+    /// The window was triggered at WX=166 on the previous line, so it spans the whole current line.
+    win_wrap: bool,
+    /// The OAM entries selected on the lines of the current frame, one bit per entry.
+    oam_selected: u64,
+    /// The OAM entries selected in the last frame.
+    oam_visible: u64,
 }
 
-fn to_palette(p: u8) -> Vec<Color> {
-    vec![
+fn to_palette(p: u8) -> [Color; 4] {
+    [
         ((p >> 0) & 0x3).into(),
         ((p >> 2) & 0x3).into(),
         ((p >> 4) & 0x3).into(),
@@ -87,9 +196,7 @@ fn to_palette(p: u8) -> Vec<Color> {
     ]
 }
 
-fn from_palette(p: Vec<Color>) -> u8 {
-    assert_eq!(p.len(), 4);
-
+fn from_palette(p: &[Color; 4]) -> u8 {
     u8::from(p[0]) | u8::from(p[1]) << 2 | u8::from(p[2]) << 4 | u8::from(p[3]) << 6
 }
 
@@ -217,13 +324,22 @@ fn color_adjust(v: u8) -> u32 {
     }
 }
 
-impl From<Color> for u32 {
-    fn from(c: Color) -> u32 {
-        match c {
-            Color::White => 0xdddddd,
-            Color::LightGray => 0xaaaaaa,
-            Color::DarkGray => 0x888888,
-            Color::Black => 0x555555,
+impl Color {
+    /// Encode the color as a pixel in the given format, using `shades` for the DMG colors.
+    fn pixel(self, format: PixelFormat, shades: &[u32; 4]) -> u32 {
+        match format {
+            PixelFormat::Rgb888 => self.rgb888(shades),
+            PixelFormat::Index => self.shade(),
+            PixelFormat::Rgb565 => {
+                let c = self.rgb888(shades);
+                (c >> 8) & 0xf800 | (c >> 5) & 0x07e0 | (c >> 3) & 0x001f
+            }
+            PixelFormat::Rgba8888 => self.rgb888(shades) << 8 | 0xff,
+        }
+    }
+
+    fn rgb888(self, shades: &[u32; 4]) -> u32 {
+        match self {
             Color::Rgb(r, g, b) => {
                 let mut c = 0;
                 c |= color_adjust(r) << 16;
@@ -231,8 +347,40 @@ impl From<Color> for u32 {
                 c |= color_adjust(b);
                 c
             }
+            c => shades[u8::from(c) as usize] & 0xffffff,
         }
     }
+
+    fn shade(self) -> u32 {
+        match self {
+            Color::Rgb(r, g, b) => {
+                // Approximate the shade from the luminance of the 5-bit components
+                let lum = (r as u32 * 3 + g as u32 * 6 + b as u32) / 10;
+                3 - lum.min(0x1f) / 8
+            }
+            c => u8::from(c) as u32,
+        }
+    }
+}
+
+/// Mix the previous pixel into the current one, weighting the previous one by `persistence` / 256.
+fn blend(cur: u32, prev: u32, persistence: u8, format: PixelFormat) -> u32 {
+    // (shift, mask) of each channel
+    let channels: &[(u32, u32)] = match format {
+        PixelFormat::Rgb888 => &[(16, 0xff), (8, 0xff), (0, 0xff)],
+        PixelFormat::Index => &[(0, 0x3)],
+        PixelFormat::Rgb565 => &[(11, 0x1f), (5, 0x3f), (0, 0x1f)],
+        PixelFormat::Rgba8888 => &[(24, 0xff), (16, 0xff), (8, 0xff), (0, 0xff)],
+    };
+
+    let p = persistence as u32;
+
+    channels.iter().fold(cur, |acc, &(shift, mask)| {
+        let c = (cur >> shift) & mask;
+        let q = (prev >> shift) & mask;
+        let v = (c * (256 - p) + q * p + 128) / 256;
+        acc & !(mask << shift) | v << shift
+    })
 }
 
 impl From<Color> for u8 {
@@ -335,15 +483,136 @@ impl Hdma {
     }
 }
 
-impl Gpu {
-    pub fn new(hw: HardwareHandle, irq: Irq) -> Self {
+/// The maximum number of sprites drawn on a line.
+const SPRITES_PER_LINE: usize = 10;
+
+/// The output of the GPU waiting to be passed to the screen.
+#[derive(Clone, Copy)]
+enum Output {
+    /// The line is rendered into the line buffer.
+    Line(usize),
+    /// The whole screen is blanked as the LCD is turned off.
+    Blank,
+}
+
+/// Pixel in the background FIFO.
+#[derive(Clone, Copy, Default)]
+struct BgPixel {
+    coli: usize,
+    palette: usize,
+    priority: bool,
+}
+
+/// Pixel in the object FIFO.
+#[derive(Clone, Copy, Default)]
+struct ObjPixel {
+    coli: usize,
+    palette: usize,
+    priority: bool,
+    oam: usize,
+}
+
+/// Sprite selected by the OAM scan for the current line.
+#[derive(Default)]
+struct LineSprite {
+    oam: usize,
+    ypos: u16,
+    xpos: u16,
+    ti: u8,
+    attr: u8,
+    fetched: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FetchStep {
+    Tile,
+    Low,
+    High,
+    Push,
+}
+
+/// State of the pixel FIFO pipeline during mode 3.
+struct Fifo {
+    bg: VecDeque<BgPixel>,
+    obj: VecDeque<ObjPixel>,
+    step: FetchStep,
+    dots: usize,
+    fetch_x: u16,
+    tile: u16,
+    attr: u8,
+    row: u16,
+    low: u8,
+    high: u8,
+    window: bool,
+    discard: usize,
+    stall: usize,
+    lx: usize,
+    elapsed: usize,
+    sprites: Vec<LineSprite>,
+}
+
+impl Fifo {
+    fn new() -> Self {
+        Self {
+            bg: VecDeque::with_capacity(16),
+            obj: VecDeque::with_capacity(16),
+            step: FetchStep::Tile,
+            dots: 0,
+            fetch_x: 0,
+            tile: 0,
+            attr: 0,
+            row: 0,
+            low: 0,
+            high: 0,
+            window: false,
+            discard: 0,
+            stall: 0,
+            lx: 0,
+            elapsed: 0,
+            sprites: Vec::with_capacity(40),
+        }
+    }
+
+    /// Reset the fetcher to fetch from the first tile of the background or the window.
+    fn restart(&mut self, window: bool) {
+        self.bg.clear();
+        self.step = FetchStep::Tile;
+        self.dots = 0;
+        self.fetch_x = 0;
+        self.window = window;
+    }
+}
+
+impl<'a> Gpu<'a> {
+    #[cfg(test)]
+    pub fn new(hw: HardwareHandle<'a>, irq: Irq, cfg: &Config) -> Self {
+        let bufs = crate::buffers::Buffers::alloc(vec![], cfg);
+
+        Self::with_buffers(hw, irq, cfg, bufs.vram, bufs.drawn, bufs.prev)
+    }
+
+    /// Create the GPU with the video RAM, the frame buffer being drawn and the previous frame for blending,
+    /// where the frame buffers are empty if disabled.
+    pub(crate) fn with_buffers(
+        hw: HardwareHandle<'a>,
+        irq: Irq,
+        cfg: &Config,
+        vram: Banks<'a>,
+        frame: Buf<'a, u32>,
+        prev: Buf<'a, u32>,
+    ) -> Self {
+        debug_assert!(frame.is_empty() || frame.len() == FRAME_SIZE);
+        debug_assert!(prev.is_empty() || prev.len() == FRAME_SIZE);
+
         Self {
             irq: irq,
+            events: Events::default(),
             clocks: 0,
             lyc_interrupt: false,
             oam_interrupt: false,
             vblank_interrupt: false,
             hblank_interrupt: false,
+            stat_line: false,
             mode: Mode::None,
             ly: 0,
             lyc: 0,
@@ -360,19 +629,19 @@ impl Gpu {
             spenable: false,
             bgenable: false,
             hw,
-            bg_palette: vec![
+            bg_palette: [
                 Color::White,
                 Color::LightGray,
                 Color::DarkGray,
                 Color::Black,
             ],
-            obj_palette0: vec![
+            obj_palette0: [
                 Color::White,
                 Color::LightGray,
                 Color::DarkGray,
                 Color::Black,
             ],
-            obj_palette1: vec![
+            obj_palette1: [
                 Color::White,
                 Color::LightGray,
                 Color::DarkGray,
@@ -380,10 +649,88 @@ impl Gpu {
             ],
             bg_color_palette: ColorPalette::new(),
             obj_color_palette: ColorPalette::new(),
-            vram: vec![vec![0; 0x2000]; 2],
+            vram,
             vram_select: 0,
             hdma: Hdma::new(),
+            format: cfg.pixel_format,
+            shades: cfg.dmg_palette,
+            headless: cfg.headless,
+            skip: false,
+            blank: false,
+            pending: None,
+            frame,
+            line: vec![0; VRAM_WIDTH],
+            bgline: vec![BgPixel::default(); VRAM_WIDTH],
+            sprites: Vec::with_capacity(SPRITES_PER_LINE),
+            persistence: if prev.is_empty() {
+                0
+            } else {
+                cfg.frame_blending
+            },
+            prev,
+            fifo: if cfg.pixel_fifo {
+                Some(Fifo::new())
+            } else {
+                None
+            },
+            split: cfg.mid_line_writes,
+            drawn: 0,
+            oam_bug: cfg.oam_bug,
+            oam_corrupt: false,
+            hblank_len: 204,
+            wy_hit: false,
+            wline: 0,
+            win_wrap: false,
+            oam_selected: 0,
+            oam_visible: 0,
+        }
+    }
+
+    /// Move out the video RAM, the frame being drawn and the previous frame to reuse them on reset.
+    pub(crate) fn take_buffers(&mut self) -> (Banks<'a>, Buf<'a, u32>, Buf<'a, u32>) {
+        let vram = core::mem::replace(&mut self.vram, Banks::new(Buf::empty(), VRAM_BANK_SIZE));
+
+        (vram, self.frame.take(), self.prev.take())
+    }
+
+    /// Report the mode changes to the hardware events.
+    pub fn set_events(&mut self, events: Events) {
+        self.events = events;
+    }
+
+    /// Return the pixels of the screen in row-major order, or empty if the frame buffer is disabled.
+    pub fn frame(&self) -> &[u32] {
+        &self.frame
+    }
+
+    /// Blank the screen as the CPU enters STOP, which halts the LCD controller.
+    ///
+    /// Returns `true` if the LCD is on, i.e. a blank frame is presented.
+    pub fn stop(&mut self) -> bool {
+        if self.enable {
+            self.pending = Some(Output::Blank);
         }
+        self.enable
+    }
+
+    /// Skip rendering the lines until the flag is cleared, e.g. to drop frames while fast-forwarding.
+    ///
+    /// The GPU keeps its timing and raises interrupts as usual.
+    pub fn set_skip(&mut self, skip: bool) {
+        self.skip = skip;
+    }
+
+    fn rendering(&self) -> bool {
+        !self.headless && !self.skip
+    }
+
+    /// Set the colors of the four DMG shades in `0xRRGGBB`, from the lightest to the darkest.
+    pub fn set_shades(&mut self, shades: [u32; 4]) {
+        self.shades = shades;
+    }
+
+    fn pixel(&self, c: Color) -> u32 {
+        c.pixel(self.format, &self.shades)
     }
 
     fn hdma_run(&mut self, mmu: &Mmu) {
@@ -397,25 +744,93 @@ impl Gpu {
         }
     }
 
-    pub fn step(&mut self, time: usize, mmu: &mut Mmu) {
+    /// Returns the clock cycles until the next mode change, or `None` if the LCD stays off.
+    ///
+    /// Zero means the GPU has work to do on the next step regardless of the time.
+    pub fn next_event(&self) -> Option<usize> {
+        if self.oam_corrupt || self.blank {
+            return Some(0);
+        }
+        if !self.enable {
+            return None;
+        }
+
+        let end = match self.mode {
+            Mode::OAM => 80,
+            // The pixel FIFO decides the end of the mode as it runs
+            Mode::VRAM if self.fifo.is_some() => return Some(4),
+            Mode::VRAM => 172,
+            Mode::HBlank => self.hblank_len,
+            Mode::VBlank => 456,
+            Mode::None => return None,
+        };
+
+        Some(end.saturating_sub(self.clocks))
+    }
+
+    /// Count the clock cycles which fall short of [`Gpu::next_event`][], where nothing but the time changes.
+    pub fn advance(&mut self, time: usize) {
+        if self.enable && self.mode != Mode::None {
+            self.clocks += time;
+        }
+    }
+
+    /// Returns `true` when the GPU enters V-blank or the screen is blanked, i.e. a frame is completed.
+    ///
+    /// The rendered output is kept until [`Gpu::flush`][] is called.
+    pub fn step(&mut self, time: usize, mmu: &mut Mmu) -> bool {
+        if self.oam_corrupt {
+            self.oam_corrupt = false;
+            self.corrupt_oam(mmu);
+        }
+
+        if !self.enable {
+            // Present a blank frame once the LCD is turned off
+            let blank = self.blank;
+            if blank {
+                self.blank = false;
+                self.pending = Some(Output::Blank);
+            }
+            return blank;
+        }
+
         let clocks = self.clocks + time;
 
         let (clocks, mode) = match &self.mode {
             Mode::OAM => {
                 if clocks >= 80 {
+                    if self.ly == self.wy {
+                        self.wy_hit = true;
+                    }
+                    self.drawn = 0;
+                    if self.fifo.is_some() {
+                        self.fifo_start(mmu);
+                    }
+
                     (0, Mode::VRAM)
                 } else {
                     (clocks, Mode::OAM)
                 }
             }
             Mode::VRAM => {
-                if clocks >= 172 {
-                    self.draw(mmu);
-                    self.hdma_run(mmu);
+                let done = if self.fifo.is_some() {
+                    self.fifo_run(time)
+                } else {
+                    clocks >= 172
+                };
 
-                    if self.hblank_interrupt {
-                        self.irq.lcd(true);
+                if done {
+                    if self.fifo.is_some() {
+                        if self.rendering() {
+                            self.pending = Some(Output::Line(self.ly as usize));
+                        }
+                    } else {
+                        if self.rendering() {
+                            self.draw(mmu);
+                        }
+                        self.window_done(self.window_x().is_some());
                     }
+                    self.hdma_run(mmu);
 
                     (0, Mode::HBlank)
                 } else {
@@ -423,23 +838,15 @@ impl Gpu {
                 }
             }
             Mode::HBlank => {
-                if clocks >= 204 {
+                if clocks >= self.hblank_len {
                     self.ly += 1;
 
                     // ly becomes 144 before vblank interrupt
                     if self.ly > 143 {
                         self.irq.vblank(true);
 
-                        if self.vblank_interrupt {
-                            self.irq.lcd(true);
-                        }
-
                         (0, Mode::VBlank)
                     } else {
-                        if self.oam_interrupt {
-                            self.irq.lcd(true);
-                        }
-
                         (0, Mode::OAM)
                     }
                 } else {
@@ -452,10 +859,9 @@ impl Gpu {
 
                     if self.ly > 153 {
                         self.ly = 0;
-
-                        if self.oam_interrupt {
-                            self.irq.lcd(true);
-                        }
+                        self.wy_hit = false;
+                        self.wline = 0;
+                        self.win_wrap = false;
 
                         (0, Mode::OAM)
                     } else {
@@ -468,24 +874,97 @@ impl Gpu {
             Mode::None => (0, Mode::None),
         };
 
-        if self.lyc_interrupt && self.lyc == self.ly {
-            self.irq.lcd(true);
+        let vblank = self.mode != Mode::VBlank && mode == Mode::VBlank;
+        if vblank {
+            self.oam_visible = core::mem::replace(&mut self.oam_selected, 0);
+        }
+
+        if mode != self.mode {
+            self.events.push(HwEvent::ModeChange {
+                mode: mode.clone().into(),
+                line: self.ly,
+            });
         }
 
         self.clocks = clocks;
         self.mode = mode;
+        self.update_stat_line();
+
+        vblank
     }
 
-    fn draw(&mut self, mmu: &Mmu) {
-        let height = VRAM_HEIGHT;
+    /// Update the internal STAT interrupt line, which is the OR of all the enabled STAT sources.
+    ///
+    /// The LCD interrupt is requested only when the line goes from low to high,
+    /// so a source doesn't raise an interrupt while another one keeps the line high.
+    fn update_stat_line(&mut self) {
+        let line = self.enable
+            && (self.lyc_interrupt && self.ly == self.lyc
+                || self.hblank_interrupt && self.mode == Mode::HBlank
+                || self.vblank_interrupt && self.mode == Mode::VBlank
+                || self.oam_interrupt && self.mode == Mode::OAM);
+
+        if line && !self.stat_line {
+            self.irq.lcd(true);
+        }
+
+        self.stat_line = line;
+    }
+
+    /// Pass the output rendered by [`Gpu::step`][] to `sink` if given, or to the hardware otherwise.
+    pub fn flush(&mut self, sink: Option<&mut dyn LineSink>) {
+        match self.pending.take() {
+            Some(Output::Line(ly)) => self.output_line(ly, sink),
+            Some(Output::Blank) => self.blank_screen(sink),
+            None => {}
+        }
+    }
+
+    fn blank_screen(&mut self, mut sink: Option<&mut dyn LineSink>) {
+        if !self.rendering() {
+            return;
+        }
+
         let width = VRAM_WIDTH;
+        let blank = self.pixel(Color::White);
 
-        if self.ly >= height as u8 {
+        self.line.iter_mut().for_each(|p| *p = blank);
+        self.frame.iter_mut().for_each(|p| *p = blank);
+
+        for ly in 0..VRAM_HEIGHT {
+            match &mut sink {
+                Some(sink) => sink.write_line(ly, &self.line[..width]),
+                None => self.hw.get().lock().vram_update(ly, &self.line),
+            }
+        }
+    }
+
+    /// Draw the background and the window up to the pixel being drawn in mode 3,
+    /// before a register write changes them.
+    fn split_line(&mut self) {
+        let latched = self.fifo.is_some() || self.split == 0;
+        if latched || self.mode != Mode::VRAM || !self.rendering() {
             return;
         }
 
-        let mut buf = vec![0; width];
-        let mut bgbuf = vec![0; width];
+        // The pixels are pushed out after the first tile fetch
+        let x = self.clocks.saturating_sub(12).min(VRAM_WIDTH);
+        let x = x / self.split * self.split;
+        if x > self.drawn {
+            self.draw_bg(self.drawn, x);
+            self.drawn = x;
+        }
+    }
+
+    /// Draw the background and the window of the pixels `from..to` on the current line.
+    fn draw_bg(&mut self, from: usize, to: usize) {
+        // Reuse the line buffers to avoid allocating on every line
+        let mut buf = core::mem::take(&mut self.line);
+        let mut bgbuf = core::mem::take(&mut self.bgline);
+        buf[from..to].iter_mut().for_each(|p| *p = 0);
+        bgbuf[from..to]
+            .iter_mut()
+            .for_each(|p| *p = BgPixel::default());
 
         if self.bgenable {
             let mapbase = self.bgmap;
@@ -494,7 +973,7 @@ impl Gpu {
             let ty = yy / 8;
             let tyoff = yy % 8;
 
-            for x in 0..width as u16 {
+            for x in from as u16..to as u16 {
                 let xx = (x + self.scx as u16) % 256;
                 let tx = xx / 8;
                 let txoff = xx % 8;
@@ -505,216 +984,622 @@ impl Gpu {
                 let tyoff = if tattr.yflip { 7 - tyoff } else { tyoff };
                 let txoff = if tattr.xflip { 7 - txoff } else { txoff };
 
-                #[cfg(feature = "color")]
-                {
-                    assert_eq!(tattr.priority, false);
-                }
-
                 let coli = self.get_tile_byte(tbase, txoff, tyoff, tattr.vram_bank);
-                let col = tattr.palette[coli].into();
+                let col = self.pixel(tattr.palette[coli]);
 
                 buf[x as usize] = col;
-                bgbuf[x as usize] = coli;
+                bgbuf[x as usize] = BgPixel {
+                    coli,
+                    palette: 0,
+                    priority: tattr.priority,
+                };
             }
         }
 
-        if self.winenable {
+        if let Some((start, cut)) = self.window_x() {
             let mapbase = self.winmap;
 
-            if self.ly >= self.wy {
-                let yy = (self.ly - self.wy) as u16;
-                let ty = yy / 8;
-                let tyoff = yy % 8;
+            let yy = self.wline;
+            let ty = yy / 8;
+            let tyoff = yy % 8;
 
-                for x in 0..width as u16 {
-                    if x + 7 < self.wx as u16 {
-                        continue;
-                    }
-                    let xx = (x + 7 - self.wx as u16) as u16; // x - (wx - 7)
-                    let tx = xx / 8;
-                    let txoff = xx % 8;
+            for x in start.max(from) as u16..to as u16 {
+                let xx = x - start as u16 + cut as u16;
+                let tx = xx / 8;
+                let txoff = xx % 8;
 
-                    let tbase = self.get_tile_base(mapbase, tx, ty);
-                    let tattr = self.get_tile_attr(mapbase, tx, ty);
+                let tbase = self.get_tile_base(mapbase, tx, ty);
+                let tattr = self.get_tile_attr(mapbase, tx, ty);
 
-                    let coli = self.get_tile_byte(tbase, txoff, tyoff, tattr.vram_bank);
-                    let col = tattr.palette[coli].into();
+                let coli = self.get_tile_byte(tbase, txoff, tyoff, tattr.vram_bank);
+                let col = self.pixel(tattr.palette[coli]);
 
-                    buf[x as usize] = col;
-                }
+                buf[x as usize] = col;
+                bgbuf[x as usize] = BgPixel {
+                    coli,
+                    palette: 0,
+                    priority: tattr.priority,
+                };
             }
         }
 
+        self.line = buf;
+        self.bgline = bgbuf;
+    }
+
+    fn draw(&mut self, mmu: &Mmu) {
+        let height = VRAM_HEIGHT;
+        let width = VRAM_WIDTH;
+
+        if self.ly >= height as u8 {
+            return;
+        }
+
+        let from = core::mem::take(&mut self.drawn);
+        self.draw_bg(from, width);
+
+        // Take the buffers out to draw the sprites over the background
+        let mut buf = core::mem::take(&mut self.line);
+        let bgbuf = core::mem::take(&mut self.bgline);
+
         if self.spenable {
-            for i in 0..40 {
-                let oam = 0xfe00 + i * 4;
-                let ypos = mmu.get8(oam + 0) as u16;
-                let xpos = mmu.get8(oam + 1) as u16;
-                let ti = mmu.get8(oam + 2);
-                let attr = self.get_sp_attr(mmu.get8(oam + 3));
-
-                let ly = self.ly as u16;
-                if ly + 16 < ypos {
-                    // This sprite doesn't hit the current ly
-                    continue;
-                }
-                let tyoff = ly as u16 + 16 - ypos; // ly - (ypos - 16)
-                if tyoff >= self.spsize {
-                    // This sprite doesn't hit the current ly
-                    continue;
-                }
-                let tyoff = if attr.yflip {
-                    self.spsize - 1 - tyoff
-                } else {
-                    tyoff
-                };
-                let ti = if self.spsize == 16 {
-                    if tyoff >= 8 {
-                        ti | 1
-                    } else {
-                        ti & 0xfe
-                    }
-                } else {
-                    ti
-                };
-                let tyoff = tyoff % 8;
+            let mut sprites = core::mem::take(&mut self.sprites);
+            self.scan_oam(mmu, &mut sprites);
 
-                let tiles = 0x8000;
+            if !cfg!(feature = "color") {
+                // On DMG, the sprite with the smaller x coordinate wins, then the one first in OAM
+                sprites.sort_by_key(|sp| sp.xpos);
+            }
+
+            // The highest priority opaque sprite pixel for each x
+            let mut objbuf = [None; VRAM_WIDTH];
 
-                for x in 0..width as u16 {
-                    if x + 8 < xpos {
+            for sp in &sprites {
+                let (low, high) = self.sprite_row(sp);
+                let attr = self.get_sp_attr(sp.attr);
+
+                for i in 0..8 {
+                    let x = sp.xpos + i;
+                    if x < 8 || x >= width as u16 + 8 {
                         continue;
                     }
-                    let txoff = x + 8 - xpos; // x - (xpos - 8)
-                    if txoff >= 8 {
+                    let x = x as usize - 8;
+                    if objbuf[x].is_some() {
                         continue;
                     }
-                    let txoff = if attr.xflip { 7 - txoff } else { txoff };
-
-                    let tbase = tiles + ti as u16 * 16;
-
-                    let coli = self.get_tile_byte(tbase, txoff, tyoff, attr.vram_bank);
 
+                    let bit = if attr.xflip { i } else { 7 - i };
+                    let coli = (((high >> bit) & 1) << 1 | (low >> bit) & 1) as usize;
                     if coli == 0 {
                         // Color index 0 means transparent
                         continue;
                     }
 
-                    let col = attr.palette[coli];
+                    objbuf[x] = Some((attr.palette[coli], attr.priority));
+                }
+            }
 
-                    let bgcoli = bgbuf[x as usize];
+            for (x, obj) in objbuf.iter().enumerate() {
+                if let Some((col, priority)) = *obj {
+                    let bg = bgbuf[x];
 
-                    if attr.priority && bgcoli != 0 {
-                        // If priority is lower than bg color 1-3, don't draw
+                    if (priority || bg.priority) && bg.coli != 0 {
+                        // BG color 1-3 is drawn over the sprite
                         continue;
                     }
 
-                    buf[x as usize] = col.into();
+                    buf[x] = self.pixel(col);
                 }
             }
+
+            self.sprites = sprites;
         }
 
-        self.hw
-            .get()
-            .borrow_mut()
-            .vram_update(self.ly as usize, &buf);
+        self.line = buf;
+        self.bgline = bgbuf;
+
+        self.pending = Some(Output::Line(self.ly as usize));
     }
 
-    fn on_write_ctrl(&mut self, value: u8) {
-        let old_enable = self.enable;
+    /// Post-process the rendered line and pass it to the frame buffer and the sink.
+    fn output_line(&mut self, ly: usize, sink: Option<&mut dyn LineSink>) {
+        let width = VRAM_WIDTH;
+        let offset = ly * width;
 
-        self.enable = value & 0x80 != 0;
-        self.winmap = if value & 0x40 != 0 { 0x9c00 } else { 0x9800 };
-        self.winenable = value & 0x20 != 0;
-        self.tiles = if value & 0x10 != 0 { 0x8000 } else { 0x8800 };
-        self.bgmap = if value & 0x08 != 0 { 0x9c00 } else { 0x9800 };
-        self.spsize = if value & 0x04 != 0 { 16 } else { 8 };
-        self.spenable = value & 0x02 != 0;
-        self.bgenable = value & 0x01 != 0;
+        if self.persistence > 0 {
+            let prev = &mut self.prev[offset..offset + width];
+            for (cur, prev) in self.line.iter_mut().zip(prev.iter_mut()) {
+                *cur = blend(*cur, *prev, self.persistence, self.format);
+                *prev = *cur;
+            }
+        }
 
-        if !old_enable && self.enable {
-            info!("LCD enabled");
-            self.clocks = 0;
-            self.mode = Mode::HBlank;
-            self.irq.vblank(false);
-        } else if old_enable && !self.enable {
-            info!("LCD disabled");
-            self.mode = Mode::None;
-            self.irq.vblank(false);
+        if !self.frame.is_empty() {
+            self.frame[offset..offset + width].copy_from_slice(&self.line);
         }
 
-        debug!("Write ctrl: {:02x}", value);
-        debug!("Window base: {:04x}", self.winmap);
-        debug!("Window enable: {}", self.winenable);
-        debug!("Bg/window base: {:04x}", self.tiles);
-        debug!("Background base: {:04x}", self.bgmap);
-        debug!("Sprite size: 8x{}", self.spsize);
-        debug!("Sprite enable: {}", self.spenable);
-        debug!("Background enable: {}", self.bgenable);
+        match sink {
+            Some(sink) => sink.write_line(ly, &self.line),
+            None => self.hw.get().lock().vram_update(ly, &self.line),
+        }
     }
 
-    fn on_write_status(&mut self, value: u8) {
-        self.lyc_interrupt = value & 0x40 != 0;
-        self.oam_interrupt = value & 0x20 != 0;
-        self.vblank_interrupt = value & 0x10 != 0;
-        self.hblank_interrupt = value & 0x08 != 0;
+    /// Returns where the window starts on the current line, as the screen x and the number of
+    /// the window pixels cut off at the left edge, or `None` if the window isn't shown.
+    ///
+    /// WX below 7 cuts off the window pixels outside the screen, except WX=0, where the window
+    /// is shifted by the fine scroll of the background instead. The window triggered at WX=166
+    /// covers the last pixel and the whole next line.
+    fn window_x(&self) -> Option<(usize, usize)> {
+        let bg_on = cfg!(feature = "color") || self.bgenable;
+        if !self.winenable || !bg_on || !self.wy_hit {
+            return None;
+        }
 
-        debug!("LYC interrupt: {}", self.lyc_interrupt);
-        debug!("OAM interrupt: {}", self.oam_interrupt);
-        debug!("VBlank interrupt: {}", self.vblank_interrupt);
-        debug!("HBlank interrupt: {}", self.hblank_interrupt);
+        match self.wx {
+            _ if self.win_wrap => Some((0, 0)),
+            0 => Some((0, self.scx as usize % 8)),
+            wx @ 1..=6 => Some((0, 7 - wx as usize)),
+            wx @ 7..=166 => Some((wx as usize - 7, 0)),
+            _ => None,
+        }
     }
 
-    fn on_read_ctrl(&mut self) -> u8 {
-        let mut v = 0;
-        v |= if self.enable { 0x80 } else { 0x00 };
-        v |= if self.winmap == 0x9c00 { 0x40 } else { 0x00 };
-        v |= if self.winenable { 0x20 } else { 0x00 };
-        v |= if self.tiles == 0x8000 { 0x10 } else { 0x00 };
-        v |= if self.bgmap == 0x9c00 { 0x08 } else { 0x00 };
-        v |= if self.spsize == 16 { 0x04 } else { 0x00 };
-        v |= if self.spenable { 0x02 } else { 0x00 };
-        v |= if self.bgenable { 0x01 } else { 0x00 };
-        v
+    /// Advance the window line counter at the end of mode 3 if the window is shown on the line.
+    fn window_done(&mut self, shown: bool) {
+        if shown {
+            self.wline += 1;
+        }
+        self.win_wrap = shown && self.wx == 166;
     }
 
-    fn on_read_status(&mut self) -> u8 {
-        let mut v = 0;
-        v |= if self.lyc_interrupt { 0x40 } else { 0x00 };
-        v |= if self.oam_interrupt { 0x20 } else { 0x00 };
-        v |= if self.vblank_interrupt { 0x10 } else { 0x00 };
-        v |= if self.hblank_interrupt { 0x08 } else { 0x00 };
-        v |= if self.ly == self.lyc { 0x04 } else { 0x00 };
-        v |= {
-            let p: u8 = self.mode.clone().into();
-            p
-        };
-        trace!("Read Status: {:02x}", v);
-        v
-    }
+    /// Start mode 3 of the pixel FIFO renderer, selecting the sprites on the line.
+    fn fifo_start(&mut self, mmu: &Mmu) {
+        let mut f = self.fifo.take().expect("pixel FIFO is disabled");
 
-    fn read_vram(&self, addr: u16, bank: usize) -> u8 {
-        let off = addr as usize - 0x8000;
-        self.vram[bank][off]
-    }
+        f.restart(false);
+        f.obj.clear();
+        f.discard = self.scx as usize % 8;
+        // The first tile fetch of the line is thrown away
+        f.stall = 6;
+        f.lx = 0;
+        f.elapsed = 0;
 
-    fn write_vram(&mut self, addr: u16, value: u8, bank: usize) {
-        let off = addr as usize - 0x8000;
-        self.vram[bank][off] = value;
+        self.scan_oam(mmu, &mut f.sprites);
+
+        self.fifo = Some(f);
     }
 
-    fn get_tile_base(&self, mapbase: u16, tx: u16, ty: u16) -> u16 {
-        let ti = tx + ty * 32;
-        let num = self.read_vram(mapbase + ti, 0);
+    /// Select the sprites on the current line, up to 10 in the OAM order.
+    fn scan_oam(&mut self, mmu: &Mmu, sprites: &mut Vec<LineSprite>) {
+        sprites.clear();
 
-        if self.tiles == 0x8000 {
-            self.tiles + num as u16 * 16
-        } else {
-            self.tiles + (0x800 + num as i8 as i16 * 16) as u16
-        }
-    }
+        // Read OAM from the memory directly as the CPU may be locked out of it
+        let oam = &mmu.ram()[0xfe00..0xfea0];
 
-    fn get_tile_attr(&self, mapbase: u16, tx: u16, ty: u16) -> MapAttribute {
+        let ly = self.ly as u16;
+        for i in 0..40 {
+            let entry = &oam[i * 4..i * 4 + 4];
+            let ypos = entry[0] as u16;
+            if ly + 16 < ypos || ly + 16 - ypos >= self.spsize {
+                // This sprite doesn't hit the current ly
+                continue;
+            }
+
+            sprites.push(LineSprite {
+                oam: i,
+                ypos,
+                xpos: entry[1] as u16,
+                ti: entry[2],
+                attr: entry[3],
+                fetched: false,
+            });
+            self.oam_selected |= 1 << i;
+
+            if sprites.len() == SPRITES_PER_LINE {
+                break;
+            }
+        }
+    }
+
+    /// Return the low and high bytes of the sprite tile row on the current line.
+    fn sprite_row(&self, sp: &LineSprite) -> (u8, u8) {
+        let tyoff = self.ly as u16 + 16 - sp.ypos;
+        let tyoff = if sp.attr & 0x40 != 0 {
+            self.spsize - 1 - tyoff
+        } else {
+            tyoff
+        };
+        let ti = if self.spsize == 16 {
+            if tyoff >= 8 {
+                sp.ti | 1
+            } else {
+                sp.ti & 0xfe
+            }
+        } else {
+            sp.ti
+        };
+        let tbase = 0x8000 + ti as u16 * 16 + (tyoff % 8) * 2;
+        let bank = if cfg!(feature = "color") {
+            (sp.attr as usize >> 3) & 1
+        } else {
+            0
+        };
+
+        (self.read_vram(tbase, bank), self.read_vram(tbase + 1, bank))
+    }
+
+    /// Run the pixel FIFO for `time` dots. Returns `true` once the whole line is pushed to the LCD.
+    fn fifo_run(&mut self, time: usize) -> bool {
+        let mut f = self.fifo.take().expect("pixel FIFO is disabled");
+
+        for _ in 0..time {
+            if f.lx >= VRAM_WIDTH {
+                break;
+            }
+            self.fifo_tick(&mut f);
+        }
+
+        let done = f.lx >= VRAM_WIDTH;
+        if done {
+            self.window_done(f.window);
+            // Mode 0 takes the rest of the 376 dots after OAM scan
+            self.hblank_len = 376usize.saturating_sub(f.elapsed);
+        }
+
+        self.fifo = Some(f);
+
+        done
+    }
+
+    fn fifo_tick(&mut self, f: &mut Fifo) {
+        f.elapsed += 1;
+
+        if f.stall > 0 {
+            f.stall -= 1;
+            return;
+        }
+
+        if !f.window {
+            if let Some((start, cut)) = self.window_x() {
+                if f.lx >= start {
+                    f.restart(true);
+                    f.discard = cut;
+                    return;
+                }
+            }
+        }
+
+        if self.spenable && f.discard == 0 && !f.bg.is_empty() {
+            let lx = f.lx as u16;
+            if let Some(i) = f
+                .sprites
+                .iter()
+                .position(|s| !s.fetched && s.xpos <= lx + 8)
+            {
+                f.sprites[i].fetched = true;
+                self.fetch_sprite(f, i);
+                f.stall = 5;
+                return;
+            }
+        }
+
+        self.fetch_tick(f);
+
+        if let Some(bg) = f.bg.pop_front() {
+            if f.discard > 0 {
+                f.discard -= 1;
+                return;
+            }
+
+            let obj = f.obj.pop_front();
+            self.line[f.lx] = self.fifo_pixel(bg, obj);
+            f.lx += 1;
+        }
+    }
+
+    fn fetch_tick(&self, f: &mut Fifo) {
+        f.dots += 1;
+
+        match f.step {
+            FetchStep::Tile => {
+                if f.dots >= 2 {
+                    let (mapbase, tx, yy) = if f.window {
+                        (self.winmap, f.fetch_x % 32, self.wline)
+                    } else {
+                        let tx = (f.fetch_x + self.scx as u16 / 8) % 32;
+                        (self.bgmap, tx, (self.ly as u16 + self.scy as u16) % 256)
+                    };
+                    let ty = yy / 8;
+
+                    f.tile = self.get_tile_base(mapbase, tx, ty);
+                    f.attr = if cfg!(feature = "color") {
+                        self.read_vram(mapbase + tx + ty * 32, 1)
+                    } else {
+                        0
+                    };
+                    f.row = if f.attr & 0x40 != 0 {
+                        7 - yy % 8
+                    } else {
+                        yy % 8
+                    };
+                    f.step = FetchStep::Low;
+                    f.dots = 0;
+                }
+            }
+            FetchStep::Low => {
+                if f.dots >= 2 {
+                    let bank = (f.attr as usize >> 3) & 1;
+                    f.low = self.read_vram(f.tile + f.row * 2, bank);
+                    f.step = FetchStep::High;
+                    f.dots = 0;
+                }
+            }
+            FetchStep::High => {
+                if f.dots >= 2 {
+                    let bank = (f.attr as usize >> 3) & 1;
+                    f.high = self.read_vram(f.tile + f.row * 2 + 1, bank);
+                    f.step = FetchStep::Push;
+                    f.dots = 0;
+                }
+            }
+            FetchStep::Push => {
+                if f.bg.is_empty() {
+                    let bg_on = cfg!(feature = "color") || self.bgenable;
+                    let xflip = f.attr & 0x20 != 0;
+
+                    for i in 0..8 {
+                        let bit = if xflip { i } else { 7 - i };
+                        let coli = ((f.high >> bit) & 1) << 1 | (f.low >> bit) & 1;
+
+                        f.bg.push_back(BgPixel {
+                            coli: if bg_on { coli as usize } else { 0 },
+                            palette: f.attr as usize & 0x7,
+                            priority: f.attr & 0x80 != 0,
+                        });
+                    }
+
+                    f.fetch_x += 1;
+                    f.step = FetchStep::Tile;
+                    f.dots = 0;
+                }
+            }
+        }
+    }
+
+    /// Fetch the row of the sprite on the current line and mix it into the object FIFO.
+    fn fetch_sprite(&self, f: &mut Fifo, i: usize) {
+        let sp = &f.sprites[i];
+
+        let (low, high) = self.sprite_row(sp);
+        let palette = if cfg!(feature = "color") {
+            sp.attr as usize & 0x7
+        } else {
+            (sp.attr as usize >> 4) & 1
+        };
+        let xflip = sp.attr & 0x20 != 0;
+        let priority = sp.attr & 0x80 != 0;
+
+        // Skip the pixels left of the current position
+        let skip = (f.lx as u16 + 8 - sp.xpos) as usize;
+
+        while f.obj.len() < 8 {
+            f.obj.push_back(ObjPixel {
+                coli: 0,
+                palette: 0,
+                priority: false,
+                oam: 0,
+            });
+        }
+
+        for x in skip..8 {
+            let bit = if xflip { x } else { 7 - x };
+            let coli = (((high >> bit) & 1) << 1 | (low >> bit) & 1) as usize;
+
+            // On DMG, the sprite fetched earlier wins as it has the smaller x coordinate.
+            // On CGB, the sprite first in OAM wins.
+            let slot = &mut f.obj[x - skip];
+            let wins = slot.coli == 0 || cfg!(feature = "color") && sp.oam < slot.oam;
+            if coli != 0 && wins {
+                *slot = ObjPixel {
+                    coli,
+                    palette,
+                    priority,
+                    oam: sp.oam,
+                };
+            }
+        }
+    }
+
+    fn fifo_pixel(&self, bg: BgPixel, obj: Option<ObjPixel>) -> u32 {
+        let color = cfg!(feature = "color");
+
+        let bgcol = if color {
+            self.bg_color_palette.cols[bg.palette][bg.coli]
+        } else {
+            self.bg_palette[bg.coli]
+        };
+
+        match obj {
+            Some(obj) if obj.coli != 0 && !((obj.priority || bg.priority) && bg.coli != 0) => {
+                let col = if color {
+                    self.obj_color_palette.cols[obj.palette][obj.coli]
+                } else if obj.palette == 0 {
+                    self.obj_palette0[obj.coli]
+                } else {
+                    self.obj_palette1[obj.coli]
+                };
+                self.pixel(col)
+            }
+            _ => self.pixel(bgcol),
+        }
+    }
+
+    fn on_write_ctrl(&mut self, value: u8) {
+        let old_enable = self.enable;
+
+        self.enable = value & 0x80 != 0;
+        self.winmap = if value & 0x40 != 0 { 0x9c00 } else { 0x9800 };
+        self.winenable = value & 0x20 != 0;
+        self.tiles = if value & 0x10 != 0 { 0x8000 } else { 0x8800 };
+        self.bgmap = if value & 0x08 != 0 { 0x9c00 } else { 0x9800 };
+        self.spsize = if value & 0x04 != 0 { 16 } else { 8 };
+        self.spenable = value & 0x02 != 0;
+        self.bgenable = value & 0x01 != 0;
+
+        if !old_enable && self.enable {
+            info!("LCD enabled");
+            self.clocks = 0;
+            self.wy_hit = false;
+            self.wline = 0;
+            self.win_wrap = false;
+            self.mode = Mode::OAM;
+            self.blank = false;
+            self.irq.vblank(false);
+        } else if old_enable && !self.enable {
+            info!("LCD disabled");
+            self.clocks = 0;
+            self.ly = 0;
+            self.mode = Mode::None;
+            self.stat_line = false;
+            self.blank = true;
+            self.irq.vblank(false);
+        }
+
+        debug!("Write ctrl: {:02x}", value);
+        debug!("Window base: {:04x}", self.winmap);
+        debug!("Window enable: {}", self.winenable);
+        debug!("Bg/window base: {:04x}", self.tiles);
+        debug!("Background base: {:04x}", self.bgmap);
+        debug!("Sprite size: 8x{}", self.spsize);
+        debug!("Sprite enable: {}", self.spenable);
+        debug!("Background enable: {}", self.bgenable);
+    }
+
+    fn on_write_status(&mut self, value: u8) {
+        self.lyc_interrupt = value & 0x40 != 0;
+        self.oam_interrupt = value & 0x20 != 0;
+        self.vblank_interrupt = value & 0x10 != 0;
+        self.hblank_interrupt = value & 0x08 != 0;
+
+        debug!("LYC interrupt: {}", self.lyc_interrupt);
+        debug!("OAM interrupt: {}", self.oam_interrupt);
+        debug!("VBlank interrupt: {}", self.vblank_interrupt);
+        debug!("HBlank interrupt: {}", self.hblank_interrupt);
+
+        self.update_stat_line();
+    }
+
+    fn on_read_ctrl(&mut self) -> u8 {
+        let mut v = 0;
+        v |= if self.enable { 0x80 } else { 0x00 };
+        v |= if self.winmap == 0x9c00 { 0x40 } else { 0x00 };
+        v |= if self.winenable { 0x20 } else { 0x00 };
+        v |= if self.tiles == 0x8000 { 0x10 } else { 0x00 };
+        v |= if self.bgmap == 0x9c00 { 0x08 } else { 0x00 };
+        v |= if self.spsize == 16 { 0x04 } else { 0x00 };
+        v |= if self.spenable { 0x02 } else { 0x00 };
+        v |= if self.bgenable { 0x01 } else { 0x00 };
+        v
+    }
+
+    fn on_read_status(&mut self) -> u8 {
+        let mut v = 0;
+        v |= if self.lyc_interrupt { 0x40 } else { 0x00 };
+        v |= if self.oam_interrupt { 0x20 } else { 0x00 };
+        v |= if self.vblank_interrupt { 0x10 } else { 0x00 };
+        v |= if self.hblank_interrupt { 0x08 } else { 0x00 };
+        v |= if self.ly == self.lyc { 0x04 } else { 0x00 };
+        v |= {
+            let p: u8 = self.mode.clone().into();
+            p
+        };
+        trace!("Read Status: {:02x}", v);
+        v
+    }
+
+    /// Corrupt the OAM row being scanned as the DMG does when the CPU
+    /// puts an address in `FE00-FEFF` on the bus during mode 2.
+    pub fn corrupt_oam(&self, mmu: &mut Mmu) {
+        if self.mode != Mode::OAM {
+            return;
+        }
+
+        // The GPU reads a row of 8 bytes every 4 clocks, and the first row isn't affected
+        let row = self.clocks / 4;
+        if row == 0 || row >= 20 {
+            return;
+        }
+
+        let oam = &mut mmu.ram_mut()[0xfe00..0xfea0];
+        let word = |oam: &[u8], i: usize| oam[i] as u16 | (oam[i + 1] as u16) << 8;
+
+        let cur = row * 8;
+        let prev = cur - 8;
+        let a = word(oam, cur);
+        let b = word(oam, prev);
+        let c = word(oam, prev + 4);
+        let v = ((a ^ c) & (b ^ c)) ^ c;
+
+        oam[cur] = v as u8;
+        oam[cur + 1] = (v >> 8) as u8;
+        for i in 2..8 {
+            oam[cur + i] = oam[prev + i];
+        }
+    }
+
+    /// Accessing `FE00-FEFF` during mode 2 corrupts OAM on DMG.
+    fn touch_oam(&mut self) {
+        if self.oam_bug && self.mode == Mode::OAM {
+            self.oam_corrupt = true;
+        }
+    }
+
+    /// Read from the unusable region `FEA0-FEFF`.
+    fn read_unusable(&self, addr: u16) -> u8 {
+        if cfg!(feature = "color") {
+            // CGB (revision E) returns the high nibble of the address twice, e.g. `0xaa` for `FEAx`
+            let n = (addr >> 4) as u8 & 0xf;
+            n << 4 | n
+        } else if self.oam_locked() {
+            0xff
+        } else {
+            0x00
+        }
+    }
+
+    /// The CPU can't access VRAM while the GPU is reading it in mode 3.
+    fn vram_locked(&self) -> bool {
+        self.mode == Mode::VRAM
+    }
+
+    /// The CPU can't access OAM while the GPU is reading it in mode 2 and 3.
+    fn oam_locked(&self) -> bool {
+        self.mode == Mode::OAM || self.mode == Mode::VRAM
+    }
+
+    fn read_vram(&self, addr: u16, bank: usize) -> u8 {
+        let off = addr as usize - 0x8000;
+        self.vram.bank(bank)[off]
+    }
+
+    fn write_vram(&mut self, addr: u16, value: u8, bank: usize) {
+        let off = addr as usize - 0x8000;
+        self.vram.bank_mut(bank)[off] = value;
+    }
+
+    fn get_tile_base(&self, mapbase: u16, tx: u16, ty: u16) -> u16 {
+        let ti = tx + ty * 32;
+        let num = self.read_vram(mapbase + ti, 0);
+
+        if self.tiles == 0x8000 {
+            self.tiles + num as u16 * 16
+        } else {
+            self.tiles + (0x800 + num as i8 as i16 * 16) as u16
+        }
+    }
+
+    fn get_tile_attr(&self, mapbase: u16, tx: u16, ty: u16) -> MapAttribute {
         if cfg!(feature = "color") {
             let ti = tx + ty * 32;
             let attr = self.read_vram(mapbase + ti, 1) as usize;
@@ -728,7 +1613,7 @@ impl Gpu {
             }
         } else {
             MapAttribute {
-                palette: &self.bg_palette,
+                palette: &self.bg_palette[..],
                 vram_bank: 0,
                 xflip: false,
                 yflip: false,
@@ -765,6 +1650,145 @@ impl Gpu {
         }
     }
 
+    /// Return the palette registers and the colors of the palettes.
+    pub fn palettes(&self) -> Palettes {
+        let rgb = |p: &[Color]| {
+            let mut cols = [0; 4];
+            for (c, p) in cols.iter_mut().zip(p) {
+                *c = p.rgb888(&self.shades);
+            }
+            cols
+        };
+
+        let mut bg = [[0; 4]; 8];
+        let mut obj = [[0; 4]; 8];
+        if cfg!(feature = "color") {
+            for i in 0..8 {
+                bg[i] = rgb(&self.bg_color_palette.cols[i]);
+                obj[i] = rgb(&self.obj_color_palette.cols[i]);
+            }
+        } else {
+            bg[0] = rgb(&self.bg_palette);
+            obj[0] = rgb(&self.obj_palette0);
+            obj[1] = rgb(&self.obj_palette1);
+        }
+
+        Palettes {
+            bgp: from_palette(&self.bg_palette),
+            obp0: from_palette(&self.obj_palette0),
+            obp1: from_palette(&self.obj_palette1),
+            bg,
+            obj,
+        }
+    }
+
+    /// Decode all the entries in `oam`, in `0xRRGGBB`.
+    pub fn debug_sprites(&self, oam: &[u8]) -> Vec<Sprite> {
+        oam.chunks(4)
+            .take(40)
+            .enumerate()
+            .map(|(index, entry)| {
+                let attr = self.get_sp_attr(entry[3]);
+                let palette = if cfg!(feature = "color") {
+                    entry[3] as usize & 0x7
+                } else {
+                    (entry[3] as usize >> 4) & 1
+                };
+                let tile = if self.spsize == 16 {
+                    entry[2] & 0xfe
+                } else {
+                    entry[2]
+                };
+
+                let mut pixels = Vec::with_capacity(8 * self.spsize as usize);
+                for y in 0..self.spsize {
+                    let tyoff = if attr.yflip { self.spsize - 1 - y } else { y };
+                    for x in 0..8 {
+                        let txoff = if attr.xflip { 7 - x } else { x };
+                        let tbase = 0x8000 + tile as u16 * 16 + tyoff / 8 * 16;
+                        let coli = self.get_tile_byte(tbase, txoff, tyoff % 8, attr.vram_bank);
+                        pixels.push(if coli == 0 {
+                            None
+                        } else {
+                            Some(attr.palette[coli].rgb888(&self.shades))
+                        });
+                    }
+                }
+
+                Sprite {
+                    index,
+                    x: entry[1],
+                    y: entry[0],
+                    tile: entry[2],
+                    attr: entry[3],
+                    palette,
+                    bank: attr.vram_bank,
+                    xflip: attr.xflip,
+                    yflip: attr.yflip,
+                    behind_bg: attr.priority,
+                    visible: self.oam_visible & (1 << index) != 0,
+                    pixels,
+                }
+            })
+            .collect()
+    }
+
+    /// Decode all the tiles in both the VRAM banks with the BG palette 0, in `0xRRGGBB`.
+    pub fn debug_tiles(&self) -> Vec<u32> {
+        let palette = if cfg!(feature = "color") {
+            &self.bg_color_palette.cols[0][..]
+        } else {
+            &self.bg_palette[..]
+        };
+        let mut buf = vec![0; TILES_WIDTH * TILES_HEIGHT];
+
+        for bank in 0..2 {
+            for i in 0..384 {
+                let tbase = 0x8000 + i as u16 * 16;
+                let left = (bank * 16 + i % 16) * 8;
+                let top = i / 16 * 8;
+
+                for y in 0..8 {
+                    for x in 0..8 {
+                        let coli = self.get_tile_byte(tbase, x as u16, y as u16, bank);
+                        buf[(top + y) * TILES_WIDTH + left + x] =
+                            palette[coli].rgb888(&self.shades);
+                    }
+                }
+            }
+        }
+
+        buf
+    }
+
+    /// Decode the whole background map with the current tile addressing and the CGB attributes, in `0xRRGGBB`.
+    pub fn debug_bg_map(&self, map: TileMap) -> Vec<u32> {
+        let mapbase = match map {
+            TileMap::Low => 0x9800,
+            TileMap::High => 0x9c00,
+        };
+        let mut buf = vec![0; MAP_SIZE * MAP_SIZE];
+
+        for ty in 0..32 {
+            for tx in 0..32 {
+                let tbase = self.get_tile_base(mapbase, tx, ty);
+                let tattr = self.get_tile_attr(mapbase, tx, ty);
+
+                for y in 0..8 {
+                    for x in 0..8 {
+                        let tyoff = if tattr.yflip { 7 - y } else { y };
+                        let txoff = if tattr.xflip { 7 - x } else { x };
+                        let coli = self.get_tile_byte(tbase, txoff, tyoff, tattr.vram_bank);
+                        buf[(ty * 8 + y) as usize * MAP_SIZE + (tx * 8 + x) as usize] =
+                            tattr.palette[coli].rgb888(&self.shades);
+                    }
+                }
+            }
+        }
+
+        buf
+    }
+
     fn get_tile_byte(&self, tilebase: u16, txoff: u16, tyoff: u16, bank: usize) -> usize {
         let l = self.read_vram(tilebase + tyoff * 2, bank);
         let h = self.read_vram(tilebase + tyoff * 2 + 1, bank);
@@ -776,10 +1800,367 @@ impl Gpu {
     }
 }
 
-impl IoHandler for Gpu {
+impl State for Mode {
+    fn save(&self, w: &mut Writer) {
+        let tag: u8 = match self {
+            Mode::OAM => 0,
+            Mode::VRAM => 1,
+            Mode::HBlank => 2,
+            Mode::VBlank => 3,
+            Mode::None => 4,
+        };
+        tag.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        *self = match r.tag("GPU mode", 5)? {
+            0 => Mode::OAM,
+            1 => Mode::VRAM,
+            2 => Mode::HBlank,
+            3 => Mode::VBlank,
+            _ => Mode::None,
+        };
+        Ok(())
+    }
+}
+
+impl State for Color {
+    fn save(&self, w: &mut Writer) {
+        match *self {
+            Color::Rgb(r, g, b) => [4, r, g, b][..].save(w),
+            c => u8::from(c).save(w),
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        *self = match r.tag("color", 5)? {
+            4 => {
+                let mut rgb = [0u8; 3];
+                rgb[..].load(r)?;
+                Color::Rgb(rgb[0], rgb[1], rgb[2])
+            }
+            c => Color::from(c),
+        };
+        Ok(())
+    }
+}
+
+impl State for ColorPalette {
+    fn save(&self, w: &mut Writer) {
+        for cols in &self.cols {
+            cols[..].save(w);
+        }
+        self.index.save(w);
+        self.auto_inc.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        for cols in &mut self.cols {
+            cols[..].load(r)?;
+        }
+        self.index = r.bounded("palette index", 0x3f)?;
+        self.auto_inc.load(r)?;
+        Ok(())
+    }
+}
+
+impl State for Hdma {
+    fn save(&self, w: &mut Writer) {
+        self.on.save(w);
+        self.src_low.save(w);
+        self.src_high.save(w);
+        self.dst_low.save(w);
+        self.dst_high.save(w);
+        self.src_wip.save(w);
+        self.dst_wip.save(w);
+        self.len.save(w);
+        self.hblank.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.on.load(r)?;
+        self.src_low.load(r)?;
+        self.src_high.load(r)?;
+        self.dst_low.load(r)?;
+        self.dst_high.load(r)?;
+        self.src_wip.load(r)?;
+        self.dst_wip.load(r)?;
+        self.len.load(r)?;
+        self.hblank.load(r)?;
+        Ok(())
+    }
+}
+
+impl State for BgPixel {
+    fn save(&self, w: &mut Writer) {
+        self.coli.save(w);
+        self.palette.save(w);
+        self.priority.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.coli.load(r)?;
+        self.palette.load(r)?;
+        self.priority.load(r)?;
+        Ok(())
+    }
+}
+
+impl State for ObjPixel {
+    fn save(&self, w: &mut Writer) {
+        self.coli.save(w);
+        self.palette.save(w);
+        self.priority.save(w);
+        self.oam.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.coli.load(r)?;
+        self.palette.load(r)?;
+        self.priority.load(r)?;
+        self.oam.load(r)?;
+        Ok(())
+    }
+}
+
+impl State for LineSprite {
+    fn save(&self, w: &mut Writer) {
+        self.oam.save(w);
+        self.ypos.save(w);
+        self.xpos.save(w);
+        self.ti.save(w);
+        self.attr.save(w);
+        self.fetched.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.oam.load(r)?;
+        self.ypos.load(r)?;
+        self.xpos.load(r)?;
+        self.ti.load(r)?;
+        self.attr.load(r)?;
+        self.fetched.load(r)?;
+        Ok(())
+    }
+}
+
+impl State for FetchStep {
+    fn save(&self, w: &mut Writer) {
+        let tag: u8 = match self {
+            FetchStep::Tile => 0,
+            FetchStep::Low => 1,
+            FetchStep::High => 2,
+            FetchStep::Push => 3,
+        };
+        tag.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        *self = match r.tag("fetch step", 4)? {
+            0 => FetchStep::Tile,
+            1 => FetchStep::Low,
+            2 => FetchStep::High,
+            _ => FetchStep::Push,
+        };
+        Ok(())
+    }
+}
+
+impl State for Fifo {
+    fn save(&self, w: &mut Writer) {
+        self.bg.save(w);
+        self.obj.save(w);
+        self.step.save(w);
+        self.dots.save(w);
+        self.fetch_x.save(w);
+        self.tile.save(w);
+        self.attr.save(w);
+        self.row.save(w);
+        self.low.save(w);
+        self.high.save(w);
+        self.window.save(w);
+        self.discard.save(w);
+        self.stall.save(w);
+        self.lx.save(w);
+        self.elapsed.save(w);
+        self.sprites.len().save(w);
+        self.sprites[..].save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.bg.load(r)?;
+        self.obj.load(r)?;
+        self.step.load(r)?;
+        self.dots.load(r)?;
+        self.fetch_x.load(r)?;
+        self.tile.load(r)?;
+        self.attr.load(r)?;
+        self.row.load(r)?;
+        self.low.load(r)?;
+        self.high.load(r)?;
+        self.window.load(r)?;
+        self.discard.load(r)?;
+        self.stall.load(r)?;
+        self.lx.load(r)?;
+        self.elapsed.load(r)?;
+
+        let mut len = 0usize;
+        len.load(r)?;
+        if len > SPRITES_PER_LINE {
+            return Err(Error::InvalidState(format!("{} sprites on a line", len)));
+        }
+        self.sprites.clear();
+        self.sprites.resize_with(len, LineSprite::default);
+        self.sprites[..].load(r)?;
+        Ok(())
+    }
+}
+
+impl<'a> Banked for Gpu<'a> {
+    fn bank(&self, bank: usize) -> &[u8] {
+        self.vram.bank(bank)
+    }
+}
+
+/// The registers, the memory and the timing of the GPU; the screen output is not saved
+/// except the part of the current line drawn before the register writes in mode 3.
+impl<'a> State for Gpu<'a> {
+    fn save(&self, w: &mut Writer) {
+        self.clocks.save(w);
+        self.lyc_interrupt.save(w);
+        self.oam_interrupt.save(w);
+        self.vblank_interrupt.save(w);
+        self.hblank_interrupt.save(w);
+        self.stat_line.save(w);
+        self.mode.save(w);
+        self.ly.save(w);
+        self.lyc.save(w);
+        self.scy.save(w);
+        self.scx.save(w);
+        self.wx.save(w);
+        self.wy.save(w);
+        self.enable.save(w);
+        self.winmap.save(w);
+        self.winenable.save(w);
+        self.tiles.save(w);
+        self.bgmap.save(w);
+        self.spsize.save(w);
+        self.spenable.save(w);
+        self.bgenable.save(w);
+        self.bg_palette[..].save(w);
+        self.obj_palette0[..].save(w);
+        self.obj_palette1[..].save(w);
+        self.bg_color_palette.save(w);
+        self.obj_color_palette.save(w);
+        self.vram.save(w);
+        self.vram_select.save(w);
+        self.hdma.save(w);
+        self.blank.save(w);
+        self.fifo.is_some().save(w);
+        if let Some(fifo) = &self.fifo {
+            fifo.save(w);
+        }
+        self.oam_corrupt.save(w);
+        self.hblank_len.save(w);
+        self.wy_hit.save(w);
+        self.wline.save(w);
+        self.win_wrap.save(w);
+        self.drawn.save(w);
+        self.line[..self.drawn].save(w);
+        self.bgline[..self.drawn].save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.clocks.load(r)?;
+        self.lyc_interrupt.load(r)?;
+        self.oam_interrupt.load(r)?;
+        self.vblank_interrupt.load(r)?;
+        self.hblank_interrupt.load(r)?;
+        self.stat_line.load(r)?;
+        self.mode.load(r)?;
+        self.ly.load(r)?;
+        self.lyc.load(r)?;
+        self.scy.load(r)?;
+        self.scx.load(r)?;
+        self.wx.load(r)?;
+        self.wy.load(r)?;
+        self.enable.load(r)?;
+        self.winmap.load(r)?;
+        self.winenable.load(r)?;
+        self.tiles.load(r)?;
+        self.bgmap.load(r)?;
+        self.spsize.load(r)?;
+        self.spenable.load(r)?;
+        self.bgenable.load(r)?;
+        self.bg_palette[..].load(r)?;
+        self.obj_palette0[..].load(r)?;
+        self.obj_palette1[..].load(r)?;
+        self.bg_color_palette.load(r)?;
+        self.obj_color_palette.load(r)?;
+        self.vram.load(r)?;
+        self.vram_select = r.bounded("VRAM bank", 1)?;
+        self.hdma.load(r)?;
+        self.blank.load(r)?;
+
+        let mut fifo = false;
+        fifo.load(r)?;
+        match (fifo, &mut self.fifo) {
+            (true, Some(f)) => f.load(r)?,
+            (false, None) => {}
+            _ => {
+                return Err(Error::InvalidState(
+                    "saved with a different pixel FIFO setting".into(),
+                ))
+            }
+        }
+
+        self.oam_corrupt.load(r)?;
+        self.hblank_len.load(r)?;
+        self.wy_hit.load(r)?;
+        self.wline.load(r)?;
+        if r.version() >= 4 {
+            self.win_wrap.load(r)?;
+        } else {
+            self.win_wrap = false;
+        }
+        if r.version() >= 5 {
+            self.drawn.load(r)?;
+            if self.drawn > VRAM_WIDTH {
+                return Err(Error::InvalidState(format!(
+                    "{} pixels drawn on a line",
+                    self.drawn
+                )));
+            }
+            self.line[..self.drawn].load(r)?;
+            self.bgline[..self.drawn].load(r)?;
+        } else {
+            self.drawn = 0;
+        }
+        self.pending = None;
+        Ok(())
+    }
+}
+
+impl<'a> IoHandler for Gpu<'a> {
     fn on_read(&mut self, _mmu: &Mmu, addr: u16) -> MemRead {
         if addr >= 0x8000 && addr <= 0x9fff {
-            MemRead::Replace(self.read_vram(addr, self.vram_select))
+            if self.vram_locked() {
+                MemRead::Replace(0xff)
+            } else {
+                MemRead::Replace(self.read_vram(addr, self.vram_select))
+            }
+        } else if (0xfe00..=0xfe9f).contains(&addr) {
+            self.touch_oam();
+
+            if self.oam_locked() {
+                MemRead::Replace(0xff)
+            } else {
+                MemRead::PassThrough
+            }
+        } else if (0xfea0..=0xfeff).contains(&addr) {
+            self.touch_oam();
+
+            MemRead::Replace(self.read_unusable(addr))
         } else if addr == 0xff40 {
             MemRead::Replace(self.on_read_ctrl())
         } else if addr == 0xff41 {
@@ -796,13 +2177,13 @@ impl IoHandler for Gpu {
             unreachable!("DMA request")
         } else if addr == 0xff47 {
             debug!("Read Bg palette");
-            MemRead::Replace(from_palette(self.bg_palette.clone()))
+            MemRead::Replace(from_palette(&self.bg_palette))
         } else if addr == 0xff48 {
             debug!("Read Object palette 0");
-            MemRead::Replace(from_palette(self.obj_palette0.clone()))
+            MemRead::Replace(from_palette(&self.obj_palette0))
         } else if addr == 0xff49 {
             debug!("Read Object palette 1");
-            MemRead::Replace(from_palette(self.obj_palette1.clone()))
+            MemRead::Replace(from_palette(&self.obj_palette1))
         } else if addr == 0xff4a {
             MemRead::Replace(self.wy)
         } else if addr == 0xff4b {
@@ -838,8 +2219,25 @@ impl IoHandler for Gpu {
 
     fn on_write(&mut self, _mmu: &Mmu, addr: u16, value: u8) -> MemWrite {
         trace!("Write GPU register: {:04x} {:02x}", addr, value);
+        if matches!(addr, 0xff40 | 0xff42 | 0xff43 | 0xff47 | 0xff4b | 0xff69) {
+            self.split_line();
+        }
+
         if addr >= 0x8000 && addr <= 0x9fff {
-            self.write_vram(addr, value, self.vram_select);
+            if !self.vram_locked() {
+                self.write_vram(addr, value, self.vram_select);
+            }
+        } else if (0xfe00..=0xfe9f).contains(&addr) {
+            self.touch_oam();
+
+            if self.oam_locked() {
+                return MemWrite::Block;
+            }
+        } else if (0xfea0..=0xfeff).contains(&addr) {
+            self.touch_oam();
+
+            // The region isn't backed by memory
+            return MemWrite::Block;
         } else if addr == 0xff40 {
             self.on_write_ctrl(value);
         } else if addr == 0xff41 {
@@ -853,6 +2251,7 @@ impl IoHandler for Gpu {
             self.ly = 0;
         } else if addr == 0xff45 {
             self.lyc = value;
+            self.update_stat_line();
         } else if addr == 0xff46 {
             unreachable!("Request DMA: {:02x}", value);
         } else if addr == 0xff47 {
@@ -900,3 +2299,417 @@ impl IoHandler for Gpu {
         MemWrite::PassThrough
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::hardware::NullHardware;
+    use crate::ic::Ic;
+
+    fn fifo_gpu() -> Gpu<'static> {
+        let cfg = Config::new().pixel_fifo(true);
+        Gpu::new(HardwareHandle::new(NullHardware), Ic::new().irq(), &cfg)
+    }
+
+    /// Count the dots spent in mode 3 of the first line.
+    fn mode3_len(gpu: &mut Gpu, mmu: &mut Mmu) -> usize {
+        while gpu.mode != Mode::VRAM {
+            gpu.step(1, mmu);
+        }
+
+        let mut dots = 0;
+        while gpu.mode == Mode::VRAM {
+            gpu.step(1, mmu);
+            dots += 1;
+        }
+        dots
+    }
+
+    #[test]
+    fn fifo_mode3_length() {
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+
+        let mut gpu = fifo_gpu();
+        gpu.on_write_ctrl(0x91);
+        assert_eq!(mode3_len(&mut gpu, &mut mmu), 172);
+
+        // Fine scroll discards the pixels at the beginning of the line
+        let mut gpu = fifo_gpu();
+        gpu.scx = 3;
+        gpu.on_write_ctrl(0x91);
+        assert_eq!(mode3_len(&mut gpu, &mut mmu), 175);
+        assert_eq!(gpu.hblank_len, 376 - 175);
+    }
+
+    #[test]
+    fn fifo_sprite_penalty() {
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        mmu.set8(0xfe00, 16);
+        mmu.set8(0xfe01, 8);
+
+        let mut gpu = fifo_gpu();
+        gpu.on_write_ctrl(0x93);
+        assert_eq!(mode3_len(&mut gpu, &mut mmu), 178);
+    }
+
+    #[test]
+    fn window_line_counter() {
+        for cfg in &[Config::new(), Config::new().pixel_fifo(true)] {
+            let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+            let mut gpu = Gpu::new(HardwareHandle::new(NullHardware), Ic::new().irq(), cfg);
+            gpu.wx = 7;
+            gpu.on_write_ctrl(0xb1);
+
+            let mut run_lines = |gpu: &mut Gpu, n: u8| {
+                let end = (gpu.ly + n) % 154;
+                while gpu.ly != end {
+                    gpu.step(4, &mut mmu);
+                }
+            };
+
+            run_lines(&mut gpu, 10);
+            assert_eq!(gpu.wline, 10);
+
+            // Disabled mid-frame, the window resumes from the line it left off
+            gpu.on_write_ctrl(0x91);
+            run_lines(&mut gpu, 10);
+            gpu.on_write_ctrl(0xb1);
+            run_lines(&mut gpu, 5);
+            assert_eq!(gpu.wline, 15);
+
+            // Once triggered, moving WY doesn't hide the window until the next frame
+            gpu.wy = 100;
+            run_lines(&mut gpu, 1);
+            assert_eq!(gpu.wline, 16);
+            gpu.wx = 167;
+            run_lines(&mut gpu, 1);
+            assert_eq!(gpu.wline, 16);
+
+            // WX=166 spans the whole next line
+            gpu.wx = 166;
+            assert_eq!(gpu.window_x(), Some((159, 0)));
+            run_lines(&mut gpu, 1);
+            assert_eq!(gpu.window_x(), Some((0, 0)));
+            gpu.wx = 3;
+            run_lines(&mut gpu, 1);
+            assert_eq!(gpu.wline, 18);
+
+            // WX below 7 cuts off the window, while WX=0 shifts it by the fine scroll
+            assert_eq!(gpu.window_x(), Some((0, 4)));
+            gpu.wx = 0;
+            gpu.scx = 3;
+            assert_eq!(gpu.window_x(), Some((0, 3)));
+
+            // The counter starts over in the next frame
+            let rest = 154 - gpu.ly;
+            run_lines(&mut gpu, rest);
+            assert_eq!((gpu.wline, gpu.window_x()), (0, None));
+        }
+    }
+
+    #[test]
+    fn mid_line_writes() {
+        for (cfg, split) in &[
+            (Config::new(), 80),
+            (Config::new().mid_line_writes(16), 80),
+            (Config::new().mid_line_writes(24), 72),
+            (Config::new().mid_line_writes(0), 0),
+        ] {
+            let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+            let mut gpu = Gpu::new(HardwareHandle::new(NullHardware), Ic::new().irq(), cfg);
+            gpu.on_write(&mmu, 0xff47, 0xe4);
+            gpu.bg_color_palette.select(0x86);
+            gpu.bg_color_palette.write(0xff);
+            gpu.bg_color_palette.write(0x7f);
+            // The tile 1 of color 3 on the fifth row of the map
+            (0..16).for_each(|i| gpu.write_vram(0x8010 + i, 0xff, 0));
+            (0..32).for_each(|i| gpu.write_vram(0x9880 + i, 1, 0));
+            gpu.on_write_ctrl(0x91);
+
+            while gpu.mode != Mode::VRAM {
+                gpu.step(4, &mut mmu);
+            }
+            // Scroll down to the fifth row at the pixel 80
+            while gpu.clocks < 12 + 80 {
+                gpu.step(4, &mut mmu);
+            }
+            gpu.on_write(&mmu, 0xff42, 32);
+
+            // The part drawn before the write survives the savestate
+            let mut w = Writer::new();
+            gpu.save(&mut w);
+            let state = w.finish();
+            let mut gpu = Gpu::new(HardwareHandle::new(NullHardware), Ic::new().irq(), cfg);
+            gpu.load(&mut Reader::new(&state)).unwrap();
+
+            while gpu.mode == Mode::VRAM {
+                gpu.step(4, &mut mmu);
+            }
+
+            // Color 0 of the first row to the left, color 3 of the fifth row to the right
+            let c0 = if cfg!(feature = "color") {
+                gpu.bg_color_palette.cols[0][0]
+            } else {
+                gpu.bg_palette[0]
+            };
+            let (c0, c3) = (gpu.pixel(c0), gpu.line[159]);
+            assert_ne!(c0, c3);
+            assert!(gpu.line[..*split].iter().all(|p| *p == c0));
+            assert!(gpu.line[*split..].iter().all(|p| *p == c3));
+        }
+    }
+
+    #[test]
+    fn sprite_limit_per_line() {
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        for i in 0..12 {
+            mmu.set8(0xfe00 + i * 4, 16);
+            mmu.set8(0xfe00 + i * 4 + 1, 8 + i as u8);
+        }
+        // Not on the line
+        mmu.set8(0xfe00, 40);
+
+        let mut gpu = fifo_gpu();
+        let mut sprites = vec![];
+        gpu.scan_oam(&mmu, &mut sprites);
+
+        let oam: Vec<usize> = sprites.iter().map(|sp| sp.oam).collect();
+        assert_eq!(oam, (1..11).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn skip_rendering() {
+        struct Count(usize);
+        impl LineSink for Count {
+            fn write_line(&mut self, _line: usize, _buf: &[u32]) {
+                self.0 += 1;
+            }
+        }
+
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut gpu = Gpu::new(
+            HardwareHandle::new(NullHardware),
+            Ic::new().irq(),
+            &Config::new(),
+        );
+        gpu.on_write_ctrl(0x91);
+
+        let mut run_line = |gpu: &mut Gpu| {
+            let mut sink = Count(0);
+            let ly = gpu.ly;
+            while gpu.ly == ly {
+                gpu.step(4, &mut mmu);
+                gpu.flush(Some(&mut sink));
+            }
+            sink.0
+        };
+
+        assert_eq!(run_line(&mut gpu), 1);
+        gpu.set_skip(true);
+        assert_eq!(run_line(&mut gpu), 0);
+        assert_eq!(gpu.ly, 2);
+        gpu.set_skip(false);
+        assert_eq!(run_line(&mut gpu), 1);
+    }
+
+    #[test]
+    fn stat_irq_blocking() {
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut ic = Ic::new();
+        ic.on_write(&mmu, 0xffff, 0x02);
+
+        let cfg = Config::new();
+        let mut gpu = Gpu::new(HardwareHandle::new(NullHardware), ic.irq(), &cfg);
+        // LYC=LY and HBlank interrupts
+        gpu.on_write_status(0x48);
+        gpu.on_write_ctrl(0x91);
+
+        gpu.step(1, &mut mmu);
+        assert_eq!(ic.poll(), Some(0x48));
+
+        // LYC=LY keeps the line high through the HBlank of line 0
+        while gpu.ly == 0 {
+            gpu.step(4, &mut mmu);
+            assert_eq!(ic.peek(), None);
+        }
+
+        // The line goes low in OAM scan of line 1, then high again in HBlank
+        while gpu.mode != Mode::HBlank {
+            gpu.step(4, &mut mmu);
+        }
+        assert_eq!(ic.poll(), Some(0x48));
+    }
+
+    #[test]
+    fn debug_viewer() {
+        let mut gpu = fifo_gpu();
+        gpu.bg_palette = to_palette(0xe4);
+        // Color 1 of the CGB BG palette 0 is red
+        gpu.bg_color_palette.select(0x82);
+        gpu.bg_color_palette.write(0x1f);
+
+        // Tile 1 has color 1 on the first row; the map points to it at (1, 0)
+        gpu.on_write_ctrl(0x11);
+        gpu.write_vram(0x8010, 0xff, 0);
+        gpu.write_vram(0x9801, 0x01, 0);
+
+        let col = |c: usize| {
+            if cfg!(feature = "color") {
+                gpu.bg_color_palette.cols[0][c].rgb888(&gpu.shades)
+            } else {
+                gpu.bg_palette[c].rgb888(&gpu.shades)
+            }
+        };
+        assert_ne!(col(0), col(1));
+
+        let tiles = gpu.debug_tiles();
+        assert_eq!(tiles.len(), TILES_WIDTH * TILES_HEIGHT);
+        assert!(tiles[8..16].iter().all(|p| *p == col(1)));
+        assert!(tiles[TILES_WIDTH + 8..TILES_WIDTH + 16]
+            .iter()
+            .all(|p| *p == col(0)));
+
+        let map = gpu.debug_bg_map(TileMap::Low);
+        assert_eq!(map.len(), MAP_SIZE * MAP_SIZE);
+        assert!(map[..8].iter().all(|p| *p == col(0)));
+        assert!(map[8..16].iter().all(|p| *p == col(1)));
+        assert!(gpu.debug_bg_map(TileMap::High).iter().all(|p| *p == col(0)));
+    }
+
+    #[test]
+    fn debug_sprites() {
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        // Sprite 1 uses the tile 2 flipped horizontally, at the top left corner
+        mmu.set8(0xfe04, 16);
+        mmu.set8(0xfe05, 8);
+        mmu.set8(0xfe06, 2);
+        mmu.set8(0xfe07, 0x20);
+        // Sprite 2 is below the screen
+        mmu.set8(0xfe08, 200);
+
+        let mut gpu = fifo_gpu();
+        gpu.obj_palette0 = to_palette(0xe4);
+        gpu.obj_color_palette.select(0x82);
+        gpu.obj_color_palette.write(0x1f);
+        // The leftmost pixel of the first row has color 1
+        gpu.write_vram(0x8020, 0x80, 0);
+
+        gpu.on_write_ctrl(0x93);
+        while !gpu.step(4, &mut mmu) {}
+
+        let sprites = gpu.debug_sprites(&mmu.ram()[0xfe00..0xfea0]);
+        assert_eq!(sprites.len(), 40);
+
+        let sp = &sprites[1];
+        assert_eq!((sp.index, sp.x, sp.y, sp.tile), (1, 8, 16, 2));
+        assert!(sp.xflip && !sp.yflip && sp.visible);
+        // The others are above or below the screen
+        assert!(!sprites[0].visible && !sprites[2].visible);
+
+        let col = if cfg!(feature = "color") {
+            gpu.obj_color_palette.cols[0][1]
+        } else {
+            gpu.obj_palette0[1]
+        };
+        assert_eq!(sp.pixels.len(), 64);
+        assert_eq!(sp.pixels[7], Some(col.rgb888(&gpu.shades)));
+        assert_eq!(sp.pixels.iter().filter(|p| p.is_some()).count(), 1);
+    }
+
+    #[test]
+    fn palettes() {
+        let mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut gpu = fifo_gpu();
+        gpu.on_write(&mmu, 0xff47, 0xe4);
+        gpu.on_write(&mmu, 0xff49, 0x1b);
+        // Color 1 of the CGB sprite palette 2 is blue
+        gpu.obj_color_palette.select(0x92);
+        gpu.obj_color_palette.write(0x00);
+        gpu.obj_color_palette.write(0x7c);
+
+        let p = gpu.palettes();
+        assert_eq!((p.bgp, p.obp0, p.obp1), (0xe4, 0xe4, 0x1b));
+
+        let shades = Config::new().dmg_palette;
+        if cfg!(feature = "color") {
+            assert_eq!(p.obj[2][1], 0x0000ff);
+            assert_eq!(p.bg[0], [0; 4]);
+        } else {
+            assert_eq!(p.bg[0], shades);
+            assert_eq!(p.obj[1], [shades[3], shades[2], shades[1], shades[0]]);
+            assert_eq!(p.obj[2], [0; 4]);
+        }
+    }
+
+    #[test]
+    fn vram_oam_locked_by_mode() {
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut gpu = fifo_gpu();
+        gpu.write_vram(0x8000, 0x12, 0);
+        mmu.set8(0xfe00, 0x34);
+        gpu.on_write_ctrl(0x91);
+
+        // Mode 2
+        gpu.step(1, &mut mmu);
+        assert!(matches!(gpu.on_read(&mmu, 0xfe00), MemRead::Replace(0xff)));
+        assert!(matches!(gpu.on_write(&mmu, 0xfe00, 0), MemWrite::Block));
+        assert!(matches!(gpu.on_read(&mmu, 0x8000), MemRead::Replace(0x12)));
+
+        // Mode 3
+        while gpu.mode != Mode::VRAM {
+            gpu.step(1, &mut mmu);
+        }
+        assert!(matches!(gpu.on_read(&mmu, 0x8000), MemRead::Replace(0xff)));
+        gpu.on_write(&mmu, 0x8000, 0x56);
+        assert_eq!(gpu.read_vram(0x8000, 0), 0x12);
+
+        // Mode 0
+        while gpu.mode != Mode::HBlank {
+            gpu.step(1, &mut mmu);
+        }
+        assert!(matches!(gpu.on_read(&mmu, 0xfe00), MemRead::PassThrough));
+        assert!(matches!(gpu.on_read(&mmu, 0x8000), MemRead::Replace(0x12)));
+    }
+
+    #[test]
+    fn oam_corruption() {
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        for i in 0..0xa0 {
+            mmu.set8(0xfe00 + i, i as u8);
+        }
+
+        let mut gpu = fifo_gpu();
+        gpu.on_write_ctrl(0x91);
+        gpu.step(9, &mut mmu);
+        gpu.corrupt_oam(&mut mmu);
+
+        let oam = &mmu.ram()[0xfe00..0xfea0];
+        // Row 2 is corrupted by row 1
+        let (a, b, c) = (0x1110u16, 0x0908u16, 0x0d0cu16);
+        let v = ((a ^ c) & (b ^ c)) ^ c;
+        assert_eq!(oam[16], v as u8);
+        assert_eq!(oam[17], (v >> 8) as u8);
+        assert_eq!(&oam[18..24], &[10, 11, 12, 13, 14, 15]);
+        // The other rows are intact
+        assert_eq!(oam[8], 8);
+        assert_eq!(oam[24], 24);
+    }
+
+    #[test]
+    fn unusable_region() {
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut gpu = fifo_gpu();
+
+        assert!(matches!(gpu.on_write(&mmu, 0xfea0, 0x12), MemWrite::Block));
+        if cfg!(feature = "color") {
+            assert!(matches!(gpu.on_read(&mmu, 0xfeb3), MemRead::Replace(0xbb)));
+        } else {
+            assert!(matches!(gpu.on_read(&mmu, 0xfeb3), MemRead::Replace(0x00)));
+
+            // Mode 2
+            gpu.on_write_ctrl(0x91);
+            gpu.step(1, &mut mmu);
+            assert!(matches!(gpu.on_read(&mmu, 0xfeb3), MemRead::Replace(0xff)));
+        }
+    }
+}
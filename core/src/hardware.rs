@@ -1,7 +1,7 @@
 use alloc::boxed::Box;
-use alloc::rc::Rc;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
-use core::cell::RefCell;
+use spin::Mutex;
 
 /// The width of the VRAM.
 pub const VRAM_WIDTH: usize = 160;
@@ -9,6 +9,63 @@ pub const VRAM_WIDTH: usize = 160;
 /// The height of the VRAM.
 pub const VRAM_HEIGHT: usize = 144;
 
+/// Pixel format of the lines rendered by the GPU.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// `0x00RRGGBB` (default).
+    Rgb888,
+    /// 2-bit shade index from 0 (lightest) to 3 (darkest).
+    Index,
+    /// RGB565 in the lower 16 bits.
+    Rgb565,
+    /// `0xRRGGBBAA`, where the alpha is always `0xff`.
+    Rgba8888,
+}
+
+/// Element of a frame buffer which the GPU renders into directly.
+///
+/// The pixels encoded in the configured [`PixelFormat`][] are truncated to the element size,
+/// e.g. use `u16` for [`PixelFormat::Rgb565`][] and `u8` for [`PixelFormat::Index`][].
+pub trait Pixel: Copy {
+    /// Convert the encoded pixel to the element.
+    fn from_pixel(pixel: u32) -> Self;
+}
+
+impl Pixel for u8 {
+    fn from_pixel(pixel: u32) -> Self {
+        pixel as u8
+    }
+}
+
+impl Pixel for u16 {
+    fn from_pixel(pixel: u32) -> Self {
+        pixel as u16
+    }
+}
+
+impl Pixel for u32 {
+    fn from_pixel(pixel: u32) -> Self {
+        pixel
+    }
+}
+
+/// Destination of the lines rendered by the GPU.
+pub(crate) trait LineSink {
+    fn write_line(&mut self, line: usize, buf: &[u32]);
+}
+
+/// Frame buffer provided by the caller.
+pub(crate) struct FrameSink<'a, P>(pub &'a mut [P]);
+
+impl<'a, P: Pixel> LineSink for FrameSink<'a, P> {
+    fn write_line(&mut self, line: usize, buf: &[u32]) {
+        let offset = line * VRAM_WIDTH;
+        for (dst, src) in self.0[offset..offset + VRAM_WIDTH].iter_mut().zip(buf) {
+            *dst = P::from_pixel(*src);
+        }
+    }
+}
+
 /// Represents a key of the joypad.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Key {
@@ -30,8 +87,44 @@ pub enum Key {
     Start,
 }
 
+/// `Send` with the `threads` feature, and no bound without it.
+///
+/// The traits passed between threads require it, so they are implementable by the types
+/// which aren't `Send`, e.g. the objects of the browser on wasm, when the feature is disabled.
+#[cfg(feature = "threads")]
+pub trait MaybeSend: Send {}
+
+#[cfg(feature = "threads")]
+impl<T: Send + ?Sized> MaybeSend for T {}
+
+/// `Send` with the `threads` feature, and no bound without it.
+///
+/// The traits passed between threads require it, so they are implementable by the types
+/// which aren't `Send`, e.g. the objects of the browser on wasm, when the feature is disabled.
+#[cfg(not(feature = "threads"))]
+pub trait MaybeSend {}
+
+#[cfg(not(feature = "threads"))]
+impl<T: ?Sized> MaybeSend for T {}
+
+/// `Sync` with the `threads` feature, and no bound without it, as [`MaybeSend`][] is `Send`.
+#[cfg(feature = "threads")]
+pub trait MaybeSync: Sync {}
+
+#[cfg(feature = "threads")]
+impl<T: Sync + ?Sized> MaybeSync for T {}
+
+/// `Sync` with the `threads` feature, and no bound without it, as [`MaybeSend`][] is `Send`.
+#[cfg(not(feature = "threads"))]
+pub trait MaybeSync {}
+
+#[cfg(not(feature = "threads"))]
+impl<T: ?Sized> MaybeSync for T {}
+
 /// Sound wave stream which generates the wave to be played by the sound device.
-pub trait Stream: Send + 'static {
+///
+/// The stream is [`MaybeSend`][], so it's `Send` only with the `threads` feature.
+pub trait Stream: MaybeSend {
     /// The maximum value of the amplitude returned by this stream.
     fn max(&self) -> u16;
 
@@ -40,53 +133,253 @@ pub trait Stream: Send + 'static {
     fn next(&mut self, rate: u32) -> u16;
 }
 
+impl<S: Stream + ?Sized> Stream for Box<S> {
+    fn max(&self) -> u16 {
+        (**self).max()
+    }
+
+    fn next(&mut self, rate: u32) -> u16 {
+        (**self).next(rate)
+    }
+}
+
 #[derive(Clone)]
-pub struct HardwareHandle(Rc<RefCell<dyn Hardware>>);
+pub struct HardwareHandle<'a>(Arc<Mutex<dyn Hardware + 'a>>);
 
-impl HardwareHandle {
-    pub fn new<T: Hardware + 'static>(inner: T) -> Self {
-        Self(Rc::new(RefCell::new(inner)))
+impl<'a> HardwareHandle<'a> {
+    pub fn new<T: Hardware + 'a>(inner: T) -> Self {
+        Self(Arc::new(Mutex::new(inner)))
     }
 
-    pub fn get(&self) -> &Rc<RefCell<dyn Hardware>> {
+    pub fn get(&self) -> &Arc<Mutex<dyn Hardware + 'a>> {
         &self.0
     }
 }
 
 /// The interface to abstracts the OS-specific functions.
 ///
-/// The users of this emulator library need to implement this trait,
-/// providing OS-specific functions.
-pub trait Hardware {
+/// The functions are grouped into the capability traits [`Screen`][], [`Speaker`][], [`Input`][],
+/// [`Clock`][], [`Persistence`][] and [`Link`][], and the trait is implemented for any type which
+/// implements all of them. Most of the functions have a default doing nothing, so e.g. a headless
+/// frontend without sound can implement [`Screen`][] and [`Speaker`][] with empty blocks.
+///
+/// The hardware is [`MaybeSend`][], so it needs to be `Send` only with the `threads` feature.
+pub trait Hardware: Screen + Speaker + Input + Clock + Persistence + Link + MaybeSend {}
+
+impl<T: Screen + Speaker + Input + Clock + Persistence + Link + MaybeSend + ?Sized> Hardware for T {}
+
+/// The display.
+pub trait Screen {
     /// Called when one horizontal line in the display is updated.
-    fn vram_update(&mut self, line: usize, buffer: &[u32]);
+    ///
+    /// `buffer` has [`VRAM_WIDTH`][] pixels of the line `line`, so frontends can stream
+    /// the lines to the display as they are rendered instead of buffering the whole frame.
+    /// The pixels are encoded in the format set by [`Config::pixel_format`][crate::Config::pixel_format].
+    fn vram_update(&mut self, _line: usize, _buffer: &[u32]) {}
 
-    /// Called when the emulator checks if the key is pressed.
-    fn joypad_pressed(&mut self, key: Key) -> bool;
+    /// Called when the CPU enters or leaves the low-power mode with the STOP instruction.
+    ///
+    /// The LCD is blank and the emulator only waits for a key press while `on` is `true`.
+    fn stop_mode(&mut self, _on: bool) {}
+}
 
+/// The sound output.
+pub trait Speaker {
     /// Called when the emulator plays a sound.
     /// The stream in the argument is the stream which keeps returning wave patterns.
-    fn sound_play(&mut self, stream: Box<dyn Stream>);
+    ///
+    /// With the `threads` feature, the stream is `Send`, so it can be moved to the audio thread of the frontend.
+    fn sound_play(&mut self, _stream: Box<dyn Stream>) {}
+}
 
+/// The joypad and the other input devices of the cartridges.
+pub trait Input {
+    /// Called when the emulator checks if the key is pressed.
+    fn joypad_pressed(&mut self, _key: Key) -> bool {
+        false
+    }
+
+    /// Called when the rumble motor of the cartridge is turned on or off.
+    fn rumble(&mut self, _on: bool) {}
+
+    /// Called when the cartridge latches its accelerometer (MBC7).
+    ///
+    /// The return value is the tilt on the x and y axes, as an offset from
+    /// the reading of a cartridge held flat. Roughly `0x70` corresponds to 1 g.
+    fn accelerometer(&mut self) -> (i16, i16) {
+        (0, 0)
+    }
+}
+
+/// The time source and the scheduling of the emulator.
+pub trait Clock {
     /// Clock source used by the emulator.
     /// The return value needs to be epoch time in microseconds.
     fn clock(&mut self) -> u64;
 
-    /// Send one byte to the serial port.
-    fn send_byte(&mut self, b: u8);
-
-    /// Try receiving one byte from the serial port.
-    fn recv_byte(&mut self) -> Option<u8>;
-
     /// Called every time the CPU executes one instruction.
     /// Returning `false` stops the emulator.
     fn sched(&mut self) -> bool {
         true
     }
 
+    /// Called when the CPU is halted and the emulator skips `cycles` clock cycles at once
+    /// as nothing happens until the next event.
+    ///
+    /// Battery-powered hosts can sleep here instead of spinning through the halt cycles.
+    fn idle(&mut self, _cycles: usize) {}
+}
+
+/// The storage of the cartridge battery-backed RAM.
+pub trait Persistence {
     /// Called when the CPU attempts to write save data to the cartridge battery-backed RAM.
-    fn load_ram(&mut self, size: usize) -> Vec<u8>;
+    fn load_ram(&mut self, size: usize) -> Vec<u8> {
+        alloc::vec![0; size]
+    }
 
     /// Called when the CPU attempts to read save data from the cartridge battery-backed RAM.
-    fn save_ram(&mut self, ram: &[u8]);
+    fn save_ram(&mut self, _ram: &[u8]) {}
 }
+
+/// The serial port and the infrared port.
+pub trait Link {
+    /// Send one byte to the serial port.
+    fn send_byte(&mut self, _b: u8) {}
+
+    /// Try receiving one byte from the serial port.
+    fn recv_byte(&mut self) -> Option<u8> {
+        None
+    }
+
+    /// Turn the infrared LED on or off.
+    ///
+    /// Called by the infrared port of the HuC-1 cartridge, and of the Game Boy Color (RP).
+    /// Connect two emulators by passing the LED of one to [`Link::ir_recv`][] of the other.
+    fn ir_send(&mut self, _on: bool) {}
+
+    /// Check if the infrared receiver detects light.
+    fn ir_recv(&mut self) -> bool {
+        false
+    }
+}
+
+// Forward the capabilities through a mutable reference, so the emulator can borrow the hardware
+// of the frontend, e.g. `System::new(cfg, rom, ram, &mut hw, dbg)`.
+
+impl<T: Screen + ?Sized> Screen for &mut T {
+    fn vram_update(&mut self, line: usize, buffer: &[u32]) {
+        (**self).vram_update(line, buffer)
+    }
+
+    fn stop_mode(&mut self, on: bool) {
+        (**self).stop_mode(on)
+    }
+}
+
+impl<T: Speaker + ?Sized> Speaker for &mut T {
+    fn sound_play(&mut self, stream: Box<dyn Stream>) {
+        (**self).sound_play(stream)
+    }
+}
+
+impl<T: Input + ?Sized> Input for &mut T {
+    fn joypad_pressed(&mut self, key: Key) -> bool {
+        (**self).joypad_pressed(key)
+    }
+
+    fn rumble(&mut self, on: bool) {
+        (**self).rumble(on)
+    }
+
+    fn accelerometer(&mut self) -> (i16, i16) {
+        (**self).accelerometer()
+    }
+}
+
+impl<T: Clock + ?Sized> Clock for &mut T {
+    fn clock(&mut self) -> u64 {
+        (**self).clock()
+    }
+
+    fn sched(&mut self) -> bool {
+        (**self).sched()
+    }
+
+    fn idle(&mut self, cycles: usize) {
+        (**self).idle(cycles)
+    }
+}
+
+impl<T: Persistence + ?Sized> Persistence for &mut T {
+    fn load_ram(&mut self, size: usize) -> Vec<u8> {
+        (**self).load_ram(size)
+    }
+
+    fn save_ram(&mut self, ram: &[u8]) {
+        (**self).save_ram(ram)
+    }
+}
+
+impl<T: Link + ?Sized> Link for &mut T {
+    fn send_byte(&mut self, b: u8) {
+        (**self).send_byte(b)
+    }
+
+    fn recv_byte(&mut self) -> Option<u8> {
+        (**self).recv_byte()
+    }
+
+    fn ir_send(&mut self, on: bool) {
+        (**self).ir_send(on)
+    }
+
+    fn ir_recv(&mut self) -> bool {
+        (**self).ir_recv()
+    }
+}
+
+/// The keys in the order of the bits of a key set, as the input of netplay and movies.
+pub(crate) const KEYS: [Key; 8] = [
+    Key::Right,
+    Key::Left,
+    Key::Up,
+    Key::Down,
+    Key::A,
+    Key::B,
+    Key::Select,
+    Key::Start,
+];
+
+/// Encode the pressed keys into the bits of a key set.
+pub(crate) fn key_bits(pressed: &[Key]) -> u8 {
+    KEYS.iter()
+        .enumerate()
+        .filter(|(_, k)| pressed.contains(k))
+        .fold(0, |keys, (i, _)| keys | 1 << i)
+}
+
+/// The hardware which does nothing, for tests.
+#[cfg(test)]
+pub(crate) struct NullHardware;
+
+#[cfg(test)]
+impl Screen for NullHardware {}
+
+#[cfg(test)]
+impl Speaker for NullHardware {}
+
+#[cfg(test)]
+impl Input for NullHardware {}
+
+#[cfg(test)]
+impl Clock for NullHardware {
+    fn clock(&mut self) -> u64 {
+        0
+    }
+}
+
+#[cfg(test)]
+impl Persistence for NullHardware {}
+
+#[cfg(test)]
+impl Link for NullHardware {}
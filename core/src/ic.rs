@@ -1,37 +1,50 @@
+use crate::debug::{Events, HwEvent};
 use crate::device::IoHandler;
+use crate::error::Error;
 use crate::mmu::{MemRead, MemWrite, Mmu};
-use alloc::rc::Rc;
-use core::cell::RefCell;
+use crate::state::{Reader, State, Writer};
+use alloc::sync::Arc;
 use log::*;
+use spin::Mutex;
 
 #[derive(Clone)]
 pub struct Irq {
-    request: Rc<RefCell<Ints>>,
+    request: Arc<Mutex<Ints>>,
 }
 
 impl Irq {
-    fn new(request: Rc<RefCell<Ints>>) -> Irq {
+    fn new(request: Arc<Mutex<Ints>>) -> Irq {
         Irq { request }
     }
 
     pub fn vblank(&self, v: bool) {
-        self.request.borrow_mut().vblank = v;
+        self.request(v, 0x40, |r| &mut r.vblank);
     }
 
     pub fn lcd(&self, v: bool) {
-        self.request.borrow_mut().lcd = v;
+        self.request(v, 0x48, |r| &mut r.lcd);
     }
 
     pub fn timer(&self, v: bool) {
-        self.request.borrow_mut().timer = v;
+        self.request(v, 0x50, |r| &mut r.timer);
     }
 
     pub fn serial(&self, v: bool) {
-        self.request.borrow_mut().serial = v;
+        self.request(v, 0x58, |r| &mut r.serial);
     }
 
     pub fn joypad(&self, v: bool) {
-        self.request.borrow_mut().joypad = v;
+        self.request(v, 0x60, |r| &mut r.joypad);
+    }
+
+    fn request(&self, v: bool, vector: u16, flag: fn(&mut Ints) -> &mut bool) {
+        let mut r = self.request.lock();
+        let f = flag(&mut r);
+        let raised = v && !*f;
+        *f = v;
+        if raised {
+            r.events.push(HwEvent::InterruptRaised(vector));
+        }
     }
 }
 
@@ -42,6 +55,8 @@ struct Ints {
     timer: bool,
     serial: bool,
     joypad: bool,
+    /// The hardware events of the interrupts, which are only used in the requests.
+    events: Events,
 }
 
 impl Ints {
@@ -65,15 +80,15 @@ impl Ints {
 }
 
 pub struct Ic {
-    enable: Rc<RefCell<Ints>>,
-    request: Rc<RefCell<Ints>>,
+    enable: Arc<Mutex<Ints>>,
+    request: Arc<Mutex<Ints>>,
 }
 
 impl Ic {
     pub fn new() -> Ic {
         Ic {
-            enable: Rc::new(RefCell::new(Ints::default())),
-            request: Rc::new(RefCell::new(Ints::default())),
+            enable: Arc::new(Mutex::new(Ints::default())),
+            request: Arc::new(Mutex::new(Ints::default())),
         }
     }
 
@@ -81,6 +96,11 @@ impl Ic {
         Irq::new(self.request.clone())
     }
 
+    /// Report the interrupts raised and serviced to the hardware events.
+    pub(crate) fn set_events(&self, events: Events) {
+        self.request.lock().events = events;
+    }
+
     pub fn peek(&self) -> Option<u8> {
         self.check(false)
     }
@@ -90,10 +110,10 @@ impl Ic {
     }
 
     fn check(&self, consume: bool) -> Option<u8> {
-        let e = self.enable.borrow();
-        let mut r = self.request.borrow_mut();
+        let e = self.enable.lock();
+        let mut r = self.request.lock();
 
-        if e.vblank && r.vblank {
+        let vector = if e.vblank && r.vblank {
             r.vblank = !consume;
             Some(0x40)
         } else if e.lcd && r.lcd {
@@ -110,18 +130,42 @@ impl Ic {
             Some(0x60)
         } else {
             None
+        };
+
+        if consume {
+            if let Some(v) = vector {
+                r.events.push(HwEvent::InterruptServiced(v as u16));
+            }
         }
+
+        vector
+    }
+}
+
+impl State for Ic {
+    fn save(&self, w: &mut Writer) {
+        self.enable.lock().get().save(w);
+        self.request.lock().get().save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        let (mut enable, mut request) = (0u8, 0u8);
+        enable.load(r)?;
+        request.load(r)?;
+        self.enable.lock().set(enable);
+        self.request.lock().set(request);
+        Ok(())
     }
 }
 
 impl IoHandler for Ic {
     fn on_read(&mut self, _mmu: &Mmu, addr: u16) -> MemRead {
         if addr == 0xffff {
-            let v = self.enable.borrow().get();
+            let v = self.enable.lock().get();
             info!("Read interrupt enable: {:02x}", v);
             MemRead::Replace(v)
         } else if addr == 0xff0f {
-            let v = self.request.borrow().get();
+            let v = self.request.lock().get();
             info!("Read interrupt: {:02x}", v);
             MemRead::Replace(v)
         } else {
@@ -132,11 +176,11 @@ impl IoHandler for Ic {
     fn on_write(&mut self, _mmu: &Mmu, addr: u16, value: u8) -> MemWrite {
         if addr == 0xffff {
             info!("Write interrupt enable: {:02x}", value);
-            self.enable.borrow_mut().set(value);
+            self.enable.lock().set(value);
             MemWrite::Block
         } else if addr == 0xff0f {
             info!("Write interrupt: {:02x}", value);
-            self.request.borrow_mut().set(value);
+            self.request.lock().set(value);
             MemWrite::Block
         } else {
             info!("Writing to IC register: {:04x}", addr);
@@ -1,5 +1,6 @@
 use crate::alu;
 use crate::cpu::Cpu;
+use crate::error::Error;
 use crate::mmu::Mmu;
 use hashbrown::HashMap;
 use lazy_static::lazy_static;
@@ -522,7 +523,7 @@ fn op_0000(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld bc,d16
 #[allow(unused_variables)]
 fn op_0001(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get16(cpu.get_pc().wrapping_add(arg));
+    let v = mmu.cpu_get16(cpu.get_pc().wrapping_add(arg));
     cpu.set_bc(v);
 
     (12, 3)
@@ -532,7 +533,7 @@ fn op_0001(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_0002(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let v = cpu.get_a();
-    mmu.set8(cpu.get_bc(), v);
+    mmu.cpu_set8(cpu.get_bc(), v);
 
     (8, 1)
 }
@@ -575,7 +576,7 @@ fn op_0005(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld b,d8
 #[allow(unused_variables)]
 fn op_0006(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_pc().wrapping_add(arg));
+    let v = mmu.cpu_get8(cpu.get_pc().wrapping_add(arg));
     cpu.set_b(v);
 
     (8, 2)
@@ -601,7 +602,8 @@ fn op_0007(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_0008(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let v = cpu.get_sp();
-    mmu.set16(mmu.get16(cpu.get_pc().wrapping_add(arg)), v);
+    let addr = mmu.cpu_get16(cpu.get_pc().wrapping_add(arg));
+    mmu.cpu_set16(addr, v);
 
     (20, 3)
 }
@@ -624,7 +626,7 @@ fn op_0009(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld a,(bc)
 #[allow(unused_variables)]
 fn op_000a(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_bc());
+    let v = mmu.cpu_get8(cpu.get_bc());
     cpu.set_a(v);
 
     (8, 1)
@@ -668,7 +670,7 @@ fn op_000d(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld c,d8
 #[allow(unused_variables)]
 fn op_000e(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_pc().wrapping_add(arg));
+    let v = mmu.cpu_get8(cpu.get_pc().wrapping_add(arg));
     cpu.set_c(v);
 
     (8, 2)
@@ -701,7 +703,7 @@ fn op_0010(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld de,d16
 #[allow(unused_variables)]
 fn op_0011(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get16(cpu.get_pc().wrapping_add(arg));
+    let v = mmu.cpu_get16(cpu.get_pc().wrapping_add(arg));
     cpu.set_de(v);
 
     (12, 3)
@@ -711,7 +713,7 @@ fn op_0011(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_0012(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let v = cpu.get_a();
-    mmu.set8(cpu.get_de(), v);
+    mmu.cpu_set8(cpu.get_de(), v);
 
     (8, 1)
 }
@@ -754,7 +756,7 @@ fn op_0015(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld d,d8
 #[allow(unused_variables)]
 fn op_0016(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_pc().wrapping_add(arg));
+    let v = mmu.cpu_get8(cpu.get_pc().wrapping_add(arg));
     cpu.set_d(v);
 
     (8, 2)
@@ -779,7 +781,7 @@ fn op_0017(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// jr r8
 #[allow(unused_variables)]
 fn op_0018(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let p = mmu.get8(cpu.get_pc().wrapping_add(arg));
+    let p = mmu.cpu_get8(cpu.get_pc().wrapping_add(arg));
     let pc = cpu.get_pc().wrapping_add(alu::signed(p));
     cpu.set_pc(pc);
 
@@ -804,7 +806,7 @@ fn op_0019(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld a,(de)
 #[allow(unused_variables)]
 fn op_001a(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_de());
+    let v = mmu.cpu_get8(cpu.get_de());
     cpu.set_a(v);
 
     (8, 1)
@@ -848,7 +850,7 @@ fn op_001d(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld e,d8
 #[allow(unused_variables)]
 fn op_001e(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_pc().wrapping_add(arg));
+    let v = mmu.cpu_get8(cpu.get_pc().wrapping_add(arg));
     cpu.set_e(v);
 
     (8, 2)
@@ -875,7 +877,7 @@ fn op_001f(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 fn op_0020(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let flg = !cpu.get_zf();
     if flg {
-        let p = mmu.get8(cpu.get_pc().wrapping_add(arg));
+        let p = mmu.cpu_get8(cpu.get_pc().wrapping_add(arg));
         let pc = cpu.get_pc().wrapping_add(alu::signed(p));
         cpu.set_pc(pc);
         return (12, 2);
@@ -887,7 +889,7 @@ fn op_0020(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld hl,d16
 #[allow(unused_variables)]
 fn op_0021(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get16(cpu.get_pc().wrapping_add(arg));
+    let v = mmu.cpu_get16(cpu.get_pc().wrapping_add(arg));
     cpu.set_hl(v);
 
     (12, 3)
@@ -897,7 +899,7 @@ fn op_0021(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_0022(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let v = cpu.get_a();
-    mmu.set8(cpu.get_hl(), v);
+    mmu.cpu_set8(cpu.get_hl(), v);
 
     cpu.set_hl(cpu.get_hl().wrapping_add(1));
 
@@ -942,7 +944,7 @@ fn op_0025(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld h,d8
 #[allow(unused_variables)]
 fn op_0026(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_pc().wrapping_add(arg));
+    let v = mmu.cpu_get8(cpu.get_pc().wrapping_add(arg));
     cpu.set_h(v);
 
     (8, 2)
@@ -984,7 +986,7 @@ fn op_0027(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 fn op_0028(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let flg = cpu.get_zf();
     if flg {
-        let p = mmu.get8(cpu.get_pc().wrapping_add(arg));
+        let p = mmu.cpu_get8(cpu.get_pc().wrapping_add(arg));
         let pc = cpu.get_pc().wrapping_add(alu::signed(p));
         cpu.set_pc(pc);
         return (12, 2);
@@ -1011,7 +1013,7 @@ fn op_0029(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ldi a,(hl)
 #[allow(unused_variables)]
 fn op_002a(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_hl());
+    let v = mmu.cpu_get8(cpu.get_hl());
     cpu.set_a(v);
 
     cpu.set_hl(cpu.get_hl().wrapping_add(1));
@@ -1057,7 +1059,7 @@ fn op_002d(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld l,d8
 #[allow(unused_variables)]
 fn op_002e(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_pc().wrapping_add(arg));
+    let v = mmu.cpu_get8(cpu.get_pc().wrapping_add(arg));
     cpu.set_l(v);
 
     (8, 2)
@@ -1079,7 +1081,7 @@ fn op_002f(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 fn op_0030(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let flg = !cpu.get_cf();
     if flg {
-        let p = mmu.get8(cpu.get_pc().wrapping_add(arg));
+        let p = mmu.cpu_get8(cpu.get_pc().wrapping_add(arg));
         let pc = cpu.get_pc().wrapping_add(alu::signed(p));
         cpu.set_pc(pc);
         return (12, 2);
@@ -1091,7 +1093,7 @@ fn op_0030(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld sp,d16
 #[allow(unused_variables)]
 fn op_0031(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get16(cpu.get_pc().wrapping_add(arg));
+    let v = mmu.cpu_get16(cpu.get_pc().wrapping_add(arg));
     cpu.set_sp(v);
 
     (12, 3)
@@ -1101,7 +1103,7 @@ fn op_0031(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_0032(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let v = cpu.get_a();
-    mmu.set8(cpu.get_hl(), v);
+    mmu.cpu_set8(cpu.get_hl(), v);
 
     cpu.set_hl(cpu.get_hl().wrapping_sub(1));
 
@@ -1120,9 +1122,9 @@ fn op_0033(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// inc (hl)
 #[allow(unused_variables)]
 fn op_0034(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_hl());
+    let v = mmu.cpu_get8(cpu.get_hl());
     let (v, h, c, z) = alu::add8(v, 1, false);
-    mmu.set8(cpu.get_hl(), v);
+    mmu.cpu_set8(cpu.get_hl(), v);
     cpu.set_zf(z);
     cpu.set_nf(false);
     cpu.set_hf(h);
@@ -1133,9 +1135,9 @@ fn op_0034(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// dec (hl)
 #[allow(unused_variables)]
 fn op_0035(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_hl());
+    let v = mmu.cpu_get8(cpu.get_hl());
     let (v, h, c, z) = alu::sub8(v, 1, false);
-    mmu.set8(cpu.get_hl(), v);
+    mmu.cpu_set8(cpu.get_hl(), v);
     cpu.set_zf(z);
     cpu.set_nf(true);
     cpu.set_hf(h);
@@ -1146,8 +1148,8 @@ fn op_0035(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld (hl),d8
 #[allow(unused_variables)]
 fn op_0036(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_pc().wrapping_add(arg));
-    mmu.set8(cpu.get_hl(), v);
+    let v = mmu.cpu_get8(cpu.get_pc().wrapping_add(arg));
+    mmu.cpu_set8(cpu.get_hl(), v);
 
     (12, 2)
 }
@@ -1169,7 +1171,7 @@ fn op_0037(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 fn op_0038(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let flg = cpu.get_cf();
     if flg {
-        let p = mmu.get8(cpu.get_pc().wrapping_add(arg));
+        let p = mmu.cpu_get8(cpu.get_pc().wrapping_add(arg));
         let pc = cpu.get_pc().wrapping_add(alu::signed(p));
         cpu.set_pc(pc);
         return (12, 2);
@@ -1196,7 +1198,7 @@ fn op_0039(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ldd a,(hl)
 #[allow(unused_variables)]
 fn op_003a(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_hl());
+    let v = mmu.cpu_get8(cpu.get_hl());
     cpu.set_a(v);
 
     cpu.set_hl(cpu.get_hl().wrapping_sub(1));
@@ -1242,7 +1244,7 @@ fn op_003d(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld a,d8
 #[allow(unused_variables)]
 fn op_003e(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_pc().wrapping_add(arg));
+    let v = mmu.cpu_get8(cpu.get_pc().wrapping_add(arg));
     cpu.set_a(v);
 
     (8, 2)
@@ -1317,7 +1319,7 @@ fn op_0045(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld b,(hl)
 #[allow(unused_variables)]
 fn op_0046(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_hl());
+    let v = mmu.cpu_get8(cpu.get_hl());
     cpu.set_b(v);
 
     (8, 1)
@@ -1389,7 +1391,7 @@ fn op_004d(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld c,(hl)
 #[allow(unused_variables)]
 fn op_004e(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_hl());
+    let v = mmu.cpu_get8(cpu.get_hl());
     cpu.set_c(v);
 
     (8, 1)
@@ -1461,7 +1463,7 @@ fn op_0055(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld d,(hl)
 #[allow(unused_variables)]
 fn op_0056(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_hl());
+    let v = mmu.cpu_get8(cpu.get_hl());
     cpu.set_d(v);
 
     (8, 1)
@@ -1533,7 +1535,7 @@ fn op_005d(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld e,(hl)
 #[allow(unused_variables)]
 fn op_005e(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_hl());
+    let v = mmu.cpu_get8(cpu.get_hl());
     cpu.set_e(v);
 
     (8, 1)
@@ -1605,7 +1607,7 @@ fn op_0065(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld h,(hl)
 #[allow(unused_variables)]
 fn op_0066(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_hl());
+    let v = mmu.cpu_get8(cpu.get_hl());
     cpu.set_h(v);
 
     (8, 1)
@@ -1677,7 +1679,7 @@ fn op_006d(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld l,(hl)
 #[allow(unused_variables)]
 fn op_006e(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_hl());
+    let v = mmu.cpu_get8(cpu.get_hl());
     cpu.set_l(v);
 
     (8, 1)
@@ -1696,7 +1698,7 @@ fn op_006f(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_0070(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let v = cpu.get_b();
-    mmu.set8(cpu.get_hl(), v);
+    mmu.cpu_set8(cpu.get_hl(), v);
 
     (8, 1)
 }
@@ -1705,7 +1707,7 @@ fn op_0070(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_0071(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let v = cpu.get_c();
-    mmu.set8(cpu.get_hl(), v);
+    mmu.cpu_set8(cpu.get_hl(), v);
 
     (8, 1)
 }
@@ -1714,7 +1716,7 @@ fn op_0071(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_0072(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let v = cpu.get_d();
-    mmu.set8(cpu.get_hl(), v);
+    mmu.cpu_set8(cpu.get_hl(), v);
 
     (8, 1)
 }
@@ -1723,7 +1725,7 @@ fn op_0072(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_0073(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let v = cpu.get_e();
-    mmu.set8(cpu.get_hl(), v);
+    mmu.cpu_set8(cpu.get_hl(), v);
 
     (8, 1)
 }
@@ -1732,7 +1734,7 @@ fn op_0073(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_0074(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let v = cpu.get_h();
-    mmu.set8(cpu.get_hl(), v);
+    mmu.cpu_set8(cpu.get_hl(), v);
 
     (8, 1)
 }
@@ -1741,7 +1743,7 @@ fn op_0074(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_0075(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let v = cpu.get_l();
-    mmu.set8(cpu.get_hl(), v);
+    mmu.cpu_set8(cpu.get_hl(), v);
 
     (8, 1)
 }
@@ -1758,7 +1760,7 @@ fn op_0076(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_0077(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let v = cpu.get_a();
-    mmu.set8(cpu.get_hl(), v);
+    mmu.cpu_set8(cpu.get_hl(), v);
 
     (8, 1)
 }
@@ -1820,7 +1822,7 @@ fn op_007d(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld a,(hl)
 #[allow(unused_variables)]
 fn op_007e(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_hl());
+    let v = mmu.cpu_get8(cpu.get_hl());
     cpu.set_a(v);
 
     (8, 1)
@@ -1929,7 +1931,7 @@ fn op_0085(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_0086(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = cpu.get_a();
-    let q = mmu.get8(cpu.get_hl());
+    let q = mmu.cpu_get8(cpu.get_hl());
     let (v, h, c, z) = alu::add8(p, q, false);
     cpu.set_a(v);
     cpu.set_zf(z);
@@ -2049,7 +2051,7 @@ fn op_008d(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_008e(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = cpu.get_a();
-    let q = mmu.get8(cpu.get_hl());
+    let q = mmu.cpu_get8(cpu.get_hl());
     let (v, h, c, z) = alu::add8(p, q, cpu.get_cf());
     cpu.set_a(v);
     cpu.set_zf(z);
@@ -2169,7 +2171,7 @@ fn op_0095(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_0096(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = cpu.get_a();
-    let q = mmu.get8(cpu.get_hl());
+    let q = mmu.cpu_get8(cpu.get_hl());
     let (v, h, c, z) = alu::sub8(p, q, false);
     cpu.set_a(v);
     cpu.set_zf(z);
@@ -2289,7 +2291,7 @@ fn op_009d(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_009e(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = cpu.get_a();
-    let q = mmu.get8(cpu.get_hl());
+    let q = mmu.cpu_get8(cpu.get_hl());
     let (v, h, c, z) = alu::sub8(p, q, cpu.get_cf());
     cpu.set_a(v);
     cpu.set_zf(z);
@@ -2396,7 +2398,7 @@ fn op_00a5(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// and (hl)
 #[allow(unused_variables)]
 fn op_00a6(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    cpu.set_a(cpu.get_a() & mmu.get8(cpu.get_hl()));
+    cpu.set_a(cpu.get_a() & mmu.cpu_get8(cpu.get_hl()));
     let z = cpu.get_a() == 0;
     cpu.set_zf(z);
     cpu.set_nf(false);
@@ -2500,7 +2502,7 @@ fn op_00ad(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// xor (hl)
 #[allow(unused_variables)]
 fn op_00ae(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    cpu.set_a(cpu.get_a() ^ mmu.get8(cpu.get_hl()));
+    cpu.set_a(cpu.get_a() ^ mmu.cpu_get8(cpu.get_hl()));
     let z = cpu.get_a() == 0;
     cpu.set_zf(z);
     cpu.set_nf(false);
@@ -2604,7 +2606,7 @@ fn op_00b5(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// or (hl)
 #[allow(unused_variables)]
 fn op_00b6(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    cpu.set_a(cpu.get_a() | mmu.get8(cpu.get_hl()));
+    cpu.set_a(cpu.get_a() | mmu.cpu_get8(cpu.get_hl()));
     let z = cpu.get_a() == 0;
     cpu.set_zf(z);
     cpu.set_nf(false);
@@ -2715,7 +2717,7 @@ fn op_00bd(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_00be(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = cpu.get_a();
-    let q = mmu.get8(cpu.get_hl());
+    let q = mmu.cpu_get8(cpu.get_hl());
     let (_, h, c, z) = alu::sub8(p, q, false);
     cpu.set_zf(z);
     cpu.set_nf(true);
@@ -2766,7 +2768,7 @@ fn op_00c1(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 fn op_00c2(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let flg = !cpu.get_zf();
     if flg {
-        let pc = mmu.get16(cpu.get_pc().wrapping_add(arg));
+        let pc = mmu.cpu_get16(cpu.get_pc().wrapping_add(arg));
         cpu.set_pc(pc);
         return (16, 0);
     }
@@ -2777,7 +2779,7 @@ fn op_00c2(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// jp a16
 #[allow(unused_variables)]
 fn op_00c3(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let pc = mmu.get16(cpu.get_pc().wrapping_add(arg));
+    let pc = mmu.cpu_get16(cpu.get_pc().wrapping_add(arg));
     cpu.set_pc(pc.wrapping_sub(3));
 
     (16, 3)
@@ -2789,7 +2791,7 @@ fn op_00c4(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let flg = !cpu.get_zf();
     if flg {
         cpu.push(mmu, cpu.get_pc().wrapping_add(3));
-        cpu.set_pc(mmu.get16(cpu.get_pc().wrapping_add(arg)));
+        cpu.set_pc(mmu.cpu_get16(cpu.get_pc().wrapping_add(arg)));
         return (24, 0);
     }
 
@@ -2808,7 +2810,7 @@ fn op_00c5(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_00c6(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = cpu.get_a();
-    let q = mmu.get8(cpu.get_pc().wrapping_add(arg));
+    let q = mmu.cpu_get8(cpu.get_pc().wrapping_add(arg));
     let (v, h, c, z) = alu::add8(p, q, false);
     cpu.set_a(v);
     cpu.set_zf(z);
@@ -2855,7 +2857,7 @@ fn op_00c9(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 fn op_00ca(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let flg = cpu.get_zf();
     if flg {
-        let pc = mmu.get16(cpu.get_pc().wrapping_add(arg));
+        let pc = mmu.cpu_get16(cpu.get_pc().wrapping_add(arg));
         cpu.set_pc(pc);
         return (16, 0);
     }
@@ -2875,7 +2877,7 @@ fn op_00cc(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let flg = cpu.get_zf();
     if flg {
         cpu.push(mmu, cpu.get_pc().wrapping_add(3));
-        cpu.set_pc(mmu.get16(cpu.get_pc().wrapping_add(arg)));
+        cpu.set_pc(mmu.cpu_get16(cpu.get_pc().wrapping_add(arg)));
         return (24, 0);
     }
 
@@ -2886,7 +2888,10 @@ fn op_00cc(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_00cd(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     cpu.push(mmu, cpu.get_pc().wrapping_add(3));
-    cpu.set_pc(mmu.get16(cpu.get_pc().wrapping_add(arg)).wrapping_sub(3));
+    cpu.set_pc(
+        mmu.cpu_get16(cpu.get_pc().wrapping_add(arg))
+            .wrapping_sub(3),
+    );
 
     (24, 3)
 }
@@ -2895,7 +2900,7 @@ fn op_00cd(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_00ce(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = cpu.get_a();
-    let q = mmu.get8(cpu.get_pc().wrapping_add(arg));
+    let q = mmu.cpu_get8(cpu.get_pc().wrapping_add(arg));
     let (v, h, c, z) = alu::add8(p, q, cpu.get_cf());
     cpu.set_a(v);
     cpu.set_zf(z);
@@ -2942,7 +2947,7 @@ fn op_00d1(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 fn op_00d2(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let flg = !cpu.get_cf();
     if flg {
-        let pc = mmu.get16(cpu.get_pc().wrapping_add(arg));
+        let pc = mmu.cpu_get16(cpu.get_pc().wrapping_add(arg));
         cpu.set_pc(pc);
         return (16, 0);
     }
@@ -2956,7 +2961,7 @@ fn op_00d4(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let flg = !cpu.get_cf();
     if flg {
         cpu.push(mmu, cpu.get_pc().wrapping_add(3));
-        cpu.set_pc(mmu.get16(cpu.get_pc().wrapping_add(arg)));
+        cpu.set_pc(mmu.cpu_get16(cpu.get_pc().wrapping_add(arg)));
         return (24, 0);
     }
 
@@ -2975,7 +2980,7 @@ fn op_00d5(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_00d6(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = cpu.get_a();
-    let q = mmu.get8(cpu.get_pc().wrapping_add(arg));
+    let q = mmu.cpu_get8(cpu.get_pc().wrapping_add(arg));
     let (v, h, c, z) = alu::sub8(p, q, false);
     cpu.set_a(v);
     cpu.set_zf(z);
@@ -3023,7 +3028,7 @@ fn op_00d9(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 fn op_00da(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let flg = cpu.get_cf();
     if flg {
-        let pc = mmu.get16(cpu.get_pc().wrapping_add(arg));
+        let pc = mmu.cpu_get16(cpu.get_pc().wrapping_add(arg));
         cpu.set_pc(pc);
         return (16, 0);
     }
@@ -3037,7 +3042,7 @@ fn op_00dc(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let flg = cpu.get_cf();
     if flg {
         cpu.push(mmu, cpu.get_pc().wrapping_add(3));
-        cpu.set_pc(mmu.get16(cpu.get_pc().wrapping_add(arg)));
+        cpu.set_pc(mmu.cpu_get16(cpu.get_pc().wrapping_add(arg)));
         return (24, 0);
     }
 
@@ -3048,7 +3053,7 @@ fn op_00dc(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_00de(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = cpu.get_a();
-    let q = mmu.get8(cpu.get_pc().wrapping_add(arg));
+    let q = mmu.cpu_get8(cpu.get_pc().wrapping_add(arg));
     let (v, h, c, z) = alu::sub8(p, q, cpu.get_cf());
     cpu.set_a(v);
     cpu.set_zf(z);
@@ -3072,7 +3077,8 @@ fn op_00df(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_00e0(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let v = cpu.get_a();
-    mmu.set8(0xff00 + mmu.get8(cpu.get_pc().wrapping_add(arg)) as u16, v);
+    let addr = 0xff00 + mmu.cpu_get8(cpu.get_pc().wrapping_add(arg)) as u16;
+    mmu.cpu_set8(addr, v);
 
     (12, 2)
 }
@@ -3090,7 +3096,7 @@ fn op_00e1(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_00e2(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let v = cpu.get_a();
-    mmu.set8(0xff00 + cpu.get_c() as u16, v);
+    mmu.cpu_set8(0xff00 + cpu.get_c() as u16, v);
 
     (8, 1)
 }
@@ -3106,7 +3112,7 @@ fn op_00e5(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// and d8
 #[allow(unused_variables)]
 fn op_00e6(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    cpu.set_a(cpu.get_a() & mmu.get8(cpu.get_pc().wrapping_add(arg)));
+    cpu.set_a(cpu.get_a() & mmu.cpu_get8(cpu.get_pc().wrapping_add(arg)));
     let z = cpu.get_a() == 0;
     cpu.set_zf(z);
     cpu.set_nf(false);
@@ -3129,7 +3135,7 @@ fn op_00e7(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_00e8(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = cpu.get_sp();
-    let q = mmu.get8(cpu.get_pc().wrapping_add(arg));
+    let q = mmu.cpu_get8(cpu.get_pc().wrapping_add(arg));
     let (v, h, c, z) = alu::add16e(p, q, false);
     cpu.set_sp(v);
     cpu.set_zf(false);
@@ -3153,7 +3159,8 @@ fn op_00e9(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_00ea(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let v = cpu.get_a();
-    mmu.set8(mmu.get16(cpu.get_pc().wrapping_add(arg)), v);
+    let addr = mmu.cpu_get16(cpu.get_pc().wrapping_add(arg));
+    mmu.cpu_set8(addr, v);
 
     (16, 3)
 }
@@ -3161,7 +3168,7 @@ fn op_00ea(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// xor d8
 #[allow(unused_variables)]
 fn op_00ee(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    cpu.set_a(cpu.get_a() ^ mmu.get8(cpu.get_pc().wrapping_add(arg)));
+    cpu.set_a(cpu.get_a() ^ mmu.cpu_get8(cpu.get_pc().wrapping_add(arg)));
     let z = cpu.get_a() == 0;
     cpu.set_zf(z);
     cpu.set_nf(false);
@@ -3183,7 +3190,10 @@ fn op_00ef(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld a,(0xff00+a8)
 #[allow(unused_variables)]
 fn op_00f0(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(0xff00 + mmu.get8(cpu.get_pc().wrapping_add(arg)) as u16);
+    let v = {
+        let addr = 0xff00 + mmu.cpu_get8(cpu.get_pc().wrapping_add(arg)) as u16;
+        mmu.cpu_get8(addr)
+    };
     cpu.set_a(v);
 
     (12, 2)
@@ -3201,7 +3211,7 @@ fn op_00f1(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld a,(0xff00+c)
 #[allow(unused_variables)]
 fn op_00f2(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(0xff00 + cpu.get_c() as u16);
+    let v = mmu.cpu_get8(0xff00 + cpu.get_c() as u16);
     cpu.set_a(v);
 
     (8, 1)
@@ -3226,7 +3236,7 @@ fn op_00f5(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// or d8
 #[allow(unused_variables)]
 fn op_00f6(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    cpu.set_a(cpu.get_a() | mmu.get8(cpu.get_pc().wrapping_add(arg)));
+    cpu.set_a(cpu.get_a() | mmu.cpu_get8(cpu.get_pc().wrapping_add(arg)));
     let z = cpu.get_a() == 0;
     cpu.set_zf(z);
     cpu.set_nf(false);
@@ -3249,7 +3259,7 @@ fn op_00f7(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_00f8(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = cpu.get_sp();
-    let q = mmu.get8(cpu.get_pc().wrapping_add(arg));
+    let q = mmu.cpu_get8(cpu.get_pc().wrapping_add(arg));
     let (v, h, c, z) = alu::add16e(p, q, false);
     cpu.set_hl(v);
     cpu.set_zf(false);
@@ -3272,7 +3282,10 @@ fn op_00f9(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ld a,(a16)
 #[allow(unused_variables)]
 fn op_00fa(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(mmu.get16(cpu.get_pc().wrapping_add(arg)));
+    let v = {
+        let addr = mmu.cpu_get16(cpu.get_pc().wrapping_add(arg));
+        mmu.cpu_get8(addr)
+    };
     cpu.set_a(v);
 
     (16, 3)
@@ -3281,7 +3294,7 @@ fn op_00fa(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// ei
 #[allow(unused_variables)]
 fn op_00fb(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    cpu.enable_interrupt();
+    cpu.enable_interrupt_delayed();
 
     (4, 1)
 }
@@ -3290,7 +3303,7 @@ fn op_00fb(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_00fe(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = cpu.get_a();
-    let q = mmu.get8(cpu.get_pc().wrapping_add(arg));
+    let q = mmu.cpu_get8(cpu.get_pc().wrapping_add(arg));
     let (_, h, c, z) = alu::sub8(p, q, false);
     cpu.set_zf(z);
     cpu.set_nf(true);
@@ -3408,11 +3421,11 @@ fn op_cb05(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// rlc (hl)
 #[allow(unused_variables)]
 fn op_cb06(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_hl());
+    let v = mmu.cpu_get8(cpu.get_hl());
     let c = v & 0x80 != 0;
     let v = v.rotate_left(1);
     let z = v == 0;
-    mmu.set8(cpu.get_hl(), v);
+    mmu.cpu_set8(cpu.get_hl(), v);
     cpu.set_zf(z);
     cpu.set_nf(false);
     cpu.set_hf(false);
@@ -3536,11 +3549,11 @@ fn op_cb0d(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// rrc (hl)
 #[allow(unused_variables)]
 fn op_cb0e(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_hl());
+    let v = mmu.cpu_get8(cpu.get_hl());
     let c = v & 1 != 0;
     let v = v.rotate_right(1);
     let z = v == 0;
-    mmu.set8(cpu.get_hl(), v);
+    mmu.cpu_set8(cpu.get_hl(), v);
     cpu.set_zf(z);
     cpu.set_nf(false);
     cpu.set_hf(false);
@@ -3670,12 +3683,12 @@ fn op_cb15(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// rl (hl)
 #[allow(unused_variables)]
 fn op_cb16(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_hl());
+    let v = mmu.cpu_get8(cpu.get_hl());
     let c = v & 0x80 != 0;
     let v = v.wrapping_shl(1);
     let v = v | if cpu.get_cf() { 1 } else { 0 };
     let z = v == 0;
-    mmu.set8(cpu.get_hl(), v);
+    mmu.cpu_set8(cpu.get_hl(), v);
     cpu.set_zf(z);
     cpu.set_nf(false);
     cpu.set_hf(false);
@@ -3806,12 +3819,12 @@ fn op_cb1d(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// rr (hl)
 #[allow(unused_variables)]
 fn op_cb1e(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_hl());
+    let v = mmu.cpu_get8(cpu.get_hl());
     let c = v & 1 != 0;
     let v = v.wrapping_shr(1);
     let v = v | if cpu.get_cf() { 0x80 } else { 0 };
     let z = v == 0;
-    mmu.set8(cpu.get_hl(), v);
+    mmu.cpu_set8(cpu.get_hl(), v);
     cpu.set_zf(z);
     cpu.set_nf(false);
     cpu.set_hf(false);
@@ -3936,11 +3949,11 @@ fn op_cb25(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// sla (hl)
 #[allow(unused_variables)]
 fn op_cb26(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_hl());
+    let v = mmu.cpu_get8(cpu.get_hl());
     let c = v & 0x80 != 0;
     let v = v.wrapping_shl(1);
     let z = v == 0;
-    mmu.set8(cpu.get_hl(), v);
+    mmu.cpu_set8(cpu.get_hl(), v);
     cpu.set_zf(z);
     cpu.set_nf(false);
     cpu.set_hf(false);
@@ -4076,13 +4089,13 @@ fn op_cb2d(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// sra (hl)
 #[allow(unused_variables)]
 fn op_cb2e(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_hl());
+    let v = mmu.cpu_get8(cpu.get_hl());
     let c = v & 1 != 0;
     let msb = v & 0x80;
     let v = v.wrapping_shr(1);
     let v = v | msb;
     let z = v == 0;
-    mmu.set8(cpu.get_hl(), v);
+    mmu.cpu_set8(cpu.get_hl(), v);
     cpu.set_zf(z);
     cpu.set_nf(false);
     cpu.set_hf(false);
@@ -4202,9 +4215,9 @@ fn op_cb35(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// swap (hl)
 #[allow(unused_variables)]
 fn op_cb36(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_hl());
+    let v = mmu.cpu_get8(cpu.get_hl());
     let v = v.rotate_left(4);
-    mmu.set8(cpu.get_hl(), v);
+    mmu.cpu_set8(cpu.get_hl(), v);
     let z = v == 0;
     cpu.set_zf(z);
     cpu.set_nf(false);
@@ -4328,11 +4341,11 @@ fn op_cb3d(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 /// srl (hl)
 #[allow(unused_variables)]
 fn op_cb3e(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
-    let v = mmu.get8(cpu.get_hl());
+    let v = mmu.cpu_get8(cpu.get_hl());
     let c = v & 1 != 0;
     let v = v.wrapping_shr(1);
     let z = v == 0;
-    mmu.set8(cpu.get_hl(), v);
+    mmu.cpu_set8(cpu.get_hl(), v);
     cpu.set_zf(z);
     cpu.set_nf(false);
     cpu.set_hf(false);
@@ -4439,7 +4452,7 @@ fn op_cb45(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cb46(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 0;
-    let q = mmu.get8(cpu.get_hl());
+    let q = mmu.cpu_get8(cpu.get_hl());
     let z = q & (1 << p) == 0;
     cpu.set_zf(z);
     cpu.set_nf(false);
@@ -4543,7 +4556,7 @@ fn op_cb4d(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cb4e(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 1;
-    let q = mmu.get8(cpu.get_hl());
+    let q = mmu.cpu_get8(cpu.get_hl());
     let z = q & (1 << p) == 0;
     cpu.set_zf(z);
     cpu.set_nf(false);
@@ -4647,7 +4660,7 @@ fn op_cb55(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cb56(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 2;
-    let q = mmu.get8(cpu.get_hl());
+    let q = mmu.cpu_get8(cpu.get_hl());
     let z = q & (1 << p) == 0;
     cpu.set_zf(z);
     cpu.set_nf(false);
@@ -4751,7 +4764,7 @@ fn op_cb5d(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cb5e(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 3;
-    let q = mmu.get8(cpu.get_hl());
+    let q = mmu.cpu_get8(cpu.get_hl());
     let z = q & (1 << p) == 0;
     cpu.set_zf(z);
     cpu.set_nf(false);
@@ -4855,7 +4868,7 @@ fn op_cb65(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cb66(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 4;
-    let q = mmu.get8(cpu.get_hl());
+    let q = mmu.cpu_get8(cpu.get_hl());
     let z = q & (1 << p) == 0;
     cpu.set_zf(z);
     cpu.set_nf(false);
@@ -4959,7 +4972,7 @@ fn op_cb6d(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cb6e(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 5;
-    let q = mmu.get8(cpu.get_hl());
+    let q = mmu.cpu_get8(cpu.get_hl());
     let z = q & (1 << p) == 0;
     cpu.set_zf(z);
     cpu.set_nf(false);
@@ -5063,7 +5076,7 @@ fn op_cb75(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cb76(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 6;
-    let q = mmu.get8(cpu.get_hl());
+    let q = mmu.cpu_get8(cpu.get_hl());
     let z = q & (1 << p) == 0;
     cpu.set_zf(z);
     cpu.set_nf(false);
@@ -5167,7 +5180,7 @@ fn op_cb7d(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cb7e(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 7;
-    let q = mmu.get8(cpu.get_hl());
+    let q = mmu.cpu_get8(cpu.get_hl());
     let z = q & (1 << p) == 0;
     cpu.set_zf(z);
     cpu.set_nf(false);
@@ -5253,8 +5266,8 @@ fn op_cb85(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cb86(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 0;
-    let q = mmu.get8(cpu.get_hl());
-    mmu.set8(cpu.get_hl(), q & !(1 << p));
+    let q = mmu.cpu_get8(cpu.get_hl());
+    mmu.cpu_set8(cpu.get_hl(), q & !(1 << p));
 
     (16, 2)
 }
@@ -5333,8 +5346,8 @@ fn op_cb8d(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cb8e(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 1;
-    let q = mmu.get8(cpu.get_hl());
-    mmu.set8(cpu.get_hl(), q & !(1 << p));
+    let q = mmu.cpu_get8(cpu.get_hl());
+    mmu.cpu_set8(cpu.get_hl(), q & !(1 << p));
 
     (16, 2)
 }
@@ -5413,8 +5426,8 @@ fn op_cb95(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cb96(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 2;
-    let q = mmu.get8(cpu.get_hl());
-    mmu.set8(cpu.get_hl(), q & !(1 << p));
+    let q = mmu.cpu_get8(cpu.get_hl());
+    mmu.cpu_set8(cpu.get_hl(), q & !(1 << p));
 
     (16, 2)
 }
@@ -5493,8 +5506,8 @@ fn op_cb9d(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cb9e(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 3;
-    let q = mmu.get8(cpu.get_hl());
-    mmu.set8(cpu.get_hl(), q & !(1 << p));
+    let q = mmu.cpu_get8(cpu.get_hl());
+    mmu.cpu_set8(cpu.get_hl(), q & !(1 << p));
 
     (16, 2)
 }
@@ -5573,8 +5586,8 @@ fn op_cba5(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cba6(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 4;
-    let q = mmu.get8(cpu.get_hl());
-    mmu.set8(cpu.get_hl(), q & !(1 << p));
+    let q = mmu.cpu_get8(cpu.get_hl());
+    mmu.cpu_set8(cpu.get_hl(), q & !(1 << p));
 
     (16, 2)
 }
@@ -5653,8 +5666,8 @@ fn op_cbad(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cbae(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 5;
-    let q = mmu.get8(cpu.get_hl());
-    mmu.set8(cpu.get_hl(), q & !(1 << p));
+    let q = mmu.cpu_get8(cpu.get_hl());
+    mmu.cpu_set8(cpu.get_hl(), q & !(1 << p));
 
     (16, 2)
 }
@@ -5733,8 +5746,8 @@ fn op_cbb5(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cbb6(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 6;
-    let q = mmu.get8(cpu.get_hl());
-    mmu.set8(cpu.get_hl(), q & !(1 << p));
+    let q = mmu.cpu_get8(cpu.get_hl());
+    mmu.cpu_set8(cpu.get_hl(), q & !(1 << p));
 
     (16, 2)
 }
@@ -5813,8 +5826,8 @@ fn op_cbbd(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cbbe(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 7;
-    let q = mmu.get8(cpu.get_hl());
-    mmu.set8(cpu.get_hl(), q & !(1 << p));
+    let q = mmu.cpu_get8(cpu.get_hl());
+    mmu.cpu_set8(cpu.get_hl(), q & !(1 << p));
 
     (16, 2)
 }
@@ -5893,8 +5906,8 @@ fn op_cbc5(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cbc6(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 0;
-    let q = mmu.get8(cpu.get_hl());
-    mmu.set8(cpu.get_hl(), q | (1 << p));
+    let q = mmu.cpu_get8(cpu.get_hl());
+    mmu.cpu_set8(cpu.get_hl(), q | (1 << p));
 
     (16, 2)
 }
@@ -5973,8 +5986,8 @@ fn op_cbcd(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cbce(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 1;
-    let q = mmu.get8(cpu.get_hl());
-    mmu.set8(cpu.get_hl(), q | (1 << p));
+    let q = mmu.cpu_get8(cpu.get_hl());
+    mmu.cpu_set8(cpu.get_hl(), q | (1 << p));
 
     (16, 2)
 }
@@ -6053,8 +6066,8 @@ fn op_cbd5(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cbd6(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 2;
-    let q = mmu.get8(cpu.get_hl());
-    mmu.set8(cpu.get_hl(), q | (1 << p));
+    let q = mmu.cpu_get8(cpu.get_hl());
+    mmu.cpu_set8(cpu.get_hl(), q | (1 << p));
 
     (16, 2)
 }
@@ -6133,8 +6146,8 @@ fn op_cbdd(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cbde(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 3;
-    let q = mmu.get8(cpu.get_hl());
-    mmu.set8(cpu.get_hl(), q | (1 << p));
+    let q = mmu.cpu_get8(cpu.get_hl());
+    mmu.cpu_set8(cpu.get_hl(), q | (1 << p));
 
     (16, 2)
 }
@@ -6213,8 +6226,8 @@ fn op_cbe5(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cbe6(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 4;
-    let q = mmu.get8(cpu.get_hl());
-    mmu.set8(cpu.get_hl(), q | (1 << p));
+    let q = mmu.cpu_get8(cpu.get_hl());
+    mmu.cpu_set8(cpu.get_hl(), q | (1 << p));
 
     (16, 2)
 }
@@ -6293,8 +6306,8 @@ fn op_cbed(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cbee(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 5;
-    let q = mmu.get8(cpu.get_hl());
-    mmu.set8(cpu.get_hl(), q | (1 << p));
+    let q = mmu.cpu_get8(cpu.get_hl());
+    mmu.cpu_set8(cpu.get_hl(), q | (1 << p));
 
     (16, 2)
 }
@@ -6373,8 +6386,8 @@ fn op_cbf5(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cbf6(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 6;
-    let q = mmu.get8(cpu.get_hl());
-    mmu.set8(cpu.get_hl(), q | (1 << p));
+    let q = mmu.cpu_get8(cpu.get_hl());
+    mmu.cpu_set8(cpu.get_hl(), q | (1 << p));
 
     (16, 2)
 }
@@ -6453,8 +6466,8 @@ fn op_cbfd(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
 #[allow(unused_variables)]
 fn op_cbfe(arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
     let p = 7;
-    let q = mmu.get8(cpu.get_hl());
-    mmu.set8(cpu.get_hl(), q | (1 << p));
+    let q = mmu.cpu_get8(cpu.get_hl());
+    mmu.cpu_set8(cpu.get_hl(), q | (1 << p));
 
     (16, 2)
 }
@@ -6475,511 +6488,514 @@ pub fn mnem(code: u16) -> &'static str {
 }
 
 /// Decodes the opecode and actually executes one instruction.
-pub fn decode(code: u16, arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> (usize, usize) {
+pub fn decode(code: u16, arg: u16, cpu: &mut Cpu, mmu: &mut Mmu) -> Result<(usize, usize), Error> {
     trace!("{:04x}: {:04x}: {}", cpu.get_pc(), code, mnem(code));
 
     match code {
-        0x0000 => op_0000(arg, cpu, mmu),
-        0x0001 => op_0001(arg, cpu, mmu),
-        0x0002 => op_0002(arg, cpu, mmu),
-        0x0003 => op_0003(arg, cpu, mmu),
-        0x0004 => op_0004(arg, cpu, mmu),
-        0x0005 => op_0005(arg, cpu, mmu),
-        0x0006 => op_0006(arg, cpu, mmu),
-        0x0007 => op_0007(arg, cpu, mmu),
-        0x0008 => op_0008(arg, cpu, mmu),
-        0x0009 => op_0009(arg, cpu, mmu),
-        0x000a => op_000a(arg, cpu, mmu),
-        0x000b => op_000b(arg, cpu, mmu),
-        0x000c => op_000c(arg, cpu, mmu),
-        0x000d => op_000d(arg, cpu, mmu),
-        0x000e => op_000e(arg, cpu, mmu),
-        0x000f => op_000f(arg, cpu, mmu),
-        0x0010 => op_0010(arg, cpu, mmu),
-        0x0011 => op_0011(arg, cpu, mmu),
-        0x0012 => op_0012(arg, cpu, mmu),
-        0x0013 => op_0013(arg, cpu, mmu),
-        0x0014 => op_0014(arg, cpu, mmu),
-        0x0015 => op_0015(arg, cpu, mmu),
-        0x0016 => op_0016(arg, cpu, mmu),
-        0x0017 => op_0017(arg, cpu, mmu),
-        0x0018 => op_0018(arg, cpu, mmu),
-        0x0019 => op_0019(arg, cpu, mmu),
-        0x001a => op_001a(arg, cpu, mmu),
-        0x001b => op_001b(arg, cpu, mmu),
-        0x001c => op_001c(arg, cpu, mmu),
-        0x001d => op_001d(arg, cpu, mmu),
-        0x001e => op_001e(arg, cpu, mmu),
-        0x001f => op_001f(arg, cpu, mmu),
-        0x0020 => op_0020(arg, cpu, mmu),
-        0x0021 => op_0021(arg, cpu, mmu),
-        0x0022 => op_0022(arg, cpu, mmu),
-        0x0023 => op_0023(arg, cpu, mmu),
-        0x0024 => op_0024(arg, cpu, mmu),
-        0x0025 => op_0025(arg, cpu, mmu),
-        0x0026 => op_0026(arg, cpu, mmu),
-        0x0027 => op_0027(arg, cpu, mmu),
-        0x0028 => op_0028(arg, cpu, mmu),
-        0x0029 => op_0029(arg, cpu, mmu),
-        0x002a => op_002a(arg, cpu, mmu),
-        0x002b => op_002b(arg, cpu, mmu),
-        0x002c => op_002c(arg, cpu, mmu),
-        0x002d => op_002d(arg, cpu, mmu),
-        0x002e => op_002e(arg, cpu, mmu),
-        0x002f => op_002f(arg, cpu, mmu),
-        0x0030 => op_0030(arg, cpu, mmu),
-        0x0031 => op_0031(arg, cpu, mmu),
-        0x0032 => op_0032(arg, cpu, mmu),
-        0x0033 => op_0033(arg, cpu, mmu),
-        0x0034 => op_0034(arg, cpu, mmu),
-        0x0035 => op_0035(arg, cpu, mmu),
-        0x0036 => op_0036(arg, cpu, mmu),
-        0x0037 => op_0037(arg, cpu, mmu),
-        0x0038 => op_0038(arg, cpu, mmu),
-        0x0039 => op_0039(arg, cpu, mmu),
-        0x003a => op_003a(arg, cpu, mmu),
-        0x003b => op_003b(arg, cpu, mmu),
-        0x003c => op_003c(arg, cpu, mmu),
-        0x003d => op_003d(arg, cpu, mmu),
-        0x003e => op_003e(arg, cpu, mmu),
-        0x003f => op_003f(arg, cpu, mmu),
-        0x0040 => op_0040(arg, cpu, mmu),
-        0x0041 => op_0041(arg, cpu, mmu),
-        0x0042 => op_0042(arg, cpu, mmu),
-        0x0043 => op_0043(arg, cpu, mmu),
-        0x0044 => op_0044(arg, cpu, mmu),
-        0x0045 => op_0045(arg, cpu, mmu),
-        0x0046 => op_0046(arg, cpu, mmu),
-        0x0047 => op_0047(arg, cpu, mmu),
-        0x0048 => op_0048(arg, cpu, mmu),
-        0x0049 => op_0049(arg, cpu, mmu),
-        0x004a => op_004a(arg, cpu, mmu),
-        0x004b => op_004b(arg, cpu, mmu),
-        0x004c => op_004c(arg, cpu, mmu),
-        0x004d => op_004d(arg, cpu, mmu),
-        0x004e => op_004e(arg, cpu, mmu),
-        0x004f => op_004f(arg, cpu, mmu),
-        0x0050 => op_0050(arg, cpu, mmu),
-        0x0051 => op_0051(arg, cpu, mmu),
-        0x0052 => op_0052(arg, cpu, mmu),
-        0x0053 => op_0053(arg, cpu, mmu),
-        0x0054 => op_0054(arg, cpu, mmu),
-        0x0055 => op_0055(arg, cpu, mmu),
-        0x0056 => op_0056(arg, cpu, mmu),
-        0x0057 => op_0057(arg, cpu, mmu),
-        0x0058 => op_0058(arg, cpu, mmu),
-        0x0059 => op_0059(arg, cpu, mmu),
-        0x005a => op_005a(arg, cpu, mmu),
-        0x005b => op_005b(arg, cpu, mmu),
-        0x005c => op_005c(arg, cpu, mmu),
-        0x005d => op_005d(arg, cpu, mmu),
-        0x005e => op_005e(arg, cpu, mmu),
-        0x005f => op_005f(arg, cpu, mmu),
-        0x0060 => op_0060(arg, cpu, mmu),
-        0x0061 => op_0061(arg, cpu, mmu),
-        0x0062 => op_0062(arg, cpu, mmu),
-        0x0063 => op_0063(arg, cpu, mmu),
-        0x0064 => op_0064(arg, cpu, mmu),
-        0x0065 => op_0065(arg, cpu, mmu),
-        0x0066 => op_0066(arg, cpu, mmu),
-        0x0067 => op_0067(arg, cpu, mmu),
-        0x0068 => op_0068(arg, cpu, mmu),
-        0x0069 => op_0069(arg, cpu, mmu),
-        0x006a => op_006a(arg, cpu, mmu),
-        0x006b => op_006b(arg, cpu, mmu),
-        0x006c => op_006c(arg, cpu, mmu),
-        0x006d => op_006d(arg, cpu, mmu),
-        0x006e => op_006e(arg, cpu, mmu),
-        0x006f => op_006f(arg, cpu, mmu),
-        0x0070 => op_0070(arg, cpu, mmu),
-        0x0071 => op_0071(arg, cpu, mmu),
-        0x0072 => op_0072(arg, cpu, mmu),
-        0x0073 => op_0073(arg, cpu, mmu),
-        0x0074 => op_0074(arg, cpu, mmu),
-        0x0075 => op_0075(arg, cpu, mmu),
-        0x0076 => op_0076(arg, cpu, mmu),
-        0x0077 => op_0077(arg, cpu, mmu),
-        0x0078 => op_0078(arg, cpu, mmu),
-        0x0079 => op_0079(arg, cpu, mmu),
-        0x007a => op_007a(arg, cpu, mmu),
-        0x007b => op_007b(arg, cpu, mmu),
-        0x007c => op_007c(arg, cpu, mmu),
-        0x007d => op_007d(arg, cpu, mmu),
-        0x007e => op_007e(arg, cpu, mmu),
-        0x007f => op_007f(arg, cpu, mmu),
-        0x0080 => op_0080(arg, cpu, mmu),
-        0x0081 => op_0081(arg, cpu, mmu),
-        0x0082 => op_0082(arg, cpu, mmu),
-        0x0083 => op_0083(arg, cpu, mmu),
-        0x0084 => op_0084(arg, cpu, mmu),
-        0x0085 => op_0085(arg, cpu, mmu),
-        0x0086 => op_0086(arg, cpu, mmu),
-        0x0087 => op_0087(arg, cpu, mmu),
-        0x0088 => op_0088(arg, cpu, mmu),
-        0x0089 => op_0089(arg, cpu, mmu),
-        0x008a => op_008a(arg, cpu, mmu),
-        0x008b => op_008b(arg, cpu, mmu),
-        0x008c => op_008c(arg, cpu, mmu),
-        0x008d => op_008d(arg, cpu, mmu),
-        0x008e => op_008e(arg, cpu, mmu),
-        0x008f => op_008f(arg, cpu, mmu),
-        0x0090 => op_0090(arg, cpu, mmu),
-        0x0091 => op_0091(arg, cpu, mmu),
-        0x0092 => op_0092(arg, cpu, mmu),
-        0x0093 => op_0093(arg, cpu, mmu),
-        0x0094 => op_0094(arg, cpu, mmu),
-        0x0095 => op_0095(arg, cpu, mmu),
-        0x0096 => op_0096(arg, cpu, mmu),
-        0x0097 => op_0097(arg, cpu, mmu),
-        0x0098 => op_0098(arg, cpu, mmu),
-        0x0099 => op_0099(arg, cpu, mmu),
-        0x009a => op_009a(arg, cpu, mmu),
-        0x009b => op_009b(arg, cpu, mmu),
-        0x009c => op_009c(arg, cpu, mmu),
-        0x009d => op_009d(arg, cpu, mmu),
-        0x009e => op_009e(arg, cpu, mmu),
-        0x009f => op_009f(arg, cpu, mmu),
-        0x00a0 => op_00a0(arg, cpu, mmu),
-        0x00a1 => op_00a1(arg, cpu, mmu),
-        0x00a2 => op_00a2(arg, cpu, mmu),
-        0x00a3 => op_00a3(arg, cpu, mmu),
-        0x00a4 => op_00a4(arg, cpu, mmu),
-        0x00a5 => op_00a5(arg, cpu, mmu),
-        0x00a6 => op_00a6(arg, cpu, mmu),
-        0x00a7 => op_00a7(arg, cpu, mmu),
-        0x00a8 => op_00a8(arg, cpu, mmu),
-        0x00a9 => op_00a9(arg, cpu, mmu),
-        0x00aa => op_00aa(arg, cpu, mmu),
-        0x00ab => op_00ab(arg, cpu, mmu),
-        0x00ac => op_00ac(arg, cpu, mmu),
-        0x00ad => op_00ad(arg, cpu, mmu),
-        0x00ae => op_00ae(arg, cpu, mmu),
-        0x00af => op_00af(arg, cpu, mmu),
-        0x00b0 => op_00b0(arg, cpu, mmu),
-        0x00b1 => op_00b1(arg, cpu, mmu),
-        0x00b2 => op_00b2(arg, cpu, mmu),
-        0x00b3 => op_00b3(arg, cpu, mmu),
-        0x00b4 => op_00b4(arg, cpu, mmu),
-        0x00b5 => op_00b5(arg, cpu, mmu),
-        0x00b6 => op_00b6(arg, cpu, mmu),
-        0x00b7 => op_00b7(arg, cpu, mmu),
-        0x00b8 => op_00b8(arg, cpu, mmu),
-        0x00b9 => op_00b9(arg, cpu, mmu),
-        0x00ba => op_00ba(arg, cpu, mmu),
-        0x00bb => op_00bb(arg, cpu, mmu),
-        0x00bc => op_00bc(arg, cpu, mmu),
-        0x00bd => op_00bd(arg, cpu, mmu),
-        0x00be => op_00be(arg, cpu, mmu),
-        0x00bf => op_00bf(arg, cpu, mmu),
-        0x00c0 => op_00c0(arg, cpu, mmu),
-        0x00c1 => op_00c1(arg, cpu, mmu),
-        0x00c2 => op_00c2(arg, cpu, mmu),
-        0x00c3 => op_00c3(arg, cpu, mmu),
-        0x00c4 => op_00c4(arg, cpu, mmu),
-        0x00c5 => op_00c5(arg, cpu, mmu),
-        0x00c6 => op_00c6(arg, cpu, mmu),
-        0x00c7 => op_00c7(arg, cpu, mmu),
-        0x00c8 => op_00c8(arg, cpu, mmu),
-        0x00c9 => op_00c9(arg, cpu, mmu),
-        0x00ca => op_00ca(arg, cpu, mmu),
-        0x00cb => op_00cb(arg, cpu, mmu),
-        0x00cc => op_00cc(arg, cpu, mmu),
-        0x00cd => op_00cd(arg, cpu, mmu),
-        0x00ce => op_00ce(arg, cpu, mmu),
-        0x00cf => op_00cf(arg, cpu, mmu),
-        0x00d0 => op_00d0(arg, cpu, mmu),
-        0x00d1 => op_00d1(arg, cpu, mmu),
-        0x00d2 => op_00d2(arg, cpu, mmu),
-        0x00d4 => op_00d4(arg, cpu, mmu),
-        0x00d5 => op_00d5(arg, cpu, mmu),
-        0x00d6 => op_00d6(arg, cpu, mmu),
-        0x00d7 => op_00d7(arg, cpu, mmu),
-        0x00d8 => op_00d8(arg, cpu, mmu),
-        0x00d9 => op_00d9(arg, cpu, mmu),
-        0x00da => op_00da(arg, cpu, mmu),
-        0x00dc => op_00dc(arg, cpu, mmu),
-        0x00de => op_00de(arg, cpu, mmu),
-        0x00df => op_00df(arg, cpu, mmu),
-        0x00e0 => op_00e0(arg, cpu, mmu),
-        0x00e1 => op_00e1(arg, cpu, mmu),
-        0x00e2 => op_00e2(arg, cpu, mmu),
-        0x00e5 => op_00e5(arg, cpu, mmu),
-        0x00e6 => op_00e6(arg, cpu, mmu),
-        0x00e7 => op_00e7(arg, cpu, mmu),
-        0x00e8 => op_00e8(arg, cpu, mmu),
-        0x00e9 => op_00e9(arg, cpu, mmu),
-        0x00ea => op_00ea(arg, cpu, mmu),
-        0x00ee => op_00ee(arg, cpu, mmu),
-        0x00ef => op_00ef(arg, cpu, mmu),
-        0x00f0 => op_00f0(arg, cpu, mmu),
-        0x00f1 => op_00f1(arg, cpu, mmu),
-        0x00f2 => op_00f2(arg, cpu, mmu),
-        0x00f3 => op_00f3(arg, cpu, mmu),
-        0x00f5 => op_00f5(arg, cpu, mmu),
-        0x00f6 => op_00f6(arg, cpu, mmu),
-        0x00f7 => op_00f7(arg, cpu, mmu),
-        0x00f8 => op_00f8(arg, cpu, mmu),
-        0x00f9 => op_00f9(arg, cpu, mmu),
-        0x00fa => op_00fa(arg, cpu, mmu),
-        0x00fb => op_00fb(arg, cpu, mmu),
-        0x00fe => op_00fe(arg, cpu, mmu),
-        0x00ff => op_00ff(arg, cpu, mmu),
-        0xcb00 => op_cb00(arg, cpu, mmu),
-        0xcb01 => op_cb01(arg, cpu, mmu),
-        0xcb02 => op_cb02(arg, cpu, mmu),
-        0xcb03 => op_cb03(arg, cpu, mmu),
-        0xcb04 => op_cb04(arg, cpu, mmu),
-        0xcb05 => op_cb05(arg, cpu, mmu),
-        0xcb06 => op_cb06(arg, cpu, mmu),
-        0xcb07 => op_cb07(arg, cpu, mmu),
-        0xcb08 => op_cb08(arg, cpu, mmu),
-        0xcb09 => op_cb09(arg, cpu, mmu),
-        0xcb0a => op_cb0a(arg, cpu, mmu),
-        0xcb0b => op_cb0b(arg, cpu, mmu),
-        0xcb0c => op_cb0c(arg, cpu, mmu),
-        0xcb0d => op_cb0d(arg, cpu, mmu),
-        0xcb0e => op_cb0e(arg, cpu, mmu),
-        0xcb0f => op_cb0f(arg, cpu, mmu),
-        0xcb10 => op_cb10(arg, cpu, mmu),
-        0xcb11 => op_cb11(arg, cpu, mmu),
-        0xcb12 => op_cb12(arg, cpu, mmu),
-        0xcb13 => op_cb13(arg, cpu, mmu),
-        0xcb14 => op_cb14(arg, cpu, mmu),
-        0xcb15 => op_cb15(arg, cpu, mmu),
-        0xcb16 => op_cb16(arg, cpu, mmu),
-        0xcb17 => op_cb17(arg, cpu, mmu),
-        0xcb18 => op_cb18(arg, cpu, mmu),
-        0xcb19 => op_cb19(arg, cpu, mmu),
-        0xcb1a => op_cb1a(arg, cpu, mmu),
-        0xcb1b => op_cb1b(arg, cpu, mmu),
-        0xcb1c => op_cb1c(arg, cpu, mmu),
-        0xcb1d => op_cb1d(arg, cpu, mmu),
-        0xcb1e => op_cb1e(arg, cpu, mmu),
-        0xcb1f => op_cb1f(arg, cpu, mmu),
-        0xcb20 => op_cb20(arg, cpu, mmu),
-        0xcb21 => op_cb21(arg, cpu, mmu),
-        0xcb22 => op_cb22(arg, cpu, mmu),
-        0xcb23 => op_cb23(arg, cpu, mmu),
-        0xcb24 => op_cb24(arg, cpu, mmu),
-        0xcb25 => op_cb25(arg, cpu, mmu),
-        0xcb26 => op_cb26(arg, cpu, mmu),
-        0xcb27 => op_cb27(arg, cpu, mmu),
-        0xcb28 => op_cb28(arg, cpu, mmu),
-        0xcb29 => op_cb29(arg, cpu, mmu),
-        0xcb2a => op_cb2a(arg, cpu, mmu),
-        0xcb2b => op_cb2b(arg, cpu, mmu),
-        0xcb2c => op_cb2c(arg, cpu, mmu),
-        0xcb2d => op_cb2d(arg, cpu, mmu),
-        0xcb2e => op_cb2e(arg, cpu, mmu),
-        0xcb2f => op_cb2f(arg, cpu, mmu),
-        0xcb30 => op_cb30(arg, cpu, mmu),
-        0xcb31 => op_cb31(arg, cpu, mmu),
-        0xcb32 => op_cb32(arg, cpu, mmu),
-        0xcb33 => op_cb33(arg, cpu, mmu),
-        0xcb34 => op_cb34(arg, cpu, mmu),
-        0xcb35 => op_cb35(arg, cpu, mmu),
-        0xcb36 => op_cb36(arg, cpu, mmu),
-        0xcb37 => op_cb37(arg, cpu, mmu),
-        0xcb38 => op_cb38(arg, cpu, mmu),
-        0xcb39 => op_cb39(arg, cpu, mmu),
-        0xcb3a => op_cb3a(arg, cpu, mmu),
-        0xcb3b => op_cb3b(arg, cpu, mmu),
-        0xcb3c => op_cb3c(arg, cpu, mmu),
-        0xcb3d => op_cb3d(arg, cpu, mmu),
-        0xcb3e => op_cb3e(arg, cpu, mmu),
-        0xcb3f => op_cb3f(arg, cpu, mmu),
-        0xcb40 => op_cb40(arg, cpu, mmu),
-        0xcb41 => op_cb41(arg, cpu, mmu),
-        0xcb42 => op_cb42(arg, cpu, mmu),
-        0xcb43 => op_cb43(arg, cpu, mmu),
-        0xcb44 => op_cb44(arg, cpu, mmu),
-        0xcb45 => op_cb45(arg, cpu, mmu),
-        0xcb46 => op_cb46(arg, cpu, mmu),
-        0xcb47 => op_cb47(arg, cpu, mmu),
-        0xcb48 => op_cb48(arg, cpu, mmu),
-        0xcb49 => op_cb49(arg, cpu, mmu),
-        0xcb4a => op_cb4a(arg, cpu, mmu),
-        0xcb4b => op_cb4b(arg, cpu, mmu),
-        0xcb4c => op_cb4c(arg, cpu, mmu),
-        0xcb4d => op_cb4d(arg, cpu, mmu),
-        0xcb4e => op_cb4e(arg, cpu, mmu),
-        0xcb4f => op_cb4f(arg, cpu, mmu),
-        0xcb50 => op_cb50(arg, cpu, mmu),
-        0xcb51 => op_cb51(arg, cpu, mmu),
-        0xcb52 => op_cb52(arg, cpu, mmu),
-        0xcb53 => op_cb53(arg, cpu, mmu),
-        0xcb54 => op_cb54(arg, cpu, mmu),
-        0xcb55 => op_cb55(arg, cpu, mmu),
-        0xcb56 => op_cb56(arg, cpu, mmu),
-        0xcb57 => op_cb57(arg, cpu, mmu),
-        0xcb58 => op_cb58(arg, cpu, mmu),
-        0xcb59 => op_cb59(arg, cpu, mmu),
-        0xcb5a => op_cb5a(arg, cpu, mmu),
-        0xcb5b => op_cb5b(arg, cpu, mmu),
-        0xcb5c => op_cb5c(arg, cpu, mmu),
-        0xcb5d => op_cb5d(arg, cpu, mmu),
-        0xcb5e => op_cb5e(arg, cpu, mmu),
-        0xcb5f => op_cb5f(arg, cpu, mmu),
-        0xcb60 => op_cb60(arg, cpu, mmu),
-        0xcb61 => op_cb61(arg, cpu, mmu),
-        0xcb62 => op_cb62(arg, cpu, mmu),
-        0xcb63 => op_cb63(arg, cpu, mmu),
-        0xcb64 => op_cb64(arg, cpu, mmu),
-        0xcb65 => op_cb65(arg, cpu, mmu),
-        0xcb66 => op_cb66(arg, cpu, mmu),
-        0xcb67 => op_cb67(arg, cpu, mmu),
-        0xcb68 => op_cb68(arg, cpu, mmu),
-        0xcb69 => op_cb69(arg, cpu, mmu),
-        0xcb6a => op_cb6a(arg, cpu, mmu),
-        0xcb6b => op_cb6b(arg, cpu, mmu),
-        0xcb6c => op_cb6c(arg, cpu, mmu),
-        0xcb6d => op_cb6d(arg, cpu, mmu),
-        0xcb6e => op_cb6e(arg, cpu, mmu),
-        0xcb6f => op_cb6f(arg, cpu, mmu),
-        0xcb70 => op_cb70(arg, cpu, mmu),
-        0xcb71 => op_cb71(arg, cpu, mmu),
-        0xcb72 => op_cb72(arg, cpu, mmu),
-        0xcb73 => op_cb73(arg, cpu, mmu),
-        0xcb74 => op_cb74(arg, cpu, mmu),
-        0xcb75 => op_cb75(arg, cpu, mmu),
-        0xcb76 => op_cb76(arg, cpu, mmu),
-        0xcb77 => op_cb77(arg, cpu, mmu),
-        0xcb78 => op_cb78(arg, cpu, mmu),
-        0xcb79 => op_cb79(arg, cpu, mmu),
-        0xcb7a => op_cb7a(arg, cpu, mmu),
-        0xcb7b => op_cb7b(arg, cpu, mmu),
-        0xcb7c => op_cb7c(arg, cpu, mmu),
-        0xcb7d => op_cb7d(arg, cpu, mmu),
-        0xcb7e => op_cb7e(arg, cpu, mmu),
-        0xcb7f => op_cb7f(arg, cpu, mmu),
-        0xcb80 => op_cb80(arg, cpu, mmu),
-        0xcb81 => op_cb81(arg, cpu, mmu),
-        0xcb82 => op_cb82(arg, cpu, mmu),
-        0xcb83 => op_cb83(arg, cpu, mmu),
-        0xcb84 => op_cb84(arg, cpu, mmu),
-        0xcb85 => op_cb85(arg, cpu, mmu),
-        0xcb86 => op_cb86(arg, cpu, mmu),
-        0xcb87 => op_cb87(arg, cpu, mmu),
-        0xcb88 => op_cb88(arg, cpu, mmu),
-        0xcb89 => op_cb89(arg, cpu, mmu),
-        0xcb8a => op_cb8a(arg, cpu, mmu),
-        0xcb8b => op_cb8b(arg, cpu, mmu),
-        0xcb8c => op_cb8c(arg, cpu, mmu),
-        0xcb8d => op_cb8d(arg, cpu, mmu),
-        0xcb8e => op_cb8e(arg, cpu, mmu),
-        0xcb8f => op_cb8f(arg, cpu, mmu),
-        0xcb90 => op_cb90(arg, cpu, mmu),
-        0xcb91 => op_cb91(arg, cpu, mmu),
-        0xcb92 => op_cb92(arg, cpu, mmu),
-        0xcb93 => op_cb93(arg, cpu, mmu),
-        0xcb94 => op_cb94(arg, cpu, mmu),
-        0xcb95 => op_cb95(arg, cpu, mmu),
-        0xcb96 => op_cb96(arg, cpu, mmu),
-        0xcb97 => op_cb97(arg, cpu, mmu),
-        0xcb98 => op_cb98(arg, cpu, mmu),
-        0xcb99 => op_cb99(arg, cpu, mmu),
-        0xcb9a => op_cb9a(arg, cpu, mmu),
-        0xcb9b => op_cb9b(arg, cpu, mmu),
-        0xcb9c => op_cb9c(arg, cpu, mmu),
-        0xcb9d => op_cb9d(arg, cpu, mmu),
-        0xcb9e => op_cb9e(arg, cpu, mmu),
-        0xcb9f => op_cb9f(arg, cpu, mmu),
-        0xcba0 => op_cba0(arg, cpu, mmu),
-        0xcba1 => op_cba1(arg, cpu, mmu),
-        0xcba2 => op_cba2(arg, cpu, mmu),
-        0xcba3 => op_cba3(arg, cpu, mmu),
-        0xcba4 => op_cba4(arg, cpu, mmu),
-        0xcba5 => op_cba5(arg, cpu, mmu),
-        0xcba6 => op_cba6(arg, cpu, mmu),
-        0xcba7 => op_cba7(arg, cpu, mmu),
-        0xcba8 => op_cba8(arg, cpu, mmu),
-        0xcba9 => op_cba9(arg, cpu, mmu),
-        0xcbaa => op_cbaa(arg, cpu, mmu),
-        0xcbab => op_cbab(arg, cpu, mmu),
-        0xcbac => op_cbac(arg, cpu, mmu),
-        0xcbad => op_cbad(arg, cpu, mmu),
-        0xcbae => op_cbae(arg, cpu, mmu),
-        0xcbaf => op_cbaf(arg, cpu, mmu),
-        0xcbb0 => op_cbb0(arg, cpu, mmu),
-        0xcbb1 => op_cbb1(arg, cpu, mmu),
-        0xcbb2 => op_cbb2(arg, cpu, mmu),
-        0xcbb3 => op_cbb3(arg, cpu, mmu),
-        0xcbb4 => op_cbb4(arg, cpu, mmu),
-        0xcbb5 => op_cbb5(arg, cpu, mmu),
-        0xcbb6 => op_cbb6(arg, cpu, mmu),
-        0xcbb7 => op_cbb7(arg, cpu, mmu),
-        0xcbb8 => op_cbb8(arg, cpu, mmu),
-        0xcbb9 => op_cbb9(arg, cpu, mmu),
-        0xcbba => op_cbba(arg, cpu, mmu),
-        0xcbbb => op_cbbb(arg, cpu, mmu),
-        0xcbbc => op_cbbc(arg, cpu, mmu),
-        0xcbbd => op_cbbd(arg, cpu, mmu),
-        0xcbbe => op_cbbe(arg, cpu, mmu),
-        0xcbbf => op_cbbf(arg, cpu, mmu),
-        0xcbc0 => op_cbc0(arg, cpu, mmu),
-        0xcbc1 => op_cbc1(arg, cpu, mmu),
-        0xcbc2 => op_cbc2(arg, cpu, mmu),
-        0xcbc3 => op_cbc3(arg, cpu, mmu),
-        0xcbc4 => op_cbc4(arg, cpu, mmu),
-        0xcbc5 => op_cbc5(arg, cpu, mmu),
-        0xcbc6 => op_cbc6(arg, cpu, mmu),
-        0xcbc7 => op_cbc7(arg, cpu, mmu),
-        0xcbc8 => op_cbc8(arg, cpu, mmu),
-        0xcbc9 => op_cbc9(arg, cpu, mmu),
-        0xcbca => op_cbca(arg, cpu, mmu),
-        0xcbcb => op_cbcb(arg, cpu, mmu),
-        0xcbcc => op_cbcc(arg, cpu, mmu),
-        0xcbcd => op_cbcd(arg, cpu, mmu),
-        0xcbce => op_cbce(arg, cpu, mmu),
-        0xcbcf => op_cbcf(arg, cpu, mmu),
-        0xcbd0 => op_cbd0(arg, cpu, mmu),
-        0xcbd1 => op_cbd1(arg, cpu, mmu),
-        0xcbd2 => op_cbd2(arg, cpu, mmu),
-        0xcbd3 => op_cbd3(arg, cpu, mmu),
-        0xcbd4 => op_cbd4(arg, cpu, mmu),
-        0xcbd5 => op_cbd5(arg, cpu, mmu),
-        0xcbd6 => op_cbd6(arg, cpu, mmu),
-        0xcbd7 => op_cbd7(arg, cpu, mmu),
-        0xcbd8 => op_cbd8(arg, cpu, mmu),
-        0xcbd9 => op_cbd9(arg, cpu, mmu),
-        0xcbda => op_cbda(arg, cpu, mmu),
-        0xcbdb => op_cbdb(arg, cpu, mmu),
-        0xcbdc => op_cbdc(arg, cpu, mmu),
-        0xcbdd => op_cbdd(arg, cpu, mmu),
-        0xcbde => op_cbde(arg, cpu, mmu),
-        0xcbdf => op_cbdf(arg, cpu, mmu),
-        0xcbe0 => op_cbe0(arg, cpu, mmu),
-        0xcbe1 => op_cbe1(arg, cpu, mmu),
-        0xcbe2 => op_cbe2(arg, cpu, mmu),
-        0xcbe3 => op_cbe3(arg, cpu, mmu),
-        0xcbe4 => op_cbe4(arg, cpu, mmu),
-        0xcbe5 => op_cbe5(arg, cpu, mmu),
-        0xcbe6 => op_cbe6(arg, cpu, mmu),
-        0xcbe7 => op_cbe7(arg, cpu, mmu),
-        0xcbe8 => op_cbe8(arg, cpu, mmu),
-        0xcbe9 => op_cbe9(arg, cpu, mmu),
-        0xcbea => op_cbea(arg, cpu, mmu),
-        0xcbeb => op_cbeb(arg, cpu, mmu),
-        0xcbec => op_cbec(arg, cpu, mmu),
-        0xcbed => op_cbed(arg, cpu, mmu),
-        0xcbee => op_cbee(arg, cpu, mmu),
-        0xcbef => op_cbef(arg, cpu, mmu),
-        0xcbf0 => op_cbf0(arg, cpu, mmu),
-        0xcbf1 => op_cbf1(arg, cpu, mmu),
-        0xcbf2 => op_cbf2(arg, cpu, mmu),
-        0xcbf3 => op_cbf3(arg, cpu, mmu),
-        0xcbf4 => op_cbf4(arg, cpu, mmu),
-        0xcbf5 => op_cbf5(arg, cpu, mmu),
-        0xcbf6 => op_cbf6(arg, cpu, mmu),
-        0xcbf7 => op_cbf7(arg, cpu, mmu),
-        0xcbf8 => op_cbf8(arg, cpu, mmu),
-        0xcbf9 => op_cbf9(arg, cpu, mmu),
-        0xcbfa => op_cbfa(arg, cpu, mmu),
-        0xcbfb => op_cbfb(arg, cpu, mmu),
-        0xcbfc => op_cbfc(arg, cpu, mmu),
-        0xcbfd => op_cbfd(arg, cpu, mmu),
-        0xcbfe => op_cbfe(arg, cpu, mmu),
-        0xcbff => op_cbff(arg, cpu, mmu),
-        _ => panic!("Invalid opcode: {:04x}: {:04x}", cpu.get_pc(), code),
+        0x0000 => Ok(op_0000(arg, cpu, mmu)),
+        0x0001 => Ok(op_0001(arg, cpu, mmu)),
+        0x0002 => Ok(op_0002(arg, cpu, mmu)),
+        0x0003 => Ok(op_0003(arg, cpu, mmu)),
+        0x0004 => Ok(op_0004(arg, cpu, mmu)),
+        0x0005 => Ok(op_0005(arg, cpu, mmu)),
+        0x0006 => Ok(op_0006(arg, cpu, mmu)),
+        0x0007 => Ok(op_0007(arg, cpu, mmu)),
+        0x0008 => Ok(op_0008(arg, cpu, mmu)),
+        0x0009 => Ok(op_0009(arg, cpu, mmu)),
+        0x000a => Ok(op_000a(arg, cpu, mmu)),
+        0x000b => Ok(op_000b(arg, cpu, mmu)),
+        0x000c => Ok(op_000c(arg, cpu, mmu)),
+        0x000d => Ok(op_000d(arg, cpu, mmu)),
+        0x000e => Ok(op_000e(arg, cpu, mmu)),
+        0x000f => Ok(op_000f(arg, cpu, mmu)),
+        0x0010 => Ok(op_0010(arg, cpu, mmu)),
+        0x0011 => Ok(op_0011(arg, cpu, mmu)),
+        0x0012 => Ok(op_0012(arg, cpu, mmu)),
+        0x0013 => Ok(op_0013(arg, cpu, mmu)),
+        0x0014 => Ok(op_0014(arg, cpu, mmu)),
+        0x0015 => Ok(op_0015(arg, cpu, mmu)),
+        0x0016 => Ok(op_0016(arg, cpu, mmu)),
+        0x0017 => Ok(op_0017(arg, cpu, mmu)),
+        0x0018 => Ok(op_0018(arg, cpu, mmu)),
+        0x0019 => Ok(op_0019(arg, cpu, mmu)),
+        0x001a => Ok(op_001a(arg, cpu, mmu)),
+        0x001b => Ok(op_001b(arg, cpu, mmu)),
+        0x001c => Ok(op_001c(arg, cpu, mmu)),
+        0x001d => Ok(op_001d(arg, cpu, mmu)),
+        0x001e => Ok(op_001e(arg, cpu, mmu)),
+        0x001f => Ok(op_001f(arg, cpu, mmu)),
+        0x0020 => Ok(op_0020(arg, cpu, mmu)),
+        0x0021 => Ok(op_0021(arg, cpu, mmu)),
+        0x0022 => Ok(op_0022(arg, cpu, mmu)),
+        0x0023 => Ok(op_0023(arg, cpu, mmu)),
+        0x0024 => Ok(op_0024(arg, cpu, mmu)),
+        0x0025 => Ok(op_0025(arg, cpu, mmu)),
+        0x0026 => Ok(op_0026(arg, cpu, mmu)),
+        0x0027 => Ok(op_0027(arg, cpu, mmu)),
+        0x0028 => Ok(op_0028(arg, cpu, mmu)),
+        0x0029 => Ok(op_0029(arg, cpu, mmu)),
+        0x002a => Ok(op_002a(arg, cpu, mmu)),
+        0x002b => Ok(op_002b(arg, cpu, mmu)),
+        0x002c => Ok(op_002c(arg, cpu, mmu)),
+        0x002d => Ok(op_002d(arg, cpu, mmu)),
+        0x002e => Ok(op_002e(arg, cpu, mmu)),
+        0x002f => Ok(op_002f(arg, cpu, mmu)),
+        0x0030 => Ok(op_0030(arg, cpu, mmu)),
+        0x0031 => Ok(op_0031(arg, cpu, mmu)),
+        0x0032 => Ok(op_0032(arg, cpu, mmu)),
+        0x0033 => Ok(op_0033(arg, cpu, mmu)),
+        0x0034 => Ok(op_0034(arg, cpu, mmu)),
+        0x0035 => Ok(op_0035(arg, cpu, mmu)),
+        0x0036 => Ok(op_0036(arg, cpu, mmu)),
+        0x0037 => Ok(op_0037(arg, cpu, mmu)),
+        0x0038 => Ok(op_0038(arg, cpu, mmu)),
+        0x0039 => Ok(op_0039(arg, cpu, mmu)),
+        0x003a => Ok(op_003a(arg, cpu, mmu)),
+        0x003b => Ok(op_003b(arg, cpu, mmu)),
+        0x003c => Ok(op_003c(arg, cpu, mmu)),
+        0x003d => Ok(op_003d(arg, cpu, mmu)),
+        0x003e => Ok(op_003e(arg, cpu, mmu)),
+        0x003f => Ok(op_003f(arg, cpu, mmu)),
+        0x0040 => Ok(op_0040(arg, cpu, mmu)),
+        0x0041 => Ok(op_0041(arg, cpu, mmu)),
+        0x0042 => Ok(op_0042(arg, cpu, mmu)),
+        0x0043 => Ok(op_0043(arg, cpu, mmu)),
+        0x0044 => Ok(op_0044(arg, cpu, mmu)),
+        0x0045 => Ok(op_0045(arg, cpu, mmu)),
+        0x0046 => Ok(op_0046(arg, cpu, mmu)),
+        0x0047 => Ok(op_0047(arg, cpu, mmu)),
+        0x0048 => Ok(op_0048(arg, cpu, mmu)),
+        0x0049 => Ok(op_0049(arg, cpu, mmu)),
+        0x004a => Ok(op_004a(arg, cpu, mmu)),
+        0x004b => Ok(op_004b(arg, cpu, mmu)),
+        0x004c => Ok(op_004c(arg, cpu, mmu)),
+        0x004d => Ok(op_004d(arg, cpu, mmu)),
+        0x004e => Ok(op_004e(arg, cpu, mmu)),
+        0x004f => Ok(op_004f(arg, cpu, mmu)),
+        0x0050 => Ok(op_0050(arg, cpu, mmu)),
+        0x0051 => Ok(op_0051(arg, cpu, mmu)),
+        0x0052 => Ok(op_0052(arg, cpu, mmu)),
+        0x0053 => Ok(op_0053(arg, cpu, mmu)),
+        0x0054 => Ok(op_0054(arg, cpu, mmu)),
+        0x0055 => Ok(op_0055(arg, cpu, mmu)),
+        0x0056 => Ok(op_0056(arg, cpu, mmu)),
+        0x0057 => Ok(op_0057(arg, cpu, mmu)),
+        0x0058 => Ok(op_0058(arg, cpu, mmu)),
+        0x0059 => Ok(op_0059(arg, cpu, mmu)),
+        0x005a => Ok(op_005a(arg, cpu, mmu)),
+        0x005b => Ok(op_005b(arg, cpu, mmu)),
+        0x005c => Ok(op_005c(arg, cpu, mmu)),
+        0x005d => Ok(op_005d(arg, cpu, mmu)),
+        0x005e => Ok(op_005e(arg, cpu, mmu)),
+        0x005f => Ok(op_005f(arg, cpu, mmu)),
+        0x0060 => Ok(op_0060(arg, cpu, mmu)),
+        0x0061 => Ok(op_0061(arg, cpu, mmu)),
+        0x0062 => Ok(op_0062(arg, cpu, mmu)),
+        0x0063 => Ok(op_0063(arg, cpu, mmu)),
+        0x0064 => Ok(op_0064(arg, cpu, mmu)),
+        0x0065 => Ok(op_0065(arg, cpu, mmu)),
+        0x0066 => Ok(op_0066(arg, cpu, mmu)),
+        0x0067 => Ok(op_0067(arg, cpu, mmu)),
+        0x0068 => Ok(op_0068(arg, cpu, mmu)),
+        0x0069 => Ok(op_0069(arg, cpu, mmu)),
+        0x006a => Ok(op_006a(arg, cpu, mmu)),
+        0x006b => Ok(op_006b(arg, cpu, mmu)),
+        0x006c => Ok(op_006c(arg, cpu, mmu)),
+        0x006d => Ok(op_006d(arg, cpu, mmu)),
+        0x006e => Ok(op_006e(arg, cpu, mmu)),
+        0x006f => Ok(op_006f(arg, cpu, mmu)),
+        0x0070 => Ok(op_0070(arg, cpu, mmu)),
+        0x0071 => Ok(op_0071(arg, cpu, mmu)),
+        0x0072 => Ok(op_0072(arg, cpu, mmu)),
+        0x0073 => Ok(op_0073(arg, cpu, mmu)),
+        0x0074 => Ok(op_0074(arg, cpu, mmu)),
+        0x0075 => Ok(op_0075(arg, cpu, mmu)),
+        0x0076 => Ok(op_0076(arg, cpu, mmu)),
+        0x0077 => Ok(op_0077(arg, cpu, mmu)),
+        0x0078 => Ok(op_0078(arg, cpu, mmu)),
+        0x0079 => Ok(op_0079(arg, cpu, mmu)),
+        0x007a => Ok(op_007a(arg, cpu, mmu)),
+        0x007b => Ok(op_007b(arg, cpu, mmu)),
+        0x007c => Ok(op_007c(arg, cpu, mmu)),
+        0x007d => Ok(op_007d(arg, cpu, mmu)),
+        0x007e => Ok(op_007e(arg, cpu, mmu)),
+        0x007f => Ok(op_007f(arg, cpu, mmu)),
+        0x0080 => Ok(op_0080(arg, cpu, mmu)),
+        0x0081 => Ok(op_0081(arg, cpu, mmu)),
+        0x0082 => Ok(op_0082(arg, cpu, mmu)),
+        0x0083 => Ok(op_0083(arg, cpu, mmu)),
+        0x0084 => Ok(op_0084(arg, cpu, mmu)),
+        0x0085 => Ok(op_0085(arg, cpu, mmu)),
+        0x0086 => Ok(op_0086(arg, cpu, mmu)),
+        0x0087 => Ok(op_0087(arg, cpu, mmu)),
+        0x0088 => Ok(op_0088(arg, cpu, mmu)),
+        0x0089 => Ok(op_0089(arg, cpu, mmu)),
+        0x008a => Ok(op_008a(arg, cpu, mmu)),
+        0x008b => Ok(op_008b(arg, cpu, mmu)),
+        0x008c => Ok(op_008c(arg, cpu, mmu)),
+        0x008d => Ok(op_008d(arg, cpu, mmu)),
+        0x008e => Ok(op_008e(arg, cpu, mmu)),
+        0x008f => Ok(op_008f(arg, cpu, mmu)),
+        0x0090 => Ok(op_0090(arg, cpu, mmu)),
+        0x0091 => Ok(op_0091(arg, cpu, mmu)),
+        0x0092 => Ok(op_0092(arg, cpu, mmu)),
+        0x0093 => Ok(op_0093(arg, cpu, mmu)),
+        0x0094 => Ok(op_0094(arg, cpu, mmu)),
+        0x0095 => Ok(op_0095(arg, cpu, mmu)),
+        0x0096 => Ok(op_0096(arg, cpu, mmu)),
+        0x0097 => Ok(op_0097(arg, cpu, mmu)),
+        0x0098 => Ok(op_0098(arg, cpu, mmu)),
+        0x0099 => Ok(op_0099(arg, cpu, mmu)),
+        0x009a => Ok(op_009a(arg, cpu, mmu)),
+        0x009b => Ok(op_009b(arg, cpu, mmu)),
+        0x009c => Ok(op_009c(arg, cpu, mmu)),
+        0x009d => Ok(op_009d(arg, cpu, mmu)),
+        0x009e => Ok(op_009e(arg, cpu, mmu)),
+        0x009f => Ok(op_009f(arg, cpu, mmu)),
+        0x00a0 => Ok(op_00a0(arg, cpu, mmu)),
+        0x00a1 => Ok(op_00a1(arg, cpu, mmu)),
+        0x00a2 => Ok(op_00a2(arg, cpu, mmu)),
+        0x00a3 => Ok(op_00a3(arg, cpu, mmu)),
+        0x00a4 => Ok(op_00a4(arg, cpu, mmu)),
+        0x00a5 => Ok(op_00a5(arg, cpu, mmu)),
+        0x00a6 => Ok(op_00a6(arg, cpu, mmu)),
+        0x00a7 => Ok(op_00a7(arg, cpu, mmu)),
+        0x00a8 => Ok(op_00a8(arg, cpu, mmu)),
+        0x00a9 => Ok(op_00a9(arg, cpu, mmu)),
+        0x00aa => Ok(op_00aa(arg, cpu, mmu)),
+        0x00ab => Ok(op_00ab(arg, cpu, mmu)),
+        0x00ac => Ok(op_00ac(arg, cpu, mmu)),
+        0x00ad => Ok(op_00ad(arg, cpu, mmu)),
+        0x00ae => Ok(op_00ae(arg, cpu, mmu)),
+        0x00af => Ok(op_00af(arg, cpu, mmu)),
+        0x00b0 => Ok(op_00b0(arg, cpu, mmu)),
+        0x00b1 => Ok(op_00b1(arg, cpu, mmu)),
+        0x00b2 => Ok(op_00b2(arg, cpu, mmu)),
+        0x00b3 => Ok(op_00b3(arg, cpu, mmu)),
+        0x00b4 => Ok(op_00b4(arg, cpu, mmu)),
+        0x00b5 => Ok(op_00b5(arg, cpu, mmu)),
+        0x00b6 => Ok(op_00b6(arg, cpu, mmu)),
+        0x00b7 => Ok(op_00b7(arg, cpu, mmu)),
+        0x00b8 => Ok(op_00b8(arg, cpu, mmu)),
+        0x00b9 => Ok(op_00b9(arg, cpu, mmu)),
+        0x00ba => Ok(op_00ba(arg, cpu, mmu)),
+        0x00bb => Ok(op_00bb(arg, cpu, mmu)),
+        0x00bc => Ok(op_00bc(arg, cpu, mmu)),
+        0x00bd => Ok(op_00bd(arg, cpu, mmu)),
+        0x00be => Ok(op_00be(arg, cpu, mmu)),
+        0x00bf => Ok(op_00bf(arg, cpu, mmu)),
+        0x00c0 => Ok(op_00c0(arg, cpu, mmu)),
+        0x00c1 => Ok(op_00c1(arg, cpu, mmu)),
+        0x00c2 => Ok(op_00c2(arg, cpu, mmu)),
+        0x00c3 => Ok(op_00c3(arg, cpu, mmu)),
+        0x00c4 => Ok(op_00c4(arg, cpu, mmu)),
+        0x00c5 => Ok(op_00c5(arg, cpu, mmu)),
+        0x00c6 => Ok(op_00c6(arg, cpu, mmu)),
+        0x00c7 => Ok(op_00c7(arg, cpu, mmu)),
+        0x00c8 => Ok(op_00c8(arg, cpu, mmu)),
+        0x00c9 => Ok(op_00c9(arg, cpu, mmu)),
+        0x00ca => Ok(op_00ca(arg, cpu, mmu)),
+        0x00cb => Ok(op_00cb(arg, cpu, mmu)),
+        0x00cc => Ok(op_00cc(arg, cpu, mmu)),
+        0x00cd => Ok(op_00cd(arg, cpu, mmu)),
+        0x00ce => Ok(op_00ce(arg, cpu, mmu)),
+        0x00cf => Ok(op_00cf(arg, cpu, mmu)),
+        0x00d0 => Ok(op_00d0(arg, cpu, mmu)),
+        0x00d1 => Ok(op_00d1(arg, cpu, mmu)),
+        0x00d2 => Ok(op_00d2(arg, cpu, mmu)),
+        0x00d4 => Ok(op_00d4(arg, cpu, mmu)),
+        0x00d5 => Ok(op_00d5(arg, cpu, mmu)),
+        0x00d6 => Ok(op_00d6(arg, cpu, mmu)),
+        0x00d7 => Ok(op_00d7(arg, cpu, mmu)),
+        0x00d8 => Ok(op_00d8(arg, cpu, mmu)),
+        0x00d9 => Ok(op_00d9(arg, cpu, mmu)),
+        0x00da => Ok(op_00da(arg, cpu, mmu)),
+        0x00dc => Ok(op_00dc(arg, cpu, mmu)),
+        0x00de => Ok(op_00de(arg, cpu, mmu)),
+        0x00df => Ok(op_00df(arg, cpu, mmu)),
+        0x00e0 => Ok(op_00e0(arg, cpu, mmu)),
+        0x00e1 => Ok(op_00e1(arg, cpu, mmu)),
+        0x00e2 => Ok(op_00e2(arg, cpu, mmu)),
+        0x00e5 => Ok(op_00e5(arg, cpu, mmu)),
+        0x00e6 => Ok(op_00e6(arg, cpu, mmu)),
+        0x00e7 => Ok(op_00e7(arg, cpu, mmu)),
+        0x00e8 => Ok(op_00e8(arg, cpu, mmu)),
+        0x00e9 => Ok(op_00e9(arg, cpu, mmu)),
+        0x00ea => Ok(op_00ea(arg, cpu, mmu)),
+        0x00ee => Ok(op_00ee(arg, cpu, mmu)),
+        0x00ef => Ok(op_00ef(arg, cpu, mmu)),
+        0x00f0 => Ok(op_00f0(arg, cpu, mmu)),
+        0x00f1 => Ok(op_00f1(arg, cpu, mmu)),
+        0x00f2 => Ok(op_00f2(arg, cpu, mmu)),
+        0x00f3 => Ok(op_00f3(arg, cpu, mmu)),
+        0x00f5 => Ok(op_00f5(arg, cpu, mmu)),
+        0x00f6 => Ok(op_00f6(arg, cpu, mmu)),
+        0x00f7 => Ok(op_00f7(arg, cpu, mmu)),
+        0x00f8 => Ok(op_00f8(arg, cpu, mmu)),
+        0x00f9 => Ok(op_00f9(arg, cpu, mmu)),
+        0x00fa => Ok(op_00fa(arg, cpu, mmu)),
+        0x00fb => Ok(op_00fb(arg, cpu, mmu)),
+        0x00fe => Ok(op_00fe(arg, cpu, mmu)),
+        0x00ff => Ok(op_00ff(arg, cpu, mmu)),
+        0xcb00 => Ok(op_cb00(arg, cpu, mmu)),
+        0xcb01 => Ok(op_cb01(arg, cpu, mmu)),
+        0xcb02 => Ok(op_cb02(arg, cpu, mmu)),
+        0xcb03 => Ok(op_cb03(arg, cpu, mmu)),
+        0xcb04 => Ok(op_cb04(arg, cpu, mmu)),
+        0xcb05 => Ok(op_cb05(arg, cpu, mmu)),
+        0xcb06 => Ok(op_cb06(arg, cpu, mmu)),
+        0xcb07 => Ok(op_cb07(arg, cpu, mmu)),
+        0xcb08 => Ok(op_cb08(arg, cpu, mmu)),
+        0xcb09 => Ok(op_cb09(arg, cpu, mmu)),
+        0xcb0a => Ok(op_cb0a(arg, cpu, mmu)),
+        0xcb0b => Ok(op_cb0b(arg, cpu, mmu)),
+        0xcb0c => Ok(op_cb0c(arg, cpu, mmu)),
+        0xcb0d => Ok(op_cb0d(arg, cpu, mmu)),
+        0xcb0e => Ok(op_cb0e(arg, cpu, mmu)),
+        0xcb0f => Ok(op_cb0f(arg, cpu, mmu)),
+        0xcb10 => Ok(op_cb10(arg, cpu, mmu)),
+        0xcb11 => Ok(op_cb11(arg, cpu, mmu)),
+        0xcb12 => Ok(op_cb12(arg, cpu, mmu)),
+        0xcb13 => Ok(op_cb13(arg, cpu, mmu)),
+        0xcb14 => Ok(op_cb14(arg, cpu, mmu)),
+        0xcb15 => Ok(op_cb15(arg, cpu, mmu)),
+        0xcb16 => Ok(op_cb16(arg, cpu, mmu)),
+        0xcb17 => Ok(op_cb17(arg, cpu, mmu)),
+        0xcb18 => Ok(op_cb18(arg, cpu, mmu)),
+        0xcb19 => Ok(op_cb19(arg, cpu, mmu)),
+        0xcb1a => Ok(op_cb1a(arg, cpu, mmu)),
+        0xcb1b => Ok(op_cb1b(arg, cpu, mmu)),
+        0xcb1c => Ok(op_cb1c(arg, cpu, mmu)),
+        0xcb1d => Ok(op_cb1d(arg, cpu, mmu)),
+        0xcb1e => Ok(op_cb1e(arg, cpu, mmu)),
+        0xcb1f => Ok(op_cb1f(arg, cpu, mmu)),
+        0xcb20 => Ok(op_cb20(arg, cpu, mmu)),
+        0xcb21 => Ok(op_cb21(arg, cpu, mmu)),
+        0xcb22 => Ok(op_cb22(arg, cpu, mmu)),
+        0xcb23 => Ok(op_cb23(arg, cpu, mmu)),
+        0xcb24 => Ok(op_cb24(arg, cpu, mmu)),
+        0xcb25 => Ok(op_cb25(arg, cpu, mmu)),
+        0xcb26 => Ok(op_cb26(arg, cpu, mmu)),
+        0xcb27 => Ok(op_cb27(arg, cpu, mmu)),
+        0xcb28 => Ok(op_cb28(arg, cpu, mmu)),
+        0xcb29 => Ok(op_cb29(arg, cpu, mmu)),
+        0xcb2a => Ok(op_cb2a(arg, cpu, mmu)),
+        0xcb2b => Ok(op_cb2b(arg, cpu, mmu)),
+        0xcb2c => Ok(op_cb2c(arg, cpu, mmu)),
+        0xcb2d => Ok(op_cb2d(arg, cpu, mmu)),
+        0xcb2e => Ok(op_cb2e(arg, cpu, mmu)),
+        0xcb2f => Ok(op_cb2f(arg, cpu, mmu)),
+        0xcb30 => Ok(op_cb30(arg, cpu, mmu)),
+        0xcb31 => Ok(op_cb31(arg, cpu, mmu)),
+        0xcb32 => Ok(op_cb32(arg, cpu, mmu)),
+        0xcb33 => Ok(op_cb33(arg, cpu, mmu)),
+        0xcb34 => Ok(op_cb34(arg, cpu, mmu)),
+        0xcb35 => Ok(op_cb35(arg, cpu, mmu)),
+        0xcb36 => Ok(op_cb36(arg, cpu, mmu)),
+        0xcb37 => Ok(op_cb37(arg, cpu, mmu)),
+        0xcb38 => Ok(op_cb38(arg, cpu, mmu)),
+        0xcb39 => Ok(op_cb39(arg, cpu, mmu)),
+        0xcb3a => Ok(op_cb3a(arg, cpu, mmu)),
+        0xcb3b => Ok(op_cb3b(arg, cpu, mmu)),
+        0xcb3c => Ok(op_cb3c(arg, cpu, mmu)),
+        0xcb3d => Ok(op_cb3d(arg, cpu, mmu)),
+        0xcb3e => Ok(op_cb3e(arg, cpu, mmu)),
+        0xcb3f => Ok(op_cb3f(arg, cpu, mmu)),
+        0xcb40 => Ok(op_cb40(arg, cpu, mmu)),
+        0xcb41 => Ok(op_cb41(arg, cpu, mmu)),
+        0xcb42 => Ok(op_cb42(arg, cpu, mmu)),
+        0xcb43 => Ok(op_cb43(arg, cpu, mmu)),
+        0xcb44 => Ok(op_cb44(arg, cpu, mmu)),
+        0xcb45 => Ok(op_cb45(arg, cpu, mmu)),
+        0xcb46 => Ok(op_cb46(arg, cpu, mmu)),
+        0xcb47 => Ok(op_cb47(arg, cpu, mmu)),
+        0xcb48 => Ok(op_cb48(arg, cpu, mmu)),
+        0xcb49 => Ok(op_cb49(arg, cpu, mmu)),
+        0xcb4a => Ok(op_cb4a(arg, cpu, mmu)),
+        0xcb4b => Ok(op_cb4b(arg, cpu, mmu)),
+        0xcb4c => Ok(op_cb4c(arg, cpu, mmu)),
+        0xcb4d => Ok(op_cb4d(arg, cpu, mmu)),
+        0xcb4e => Ok(op_cb4e(arg, cpu, mmu)),
+        0xcb4f => Ok(op_cb4f(arg, cpu, mmu)),
+        0xcb50 => Ok(op_cb50(arg, cpu, mmu)),
+        0xcb51 => Ok(op_cb51(arg, cpu, mmu)),
+        0xcb52 => Ok(op_cb52(arg, cpu, mmu)),
+        0xcb53 => Ok(op_cb53(arg, cpu, mmu)),
+        0xcb54 => Ok(op_cb54(arg, cpu, mmu)),
+        0xcb55 => Ok(op_cb55(arg, cpu, mmu)),
+        0xcb56 => Ok(op_cb56(arg, cpu, mmu)),
+        0xcb57 => Ok(op_cb57(arg, cpu, mmu)),
+        0xcb58 => Ok(op_cb58(arg, cpu, mmu)),
+        0xcb59 => Ok(op_cb59(arg, cpu, mmu)),
+        0xcb5a => Ok(op_cb5a(arg, cpu, mmu)),
+        0xcb5b => Ok(op_cb5b(arg, cpu, mmu)),
+        0xcb5c => Ok(op_cb5c(arg, cpu, mmu)),
+        0xcb5d => Ok(op_cb5d(arg, cpu, mmu)),
+        0xcb5e => Ok(op_cb5e(arg, cpu, mmu)),
+        0xcb5f => Ok(op_cb5f(arg, cpu, mmu)),
+        0xcb60 => Ok(op_cb60(arg, cpu, mmu)),
+        0xcb61 => Ok(op_cb61(arg, cpu, mmu)),
+        0xcb62 => Ok(op_cb62(arg, cpu, mmu)),
+        0xcb63 => Ok(op_cb63(arg, cpu, mmu)),
+        0xcb64 => Ok(op_cb64(arg, cpu, mmu)),
+        0xcb65 => Ok(op_cb65(arg, cpu, mmu)),
+        0xcb66 => Ok(op_cb66(arg, cpu, mmu)),
+        0xcb67 => Ok(op_cb67(arg, cpu, mmu)),
+        0xcb68 => Ok(op_cb68(arg, cpu, mmu)),
+        0xcb69 => Ok(op_cb69(arg, cpu, mmu)),
+        0xcb6a => Ok(op_cb6a(arg, cpu, mmu)),
+        0xcb6b => Ok(op_cb6b(arg, cpu, mmu)),
+        0xcb6c => Ok(op_cb6c(arg, cpu, mmu)),
+        0xcb6d => Ok(op_cb6d(arg, cpu, mmu)),
+        0xcb6e => Ok(op_cb6e(arg, cpu, mmu)),
+        0xcb6f => Ok(op_cb6f(arg, cpu, mmu)),
+        0xcb70 => Ok(op_cb70(arg, cpu, mmu)),
+        0xcb71 => Ok(op_cb71(arg, cpu, mmu)),
+        0xcb72 => Ok(op_cb72(arg, cpu, mmu)),
+        0xcb73 => Ok(op_cb73(arg, cpu, mmu)),
+        0xcb74 => Ok(op_cb74(arg, cpu, mmu)),
+        0xcb75 => Ok(op_cb75(arg, cpu, mmu)),
+        0xcb76 => Ok(op_cb76(arg, cpu, mmu)),
+        0xcb77 => Ok(op_cb77(arg, cpu, mmu)),
+        0xcb78 => Ok(op_cb78(arg, cpu, mmu)),
+        0xcb79 => Ok(op_cb79(arg, cpu, mmu)),
+        0xcb7a => Ok(op_cb7a(arg, cpu, mmu)),
+        0xcb7b => Ok(op_cb7b(arg, cpu, mmu)),
+        0xcb7c => Ok(op_cb7c(arg, cpu, mmu)),
+        0xcb7d => Ok(op_cb7d(arg, cpu, mmu)),
+        0xcb7e => Ok(op_cb7e(arg, cpu, mmu)),
+        0xcb7f => Ok(op_cb7f(arg, cpu, mmu)),
+        0xcb80 => Ok(op_cb80(arg, cpu, mmu)),
+        0xcb81 => Ok(op_cb81(arg, cpu, mmu)),
+        0xcb82 => Ok(op_cb82(arg, cpu, mmu)),
+        0xcb83 => Ok(op_cb83(arg, cpu, mmu)),
+        0xcb84 => Ok(op_cb84(arg, cpu, mmu)),
+        0xcb85 => Ok(op_cb85(arg, cpu, mmu)),
+        0xcb86 => Ok(op_cb86(arg, cpu, mmu)),
+        0xcb87 => Ok(op_cb87(arg, cpu, mmu)),
+        0xcb88 => Ok(op_cb88(arg, cpu, mmu)),
+        0xcb89 => Ok(op_cb89(arg, cpu, mmu)),
+        0xcb8a => Ok(op_cb8a(arg, cpu, mmu)),
+        0xcb8b => Ok(op_cb8b(arg, cpu, mmu)),
+        0xcb8c => Ok(op_cb8c(arg, cpu, mmu)),
+        0xcb8d => Ok(op_cb8d(arg, cpu, mmu)),
+        0xcb8e => Ok(op_cb8e(arg, cpu, mmu)),
+        0xcb8f => Ok(op_cb8f(arg, cpu, mmu)),
+        0xcb90 => Ok(op_cb90(arg, cpu, mmu)),
+        0xcb91 => Ok(op_cb91(arg, cpu, mmu)),
+        0xcb92 => Ok(op_cb92(arg, cpu, mmu)),
+        0xcb93 => Ok(op_cb93(arg, cpu, mmu)),
+        0xcb94 => Ok(op_cb94(arg, cpu, mmu)),
+        0xcb95 => Ok(op_cb95(arg, cpu, mmu)),
+        0xcb96 => Ok(op_cb96(arg, cpu, mmu)),
+        0xcb97 => Ok(op_cb97(arg, cpu, mmu)),
+        0xcb98 => Ok(op_cb98(arg, cpu, mmu)),
+        0xcb99 => Ok(op_cb99(arg, cpu, mmu)),
+        0xcb9a => Ok(op_cb9a(arg, cpu, mmu)),
+        0xcb9b => Ok(op_cb9b(arg, cpu, mmu)),
+        0xcb9c => Ok(op_cb9c(arg, cpu, mmu)),
+        0xcb9d => Ok(op_cb9d(arg, cpu, mmu)),
+        0xcb9e => Ok(op_cb9e(arg, cpu, mmu)),
+        0xcb9f => Ok(op_cb9f(arg, cpu, mmu)),
+        0xcba0 => Ok(op_cba0(arg, cpu, mmu)),
+        0xcba1 => Ok(op_cba1(arg, cpu, mmu)),
+        0xcba2 => Ok(op_cba2(arg, cpu, mmu)),
+        0xcba3 => Ok(op_cba3(arg, cpu, mmu)),
+        0xcba4 => Ok(op_cba4(arg, cpu, mmu)),
+        0xcba5 => Ok(op_cba5(arg, cpu, mmu)),
+        0xcba6 => Ok(op_cba6(arg, cpu, mmu)),
+        0xcba7 => Ok(op_cba7(arg, cpu, mmu)),
+        0xcba8 => Ok(op_cba8(arg, cpu, mmu)),
+        0xcba9 => Ok(op_cba9(arg, cpu, mmu)),
+        0xcbaa => Ok(op_cbaa(arg, cpu, mmu)),
+        0xcbab => Ok(op_cbab(arg, cpu, mmu)),
+        0xcbac => Ok(op_cbac(arg, cpu, mmu)),
+        0xcbad => Ok(op_cbad(arg, cpu, mmu)),
+        0xcbae => Ok(op_cbae(arg, cpu, mmu)),
+        0xcbaf => Ok(op_cbaf(arg, cpu, mmu)),
+        0xcbb0 => Ok(op_cbb0(arg, cpu, mmu)),
+        0xcbb1 => Ok(op_cbb1(arg, cpu, mmu)),
+        0xcbb2 => Ok(op_cbb2(arg, cpu, mmu)),
+        0xcbb3 => Ok(op_cbb3(arg, cpu, mmu)),
+        0xcbb4 => Ok(op_cbb4(arg, cpu, mmu)),
+        0xcbb5 => Ok(op_cbb5(arg, cpu, mmu)),
+        0xcbb6 => Ok(op_cbb6(arg, cpu, mmu)),
+        0xcbb7 => Ok(op_cbb7(arg, cpu, mmu)),
+        0xcbb8 => Ok(op_cbb8(arg, cpu, mmu)),
+        0xcbb9 => Ok(op_cbb9(arg, cpu, mmu)),
+        0xcbba => Ok(op_cbba(arg, cpu, mmu)),
+        0xcbbb => Ok(op_cbbb(arg, cpu, mmu)),
+        0xcbbc => Ok(op_cbbc(arg, cpu, mmu)),
+        0xcbbd => Ok(op_cbbd(arg, cpu, mmu)),
+        0xcbbe => Ok(op_cbbe(arg, cpu, mmu)),
+        0xcbbf => Ok(op_cbbf(arg, cpu, mmu)),
+        0xcbc0 => Ok(op_cbc0(arg, cpu, mmu)),
+        0xcbc1 => Ok(op_cbc1(arg, cpu, mmu)),
+        0xcbc2 => Ok(op_cbc2(arg, cpu, mmu)),
+        0xcbc3 => Ok(op_cbc3(arg, cpu, mmu)),
+        0xcbc4 => Ok(op_cbc4(arg, cpu, mmu)),
+        0xcbc5 => Ok(op_cbc5(arg, cpu, mmu)),
+        0xcbc6 => Ok(op_cbc6(arg, cpu, mmu)),
+        0xcbc7 => Ok(op_cbc7(arg, cpu, mmu)),
+        0xcbc8 => Ok(op_cbc8(arg, cpu, mmu)),
+        0xcbc9 => Ok(op_cbc9(arg, cpu, mmu)),
+        0xcbca => Ok(op_cbca(arg, cpu, mmu)),
+        0xcbcb => Ok(op_cbcb(arg, cpu, mmu)),
+        0xcbcc => Ok(op_cbcc(arg, cpu, mmu)),
+        0xcbcd => Ok(op_cbcd(arg, cpu, mmu)),
+        0xcbce => Ok(op_cbce(arg, cpu, mmu)),
+        0xcbcf => Ok(op_cbcf(arg, cpu, mmu)),
+        0xcbd0 => Ok(op_cbd0(arg, cpu, mmu)),
+        0xcbd1 => Ok(op_cbd1(arg, cpu, mmu)),
+        0xcbd2 => Ok(op_cbd2(arg, cpu, mmu)),
+        0xcbd3 => Ok(op_cbd3(arg, cpu, mmu)),
+        0xcbd4 => Ok(op_cbd4(arg, cpu, mmu)),
+        0xcbd5 => Ok(op_cbd5(arg, cpu, mmu)),
+        0xcbd6 => Ok(op_cbd6(arg, cpu, mmu)),
+        0xcbd7 => Ok(op_cbd7(arg, cpu, mmu)),
+        0xcbd8 => Ok(op_cbd8(arg, cpu, mmu)),
+        0xcbd9 => Ok(op_cbd9(arg, cpu, mmu)),
+        0xcbda => Ok(op_cbda(arg, cpu, mmu)),
+        0xcbdb => Ok(op_cbdb(arg, cpu, mmu)),
+        0xcbdc => Ok(op_cbdc(arg, cpu, mmu)),
+        0xcbdd => Ok(op_cbdd(arg, cpu, mmu)),
+        0xcbde => Ok(op_cbde(arg, cpu, mmu)),
+        0xcbdf => Ok(op_cbdf(arg, cpu, mmu)),
+        0xcbe0 => Ok(op_cbe0(arg, cpu, mmu)),
+        0xcbe1 => Ok(op_cbe1(arg, cpu, mmu)),
+        0xcbe2 => Ok(op_cbe2(arg, cpu, mmu)),
+        0xcbe3 => Ok(op_cbe3(arg, cpu, mmu)),
+        0xcbe4 => Ok(op_cbe4(arg, cpu, mmu)),
+        0xcbe5 => Ok(op_cbe5(arg, cpu, mmu)),
+        0xcbe6 => Ok(op_cbe6(arg, cpu, mmu)),
+        0xcbe7 => Ok(op_cbe7(arg, cpu, mmu)),
+        0xcbe8 => Ok(op_cbe8(arg, cpu, mmu)),
+        0xcbe9 => Ok(op_cbe9(arg, cpu, mmu)),
+        0xcbea => Ok(op_cbea(arg, cpu, mmu)),
+        0xcbeb => Ok(op_cbeb(arg, cpu, mmu)),
+        0xcbec => Ok(op_cbec(arg, cpu, mmu)),
+        0xcbed => Ok(op_cbed(arg, cpu, mmu)),
+        0xcbee => Ok(op_cbee(arg, cpu, mmu)),
+        0xcbef => Ok(op_cbef(arg, cpu, mmu)),
+        0xcbf0 => Ok(op_cbf0(arg, cpu, mmu)),
+        0xcbf1 => Ok(op_cbf1(arg, cpu, mmu)),
+        0xcbf2 => Ok(op_cbf2(arg, cpu, mmu)),
+        0xcbf3 => Ok(op_cbf3(arg, cpu, mmu)),
+        0xcbf4 => Ok(op_cbf4(arg, cpu, mmu)),
+        0xcbf5 => Ok(op_cbf5(arg, cpu, mmu)),
+        0xcbf6 => Ok(op_cbf6(arg, cpu, mmu)),
+        0xcbf7 => Ok(op_cbf7(arg, cpu, mmu)),
+        0xcbf8 => Ok(op_cbf8(arg, cpu, mmu)),
+        0xcbf9 => Ok(op_cbf9(arg, cpu, mmu)),
+        0xcbfa => Ok(op_cbfa(arg, cpu, mmu)),
+        0xcbfb => Ok(op_cbfb(arg, cpu, mmu)),
+        0xcbfc => Ok(op_cbfc(arg, cpu, mmu)),
+        0xcbfd => Ok(op_cbfd(arg, cpu, mmu)),
+        0xcbfe => Ok(op_cbfe(arg, cpu, mmu)),
+        0xcbff => Ok(op_cbff(arg, cpu, mmu)),
+        _ => Err(Error::InvalidOpcode {
+            pc: cpu.get_pc(),
+            code,
+        }),
     }
 }
@@ -1,69 +1,114 @@
 use crate::device::IoHandler;
+use crate::error::Error;
 use crate::hardware::{HardwareHandle, Key};
 use crate::ic::Irq;
 use crate::mmu::{MemRead, MemWrite, Mmu};
+use crate::state::{Reader, State, Writer};
 use log::*;
 
-pub struct Joypad {
-    hw: HardwareHandle,
+pub struct Joypad<'a> {
+    hw: HardwareHandle<'a>,
     irq: Irq,
     select: u8,
     pressed: u8,
+    injected: u8,
 }
 
-impl Joypad {
-    pub fn new(hw: HardwareHandle, irq: Irq) -> Self {
+impl<'a> Joypad<'a> {
+    pub fn new(hw: HardwareHandle<'a>, irq: Irq) -> Self {
         Self {
             hw,
             irq,
             select: 0xff,
             pressed: 0x0f,
+            injected: 0x00,
         }
     }
 
+    /// Press or release the key regardless of [`Input::joypad_pressed`][crate::Input::joypad_pressed].
+    pub fn set_button(&mut self, key: Key, pressed: bool) {
+        if pressed {
+            self.injected |= key_bit(&key);
+        } else {
+            self.injected &= !key_bit(&key);
+        }
+    }
+
+    /// Sample the selected button lines, requesting the joypad interrupt
+    /// if any of them goes from high to low.
     pub fn poll(&mut self) {
         let pressed = self.check();
 
-        for i in 0..4 {
-            let bit = 1 << i;
-            if self.pressed & bit != 0 && pressed & bit == 0 {
-                self.irq.joypad(true);
-                break;
-            }
+        if self.pressed & !pressed & 0x0f != 0 {
+            self.irq.joypad(true);
         }
 
         self.pressed = pressed;
     }
 
+    /// Check if any of the selected keys is pressed, which wakes the CPU up from STOP.
+    pub fn pressed(&self) -> bool {
+        self.check() & 0x0f != 0x0f
+    }
+
     fn check(&self) -> u8 {
-        let p = |key| self.hw.get().borrow_mut().joypad_pressed(key);
+        let p =
+            |key| self.injected & key_bit(&key) != 0 || self.hw.get().lock().joypad_pressed(key);
 
-        let mut value = 0;
+        // The lines of both groups are wired together when both are selected
+        let mut value = 0x0f;
 
         if self.select & 0x10 == 0 {
-            value |= if p(Key::Right) { 0x00 } else { 0x01 };
-            value |= if p(Key::Left) { 0x00 } else { 0x02 };
-            value |= if p(Key::Up) { 0x00 } else { 0x04 };
-            value |= if p(Key::Down) { 0x00 } else { 0x08 };
-        } else if self.select & 0x20 == 0 {
-            value |= if p(Key::A) { 0x00 } else { 0x01 };
-            value |= if p(Key::B) { 0x00 } else { 0x02 };
-            value |= if p(Key::Select) { 0x00 } else { 0x04 };
-            value |= if p(Key::Start) { 0x0 } else { 0x08 };
-        } else {
-            value = 0x0f;
+            value &= if p(Key::Right) { !0x01 } else { 0xff };
+            value &= if p(Key::Left) { !0x02 } else { 0xff };
+            value &= if p(Key::Up) { !0x04 } else { 0xff };
+            value &= if p(Key::Down) { !0x08 } else { 0xff };
+        }
+        if self.select & 0x20 == 0 {
+            value &= if p(Key::A) { !0x01 } else { 0xff };
+            value &= if p(Key::B) { !0x02 } else { 0xff };
+            value &= if p(Key::Select) { !0x04 } else { 0xff };
+            value &= if p(Key::Start) { !0x08 } else { 0xff };
         }
 
         value
     }
 }
 
-impl IoHandler for Joypad {
+fn key_bit(key: &Key) -> u8 {
+    match key {
+        Key::Right => 0x01,
+        Key::Left => 0x02,
+        Key::Up => 0x04,
+        Key::Down => 0x08,
+        Key::A => 0x10,
+        Key::B => 0x20,
+        Key::Select => 0x40,
+        Key::Start => 0x80,
+    }
+}
+
+impl<'a> State for Joypad<'a> {
+    fn save(&self, w: &mut Writer) {
+        self.select.save(w);
+        self.pressed.save(w);
+        self.injected.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.select.load(r)?;
+        self.pressed.load(r)?;
+        self.injected.load(r)?;
+        Ok(())
+    }
+}
+
+impl<'a> IoHandler for Joypad<'a> {
     fn on_read(&mut self, _mmu: &Mmu, addr: u16) -> MemRead {
         if addr == 0xff00 {
             debug!("Joypad read: dir: {:02x}", self.select);
 
-            MemRead::Replace(self.check())
+            MemRead::Replace(0xc0 | self.select | self.check())
         } else {
             MemRead::PassThrough
         }
@@ -72,7 +117,55 @@ impl IoHandler for Joypad {
     fn on_write(&mut self, _mmu: &Mmu, addr: u16, value: u8) -> MemWrite {
         if addr == 0xff00 {
             self.select = value & 0xf0;
+            // Selecting a group with a key held pulls its line low
+            self.poll();
         }
         MemWrite::PassThrough
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::Device;
+    use crate::hardware::NullHardware;
+    use crate::ic::Ic;
+    use alloc::vec;
+
+    #[test]
+    fn joypad_interrupt() {
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        let ic = Device::new(Ic::new());
+        let joypad = Device::new(Joypad::new(
+            HardwareHandle::new(NullHardware),
+            ic.borrow().irq(),
+        ));
+        mmu.add_handler((0xff0f, 0xff0f), ic.handler());
+        mmu.add_handler((0xff00, 0xff00), joypad.handler());
+
+        // Select the buttons
+        mmu.set8(0xff00, 0x10);
+        joypad.borrow_mut().poll();
+        assert_eq!(mmu.get8(0xff0f) & 0x10, 0x00);
+
+        // Pressing a key not selected doesn't interrupt
+        joypad.borrow_mut().set_button(Key::Up, true);
+        joypad.borrow_mut().poll();
+        assert_eq!(mmu.get8(0xff0f) & 0x10, 0x00);
+
+        joypad.borrow_mut().set_button(Key::A, true);
+        joypad.borrow_mut().poll();
+        assert_eq!(mmu.get8(0xff0f) & 0x10, 0x10);
+        assert_eq!(mmu.get8(0xff00), 0xde);
+
+        // Releasing doesn't interrupt
+        mmu.set8(0xff0f, 0x00);
+        joypad.borrow_mut().set_button(Key::A, false);
+        joypad.borrow_mut().poll();
+        assert_eq!(mmu.get8(0xff0f) & 0x10, 0x00);
+
+        // Selecting the group of the held key does
+        mmu.set8(0xff00, 0x20);
+        assert_eq!(mmu.get8(0xff0f) & 0x10, 0x10);
+    }
+}
@@ -4,6 +4,11 @@
 //! The users of this library only needs to implement [`Hardware`][] trait, which abstracts OS-specific function.
 //! Once it's implemented, the emulator works.
 //!
+//! [`Hardware`][] is implemented for any type which implements the capability traits
+//! [`Screen`][], [`Speaker`][], [`Input`][], [`Clock`][], [`Persistence`][] and [`Link`][].
+//! Only [`Clock::clock`][] is mandatory; the other functions do nothing by default,
+//! so a frontend only implements the capabilities it has.
+//!
 //! The following code is the example which just implements `Hardware`. The implementation does nothing.
 //! You can replace the body of each function with the actual meaningful logic.
 //!
@@ -23,7 +28,7 @@
 //!     }
 //! }
 //!
-//! impl rgy::Hardware for Hardware {
+//! impl rgy::Screen for Hardware {
 //!     // Called when a horizontal line in the display is updated by the emulator.
 //!     fn vram_update(&mut self, line: usize, buffer: &[u32]) {
 //!         // `line` corresponds to the y coordinate.
@@ -34,7 +39,9 @@
 //!             self.dummy_display[x][y] = *col;
 //!         }
 //!     }
+//! }
 //!
+//! impl rgy::Input for Hardware {
 //!     // Called when the emulator checks if a key is pressed or not.
 //!     fn joypad_pressed(&mut self, key: Key) -> bool {
 //!         println!("Is {:?} pressed?", key);
@@ -43,12 +50,16 @@
 //!
 //!         false
 //!     }
+//! }
 //!
+//! impl rgy::Speaker for Hardware {
 //!     // Called when the emulator plays a sound.
 //!     fn sound_play(&mut self, _stream: Box<dyn Stream>) {
 //!         // TODO: Play the wave pattern provided `Stream`.
 //!     }
+//! }
 //!
+//! impl rgy::Clock for Hardware {
 //!     // Provides clock for the emulator.
 //!     fn clock(&mut self) -> u64 {
 //!         // TODO: Return the epoch in microseconds.
@@ -58,6 +69,15 @@
 //!         epoch.as_micros() as u64
 //!     }
 //!
+//!     // Called every time the emulator executes an instruction.
+//!     fn sched(&mut self) -> bool {
+//!         // TODO: Do some periodic jobs if any. Return `true` to continue, `false` to stop the emulator.
+//!         println!("It's running!");
+//!         true
+//!     }
+//! }
+//!
+//! impl rgy::Link for Hardware {
 //!     // Called when the emulator sends a byte to the serial port.
 //!     fn send_byte(&mut self, _b: u8) {
 //!         // TODO: Send a byte to a serial port.
@@ -68,14 +88,9 @@
 //!         // TODO: Check the status of the serial port and read a byte if any.
 //!         None
 //!     }
+//! }
 //!
-//!     // Called every time the emulator executes an instruction.
-//!     fn sched(&mut self) -> bool {
-//!         // TODO: Do some periodic jobs if any. Return `true` to continue, `false` to stop the emulator.
-//!         println!("It's running!");
-//!         true
-//!     }
-//!
+//! impl rgy::Persistence for Hardware {
 //!     // Called when the emulator stores the save data to the battery-backed RAM.
 //!     fn load_ram(&mut self, size: usize) -> Vec<u8> {
 //!         // TODO: Return save data.
@@ -99,28 +114,88 @@
 //!     let rom = vec![0u8; 1024];
 //!
 //!     // Run the emulator.
-//!     rgy::run(cfg, &rom, hw);
+//!     if let Err(e) = rgy::run(cfg, &rom, hw) {
+//!         println!("Emulator stopped: {}", e);
+//!     }
 //! }
 //! ```
+//!
+//! # Features
+//!
+//! The peripherals and the debugging support are enabled by default, and can be compiled out
+//! with `default-features = false` for the smallest core:
+//!
+//! * `sound`: The sound controller. Without it, the sound registers read back as plain memory.
+//! * `serial`: The serial port, and the link cables, the printer, the adapter and the netplay on it.
+//! * `cgb`: The CGB registers, i.e. the work RAM banks, the speed switch and the infrared port.
+//!   Enabled by `color`, which emulates the Game Boy Color.
+//! * `debugger`: The breakpoints, the watchpoints, the memory access log, the call stack
+//!   and the calls to [`Debugger`][debug::Debugger]. Enabled by `gdb`.
+//! * `threads`: The `Send` and `Sync` bounds of [`MaybeSend`][] and [`MaybeSync`][], so the emulator
+//!   can run on another thread and the [`Stream`][] passed to [`Speaker::sound_play`][] can be played on one.
+//!   Without it, [`Hardware`][], [`Mapper`][] and the [`Debugger`][debug::Debugger] can hold
+//!   the types which aren't `Send`.
+//!
+//! The savestates only load into the emulator built with the same features.
+//!
+//! # WebAssembly
+//!
+//! The core builds for `wasm32-unknown-unknown` as is, and without the `threads` feature
+//! the speaker API has no `Send` bounds. In the browser, use
+//! [`Pacing::External`][] and call [`System::run_frame_av`][] from `requestAnimationFrame`,
+//! which returns the pixels and the audio samples of a frame in one go, instead of
+//! playing the [`Stream`][] passed to [`Speaker::sound_play`][] on a separate thread.
 
 #![no_std]
 #![warn(missing_docs)]
 
 extern crate alloc;
+#[cfg(feature = "accuracy")]
+extern crate std;
 
+#[cfg(feature = "serial")]
+mod adapter;
 mod alu;
+mod boot;
+mod buffers;
+#[cfg(feature = "cgb")]
 mod cgb;
+mod cheat;
 mod dma;
+mod error;
+#[cfg(feature = "debugger")]
+mod expr;
 mod fc;
 mod gpu;
 mod ic;
 mod joypad;
+#[cfg(feature = "serial")]
+mod link;
 mod mbc;
+mod movie;
+#[cfg(feature = "serial")]
+mod netplay;
+mod patch;
+#[cfg(feature = "serial")]
+mod printer;
+mod resample;
+mod rewind;
+#[cfg(feature = "serial")]
 mod serial;
+mod slots;
+#[cfg(feature = "sound")]
 mod sound;
+mod state;
 mod system;
 mod timer;
 
+/// Harness running suites of test ROMs against the reference frames.
+#[cfg(feature = "accuracy")]
+pub mod accuracy;
+
+/// Cartridge header parsing.
+pub mod cart;
+
 /// CPU state.
 pub mod cpu;
 
@@ -130,14 +205,48 @@ pub mod debug;
 /// Adaptor to register devices to MMU.
 pub mod device;
 
+/// Disassembler for the CPU instructions.
+pub mod disasm;
+
+/// GDB remote serial protocol stub.
+#[cfg(feature = "gdb")]
+pub mod gdb;
+
 /// Decoder which evaluates each CPU instructions.
 pub mod inst;
 
 /// Handles memory and I/O port access from the CPU.
 pub mod mmu;
 
+/// Headless runner of test ROMs for integration tests.
+pub mod testing;
+
 /// Hardware interface, which abstracts OS-specific functions.
 mod hardware;
 
-pub use crate::hardware::{Hardware, Key, Stream, VRAM_HEIGHT, VRAM_WIDTH};
-pub use crate::system::{run, run_debug, Config, System};
+#[cfg(feature = "serial")]
+pub use crate::adapter::{AdapterPort, FourPlayerAdapter};
+pub use crate::boot::Model;
+pub use crate::buffers::{Buffers, FRAME_SIZE, MEMORY_SIZE, VRAM_SIZE, WRAM_SIZE};
+pub use crate::cheat::{Cheat, Filter, RamSearch};
+pub use crate::error::Error;
+pub use crate::fc::Pacing;
+pub use crate::gpu::{Palettes, Sprite, TileMap, MAP_SIZE, TILES_HEIGHT, TILES_WIDTH};
+pub use crate::hardware::{
+    Clock, Hardware, Input, Key, Link, MaybeSend, MaybeSync, Persistence, Pixel, PixelFormat,
+    Screen, Speaker, Stream, VRAM_HEIGHT, VRAM_WIDTH,
+};
+#[cfg(feature = "serial")]
+pub use crate::link::{LinkCable, LocalLink};
+pub use crate::mbc::Mapper;
+pub use crate::movie::Movie;
+#[cfg(feature = "serial")]
+pub use crate::netplay::{Netplay, NetplayEvent, Side, Transport};
+pub use crate::patch::apply_patch;
+#[cfg(feature = "serial")]
+pub use crate::printer::Printer;
+pub use crate::resample::Resampler;
+pub use crate::slots::{Slot, SlotInfo, Slots};
+#[cfg(feature = "sound")]
+pub use crate::sound::{AudioSink, Channel};
+pub use crate::system::{run, run_debug, Config, PollEvent, System};
@@ -0,0 +1,127 @@
+use crate::hardware::{HardwareHandle, MaybeSend};
+use alloc::sync::Arc;
+use spin::Mutex;
+
+/// The link cable which connects the serial port to the peer.
+///
+/// A transfer exchanges one byte between the two ends. The end using the internal clock
+/// drives the transfer by [`LinkCable::send`][], while the end using the external clock
+/// waits for the peer by [`LinkCable::recv`][].
+///
+/// Connect it to the emulator by [`System::connect_link`][crate::System::connect_link].
+pub trait LinkCable: MaybeSend {
+    /// Transfer `data` to the peer supplying the clock.
+    ///
+    /// Returns the byte shifted in from the peer, or `None` if the peer doesn't respond,
+    /// in which case the serial port receives `0xff`.
+    fn send(&mut self, data: u8) -> Option<u8>;
+
+    /// Check if the peer supplying the clock has started a transfer, offering `data` to it.
+    ///
+    /// Returns the byte from the peer if the transfer is done, in which case `data` is passed to the peer.
+    /// Called repeatedly while the serial port waits for the external clock.
+    fn recv(&mut self, data: u8) -> Option<u8>;
+}
+
+/// The default link cable, which sends and receives the bytes through [`Hardware`][crate::Hardware].
+pub(crate) struct HardwareLink<'a>(pub HardwareHandle<'a>);
+
+impl<'a> LinkCable for HardwareLink<'a> {
+    fn send(&mut self, data: u8) -> Option<u8> {
+        let mut hw = self.0.get().lock();
+        hw.send_byte(data);
+        hw.recv_byte()
+    }
+
+    fn recv(&mut self, data: u8) -> Option<u8> {
+        let mut hw = self.0.get().lock();
+        let recv = hw.recv_byte();
+        if recv.is_some() {
+            hw.send_byte(data);
+        }
+        recv
+    }
+}
+
+/// The state of one end of [`LocalLink`][].
+#[derive(Default)]
+struct End {
+    /// The byte offered by the end waiting for the external clock.
+    ready: Option<u8>,
+    /// The byte transferred to the end by the peer.
+    inbox: Option<u8>,
+}
+
+/// The in-process link cable to connect two emulators running in the same program,
+/// possibly on different threads.
+///
+/// ```rust,no_run
+/// # fn link<'a, D: rgy::debug::Debugger + rgy::MaybeSend + 'a>(a: &mut rgy::System<'a, D>, b: &mut rgy::System<'a, D>) {
+/// let (l, r) = rgy::LocalLink::pair();
+/// a.connect_link(l);
+/// b.connect_link(r);
+/// # }
+/// ```
+pub struct LocalLink {
+    ends: Arc<Mutex<[End; 2]>>,
+    side: usize,
+}
+
+impl LocalLink {
+    /// Create the two ends of a link cable.
+    pub fn pair() -> (Self, Self) {
+        let ends = Arc::new(Mutex::new([End::default(), End::default()]));
+
+        (
+            Self {
+                ends: ends.clone(),
+                side: 0,
+            },
+            Self { ends, side: 1 },
+        )
+    }
+}
+
+impl LinkCable for LocalLink {
+    fn send(&mut self, data: u8) -> Option<u8> {
+        let mut ends = self.ends.lock();
+        let peer = &mut ends[1 - self.side];
+
+        let recv = peer.ready.take()?;
+        peer.inbox = Some(data);
+        Some(recv)
+    }
+
+    fn recv(&mut self, data: u8) -> Option<u8> {
+        let mut ends = self.ends.lock();
+        let end = &mut ends[self.side];
+
+        match end.inbox.take() {
+            Some(recv) => Some(recv),
+            None => {
+                end.ready = Some(data);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn local_link() {
+        let (mut a, mut b) = LocalLink::pair();
+
+        // Nobody is waiting for the clock
+        assert_eq!(a.send(0x12), None);
+
+        assert_eq!(b.recv(0x34), None);
+        assert_eq!(a.send(0x12), Some(0x34));
+        assert_eq!(b.recv(0x34), Some(0x12));
+
+        // The offer is consumed by the transfer
+        assert_eq!(a.send(0x56), None);
+    }
+}
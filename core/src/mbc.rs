@@ -1,11 +1,22 @@
+use crate::cart::Header;
+use crate::debug::{Events, HwEvent};
 use crate::device::IoHandler;
-use crate::hardware::HardwareHandle;
+use crate::error::Error;
+use crate::hardware::{HardwareHandle, MaybeSend};
 use crate::mmu::{MemRead, MemWrite, Mmu};
-use alloc::{
-    string::{String, ToString},
-    vec::Vec,
-};
+use crate::state::{Reader, State, Writer};
+use crate::system::Config;
+use alloc::borrow::Cow;
+use alloc::sync::Arc;
+use alloc::{boxed::Box, vec::Vec};
 use log::*;
+use spin::Mutex;
+
+/// The cartridge ROM, either borrowed from the caller or copied.
+pub(crate) type Rom<'a> = Cow<'a, [u8]>;
+
+/// The clock cycles per second the RTC counts on the emulated clock.
+const RTC_CYCLES_PER_SEC: u64 = 4194304;
 
 const BOOT_ROM: &[u8] = {
     #[cfg(feature = "color")]
@@ -18,18 +29,54 @@ const BOOT_ROM: &[u8] = {
     }
 };
 
-struct MbcNone {
-    rom: Vec<u8>,
+/// The interface to implement a custom memory bank controller.
+///
+/// The implementation is passed to [`System::with_mapper`][crate::System::with_mapper]
+/// and takes over all the cartridge address ranges (0000-7fff and a000-bfff).
+/// It needs to be `Send` only with the `threads` feature.
+pub trait Mapper: MaybeSend {
+    /// Read a byte from the ROM area (0000-7fff).
+    fn read_rom(&mut self, addr: u16) -> u8;
+
+    /// Read a byte from the external RAM area (a000-bfff).
+    fn read_ram(&mut self, addr: u16) -> u8;
+
+    /// Write a byte to the external RAM area (a000-bfff).
+    fn write_ram(&mut self, addr: u16, value: u8);
+
+    /// Write a byte to the mapper registers, which are mapped to the ROM area (0000-7fff).
+    fn write_register(&mut self, addr: u16, value: u8);
+
+    /// Reset the mapper registers to the power-on state, keeping the RAM content.
+    fn reset(&mut self) {}
+
+    /// Encode the registers and the RAM for savestates.
+    ///
+    /// The default saves nothing, i.e. the mapper keeps its state across loading savestates.
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restore the state encoded by [`Mapper::save_state`][].
+    fn load_state(&mut self, _state: &[u8]) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+struct MbcCustom<'a> {
+    mapper: Box<dyn Mapper + 'a>,
 }
 
-impl MbcNone {
-    fn new(rom: Vec<u8>) -> Self {
-        Self { rom }
+impl<'a> MbcCustom<'a> {
+    fn new(mapper: Box<dyn Mapper + 'a>) -> Self {
+        Self { mapper }
     }
 
     fn on_read(&mut self, _mmu: &Mmu, addr: u16) -> MemRead {
         if addr <= 0x7fff {
-            MemRead::Replace(self.rom[addr as usize])
+            MemRead::Replace(self.mapper.read_rom(addr))
+        } else if (0xa000..=0xbfff).contains(&addr) {
+            MemRead::Replace(self.mapper.read_ram(addr))
         } else {
             MemRead::PassThrough
         }
@@ -37,67 +84,206 @@ impl MbcNone {
 
     fn on_write(&mut self, _mmu: &Mmu, addr: u16, value: u8) -> MemWrite {
         if addr <= 0x7fff {
+            self.mapper.write_register(addr, value);
             MemWrite::Block
-        } else if addr >= 0xa000 && addr <= 0xbfff {
+        } else if (0xa000..=0xbfff).contains(&addr) {
+            self.mapper.write_ram(addr, value);
+            MemWrite::Block
+        } else {
             MemWrite::PassThrough
+        }
+    }
+}
+
+/// Load the battery-backed RAM of the given size from the hardware.
+fn load_ram(hw: &HardwareHandle, size: usize) -> Vec<u8> {
+    if size == 0 {
+        return Vec::new();
+    }
+
+    let mut ram = hw.get().lock().load_ram(size);
+
+    if ram.len() != size {
+        warn!(
+            "Save data size mismatch: expect: {:x}, actual: {:x}",
+            size,
+            ram.len()
+        );
+        ram.resize(size, 0);
+    }
+
+    ram
+}
+
+/// Read a byte from the ROM bank.
+///
+/// The bank number is masked by the number of banks the ROM has, as the unused
+/// upper bank bits are not connected. Banks beyond the ROM image read 0xff.
+fn rom_read(rom: &[u8], bank: usize, offset: usize) -> u8 {
+    let banks = (rom.len() / 0x4000).max(1).next_power_of_two();
+    let addr = (bank & (banks - 1)) * 0x4000 + offset;
+
+    rom.get(addr).copied().unwrap_or(0xff)
+}
+
+fn ram_addr(ram: &[u8], bank: usize, offset: usize) -> usize {
+    if ram.len() < 0x2000 {
+        // RAM smaller than a bank is mirrored throughout the bank.
+        offset & (ram.len().next_power_of_two() - 1)
+    } else {
+        let banks = (ram.len() / 0x2000).next_power_of_two();
+        (bank & (banks - 1)) * 0x2000 + offset
+    }
+}
+
+/// Read a byte from the external RAM bank, which reads 0xff if the RAM doesn't exist.
+fn ram_read(ram: &[u8], bank: usize, offset: usize) -> u8 {
+    if ram.is_empty() {
+        return 0xff;
+    }
+
+    ram.get(ram_addr(ram, bank, offset))
+        .copied()
+        .unwrap_or(0xff)
+}
+
+/// Write a byte to the external RAM bank, which is ignored if the RAM doesn't exist.
+fn ram_write(ram: &mut [u8], bank: usize, offset: usize, value: u8) {
+    if ram.is_empty() {
+        return;
+    }
+
+    let addr = ram_addr(ram, bank, offset);
+
+    if let Some(b) = ram.get_mut(addr) {
+        *b = value;
+    }
+}
+
+struct MbcNone<'a> {
+    hw: HardwareHandle<'a>,
+    rom: Rom<'a>,
+    ram: Vec<u8>,
+}
+
+impl<'a> MbcNone<'a> {
+    fn new(hw: HardwareHandle<'a>, rom: Rom<'a>, ram_size: usize) -> Self {
+        let ram = load_ram(&hw, ram_size);
+
+        Self { hw, rom, ram }
+    }
+
+    fn on_read(&mut self, _mmu: &Mmu, addr: u16) -> MemRead {
+        if addr <= 0x7fff {
+            MemRead::Replace(rom_read(&self.rom, 0, addr as usize))
+        } else if (0xa000..=0xbfff).contains(&addr) && !self.ram.is_empty() {
+            MemRead::Replace(ram_read(&self.ram, 0, addr as usize - 0xa000))
+        } else {
+            MemRead::PassThrough
+        }
+    }
+
+    fn on_write(&mut self, _mmu: &Mmu, addr: u16, value: u8) -> MemWrite {
+        if addr <= 0x7fff {
+            MemWrite::Block
+        } else if addr >= 0xa000 && addr <= 0xbfff {
+            if self.ram.is_empty() {
+                MemWrite::PassThrough
+            } else {
+                ram_write(&mut self.ram, 0, addr as usize - 0xa000, value);
+                MemWrite::Block
+            }
         } else {
-            unreachable!("Write to ROM: {:02x} {:02x}", addr, value);
+            warn!("write to rom {:04x} {:02x}", addr, value);
+            MemWrite::PassThrough
         }
     }
 }
 
-struct Mbc1 {
-    hw: HardwareHandle,
-    rom: Vec<u8>,
+struct Mbc1<'a> {
+    hw: HardwareHandle<'a>,
+    rom: Rom<'a>,
     ram: Vec<u8>,
-    rom_bank: usize,
-    ram_bank: usize,
+    bank1: usize,
+    bank2: usize,
     ram_enable: bool,
     ram_select: bool,
+    multicart: bool,
 }
 
-impl Mbc1 {
-    fn new(hw: HardwareHandle, rom: Vec<u8>) -> Self {
-        let ram = hw.get().borrow_mut().load_ram(0x8000);
+impl<'a> Mbc1<'a> {
+    fn new(hw: HardwareHandle<'a>, rom: Rom<'a>, ram_size: usize, multicart: bool) -> Self {
+        let ram = load_ram(&hw, ram_size);
+
+        if multicart {
+            info!("MBC1 multicart wiring");
+        }
 
         Self {
             hw,
             rom,
             ram,
-            rom_bank: 0,
-            ram_bank: 0,
+            bank1: 1,
+            bank2: 0,
             ram_enable: false,
             ram_select: false,
+            multicart,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.bank1 = 1;
+        self.bank2 = 0;
+        self.ram_enable = false;
+        self.ram_select = false;
+    }
+
+    fn bank2_shift(&self) -> usize {
+        // Multicarts don't connect bit 4 of the lower bank register,
+        // so the upper register is wired one bit lower.
+        if self.multicart {
+            4
+        } else {
+            5
+        }
+    }
+
+    fn rom_bank_low(&self) -> usize {
+        if self.ram_select {
+            self.bank2 << self.bank2_shift()
+        } else {
+            0
+        }
+    }
+
+    fn rom_bank_high(&self) -> usize {
+        let mask = if self.multicart { 0xf } else { 0x1f };
+        (self.bank2 << self.bank2_shift()) | (self.bank1 & mask)
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.ram_select {
+            self.bank2
+        } else {
+            0
         }
     }
 
     fn on_read(&mut self, _mmu: &Mmu, addr: u16) -> MemRead {
         if addr <= 0x3fff {
-            MemRead::Replace(self.rom[addr as usize])
+            MemRead::Replace(rom_read(&self.rom, self.rom_bank_low(), addr as usize))
         } else if addr >= 0x4000 && addr <= 0x7fff {
-            let rom_bank = self.rom_bank.max(1);
-
-            // ROM bank 0x20, 0x40, 0x60 are somehow not available
-            let rom_bank = if rom_bank == 0x20 || rom_bank == 0x40 || rom_bank == 0x60 {
-                warn!("Odd ROM bank selection: {:02x}", rom_bank);
-                rom_bank + 1
-            } else {
-                rom_bank
-            };
-
-            let base = rom_bank * 0x4000;
-            let offset = addr as usize - 0x4000;
-            let addr = (base + offset) & (self.rom.len() - 1);
-            MemRead::Replace(self.rom[addr])
+            MemRead::Replace(rom_read(
+                &self.rom,
+                self.rom_bank_high(),
+                addr as usize - 0x4000,
+            ))
         } else if addr >= 0xa000 && addr <= 0xbfff {
             if self.ram_enable {
-                let base = self.ram_bank as usize * 0x2000;
-                let offset = addr as usize - 0xa000;
-                let addr = (base + offset) & (self.rom.len() - 1);
-                MemRead::Replace(self.ram[addr])
+                MemRead::Replace(ram_read(&self.ram, self.ram_bank(), addr as usize - 0xa000))
             } else {
                 warn!("Read from disabled external RAM: {:04x}", addr);
-                MemRead::Replace(0)
+                MemRead::Replace(0xff)
             }
         } else {
             MemRead::PassThrough
@@ -112,56 +298,61 @@ impl Mbc1 {
             } else {
                 info!("External RAM disabled");
                 self.ram_enable = false;
-                self.hw.get().borrow_mut().save_ram(&self.ram);
+                self.hw.get().lock().save_ram(&self.ram);
             }
             MemWrite::Block
         } else if addr >= 0x2000 && addr <= 0x3fff {
-            self.rom_bank = (self.rom_bank & !0x1f) | (value as usize & 0x1f);
-            debug!("Switch ROM bank to {:02x}", self.rom_bank);
+            // Writing zero selects bank 1, which also makes 0x20, 0x40 and 0x60 unreachable.
+            self.bank1 = (value as usize & 0x1f).max(1);
+            debug!("Switch ROM bank to {:02x}", self.rom_bank_high());
             MemWrite::Block
         } else if addr >= 0x4000 && addr <= 0x5fff {
-            if self.ram_select {
-                self.ram_bank = value as usize & 0x3;
-            } else {
-                self.rom_bank = (self.rom_bank & !0x60) | ((value as usize & 0x3) << 5);
-            }
+            self.bank2 = value as usize & 0x3;
+            debug!("Switch ROM/RAM upper bank to {:02x}", self.bank2);
             MemWrite::Block
         } else if addr >= 0x6000 && addr <= 0x7fff {
-            if value == 0x00 {
-                self.ram_select = false;
-            } else if value == 0x01 {
-                self.ram_select = true;
-            } else {
-                unimplemented!("Invalid ROM/RAM select mode");
-            }
+            self.ram_select = value & 0x01 != 0;
             MemWrite::Block
         } else if addr >= 0xa000 && addr <= 0xbfff {
             if self.ram_enable {
-                let base = self.ram_bank as usize * 0x2000;
-                let offset = addr as usize - 0xa000;
-                self.ram[base + offset] = value;
+                let bank = self.ram_bank();
+                ram_write(&mut self.ram, bank, addr as usize - 0xa000, value);
                 MemWrite::Block
             } else {
                 warn!("Write to disabled external RAM: {:04x} {:02x}", addr, value);
                 MemWrite::Block
             }
         } else {
-            unimplemented!("write to rom {:04x} {:02x}", addr, value)
+            warn!("write to rom {:04x} {:02x}", addr, value);
+            MemWrite::PassThrough
         }
     }
 }
 
-struct Mbc2 {
-    hw: HardwareHandle,
-    rom: Vec<u8>,
+/// Detect MBC1 multicarts by looking for a second boot logo at the start of bank 0x10.
+fn is_mbc1_multicart(rom: &[u8]) -> bool {
+    if rom.len() != 0x100000 {
+        return false;
+    }
+
+    let logo = &rom[0x104..0x134];
+    let base = 0x10 * 0x4000;
+
+    logo == &rom[base + 0x104..base + 0x134]
+}
+
+struct Mbc2<'a> {
+    hw: HardwareHandle<'a>,
+    rom: Rom<'a>,
     ram: Vec<u8>,
     rom_bank: usize,
     ram_enable: bool,
 }
 
-impl Mbc2 {
-    fn new(hw: HardwareHandle, rom: Vec<u8>) -> Self {
-        let ram = hw.get().borrow_mut().load_ram(0x200);
+impl<'a> Mbc2<'a> {
+    fn new(hw: HardwareHandle<'a>, rom: Rom<'a>) -> Self {
+        // 512 x 4-bit RAM built into the MBC2 chip itself.
+        let ram = load_ram(&hw, 0x200);
 
         Self {
             hw,
@@ -172,19 +363,29 @@ impl Mbc2 {
         }
     }
 
+    fn reset(&mut self) {
+        self.rom_bank = 1;
+        self.ram_enable = false;
+    }
+
+    fn ram_offset(&self, addr: u16) -> usize {
+        // Only the lower 9 address bits are decoded, so the 512 bytes
+        // are mirrored throughout a000-bfff.
+        addr as usize & 0x1ff
+    }
+
     fn on_read(&mut self, _mmu: &Mmu, addr: u16) -> MemRead {
         if addr <= 0x3fff {
-            MemRead::Replace(self.rom[addr as usize])
+            MemRead::Replace(rom_read(&self.rom, 0, addr as usize))
         } else if addr >= 0x4000 && addr <= 0x7fff {
-            let base = self.rom_bank.max(1) * 0x4000;
-            let offset = addr as usize - 0x4000;
-            MemRead::Replace(self.rom[base + offset])
-        } else if addr >= 0xa000 && addr <= 0xa1ff {
+            MemRead::Replace(rom_read(&self.rom, self.rom_bank, addr as usize - 0x4000))
+        } else if (0xa000..=0xbfff).contains(&addr) {
             if self.ram_enable {
-                MemRead::Replace(self.ram[addr as usize - 0xa000] & 0xf)
+                // The upper 4 bits are not connected and read as 1.
+                MemRead::Replace(self.ram[self.ram_offset(addr)] | 0xf0)
             } else {
                 warn!("Read from disabled cart RAM: {:04x}", addr);
-                MemRead::Replace(0)
+                MemRead::Replace(0xff)
             }
         } else {
             MemRead::PassThrough
@@ -192,7 +393,8 @@ impl Mbc2 {
     }
 
     fn on_write(&mut self, _mmu: &Mmu, addr: u16, value: u8) -> MemWrite {
-        if addr <= 0x1fff {
+        if addr <= 0x3fff {
+            // Bit 8 of the address selects the register in the whole 0000-3fff range.
             if addr & 0x100 == 0 {
                 self.ram_enable = (value & 0x0f) == 0x0a;
                 info!(
@@ -205,12 +407,9 @@ impl Mbc2 {
                     value
                 );
                 if !self.ram_enable {
-                    self.hw.get().borrow_mut().save_ram(&self.ram);
+                    self.hw.get().lock().save_ram(&self.ram);
                 }
-            }
-            MemWrite::Block
-        } else if addr >= 0x2000 && addr <= 0x3fff {
-            if addr & 0x100 != 0 {
+            } else {
                 self.rom_bank = (value as usize & 0xf).max(1);
                 debug!("Switch ROM bank to {:02x}", self.rom_bank);
             }
@@ -218,9 +417,10 @@ impl Mbc2 {
         } else if addr >= 0x4000 && addr <= 0x7fff {
             warn!("Writing to read-only range: {:04x} {:02x}", addr, value);
             MemWrite::Block
-        } else if addr >= 0xa000 && addr <= 0xa1ff {
+        } else if (0xa000..=0xbfff).contains(&addr) {
             if self.ram_enable {
-                self.ram[addr as usize - 0xa000] = value & 0xf;
+                let offset = self.ram_offset(addr);
+                self.ram[offset] = value & 0xf;
                 MemWrite::Block
             } else {
                 warn!("Write to disabled cart RAM: {:04x} {:02x}", addr, value);
@@ -233,9 +433,9 @@ impl Mbc2 {
     }
 }
 
-struct Mbc3 {
-    hw: HardwareHandle,
-    rom: Vec<u8>,
+struct Mbc3<'a> {
+    hw: HardwareHandle<'a>,
+    rom: Rom<'a>,
     ram: Vec<u8>,
     rom_bank: usize,
     enable: bool,
@@ -247,17 +447,13 @@ struct Mbc3 {
     rtc_day_high: u8,
     epoch: u64,
     prelatch: bool,
+    /// The emulated clock cycles to run the RTC on instead of the hardware clock.
+    cycles: Option<Arc<Mutex<u64>>>,
 }
 
-impl Drop for Mbc3 {
-    fn drop(&mut self) {
-        self.save();
-    }
-}
-
-impl Mbc3 {
-    fn new(hw: HardwareHandle, rom: Vec<u8>) -> Self {
-        let ram = hw.get().borrow_mut().load_ram(0x8000);
+impl<'a> Mbc3<'a> {
+    fn new(hw: HardwareHandle<'a>, rom: Rom<'a>, ram_size: usize) -> Self {
+        let ram = load_ram(&hw, ram_size);
 
         let mut s = Self {
             hw,
@@ -273,43 +469,58 @@ impl Mbc3 {
             rtc_day_high: 0,
             epoch: 0,
             prelatch: false,
+            cycles: None,
         };
         s.update_epoch();
         s
     }
 
+    fn reset(&mut self) {
+        self.rom_bank = 0;
+        self.enable = false;
+        self.select = 0;
+        self.prelatch = false;
+
+        // The RTC keeps running while the emulated clock restarts
+        if self.cycles.is_some() {
+            self.latch();
+        }
+    }
+
     fn save(&mut self) {
-        self.hw.get().borrow_mut().save_ram(&self.ram);
+        self.hw.get().lock().save_ram(&self.ram);
     }
 
     fn epoch(&self) -> u64 {
-        self.hw.get().borrow_mut().clock() / 1000_000
+        match &self.cycles {
+            Some(cycles) => *cycles.lock() / RTC_CYCLES_PER_SEC,
+            None => self.hw.get().lock().clock() / 1_000_000,
+        }
     }
 
     fn on_read(&mut self, _mmu: &Mmu, addr: u16) -> MemRead {
         if addr <= 0x3fff {
-            MemRead::Replace(self.rom[addr as usize])
+            MemRead::Replace(rom_read(&self.rom, 0, addr as usize))
         } else if addr >= 0x4000 && addr <= 0x7fff {
             let rom_bank = self.rom_bank.max(1);
-            let base = rom_bank * 0x4000;
-            let offset = addr as usize - 0x4000;
-            MemRead::Replace(self.rom[base + offset])
+            MemRead::Replace(rom_read(&self.rom, rom_bank, addr as usize - 0x4000))
         } else if addr >= 0xa000 && addr <= 0xbfff {
             match self.select {
                 x if x == 0x00 || x == 0x01 || x == 0x02 || x == 0x03 => {
-                    let base = x as usize * 0x2000;
-                    let offset = addr as usize - 0xa000;
-                    MemRead::Replace(self.ram[base + offset])
+                    MemRead::Replace(ram_read(&self.ram, x as usize, addr as usize - 0xa000))
                 }
                 0x08 => MemRead::Replace(self.rtc_secs),
                 0x09 => MemRead::Replace(self.rtc_mins),
                 0x0a => MemRead::Replace(self.rtc_hours),
                 0x0b => MemRead::Replace(self.rtc_day_low),
                 0x0c => MemRead::Replace(self.rtc_day_high),
-                s => unimplemented!("Unknown selector: {:02x}", s),
+                s => {
+                    warn!("Read from unknown RAM bank/RTC: {:02x}", s);
+                    MemRead::Replace(0xff)
+                }
             }
         } else {
-            unreachable!("Invalid read from ROM: {:02x}", addr);
+            MemRead::PassThrough
         }
     }
 
@@ -347,9 +558,7 @@ impl Mbc3 {
         } else if addr >= 0xa000 && addr <= 0xbfff {
             match self.select {
                 x if x == 0x00 || x == 0x01 || x == 0x02 || x == 0x03 => {
-                    let base = x as usize * 0x2000;
-                    let offset = addr as usize - 0xa000;
-                    self.ram[base + offset] = value;
+                    ram_write(&mut self.ram, x as usize, addr as usize - 0xa000, value);
                     MemWrite::Block
                 }
                 0x08 => {
@@ -377,10 +586,14 @@ impl Mbc3 {
                     self.update_epoch();
                     MemWrite::Block
                 }
-                s => unimplemented!("Unknown selector: {:02x}", s),
+                s => {
+                    warn!("Write to unknown RAM bank/RTC: {:02x}", s);
+                    MemWrite::Block
+                }
             }
         } else {
-            unimplemented!("write to rom {:04x} {:02x}", addr, value)
+            warn!("write to rom {:04x} {:02x}", addr, value);
+            MemWrite::PassThrough
         }
     }
 
@@ -419,7 +632,7 @@ impl Mbc3 {
             // Halt
             self.epoch
         };
-        let elapsed = new_epoch - self.epoch;
+        let elapsed = new_epoch.saturating_sub(self.epoch);
 
         let last_day = self.day();
         let last_secs = self.dhms_to_secs();
@@ -443,44 +656,62 @@ impl Mbc3 {
     }
 }
 
-struct Mbc5 {
-    hw: HardwareHandle,
-    rom: Vec<u8>,
+struct Mbc5<'a> {
+    hw: HardwareHandle<'a>,
+    rom: Rom<'a>,
     ram: Vec<u8>,
     rom_bank: usize,
     ram_bank: usize,
     ram_enable: bool,
+    rumble: Option<bool>,
 }
 
-impl Mbc5 {
-    fn new(hw: HardwareHandle, rom: Vec<u8>) -> Self {
-        let ram = hw.get().borrow_mut().load_ram(0x20000);
+impl<'a> Mbc5<'a> {
+    fn new(hw: HardwareHandle<'a>, rom: Rom<'a>, ram_size: usize, rumble: bool) -> Self {
+        let ram = load_ram(&hw, ram_size);
 
         Self {
             hw,
             rom,
             ram,
-            rom_bank: 0,
+            rom_bank: 1,
             ram_bank: 0,
             ram_enable: false,
+            rumble: if rumble { Some(false) } else { None },
+        }
+    }
+
+    fn reset(&mut self) {
+        self.rom_bank = 1;
+        self.ram_bank = 0;
+        self.ram_enable = false;
+        if self.rumble.is_some() {
+            self.set_rumble(false);
+        }
+    }
+
+    fn set_rumble(&mut self, on: bool) {
+        if let Some(rumble) = self.rumble {
+            if rumble != on {
+                debug!("Rumble {}", if on { "on" } else { "off" });
+                self.rumble = Some(on);
+                self.hw.get().lock().rumble(on);
+            }
         }
     }
 
     fn on_read(&mut self, _mmu: &Mmu, addr: u16) -> MemRead {
         if addr <= 0x3fff {
-            MemRead::Replace(self.rom[addr as usize])
+            MemRead::Replace(rom_read(&self.rom, 0, addr as usize))
         } else if addr >= 0x4000 && addr <= 0x7fff {
-            let base = self.rom_bank * 0x4000;
-            let offset = addr as usize - 0x4000;
-            MemRead::Replace(self.rom[base + offset])
+            // Bank 0 is selectable on MBC5, unlike MBC1/MBC3.
+            MemRead::Replace(rom_read(&self.rom, self.rom_bank, addr as usize - 0x4000))
         } else if addr >= 0xa000 && addr <= 0xbfff {
             if self.ram_enable {
-                let base = self.ram_bank * 0x2000;
-                let offset = addr as usize - 0xa000;
-                MemRead::Replace(self.ram[base + offset])
+                MemRead::Replace(ram_read(&self.ram, self.ram_bank, addr as usize - 0xa000))
             } else {
                 warn!("Read from disabled external RAM: {:04x}", addr);
-                MemRead::Replace(0)
+                MemRead::Replace(0xff)
             }
         } else {
             MemRead::PassThrough
@@ -495,7 +726,7 @@ impl Mbc5 {
             } else {
                 info!("External RAM disabled");
                 self.ram_enable = false;
-                self.hw.get().borrow_mut().save_ram(&self.ram);
+                self.hw.get().lock().save_ram(&self.ram);
             }
             MemWrite::Block
         } else if addr >= 0x2000 && addr <= 0x2fff {
@@ -507,70 +738,674 @@ impl Mbc5 {
             debug!("Switch ROM bank to {:02x}", self.rom_bank);
             MemWrite::Block
         } else if addr >= 0x4000 && addr <= 0x5fff {
-            self.ram_bank = value as usize & 0xf;
+            if self.rumble.is_some() {
+                // On rumble carts, bit 3 drives the motor instead of selecting RAM banks.
+                self.ram_bank = value as usize & 0x7;
+                self.set_rumble(value & 0x08 != 0);
+            } else {
+                self.ram_bank = value as usize & 0xf;
+            }
+            MemWrite::Block
+        } else if (0x6000..=0x7fff).contains(&addr) {
+            warn!("Writing to unused MBC5 range: {:04x} {:02x}", addr, value);
             MemWrite::Block
         } else if addr >= 0xa000 && addr <= 0xbfff {
             if self.ram_enable {
-                let base = self.ram_bank * 0x2000;
-                let offset = addr as usize - 0xa000;
-                self.ram[base + offset] = value;
+                ram_write(&mut self.ram, self.ram_bank, addr as usize - 0xa000, value);
                 MemWrite::Block
             } else {
                 warn!("Write to disabled external RAM: {:04x} {:02x}", addr, value);
                 MemWrite::Block
             }
         } else {
-            unimplemented!("write to rom {:04x} {:02x}", addr, value)
+            warn!("write to rom {:04x} {:02x}", addr, value);
+            MemWrite::PassThrough
         }
     }
 }
 
-#[allow(unused)]
-struct HuC1 {
-    rom: Vec<u8>,
+#[derive(Debug, Clone, Copy)]
+enum EepromState {
+    Idle,
+    Command,
+    Read(u16, usize),
+    Write(u8, bool),
+}
+
+/// 93LC56 serial EEPROM (128 x 16-bit words) used as the save memory of MBC7.
+struct Eeprom {
+    data: Vec<u8>,
+    state: EepromState,
+    shift: u16,
+    bits: usize,
+    write_enable: bool,
+    cs: bool,
+    clk: bool,
+    di: bool,
+    dout: bool,
 }
 
-impl HuC1 {
-    fn new(rom: Vec<u8>) -> Self {
-        Self { rom }
+impl Eeprom {
+    fn new(mut data: Vec<u8>) -> Self {
+        data.resize(0x100, 0xff);
+
+        Self {
+            data,
+            state: EepromState::Idle,
+            shift: 0,
+            bits: 0,
+            write_enable: false,
+            cs: false,
+            clk: false,
+            di: false,
+            dout: true,
+        }
+    }
+
+    fn word(&self, addr: u8) -> u16 {
+        let i = (addr as usize & 0x7f) * 2;
+        (self.data[i + 1] as u16) << 8 | self.data[i] as u16
     }
 
-    fn on_read(&mut self, _mmu: &Mmu, _addr: u16) -> MemRead {
-        unimplemented!()
+    fn set_word(&mut self, addr: u8, value: u16) {
+        let i = (addr as usize & 0x7f) * 2;
+        self.data[i] = value as u8;
+        self.data[i + 1] = (value >> 8) as u8;
     }
 
-    fn on_write(&mut self, _mmu: &Mmu, _addr: u16, _value: u8) -> MemWrite {
-        unimplemented!()
+    fn read(&self) -> u8 {
+        let mut v = 0;
+        v |= if self.cs { 0x80 } else { 0x00 };
+        v |= if self.clk { 0x40 } else { 0x00 };
+        v |= if self.di { 0x02 } else { 0x00 };
+        v |= if self.dout { 0x01 } else { 0x00 };
+        v
+    }
+
+    /// Drive the chip select, clock and data-in lines.
+    /// Returns `true` if the content of the EEPROM is modified.
+    fn write(&mut self, value: u8) -> bool {
+        let cs = value & 0x80 != 0;
+        let clk = value & 0x40 != 0;
+        let rising = !self.clk && clk;
+
+        self.cs = cs;
+        self.clk = clk;
+        self.di = value & 0x02 != 0;
+
+        if !cs {
+            self.state = EepromState::Idle;
+            return false;
+        }
+
+        if rising {
+            self.clock()
+        } else {
+            false
+        }
+    }
+
+    fn clock(&mut self) -> bool {
+        let di = self.di as u16;
+
+        match self.state {
+            EepromState::Idle => {
+                if di != 0 {
+                    // Start bit
+                    self.state = EepromState::Command;
+                    self.shift = 0;
+                    self.bits = 0;
+                }
+                false
+            }
+            EepromState::Command => {
+                self.shift = self.shift << 1 | di;
+                self.bits += 1;
+                if self.bits == 10 {
+                    self.command()
+                } else {
+                    false
+                }
+            }
+            EepromState::Read(data, remain) => {
+                if remain == 0 {
+                    self.state = EepromState::Idle;
+                } else {
+                    self.dout = data & 0x8000 != 0;
+                    self.state = EepromState::Read(data << 1, remain - 1);
+                }
+                false
+            }
+            EepromState::Write(addr, all) => {
+                self.shift = self.shift << 1 | di;
+                self.bits += 1;
+                if self.bits < 16 {
+                    return false;
+                }
+
+                self.state = EepromState::Idle;
+                self.dout = true;
+
+                if !self.write_enable {
+                    return false;
+                }
+
+                if all {
+                    for a in 0..0x80 {
+                        self.set_word(a, self.shift);
+                    }
+                } else {
+                    self.set_word(addr, self.shift);
+                }
+                true
+            }
+        }
+    }
+
+    fn command(&mut self) -> bool {
+        let op = (self.shift >> 8) & 0x3;
+        let addr = self.shift as u8;
+
+        self.shift = 0;
+        self.bits = 0;
+        self.state = EepromState::Idle;
+
+        match op {
+            0x2 => {
+                trace!("EEPROM read: {:02x}", addr);
+                self.dout = false;
+                self.state = EepromState::Read(self.word(addr), 16);
+                false
+            }
+            0x1 => {
+                trace!("EEPROM write: {:02x}", addr);
+                self.state = EepromState::Write(addr, false);
+                false
+            }
+            0x3 => {
+                debug!("EEPROM erase: {:02x}", addr);
+                self.dout = true;
+                if self.write_enable {
+                    self.set_word(addr, 0xffff);
+                }
+                self.write_enable
+            }
+            _ => match addr >> 6 {
+                0x3 => {
+                    debug!("EEPROM write enabled");
+                    self.write_enable = true;
+                    false
+                }
+                0x0 => {
+                    debug!("EEPROM write disabled");
+                    self.write_enable = false;
+                    false
+                }
+                0x2 => {
+                    debug!("EEPROM erase all");
+                    self.dout = true;
+                    if self.write_enable {
+                        for b in self.data.iter_mut() {
+                            *b = 0xff;
+                        }
+                    }
+                    self.write_enable
+                }
+                _ => {
+                    debug!("EEPROM write all");
+                    self.state = EepromState::Write(0, true);
+                    false
+                }
+            },
+        }
     }
 }
 
-enum MbcType {
-    None(MbcNone),
-    Mbc1(Mbc1),
-    Mbc2(Mbc2),
-    Mbc3(Mbc3),
-    Mbc5(Mbc5),
-    HuC1(HuC1),
+struct Mbc7<'a> {
+    hw: HardwareHandle<'a>,
+    rom: Rom<'a>,
+    rom_bank: usize,
+    ram_enable1: bool,
+    ram_enable2: bool,
+    eeprom: Eeprom,
+    accel_x: u16,
+    accel_y: u16,
+    accel_latched: bool,
 }
 
-impl MbcType {
-    fn new(hw: HardwareHandle, code: u8, rom: Vec<u8>) -> Self {
-        match code {
-            0x00 => MbcType::None(MbcNone::new(rom)),
-            0x01 | 0x02 | 0x03 => MbcType::Mbc1(Mbc1::new(hw, rom)),
-            0x05 | 0x06 => MbcType::Mbc2(Mbc2::new(hw, rom)),
-            0x08 | 0x09 => unimplemented!("ROM+RAM: {:02x}", code),
-            0x0b | 0x0c | 0x0d => unimplemented!("MMM01: {:02x}", code),
-            0x0f | 0x10 | 0x11 | 0x12 | 0x13 => MbcType::Mbc3(Mbc3::new(hw, rom)),
-            0x15 | 0x16 | 0x17 => unimplemented!("Mbc4: {:02x}", code),
-            0x19 | 0x1a | 0x1b | 0x1c | 0x1d | 0x1e => MbcType::Mbc5(Mbc5::new(hw, rom)),
-            0xfc => unimplemented!("POCKET CAMERA"),
-            0xfd => unimplemented!("BANDAI TAMAS"),
-            0xfe => unimplemented!("HuC3"),
-            0xff => MbcType::HuC1(HuC1::new(rom)),
-            _ => unreachable!("Invalid cartridge type: {:02x}", code),
+impl<'a> Mbc7<'a> {
+    fn new(hw: HardwareHandle<'a>, rom: Rom<'a>) -> Self {
+        let eeprom = Eeprom::new(load_ram(&hw, 0x100));
+
+        Self {
+            hw,
+            rom,
+            rom_bank: 1,
+            ram_enable1: false,
+            ram_enable2: false,
+            eeprom,
+            accel_x: 0x8000,
+            accel_y: 0x8000,
+            accel_latched: false,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.rom_bank = 1;
+        self.ram_enable1 = false;
+        self.ram_enable2 = false;
+        self.accel_x = 0x8000;
+        self.accel_y = 0x8000;
+        self.accel_latched = false;
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enable1 && self.ram_enable2
+    }
+
+    fn latch_accelerometer(&mut self) {
+        // The sensor reads around 0x81d0 when the cartridge is held flat.
+        let (x, y) = self.hw.get().lock().accelerometer();
+        self.accel_x = (0x81d0 + x as i32) as u16;
+        self.accel_y = (0x81d0 + y as i32) as u16;
+        trace!(
+            "Latch accelerometer: {:04x} {:04x}",
+            self.accel_x,
+            self.accel_y
+        );
+    }
+
+    fn on_read(&mut self, _mmu: &Mmu, addr: u16) -> MemRead {
+        if addr <= 0x3fff {
+            MemRead::Replace(rom_read(&self.rom, 0, addr as usize))
+        } else if (0x4000..=0x7fff).contains(&addr) {
+            MemRead::Replace(rom_read(&self.rom, self.rom_bank, addr as usize - 0x4000))
+        } else if (0xa000..=0xafff).contains(&addr) {
+            if !self.ram_enabled() {
+                return MemRead::Replace(0xff);
+            }
+            let v = match (addr >> 4) & 0xf {
+                0x2 => self.accel_x as u8,
+                0x3 => (self.accel_x >> 8) as u8,
+                0x4 => self.accel_y as u8,
+                0x5 => (self.accel_y >> 8) as u8,
+                0x6 => 0x00,
+                0x8 => self.eeprom.read(),
+                _ => 0xff,
+            };
+            MemRead::Replace(v)
+        } else if (0xb000..=0xbfff).contains(&addr) {
+            MemRead::Replace(0xff)
+        } else {
+            MemRead::PassThrough
+        }
+    }
+
+    fn on_write(&mut self, _mmu: &Mmu, addr: u16, value: u8) -> MemWrite {
+        if addr <= 0x1fff {
+            self.ram_enable1 = value == 0x0a;
+            MemWrite::Block
+        } else if (0x2000..=0x3fff).contains(&addr) {
+            self.rom_bank = value as usize & 0x7f;
+            debug!("Switch ROM bank to {:02x}", self.rom_bank);
+            MemWrite::Block
+        } else if (0x4000..=0x5fff).contains(&addr) {
+            self.ram_enable2 = value == 0x40;
+            MemWrite::Block
+        } else if (0x6000..=0x7fff).contains(&addr) {
+            MemWrite::Block
+        } else if (0xa000..=0xafff).contains(&addr) {
+            if !self.ram_enabled() {
+                return MemWrite::Block;
+            }
+            match (addr >> 4) & 0xf {
+                0x0 if value == 0x55 => {
+                    self.accel_x = 0x8000;
+                    self.accel_y = 0x8000;
+                    self.accel_latched = false;
+                }
+                0x1 if value == 0xaa && !self.accel_latched => {
+                    self.latch_accelerometer();
+                    self.accel_latched = true;
+                }
+                // The EEPROM is saved when a write or an erase completes.
+                0x8 if self.eeprom.write(value) => {
+                    self.hw.get().lock().save_ram(&self.eeprom.data);
+                }
+                _ => {}
+            }
+            MemWrite::Block
+        } else if (0xb000..=0xbfff).contains(&addr) {
+            MemWrite::Block
+        } else {
+            warn!("write to rom {:04x} {:02x}", addr, value);
+            MemWrite::PassThrough
         }
     }
+}
+
+struct HuC1<'a> {
+    hw: HardwareHandle<'a>,
+    rom: Rom<'a>,
+    ram: Vec<u8>,
+    rom_bank: usize,
+    ram_bank: usize,
+    ir_mode: bool,
+}
+
+impl<'a> HuC1<'a> {
+    fn new(hw: HardwareHandle<'a>, rom: Rom<'a>, ram_size: usize) -> Self {
+        let ram = load_ram(&hw, ram_size);
+
+        Self {
+            hw,
+            rom,
+            ram,
+            rom_bank: 1,
+            ram_bank: 0,
+            ir_mode: false,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.rom_bank = 1;
+        self.ram_bank = 0;
+        self.ir_mode = false;
+    }
+
+    fn on_read(&mut self, _mmu: &Mmu, addr: u16) -> MemRead {
+        if addr <= 0x3fff {
+            MemRead::Replace(rom_read(&self.rom, 0, addr as usize))
+        } else if (0x4000..=0x7fff).contains(&addr) {
+            MemRead::Replace(rom_read(&self.rom, self.rom_bank, addr as usize - 0x4000))
+        } else if (0xa000..=0xbfff).contains(&addr) {
+            if self.ir_mode {
+                // 0xc1 if light is detected, 0xc0 otherwise.
+                let light = self.hw.get().lock().ir_recv();
+                MemRead::Replace(if light { 0xc1 } else { 0xc0 })
+            } else {
+                MemRead::Replace(ram_read(&self.ram, self.ram_bank, addr as usize - 0xa000))
+            }
+        } else {
+            MemRead::PassThrough
+        }
+    }
+
+    fn on_write(&mut self, _mmu: &Mmu, addr: u16, value: u8) -> MemWrite {
+        if addr <= 0x1fff {
+            // HuC-1 has no RAM enable; the register switches A000-BFFF between RAM and IR.
+            self.ir_mode = value & 0xf == 0x0e;
+            if !self.ir_mode {
+                self.hw.get().lock().save_ram(&self.ram);
+            }
+            debug!("HuC-1 {} mode", if self.ir_mode { "IR" } else { "RAM" });
+            MemWrite::Block
+        } else if (0x2000..=0x3fff).contains(&addr) {
+            self.rom_bank = (value as usize & 0x3f).max(1);
+            debug!("Switch ROM bank to {:02x}", self.rom_bank);
+            MemWrite::Block
+        } else if (0x4000..=0x5fff).contains(&addr) {
+            self.ram_bank = value as usize & 0x3;
+            MemWrite::Block
+        } else if (0x6000..=0x7fff).contains(&addr) {
+            MemWrite::Block
+        } else if (0xa000..=0xbfff).contains(&addr) {
+            if self.ir_mode {
+                self.hw.get().lock().ir_send(value & 0x01 != 0);
+            } else {
+                ram_write(&mut self.ram, self.ram_bank, addr as usize - 0xa000, value);
+            }
+            MemWrite::Block
+        } else {
+            warn!("write to rom {:04x} {:02x}", addr, value);
+            MemWrite::PassThrough
+        }
+    }
+}
+
+impl<'a> State for MbcNone<'a> {
+    fn save(&self, w: &mut Writer) {
+        self.ram.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.ram.load(r)?;
+        Ok(())
+    }
+}
+
+impl<'a> State for Mbc1<'a> {
+    fn save(&self, w: &mut Writer) {
+        self.ram.save(w);
+        self.bank1.save(w);
+        self.bank2.save(w);
+        self.ram_enable.save(w);
+        self.ram_select.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.ram.load(r)?;
+        self.bank1.load(r)?;
+        self.bank2.load(r)?;
+        self.ram_enable.load(r)?;
+        self.ram_select.load(r)?;
+        Ok(())
+    }
+}
+
+impl<'a> State for Mbc2<'a> {
+    fn save(&self, w: &mut Writer) {
+        self.ram.save(w);
+        self.rom_bank.save(w);
+        self.ram_enable.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.ram.load(r)?;
+        self.rom_bank.load(r)?;
+        self.ram_enable.load(r)?;
+        Ok(())
+    }
+}
+
+impl<'a> State for Mbc3<'a> {
+    fn save(&self, w: &mut Writer) {
+        self.ram.save(w);
+        self.rom_bank.save(w);
+        self.enable.save(w);
+        self.select.save(w);
+        self.rtc_secs.save(w);
+        self.rtc_mins.save(w);
+        self.rtc_hours.save(w);
+        self.rtc_day_low.save(w);
+        self.rtc_day_high.save(w);
+        self.epoch.save(w);
+        self.prelatch.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.ram.load(r)?;
+        self.rom_bank.load(r)?;
+        self.enable.load(r)?;
+        self.select.load(r)?;
+        self.rtc_secs.load(r)?;
+        self.rtc_mins.load(r)?;
+        self.rtc_hours.load(r)?;
+        self.rtc_day_low.load(r)?;
+        self.rtc_day_high.load(r)?;
+        self.epoch.load(r)?;
+        self.prelatch.load(r)?;
+        Ok(())
+    }
+}
+
+impl<'a> State for Mbc5<'a> {
+    fn save(&self, w: &mut Writer) {
+        self.ram.save(w);
+        self.rom_bank.save(w);
+        self.ram_bank.save(w);
+        self.ram_enable.save(w);
+        self.rumble.unwrap_or(false).save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        let mut rumble = false;
+
+        self.ram.load(r)?;
+        self.rom_bank.load(r)?;
+        self.ram_bank.load(r)?;
+        self.ram_enable.load(r)?;
+        rumble.load(r)?;
+        self.set_rumble(rumble);
+        Ok(())
+    }
+}
+
+impl<'a> State for Mbc7<'a> {
+    fn save(&self, w: &mut Writer) {
+        self.rom_bank.save(w);
+        self.ram_enable1.save(w);
+        self.ram_enable2.save(w);
+        self.eeprom.save(w);
+        self.accel_x.save(w);
+        self.accel_y.save(w);
+        self.accel_latched.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.rom_bank.load(r)?;
+        self.ram_enable1.load(r)?;
+        self.ram_enable2.load(r)?;
+        self.eeprom.load(r)?;
+        self.accel_x.load(r)?;
+        self.accel_y.load(r)?;
+        self.accel_latched.load(r)?;
+        Ok(())
+    }
+}
+
+impl<'a> State for HuC1<'a> {
+    fn save(&self, w: &mut Writer) {
+        self.ram.save(w);
+        self.rom_bank.save(w);
+        self.ram_bank.save(w);
+        self.ir_mode.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.ram.load(r)?;
+        self.rom_bank.load(r)?;
+        self.ram_bank.load(r)?;
+        self.ir_mode.load(r)?;
+        Ok(())
+    }
+}
+
+impl State for EepromState {
+    fn save(&self, w: &mut Writer) {
+        match *self {
+            EepromState::Idle => 0u8.save(w),
+            EepromState::Command => 1u8.save(w),
+            EepromState::Read(data, remain) => {
+                2u8.save(w);
+                data.save(w);
+                remain.save(w);
+            }
+            EepromState::Write(addr, all) => {
+                3u8.save(w);
+                addr.save(w);
+                all.save(w);
+            }
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        *self = match r.tag("EEPROM state", 4)? {
+            0 => EepromState::Idle,
+            1 => EepromState::Command,
+            2 => {
+                let mut data = 0u16;
+                data.load(r)?;
+                EepromState::Read(data, r.bounded("EEPROM read bits", 16)?)
+            }
+            _ => {
+                let (mut addr, mut all) = (0u8, false);
+                addr.load(r)?;
+                all.load(r)?;
+                EepromState::Write(addr, all)
+            }
+        };
+        Ok(())
+    }
+}
+
+impl State for Eeprom {
+    fn save(&self, w: &mut Writer) {
+        self.data.save(w);
+        self.state.save(w);
+        self.shift.save(w);
+        self.bits.save(w);
+        self.write_enable.save(w);
+        self.cs.save(w);
+        self.clk.save(w);
+        self.di.save(w);
+        self.dout.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.data.load(r)?;
+        self.state.load(r)?;
+        self.shift.load(r)?;
+        self.bits = r.bounded("EEPROM bits", 16)?;
+        self.write_enable.load(r)?;
+        self.cs.load(r)?;
+        self.clk.load(r)?;
+        self.di.load(r)?;
+        self.dout.load(r)?;
+        Ok(())
+    }
+}
+
+enum MbcType<'a> {
+    None(MbcNone<'a>),
+    Mbc1(Mbc1<'a>),
+    Mbc2(Mbc2<'a>),
+    Mbc3(Mbc3<'a>),
+    Mbc5(Mbc5<'a>),
+    Mbc7(Mbc7<'a>),
+    HuC1(HuC1<'a>),
+    Custom(MbcCustom<'a>),
+}
+
+impl<'a> MbcType<'a> {
+    fn new(
+        hw: HardwareHandle<'a>,
+        header: &Header,
+        rom: Rom<'a>,
+        cfg: &Config,
+    ) -> Result<Self, Error> {
+        let code = header.cart_type;
+        let ram_size = header.ram_size;
+
+        let mbc = match code {
+            0x00 | 0x08 | 0x09 => MbcType::None(MbcNone::new(hw, rom, ram_size)),
+            0x01..=0x03 => {
+                let multicart = cfg
+                    .mbc1_multicart
+                    .unwrap_or_else(|| is_mbc1_multicart(&rom));
+                MbcType::Mbc1(Mbc1::new(hw, rom, ram_size, multicart))
+            }
+            0x05 | 0x06 => MbcType::Mbc2(Mbc2::new(hw, rom)),
+            0x0f..=0x13 => MbcType::Mbc3(Mbc3::new(hw, rom, ram_size)),
+            0x19..=0x1b => MbcType::Mbc5(Mbc5::new(hw, rom, ram_size, false)),
+            0x1c..=0x1e => MbcType::Mbc5(Mbc5::new(hw, rom, ram_size, true)),
+            0x22 => MbcType::Mbc7(Mbc7::new(hw, rom)),
+            0xff => MbcType::HuC1(HuC1::new(hw, rom, ram_size)),
+            _ => return Err(Error::UnsupportedCartridge(code)),
+        };
+
+        Ok(mbc)
+    }
 
     fn on_read(&mut self, mmu: &Mmu, addr: u16) -> MemRead {
         match self {
@@ -579,7 +1414,9 @@ impl MbcType {
             MbcType::Mbc2(c) => c.on_read(mmu, addr),
             MbcType::Mbc3(c) => c.on_read(mmu, addr),
             MbcType::Mbc5(c) => c.on_read(mmu, addr),
+            MbcType::Mbc7(c) => c.on_read(mmu, addr),
             MbcType::HuC1(c) => c.on_read(mmu, addr),
+            MbcType::Custom(c) => c.on_read(mmu, addr),
         }
     }
 
@@ -590,12 +1427,117 @@ impl MbcType {
             MbcType::Mbc2(c) => c.on_write(mmu, addr, value),
             MbcType::Mbc3(c) => c.on_write(mmu, addr, value),
             MbcType::Mbc5(c) => c.on_write(mmu, addr, value),
+            MbcType::Mbc7(c) => c.on_write(mmu, addr, value),
             MbcType::HuC1(c) => c.on_write(mmu, addr, value),
+            MbcType::Custom(c) => c.on_write(mmu, addr, value),
+        }
+    }
+
+    /// Returns the ROM bank mapped at 4000-7fff, which is unknown (0) for the custom mappers.
+    fn rom_bank(&self) -> usize {
+        match self {
+            MbcType::None(_) => 1,
+            MbcType::Mbc1(c) => c.rom_bank_high(),
+            MbcType::Mbc2(c) => c.rom_bank,
+            MbcType::Mbc3(c) => c.rom_bank.max(1),
+            MbcType::Mbc5(c) => c.rom_bank,
+            MbcType::Mbc7(c) => c.rom_bank,
+            MbcType::HuC1(c) => c.rom_bank,
+            MbcType::Custom(_) => 0,
+        }
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            MbcType::None(_) => 0,
+            MbcType::Mbc1(_) => 1,
+            MbcType::Mbc2(_) => 2,
+            MbcType::Mbc3(_) => 3,
+            MbcType::Mbc5(_) => 5,
+            MbcType::Mbc7(_) => 7,
+            MbcType::HuC1(_) => 0xc1,
+            MbcType::Custom(_) => 0xff,
+        }
+    }
+
+    /// Pass the battery-backed RAM to [`Persistence::save_ram`][crate::Persistence::save_ram].
+    fn save_ram(&mut self) {
+        let (hw, ram) = match self {
+            MbcType::None(c) => (&c.hw, &c.ram[..]),
+            MbcType::Mbc1(c) => (&c.hw, &c.ram[..]),
+            MbcType::Mbc2(c) => (&c.hw, &c.ram[..]),
+            MbcType::Mbc3(c) => return c.save(),
+            MbcType::Mbc5(c) => (&c.hw, &c.ram[..]),
+            MbcType::Mbc7(c) => (&c.hw, &c.eeprom.data[..]),
+            MbcType::HuC1(c) => (&c.hw, &c.ram[..]),
+            // The custom mappers keep their own RAM
+            MbcType::Custom(_) => return,
+        };
+
+        if !ram.is_empty() {
+            hw.get().lock().save_ram(ram);
+        }
+    }
+
+    fn reset(&mut self) {
+        match self {
+            MbcType::None(_) => {}
+            MbcType::Mbc1(c) => c.reset(),
+            MbcType::Mbc2(c) => c.reset(),
+            MbcType::Mbc3(c) => c.reset(),
+            MbcType::Mbc5(c) => c.reset(),
+            MbcType::Mbc7(c) => c.reset(),
+            MbcType::HuC1(c) => c.reset(),
+            MbcType::Custom(c) => c.mapper.reset(),
         }
     }
 }
 
-impl alloc::fmt::Display for MbcType {
+/// The state of the memory bank controller, tagged with its type.
+impl<'a> State for MbcType<'a> {
+    fn save(&self, w: &mut Writer) {
+        self.tag().save(w);
+
+        match self {
+            MbcType::None(c) => c.save(w),
+            MbcType::Mbc1(c) => c.save(w),
+            MbcType::Mbc2(c) => c.save(w),
+            MbcType::Mbc3(c) => c.save(w),
+            MbcType::Mbc5(c) => c.save(w),
+            MbcType::Mbc7(c) => c.save(w),
+            MbcType::HuC1(c) => c.save(w),
+            MbcType::Custom(c) => c.mapper.save_state().save(w),
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        let mut tag = 0u8;
+        tag.load(r)?;
+        if tag != self.tag() {
+            return Err(Error::InvalidState(alloc::format!(
+                "saved with another cartridge type: {}",
+                tag
+            )));
+        }
+
+        match self {
+            MbcType::None(c) => c.load(r),
+            MbcType::Mbc1(c) => c.load(r),
+            MbcType::Mbc2(c) => c.load(r),
+            MbcType::Mbc3(c) => c.load(r),
+            MbcType::Mbc5(c) => c.load(r),
+            MbcType::Mbc7(c) => c.load(r),
+            MbcType::HuC1(c) => c.load(r),
+            MbcType::Custom(c) => {
+                let mut len = 0usize;
+                len.load(r)?;
+                c.mapper.load_state(r.take(len)?)
+            }
+        }
+    }
+}
+
+impl<'a> alloc::fmt::Display for MbcType<'a> {
     fn fmt(&self, f: &mut alloc::fmt::Formatter) -> alloc::fmt::Result {
         let name = match self {
             MbcType::None(_) => "None",
@@ -603,119 +1545,90 @@ impl alloc::fmt::Display for MbcType {
             MbcType::Mbc2(_) => "Mbc2",
             MbcType::Mbc3(_) => "Mbc3",
             MbcType::Mbc5(_) => "Mbc5",
+            MbcType::Mbc7(_) => "Mbc7",
             MbcType::HuC1(_) => "HuC1",
+            MbcType::Custom(_) => "Custom",
         };
 
         write!(f, "{}", name)
     }
 }
 
-fn parse_str(b: &[u8]) -> String {
-    let b: Vec<u8> = b
-        .iter()
-        .take_while(|b| *b & 0x80 == 0)
-        .map(|b| if *b == 0x00 { b' ' } else { *b })
-        .collect();
-    String::from_utf8_lossy(&b).to_string()
+struct Cartridge<'a> {
+    header: Header,
+    mbc: MbcType<'a>,
+    /// The external RAM may have been written since it was saved last time.
+    dirty: bool,
 }
 
-struct Cartridge {
-    title: String,
-    cgb: bool,
-    cgb_only: bool,
-    license_new: String,
-    license_old: u8,
-    sgb: bool,
-    mbc: MbcType,
-    rom_size: u8,
-    ram_size: u8,
-    dstcode: u8,
-    rom_version: u8,
+impl<'a> Drop for Cartridge<'a> {
+    /// Save the RAM written since the last save, whichever the memory bank controller is.
+    fn drop(&mut self) {
+        if self.dirty {
+            self.mbc.save_ram();
+        }
+    }
 }
 
-fn verify(rom: &[u8], checksum: u16) {
-    let mut sum = 0u16;
+impl<'a> Cartridge<'a> {
+    fn new(hw: HardwareHandle<'a>, rom: Rom<'a>, cfg: &Config) -> Result<Self, Error> {
+        let header = Self::header(&rom, cfg)?;
+        let mbc = MbcType::new(hw, &header, rom, cfg)?;
 
-    for (i, b) in rom.iter().enumerate() {
-        if i == 0x14e || i == 0x14f {
-            continue;
-        }
-        sum = sum.wrapping_add(*b as u16);
+        Ok(Self {
+            header,
+            mbc,
+            dirty: false,
+        })
     }
 
-    if sum == checksum {
-        info!("ROM checksum verified: {:04x}", checksum);
-    } else {
-        warn!(
-            "ROM checksum mismatch: expect: {:04x}, actual: {:04x}",
-            checksum, sum
-        );
-    }
-}
+    fn with_mbc(rom: &[u8], mbc: MbcType<'a>, cfg: &Config) -> Result<Self, Error> {
+        let header = Self::header(rom, cfg)?;
 
-impl Cartridge {
-    fn new(hw: HardwareHandle, rom: Vec<u8>) -> Self {
-        let checksum = (rom[0x14e] as u16) << 8 | (rom[0x14f] as u16);
+        Ok(Self {
+            header,
+            mbc,
+            dirty: false,
+        })
+    }
 
-        verify(&rom, checksum);
+    fn header(rom: &[u8], cfg: &Config) -> Result<Header, Error> {
+        let header = Header::parse(rom)?;
+        header.validate(cfg.header_validation)?;
 
-        Self {
-            title: parse_str(&rom[0x134..0x144]),
-            cgb: rom[0x143] & 0x80 != 0,
-            cgb_only: rom[0x143] == 0xc0,
-            license_new: parse_str(&rom[0x144..0x146]),
-            license_old: rom[0x14b],
-            sgb: rom[0x146] == 0x03,
-            mbc: MbcType::new(hw, rom[0x147], rom.clone()),
-            rom_size: rom[0x148],
-            ram_size: rom[0x149],
-            dstcode: rom[0x14a],
-            rom_version: rom[0x14c],
+        if header.global_checksum_valid {
+            info!("ROM checksum verified: {:04x}", header.global_checksum);
+        } else {
+            warn!("ROM checksum mismatch: {:04x}", header.global_checksum);
         }
+
+        Ok(header)
     }
 
     fn show_info(&self) {
-        info!("Title: {}", self.title);
+        let h = &self.header;
+
+        info!("Title: {}", h.title);
         info!(
             "License: {} ({:02x}), Version: {}",
-            self.license_new, self.license_old, self.rom_version,
+            h.new_licensee, h.old_licensee, h.version,
+        );
+        info!(
+            "Destination: {}",
+            if h.japanese {
+                "Japanese"
+            } else {
+                "Non-Japanese"
+            }
         );
-        let dstcode = match self.dstcode {
-            0x00 => "Japanese",
-            0x01 => "Non-Japanese",
-            _ => "Unknown",
-        };
-        info!("Destination: {}", dstcode);
 
         info!("Mbc: {}", self.mbc);
         info!(
             "Color: {} (Compat: {}), Super: {}",
-            self.cgb, !self.cgb_only, self.sgb,
+            h.cgb, !h.cgb_only, h.sgb
         );
-
-        let rom_size = match self.rom_size {
-            0x00 => "32KByte (no ROM banking)",
-            0x01 => "64KByte (4 banks)",
-            0x02 => "128KByte (8 banks)",
-            0x03 => "256KByte (16 banks)",
-            0x04 => "512KByte (32 banks)",
-            0x05 => "1MByte (64 banks)  - only 63 banks used by Mbc1",
-            0x06 => "2MByte (128 banks) - only 125 banks used by Mbc1",
-            0x07 => "4MByte (256 banks)",
-            0x52 => "1.1MByte (72 banks)",
-            0x53 => "1.2MByte (80 banks)",
-            0x54 => "1.5MByte (96 banks)",
-            _ => "Unknown",
-        };
-        let ram_size = match self.ram_size {
-            0x00 => "None",
-            0x01 => "2 KBytes",
-            0x02 => "8 Kbytes",
-            0x03 => "32 KBytes (4 banks of 8KBytes each)",
-            _ => "Unknown",
-        };
-        info!("ROM size: {}", rom_size);
-        info!("RAM size: {}", ram_size);
+        info!("ROM size: {} KBytes", h.rom_size / 1024);
+        info!("RAM size: {} KBytes", h.ram_size / 1024);
     }
 
     fn on_read(&mut self, mmu: &Mmu, addr: u16) -> MemRead {
@@ -723,24 +1636,100 @@ impl Cartridge {
     }
 
     fn on_write(&mut self, mmu: &Mmu, addr: u16, value: u8) -> MemWrite {
-        self.mbc.on_write(mmu, addr, value)
+        let res = self.mbc.on_write(mmu, addr, value);
+
+        // Any write handled by the cartridge in the RAM area, including the RTC registers
+        if (0xa000..=0xbfff).contains(&addr) && matches!(res, MemWrite::Block) {
+            self.dirty = true;
+        }
+
+        res
     }
 }
 
-pub struct Mbc {
-    cartridge: Cartridge,
+pub struct Mbc<'a> {
+    cartridge: Cartridge<'a>,
     use_boot_rom: bool,
+    events: Events,
 }
 
-impl Mbc {
-    pub fn new(hw: HardwareHandle, rom: Vec<u8>) -> Self {
-        let cartridge = Cartridge::new(hw, rom);
+impl<'a> Mbc<'a> {
+    pub fn new(hw: HardwareHandle<'a>, rom: Rom<'a>, cfg: &Config) -> Result<Self, Error> {
+        Ok(Self::with_cartridge(Cartridge::new(hw, rom, cfg)?))
+    }
+
+    pub fn with_mapper(
+        rom: &[u8],
+        mapper: Box<dyn Mapper + 'a>,
+        cfg: &Config,
+    ) -> Result<Self, Error> {
+        let mbc = MbcType::Custom(MbcCustom::new(mapper));
+        Ok(Self::with_cartridge(Cartridge::with_mbc(rom, mbc, cfg)?))
+    }
 
+    fn with_cartridge(cartridge: Cartridge<'a>) -> Self {
         cartridge.show_info();
 
         Self {
             cartridge,
             use_boot_rom: true,
+            events: Events::default(),
+        }
+    }
+
+    /// Map the boot ROM again and reset the memory bank controller.
+    pub fn reset(&mut self) {
+        self.use_boot_rom = true;
+        self.cartridge.mbc.reset();
+    }
+
+    /// Replace the cartridge with the one of `other`, keeping the boot ROM mapped or unmapped.
+    ///
+    /// The old cartridge saves the RAM written since [`Mbc::flush_ram`][] as it's dropped,
+    /// so flush it before creating `other` to save it before the new RAM is loaded.
+    pub fn swap(&mut self, other: Mbc<'a>) {
+        self.cartridge = other.cartridge;
+    }
+
+    /// Returns the ROM bank of the address, which is 0 outside the switchable bank (4000-7fff).
+    #[cfg(feature = "debugger")]
+    pub fn rom_bank(&self, addr: u16) -> usize {
+        if (0x4000..=0x7fff).contains(&addr) {
+            self.cartridge.mbc.rom_bank()
+        } else {
+            0
+        }
+    }
+
+    /// Report the ROM bank switches to the hardware events.
+    pub fn set_events(&mut self, events: Events) {
+        self.events = events;
+    }
+
+    /// Returns the header of the cartridge.
+    pub fn header(&self) -> &Header {
+        &self.cartridge.header
+    }
+
+    /// Check if the external RAM may have changed since [`Mbc::flush_ram`][] was called.
+    pub fn ram_dirty(&self) -> bool {
+        self.cartridge.dirty
+    }
+
+    /// Save the external RAM to the hardware.
+    pub fn flush_ram(&mut self) {
+        self.cartridge.mbc.save_ram();
+        self.cartridge.dirty = false;
+    }
+
+    /// Run the real-time clock of the cartridge on the emulated clock cycles
+    /// instead of [`Clock::clock`][crate::Clock::clock].
+    ///
+    /// Call again after the cycles are rewritten, to restart the clock from the new value.
+    pub(crate) fn set_clock(&mut self, cycles: Arc<Mutex<u64>>) {
+        if let MbcType::Mbc3(c) = &mut self.cartridge.mbc {
+            c.cycles = Some(cycles);
+            c.update_epoch();
         }
     }
 
@@ -757,7 +1746,21 @@ impl Mbc {
     }
 }
 
-impl IoHandler for Mbc {
+impl<'a> State for Mbc<'a> {
+    fn save(&self, w: &mut Writer) {
+        self.use_boot_rom.save(w);
+        self.cartridge.mbc.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.use_boot_rom.load(r)?;
+        // The RAM is replaced with the saved one
+        self.cartridge.dirty = true;
+        self.cartridge.mbc.load(r)
+    }
+}
+
+impl<'a> IoHandler for Mbc<'a> {
     fn on_read(&mut self, mmu: &Mmu, addr: u16) -> MemRead {
         if self.use_boot_rom && self.in_boot_rom(addr) {
             MemRead::Replace(BOOT_ROM[addr as usize])
@@ -768,13 +1771,349 @@ impl IoHandler for Mbc {
 
     fn on_write(&mut self, mmu: &Mmu, addr: u16, value: u8) -> MemWrite {
         if self.use_boot_rom && addr < 0x100 {
-            unreachable!("Writing to boot ROM")
+            warn!("Writing to boot ROM: {:04x} {:02x}", addr, value);
+            MemWrite::Block
         } else if addr == 0xff50 {
             info!("Disable boot ROM");
             self.use_boot_rom = false;
             MemWrite::Block
+        } else if addr <= 0x7fff {
+            let bank = self.cartridge.mbc.rom_bank();
+            let res = self.cartridge.on_write(mmu, addr, value);
+            let new = self.cartridge.mbc.rom_bank();
+            if new != bank {
+                self.events.push(HwEvent::BankSwitch(new));
+            }
+            res
         } else {
             self.cartridge.on_write(mmu, addr, value)
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    fn value(r: MemRead) -> u8 {
+        match r {
+            MemRead::Replace(v) => v,
+            MemRead::PassThrough => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn rom_bank_wraps_to_rom_size() {
+        // 256 KB ROM has 16 banks, so bank 0x12 maps to bank 0x02.
+        let mut rom = vec![0u8; 0x40000];
+        rom[0x2 * 0x4000] = 0x42;
+
+        assert_eq!(rom_read(&rom, 0x12, 0), 0x42);
+        assert_eq!(rom_read(&rom, 0x02, 0), 0x42);
+    }
+
+    #[test]
+    fn ram_bank_wraps_to_ram_size() {
+        let mut ram = vec![0u8; 0x2000];
+
+        ram_write(&mut ram, 3, 0x10, 0x55);
+        assert_eq!(ram_read(&ram, 0, 0x10), 0x55);
+
+        // 2 KB RAM is mirrored throughout the bank.
+        let mut ram = vec![0u8; 0x800];
+
+        ram_write(&mut ram, 0, 0x810, 0xaa);
+        assert_eq!(ram_read(&ram, 0, 0x10), 0xaa);
+
+        // No RAM reads as open bus.
+        assert_eq!(ram_read(&[], 0, 0x10), 0xff);
+    }
+
+    #[test]
+    fn bank_switch_event() {
+        // MBC1 with 4 banks
+        let mut rom = vec![0u8; 0x10000];
+        rom[0x147] = 0x01;
+        rom[0x148] = 0x01;
+
+        let hw = HardwareHandle::new(crate::hardware::NullHardware);
+        let mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut mbc = Mbc::new(hw, rom.into(), &Config::new()).unwrap();
+        let events = Events::new(true);
+        mbc.set_events(events.clone());
+
+        mbc.on_write(&mmu, 0x2000, 0x02);
+        mbc.on_write(&mmu, 0x2000, 0x02);
+        mbc.on_write(&mmu, 0x2000, 0x03);
+
+        let mut switched = vec![];
+        events.drain(|e| switched.push(*e));
+        assert_eq!(switched, [HwEvent::BankSwitch(2), HwEvent::BankSwitch(3)]);
+    }
+
+    #[test]
+    fn ram_dirty() {
+        use crate::hardware::{Clock, Input, Link, Persistence, Screen, Speaker};
+
+        struct Saves(Arc<Mutex<Vec<u8>>>);
+        impl Screen for Saves {}
+        impl Speaker for Saves {}
+        impl Input for Saves {}
+        impl Link for Saves {}
+        impl Clock for Saves {
+            fn clock(&mut self) -> u64 {
+                0
+            }
+        }
+        impl Persistence for Saves {
+            fn save_ram(&mut self, ram: &[u8]) {
+                *self.0.lock() = ram.to_vec();
+            }
+        }
+
+        // MBC1 with the battery and 8 KB RAM
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+
+        let saved = Arc::new(Mutex::new(vec![]));
+        let hw = HardwareHandle::new(Saves(saved.clone()));
+        let mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut mbc = Mbc::new(hw, rom.into(), &Config::new()).unwrap();
+        assert!(!mbc.ram_dirty());
+
+        // Enabling RAM doesn't touch it
+        mbc.on_write(&mmu, 0xff50, 0x01);
+        mbc.on_write(&mmu, 0x0000, 0x0a);
+        assert!(!mbc.ram_dirty());
+
+        mbc.on_write(&mmu, 0xa001, 0x42);
+        assert!(mbc.ram_dirty());
+
+        mbc.flush_ram();
+        assert!(!mbc.ram_dirty());
+        assert_eq!(saved.lock().len(), 0x2000);
+        assert_eq!(saved.lock()[1], 0x42);
+    }
+
+    #[test]
+    fn rtc_on_emulated_clock() {
+        let hw = HardwareHandle::new(crate::hardware::NullHardware);
+        let mut mbc = Mbc3::new(hw, vec![0u8; 0x8000].into(), 0x2000);
+        let cycles = Arc::new(Mutex::new(1000));
+        mbc.cycles = Some(cycles.clone());
+        mbc.update_epoch();
+
+        // 1 hour, 2 minutes and 3 seconds later
+        *cycles.lock() = 1000 + RTC_CYCLES_PER_SEC * 3723;
+        mbc.latch();
+        assert_eq!((mbc.rtc_hours, mbc.rtc_mins, mbc.rtc_secs), (1, 2, 3));
+
+        // Halted
+        mbc.rtc_day_high = 0x40;
+        *cycles.lock() += RTC_CYCLES_PER_SEC * 10;
+        mbc.latch();
+        assert_eq!(mbc.rtc_secs, 3);
+    }
+
+    #[test]
+    fn mbc2_ram_mirroring() {
+        let hw = HardwareHandle::new(crate::hardware::NullHardware);
+        let mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut mbc = Mbc2::new(hw, vec![0u8; 0x8000].into());
+
+        mbc.on_write(&mmu, 0x0000, 0x0a);
+        mbc.on_write(&mmu, 0xa000, 0x35);
+
+        // Only the lower 4 bits are stored, and the 512 bytes repeat every 0x200.
+        for addr in [0xa000, 0xa200, 0xa400, 0xbe00] {
+            assert_eq!(value(mbc.on_read(&mmu, addr)), 0xf5);
+        }
+
+        mbc.on_write(&mmu, 0xa201, 0x0c);
+        assert_eq!(value(mbc.on_read(&mmu, 0xa001)), 0xfc);
+
+        // Disabled RAM reads as open bus.
+        mbc.on_write(&mmu, 0x0000, 0x00);
+        assert_eq!(value(mbc.on_read(&mmu, 0xa200)), 0xff);
+    }
+
+    #[test]
+    fn mbc1_multicart_routing() {
+        // 1 MB MBC1 with a second boot logo at bank 0x10, each bank starting with its number
+        let mut rom = vec![0u8; 0x100000];
+        for bank in 0..0x40 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom[0x104..0x134].iter_mut().for_each(|b| *b = 0xce);
+        rom[0x40104..0x40134].iter_mut().for_each(|b| *b = 0xce);
+        rom[0x147] = 0x01;
+        rom[0x148] = 0x05;
+
+        let hw = HardwareHandle::new(crate::hardware::NullHardware);
+        let mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut mbc = Mbc::new(hw, rom.into(), &Config::new()).unwrap();
+        mbc.on_write(&mmu, 0xff50, 0x01);
+
+        // The upper register selects the game in steps of 0x10 banks, and bit 4 of the
+        // lower register is not connected.
+        mbc.on_write(&mmu, 0x4000, 0x01);
+        mbc.on_write(&mmu, 0x2000, 0x12);
+        assert_eq!(value(mbc.on_read(&mmu, 0x4000)), 0x12);
+
+        // Bank 0 of the game is mapped at 0000-3fff in the advanced mode.
+        assert_eq!(value(mbc.on_read(&mmu, 0x0000)), 0x00);
+        mbc.on_write(&mmu, 0x6000, 0x01);
+        assert_eq!(value(mbc.on_read(&mmu, 0x0000)), 0x10);
+    }
+
+    #[test]
+    fn mbc7_eeprom() {
+        // Clock the bits out MSB first with the chip selected, returning if the data is modified.
+        fn send(e: &mut Eeprom, bits: u32, n: usize) -> bool {
+            let mut modified = false;
+            for i in (0..n).rev() {
+                let di = if bits >> i & 1 != 0 { 0x02 } else { 0x00 };
+                e.write(0x80 | di);
+                modified |= e.write(0xc0 | di);
+            }
+            modified
+        }
+
+        fn recv(e: &mut Eeprom) -> u16 {
+            (0..16).fold(0, |v, _| {
+                e.write(0x80);
+                e.write(0xc0);
+                v << 1 | (e.read() & 0x01) as u16
+            })
+        }
+
+        // The start bit, the opcode and the address
+        let command = |op: u32, addr: u32| 1 << 10 | op << 8 | addr;
+
+        let mut e = Eeprom::new(vec![]);
+
+        // WRITE is ignored until EWEN
+        assert!(!send(&mut e, command(0x1, 0x05), 11));
+        assert!(!send(&mut e, 0x1234, 16));
+        assert_eq!(e.word(0x05), 0xffff);
+        e.write(0x00);
+
+        assert!(!send(&mut e, command(0x0, 0xc0), 11));
+        e.write(0x00);
+
+        assert!(!send(&mut e, command(0x1, 0x05), 11));
+        assert!(send(&mut e, 0x1234, 16));
+        assert_eq!(e.word(0x05), 0x1234);
+        e.write(0x00);
+
+        // READ outputs a dummy zero bit followed by the word
+        assert!(!send(&mut e, command(0x2, 0x05), 11));
+        assert_eq!(e.read() & 0x01, 0x00);
+        assert_eq!(recv(&mut e), 0x1234);
+        e.write(0x00);
+
+        assert!(send(&mut e, command(0x3, 0x05), 11));
+        assert_eq!(e.word(0x05), 0xffff);
+    }
+
+    #[test]
+    fn huc1_infrared() {
+        use crate::hardware::{Clock, Input, Link, Persistence, Screen, Speaker};
+
+        /// The hardware which reflects the infrared LED back to the receiver.
+        struct Mirror(Arc<Mutex<bool>>);
+        impl Screen for Mirror {}
+        impl Speaker for Mirror {}
+        impl Input for Mirror {}
+        impl Persistence for Mirror {}
+        impl Clock for Mirror {
+            fn clock(&mut self) -> u64 {
+                0
+            }
+        }
+        impl Link for Mirror {
+            fn ir_send(&mut self, on: bool) {
+                *self.0.lock() = on;
+            }
+
+            fn ir_recv(&mut self) -> bool {
+                *self.0.lock()
+            }
+        }
+
+        let led = Arc::new(Mutex::new(false));
+        let hw = HardwareHandle::new(Mirror(led.clone()));
+        let mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut mbc = HuC1::new(hw, vec![0u8; 0x8000].into(), 0x2000);
+
+        mbc.on_write(&mmu, 0xa000, 0x42);
+        assert_eq!(value(mbc.on_read(&mmu, 0xa000)), 0x42);
+
+        // The IR mode replaces the RAM with the LED and the receiver
+        mbc.on_write(&mmu, 0x0000, 0x0e);
+        assert_eq!(value(mbc.on_read(&mmu, 0xa000)), 0xc0);
+        mbc.on_write(&mmu, 0xa000, 0x01);
+        assert!(*led.lock());
+        assert_eq!(value(mbc.on_read(&mmu, 0xa000)), 0xc1);
+        mbc.on_write(&mmu, 0xa000, 0x00);
+        assert_eq!(value(mbc.on_read(&mmu, 0xa000)), 0xc0);
+
+        mbc.on_write(&mmu, 0x0000, 0x00);
+        assert_eq!(value(mbc.on_read(&mmu, 0xa000)), 0x42);
+    }
+
+    #[test]
+    fn custom_mapper() {
+        /// A mapper switching the whole upper half of the ROM, with 16 bytes of RAM.
+        struct Flat {
+            bank: u8,
+            ram: [u8; 0x10],
+        }
+
+        impl Mapper for Flat {
+            fn read_rom(&mut self, addr: u16) -> u8 {
+                if addr >= 0x4000 {
+                    self.bank
+                } else {
+                    0x00
+                }
+            }
+
+            fn read_ram(&mut self, addr: u16) -> u8 {
+                self.ram[addr as usize & 0xf]
+            }
+
+            fn write_ram(&mut self, addr: u16, value: u8) {
+                self.ram[addr as usize & 0xf] = value;
+            }
+
+            fn write_register(&mut self, _addr: u16, value: u8) {
+                self.bank = value;
+            }
+        }
+
+        let mapper = Flat {
+            bank: 1,
+            ram: [0; 0x10],
+        };
+        let mmu = Mmu::new(vec![0u8; 0x10000]);
+        let mut mbc = Mbc::with_mapper(&[0u8; 0x8000], Box::new(mapper), &Config::new()).unwrap();
+        mbc.on_write(&mmu, 0xff50, 0x01);
+
+        assert_eq!(value(mbc.on_read(&mmu, 0x4000)), 0x01);
+        mbc.on_write(&mmu, 0x2000, 0x03);
+        assert_eq!(value(mbc.on_read(&mmu, 0x7fff)), 0x03);
+
+        mbc.on_write(&mmu, 0xa015, 0x77);
+        assert_eq!(value(mbc.on_read(&mmu, 0xa005)), 0x77);
+
+        // The work RAM is not routed to the mapper
+        assert!(matches!(mbc.on_read(&mmu, 0xc000), MemRead::PassThrough));
+        assert!(matches!(
+            mbc.on_write(&mmu, 0xc000, 0x00),
+            MemWrite::PassThrough
+        ));
+    }
+}
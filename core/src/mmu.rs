@@ -1,6 +1,27 @@
-use alloc::rc::Rc;
+use crate::buffers::Buf;
+#[cfg(feature = "cgb")]
+use crate::cgb::Cgb;
+use crate::cheat::Cheats;
+#[cfg(feature = "debugger")]
+use crate::debug::{AccessLog, Breakpoints};
+use crate::device::IoMemHandler;
+use crate::dma::Dma;
+use crate::error::Error;
+use crate::gpu::Gpu;
+use crate::hardware::{MaybeSend, MaybeSync};
+use crate::ic::Ic;
+use crate::joypad::Joypad;
+use crate::mbc::Mbc;
+#[cfg(feature = "serial")]
+use crate::serial::Serial;
+#[cfg(feature = "sound")]
+use crate::sound::Sound;
+use crate::state::{Reader, State, Writer};
+use crate::timer::Timer;
+use alloc::sync::Arc;
 use alloc::{vec, vec::Vec};
 use hashbrown::HashMap;
+use spin::Mutex;
 
 /// The variants to control memory read access from the CPU.
 pub enum MemRead {
@@ -21,7 +42,9 @@ pub enum MemWrite {
 }
 
 /// The handler to intercept memory access from the CPU.
-pub trait MemHandler {
+///
+/// The handler is shared by the system and the MMU, so it's [`MaybeSend`][] and [`MaybeSync`][].
+pub trait MemHandler: MaybeSend + MaybeSync {
     /// The function is called when the CPU attempts to read from the memory.
     fn on_read(&self, mmu: &Mmu, addr: u16) -> MemRead;
 
@@ -29,6 +52,154 @@ pub trait MemHandler {
     fn on_write(&self, mmu: &Mmu, addr: u16, value: u8) -> MemWrite;
 }
 
+impl<T: MemHandler + ?Sized> MemHandler for Arc<T> {
+    fn on_read(&self, mmu: &Mmu, addr: u16) -> MemRead {
+        (**self).on_read(mmu, addr)
+    }
+
+    fn on_write(&self, mmu: &Mmu, addr: u16, value: u8) -> MemWrite {
+        (**self).on_write(mmu, addr, value)
+    }
+}
+
+/// Map the echo RAM (`E000-FDFF`) to the work RAM (`C000-DDFF`) it mirrors,
+/// so that the accesses go through the work RAM handlers, e.g. CGB bank switching.
+fn mirror(addr: u16) -> u16 {
+    if (0xe000..=0xfdff).contains(&addr) {
+        addr - 0x2000
+    } else {
+        addr
+    }
+}
+
+/// The indices of the pages covering the address range.
+fn pages(range: (u16, u16)) -> core::ops::RangeInclusive<usize> {
+    (range.0 >> 8) as usize..=(range.1 >> 8) as usize
+}
+
+/// The devices which run along with the memory cycles of the CPU.
+pub(crate) trait Clock: MaybeSend {
+    /// Advance the devices by the given clock cycles.
+    fn tick(&mut self, time: usize, mmu: &mut Mmu);
+
+    /// Run the cycles deferred by [`Clock::tick`][] before the registers of the devices are accessed.
+    fn sync(&mut self, _write: bool) {}
+}
+
+/// The number of the pages in the page table, one for each value of the upper address byte.
+const PAGES: usize = 0x100;
+
+/// The built-in peripherals, which are called directly instead of through a trait object.
+#[derive(Clone)]
+pub(crate) enum Builtin<'a> {
+    Cheats(IoMemHandler<Cheats>),
+    #[cfg(feature = "debugger")]
+    Breaks(IoMemHandler<Breakpoints>),
+    /// The bus conflicts with the OAM DMA, which runs on every cycle while transferring.
+    DmaBus(IoMemHandler<Dma>),
+    Dma(IoMemHandler<Dma>),
+    #[cfg(feature = "cgb")]
+    Cgb(IoMemHandler<Cgb<'a>>),
+    Mbc(IoMemHandler<Mbc<'a>>),
+    #[cfg(feature = "sound")]
+    Sound(IoMemHandler<Sound>),
+    Gpu(IoMemHandler<Gpu<'a>>),
+    Ic(IoMemHandler<Ic>),
+    Joypad(IoMemHandler<Joypad<'a>>),
+    Timer(IoMemHandler<Timer>),
+    #[cfg(feature = "serial")]
+    Serial(IoMemHandler<Serial<'a>>),
+}
+
+/// The handler called for an address.
+#[derive(Clone)]
+enum Target<'a> {
+    Builtin(Builtin<'a>),
+    Dyn(Arc<dyn MemHandler + 'a>),
+}
+
+impl<'a> Target<'a> {
+    /// The handler belongs to the devices on the bus, rather than watching all the accesses.
+    fn is_device(&self) -> bool {
+        match self {
+            Target::Builtin(Builtin::Cheats(_) | Builtin::DmaBus(_)) => false,
+            #[cfg(feature = "debugger")]
+            Target::Builtin(Builtin::Breaks(_)) => false,
+            Target::Builtin(_) => true,
+            Target::Dyn(_) => false,
+        }
+    }
+
+    /// The handler belongs to the devices run by the clock.
+    fn clocked(&self) -> bool {
+        match self {
+            Target::Builtin(Builtin::Dma(_) | Builtin::Gpu(_) | Builtin::Timer(_)) => true,
+            #[cfg(feature = "serial")]
+            Target::Builtin(Builtin::Serial(_)) => true,
+            _ => false,
+        }
+    }
+
+    fn on_read(&self, mmu: &Mmu, addr: u16) -> MemRead {
+        match self {
+            Target::Builtin(Builtin::Cheats(h)) => h.on_read(mmu, addr),
+            #[cfg(feature = "debugger")]
+            Target::Builtin(Builtin::Breaks(h)) => h.on_read(mmu, addr),
+            Target::Builtin(Builtin::DmaBus(h)) => h.on_read(mmu, addr),
+            Target::Builtin(Builtin::Dma(h)) => h.on_read(mmu, addr),
+            #[cfg(feature = "cgb")]
+            Target::Builtin(Builtin::Cgb(h)) => h.on_read(mmu, addr),
+            Target::Builtin(Builtin::Mbc(h)) => h.on_read(mmu, addr),
+            #[cfg(feature = "sound")]
+            Target::Builtin(Builtin::Sound(h)) => h.on_read(mmu, addr),
+            Target::Builtin(Builtin::Gpu(h)) => h.on_read(mmu, addr),
+            Target::Builtin(Builtin::Ic(h)) => h.on_read(mmu, addr),
+            Target::Builtin(Builtin::Joypad(h)) => h.on_read(mmu, addr),
+            Target::Builtin(Builtin::Timer(h)) => h.on_read(mmu, addr),
+            #[cfg(feature = "serial")]
+            Target::Builtin(Builtin::Serial(h)) => h.on_read(mmu, addr),
+            Target::Dyn(h) => h.on_read(mmu, addr),
+        }
+    }
+
+    fn on_write(&self, mmu: &Mmu, addr: u16, value: u8) -> MemWrite {
+        match self {
+            Target::Builtin(Builtin::Cheats(h)) => h.on_write(mmu, addr, value),
+            #[cfg(feature = "debugger")]
+            Target::Builtin(Builtin::Breaks(h)) => h.on_write(mmu, addr, value),
+            Target::Builtin(Builtin::DmaBus(h)) => h.on_write(mmu, addr, value),
+            Target::Builtin(Builtin::Dma(h)) => h.on_write(mmu, addr, value),
+            #[cfg(feature = "cgb")]
+            Target::Builtin(Builtin::Cgb(h)) => h.on_write(mmu, addr, value),
+            Target::Builtin(Builtin::Mbc(h)) => h.on_write(mmu, addr, value),
+            #[cfg(feature = "sound")]
+            Target::Builtin(Builtin::Sound(h)) => h.on_write(mmu, addr, value),
+            Target::Builtin(Builtin::Gpu(h)) => h.on_write(mmu, addr, value),
+            Target::Builtin(Builtin::Ic(h)) => h.on_write(mmu, addr, value),
+            Target::Builtin(Builtin::Joypad(h)) => h.on_write(mmu, addr, value),
+            Target::Builtin(Builtin::Timer(h)) => h.on_write(mmu, addr, value),
+            #[cfg(feature = "serial")]
+            Target::Builtin(Builtin::Serial(h)) => h.on_write(mmu, addr, value),
+            Target::Dyn(h) => h.on_write(mmu, addr, value),
+        }
+    }
+}
+
+/// A memory handler registered for an address range.
+#[derive(Clone)]
+struct Entry<'a> {
+    handle: Handle,
+    range: (u16, u16),
+    target: Target<'a>,
+    clocked: bool,
+}
+
+impl<'a> Entry<'a> {
+    fn contains(&self, addr: u16) -> bool {
+        addr >= self.range.0 && addr <= self.range.1
+    }
+}
+
 /// The handle of a memory handler.
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Handle(u64);
@@ -38,21 +209,35 @@ pub struct Handle(u64);
 /// This unit holds a memory byte array which represents address space of the memory.
 /// It provides the logic to intercept access from the CPU to the memory byte array,
 /// and to modify the memory access behaviour.
-pub struct Mmu {
-    ram: Vec<u8>,
+pub struct Mmu<'a> {
+    ram: Buf<'a, u8>,
     handles: HashMap<Handle, (u16, u16)>,
-    handlers: HashMap<u16, Vec<(Handle, Rc<dyn MemHandler>)>>,
+    /// The handlers overlapping each 256-byte page, in the order they are added.
+    pages: Vec<Vec<Entry<'a>>>,
     hdgen: u64,
+    #[cfg(feature = "debugger")]
+    log: Option<Arc<Mutex<AccessLog>>>,
+    clock: Option<Arc<Mutex<dyn Clock + 'a>>>,
+    ticked: usize,
 }
 
-impl Mmu {
+impl<'a> Mmu<'a> {
     /// Create a new MMU instance.
-    pub fn new(ram: Vec<u8>) -> Mmu {
-        Mmu {
-            ram, // vec![0u8; 0x10000],
+    pub fn new(ram: Vec<u8>) -> Self {
+        Self::with_ram(ram.into())
+    }
+
+    /// Create a new MMU instance on the memory either allocated or provided by the caller.
+    pub(crate) fn with_ram(ram: Buf<'a, u8>) -> Self {
+        Self {
+            ram,
             handles: HashMap::new(),
-            handlers: HashMap::new(),
+            pages: vec![Vec::new(); PAGES],
             hdgen: 0,
+            #[cfg(feature = "debugger")]
+            log: None,
+            clock: None,
+            ticked: 0,
         }
     }
 
@@ -60,6 +245,15 @@ impl Mmu {
         &self.ram
     }
 
+    pub(crate) fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    /// Move out the memory to reuse it on reset.
+    pub(crate) fn into_ram(self) -> Buf<'a, u8> {
+        self.ram
+    }
+
     fn next_handle(&mut self) -> Handle {
         let handle = self.hdgen;
 
@@ -71,23 +265,45 @@ impl Mmu {
     /// Add a new memory handler.
     pub fn add_handler<T>(&mut self, range: (u16, u16), handler: T) -> Handle
     where
-        T: MemHandler + 'static,
+        T: MemHandler + 'a,
     {
+        self.insert(range, Target::Dyn(Arc::new(handler)), false)
+    }
+
+    /// Add the memory handler of a device, which is called before the built-in peripherals
+    /// but after the handlers watching all the accesses, e.g. the debugger and the cheats.
+    pub(crate) fn add_device<T>(&mut self, range: (u16, u16), handler: T) -> Handle
+    where
+        T: MemHandler + 'a,
+    {
+        self.insert(range, Target::Dyn(Arc::new(handler)), true)
+    }
+
+    /// Add the handler of a built-in peripheral, which skips the dynamic dispatch.
+    pub(crate) fn add_builtin(&mut self, range: (u16, u16), handler: Builtin<'a>) -> Handle {
+        self.insert(range, Target::Builtin(handler), false)
+    }
+
+    fn insert(&mut self, range: (u16, u16), target: Target<'a>, before_devices: bool) -> Handle {
         let handle = self.next_handle();
-        let handler = Rc::new(handler);
+        let entry = Entry {
+            handle: handle.clone(),
+            range,
+            clocked: target.clocked(),
+            target,
+        };
 
         self.handles.insert(handle.clone(), range);
 
-        for i in range.0..=range.1 {
-            if self.handlers.contains_key(&i) {
-                match self.handlers.get_mut(&i) {
-                    Some(v) => v.push((handle.clone(), handler.clone())),
-                    None => {}
-                }
+        for page in &mut self.pages[pages(range)] {
+            let pos = if before_devices {
+                page.iter()
+                    .position(|e| e.target.is_device())
+                    .unwrap_or(page.len())
             } else {
-                self.handlers
-                    .insert(i, vec![(handle.clone(), handler.clone())]);
-            }
+                page.len()
+            };
+            page.insert(pos, entry.clone());
         }
 
         handle
@@ -97,61 +313,106 @@ impl Mmu {
     #[allow(unused)]
     pub fn remove_handler<T>(&mut self, handle: &Handle)
     where
-        T: MemHandler + 'static,
+        T: MemHandler + 'a,
     {
         let range = match self.handles.remove(&handle) {
             Some(range) => range,
             None => return,
         };
 
-        for i in range.0..range.1 {
-            match self.handlers.get_mut(&i) {
-                Some(v) => v.retain(|(hd, _)| hd != handle),
-                None => {}
-            }
+        for page in &mut self.pages[pages(range)] {
+            page.retain(|e| &e.handle != handle);
         }
     }
 
+    #[cfg(feature = "debugger")]
+    pub(crate) fn set_access_log(&mut self, log: Option<Arc<Mutex<AccessLog>>>) {
+        self.log = log;
+    }
+
+    pub(crate) fn set_clock(&mut self, clock: Option<Arc<Mutex<dyn Clock + 'a>>>) {
+        self.clock = clock;
+    }
+
+    /// Spend a memory cycle (4 clock cycles) of the CPU, advancing the devices by the cycle.
+    pub fn tick(&mut self) {
+        if let Some(clock) = self.clock.clone() {
+            clock.lock().tick(4, self);
+            self.ticked += 4;
+        }
+    }
+
+    /// Bring the devices run by the clock up to date before accessing their registers.
+    fn sync(&self, write: bool) {
+        // The devices are up to date while the clock is running them
+        if let Some(mut clock) = self.clock.as_ref().and_then(|c| c.try_lock()) {
+            clock.sync(write);
+        }
+    }
+
+    /// Returns the clock cycles spent by [`Mmu::tick`][] since the last call.
+    pub(crate) fn take_ticked(&mut self) -> usize {
+        core::mem::replace(&mut self.ticked, 0)
+    }
+
     /// Reads one byte from the given address in the memory.
     pub fn get8(&self, addr: u16) -> u8 {
-        if let Some(handlers) = self.handlers.get(&addr) {
-            for (_, handler) in handlers {
-                match handler.on_read(self, addr) {
-                    MemRead::Replace(alt) => return alt,
-                    MemRead::PassThrough => {}
-                }
-            }
+        let v = self.read8(addr);
+
+        #[cfg(feature = "debugger")]
+        if let Some(log) = &self.log {
+            log.lock().record(addr, v, false);
         }
 
-        if addr >= 0xe000 && addr <= 0xfdff {
-            // echo ram
-            self.ram[addr as usize - 0x2000]
-        } else {
-            self.ram[addr as usize]
+        v
+    }
+
+    fn read8(&self, addr: u16) -> u8 {
+        let addr = mirror(addr);
+
+        for e in &self.pages[(addr >> 8) as usize] {
+            if !e.contains(addr) {
+                continue;
+            }
+            if e.clocked {
+                self.sync(false);
+            }
+            match e.target.on_read(self, addr) {
+                MemRead::Replace(alt) => return alt,
+                MemRead::PassThrough => {}
+            }
         }
+
+        self.ram[addr as usize]
     }
 
     /// Writes one byte at the given address in the memory.
     pub fn set8(&mut self, addr: u16, v: u8) {
-        if let Some(handlers) = self.handlers.get(&addr) {
-            for (_, handler) in handlers {
-                match handler.on_write(self, addr, v) {
-                    MemWrite::Replace(alt) => {
-                        self.ram[addr as usize] = alt;
-                        return;
-                    }
-                    MemWrite::PassThrough => {}
-                    MemWrite::Block => return,
+        #[cfg(feature = "debugger")]
+        if let Some(log) = &self.log {
+            log.lock().record(addr, v, true);
+        }
+
+        let addr = mirror(addr);
+
+        for e in &self.pages[(addr >> 8) as usize] {
+            if !e.contains(addr) {
+                continue;
+            }
+            if e.clocked {
+                self.sync(true);
+            }
+            match e.target.on_write(self, addr, v) {
+                MemWrite::Replace(alt) => {
+                    self.ram[addr as usize] = alt;
+                    return;
                 }
+                MemWrite::PassThrough => {}
+                MemWrite::Block => return,
             }
         }
 
-        if addr >= 0xe000 && addr <= 0xfdff {
-            // echo ram
-            self.ram[addr as usize - 0x2000] = v
-        } else {
-            self.ram[addr as usize] = v
-        }
+        self.ram[addr as usize] = v
     }
 
     /// Reads two bytes from the given addresss in the memory.
@@ -167,8 +428,107 @@ impl Mmu {
         self.set8(addr + 1, (v >> 8) as u8);
     }
 
+    /// Reads one byte as the CPU does, spending a memory cycle before the access.
+    pub fn cpu_get8(&mut self, addr: u16) -> u8 {
+        self.tick();
+        self.get8(addr)
+    }
+
+    /// Writes one byte as the CPU does, spending a memory cycle before the access.
+    pub fn cpu_set8(&mut self, addr: u16, v: u8) {
+        self.tick();
+        self.set8(addr, v)
+    }
+
+    /// Reads two bytes as the CPU does, spending a memory cycle for each byte.
+    pub fn cpu_get16(&mut self, addr: u16) -> u16 {
+        let l = self.cpu_get8(addr);
+        let h = self.cpu_get8(addr.wrapping_add(1));
+        (h as u16) << 8 | l as u16
+    }
+
+    /// Writes two bytes as the CPU does, spending a memory cycle for each byte.
+    pub fn cpu_set16(&mut self, addr: u16, v: u16) {
+        self.cpu_set8(addr, v as u8);
+        self.cpu_set8(addr.wrapping_add(1), (v >> 8) as u8);
+    }
+
     /// Dump the entire array backing the RAM
     pub fn dump(&self) -> &[u8] {
         &self.ram
     }
 }
+
+impl<'a> State for Mmu<'a> {
+    fn save(&self, w: &mut Writer) {
+        self.ram.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.ram.load(r)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "cgb")]
+    fn echo_ram_mirrors_work_ram() {
+        use crate::cgb::Cgb;
+        use crate::device::Device;
+        use crate::hardware::{HardwareHandle, NullHardware};
+
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        let cgb = Device::new(Cgb::new(HardwareHandle::new(NullHardware)));
+        mmu.add_handler((0xc000, 0xdfff), cgb.handler());
+        mmu.add_handler((0xff70, 0xff70), cgb.handler());
+
+        mmu.set8(0xe010, 0x12);
+        assert_eq!(mmu.get8(0xc010), 0x12);
+        mmu.set8(0xc020, 0x34);
+        assert_eq!(mmu.get8(0xe020), 0x34);
+
+        // Echo RAM follows the switchable bank
+        mmu.set8(0xff70, 2);
+        mmu.set8(0xd000, 0x56);
+        assert_eq!(mmu.get8(0xf000), 0x56);
+        mmu.set8(0xff70, 3);
+        assert_eq!(mmu.get8(0xf000), 0x00);
+    }
+
+    struct Fixed(u8);
+
+    impl MemHandler for Fixed {
+        fn on_read(&self, _: &Mmu, _: u16) -> MemRead {
+            MemRead::Replace(self.0)
+        }
+
+        fn on_write(&self, _: &Mmu, _: u16, _: u8) -> MemWrite {
+            MemWrite::Block
+        }
+    }
+
+    #[test]
+    fn handlers_in_page_table() {
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        let first = mmu.add_handler((0x80f0, 0x8110), Fixed(1));
+        mmu.add_handler((0x8100, 0x81ff), Fixed(2));
+
+        // Only the addresses in the range hit the handler, even in the same page
+        assert_eq!(mmu.get8(0x80ef), 0);
+        assert_eq!(mmu.get8(0x80f0), 1);
+        assert_eq!(mmu.get8(0x8110), 1);
+        assert_eq!(mmu.get8(0x8111), 2);
+        assert_eq!(mmu.get8(0x8200), 0);
+
+        mmu.set8(0x8200, 3);
+        assert_eq!(mmu.get8(0x8200), 3);
+
+        // The handler added first wins, and the next one takes over once removed
+        mmu.remove_handler::<Fixed>(&first);
+        assert_eq!(mmu.get8(0x80f0), 0);
+        assert_eq!(mmu.get8(0x8110), 2);
+    }
+}
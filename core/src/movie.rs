@@ -0,0 +1,360 @@
+use crate::debug::Debugger;
+use crate::error::Error;
+use crate::hardware::{key_bits, Key, MaybeSend, KEYS};
+use crate::state::fnv1a;
+use crate::system::{PollEvent, System};
+use alloc::{format, vec::Vec};
+
+const MAGIC: &[u8; 4] = b"RGYM";
+const VERSION: u8 = 2;
+
+/// The size of the header: the magic, the version, the hash of the ROM and the number of frames.
+///
+/// Since version 2, the header is followed by the length of the savestate the movie starts from,
+/// zero from the power on, and the savestate.
+const HEADER_SIZE: usize = 4 + 1 + 8 + 4;
+
+/// The recording of the keys pressed in each frame, replayed bit-exactly.
+///
+/// A movie created by [`Movie::new`][] starts from the power on; record and play it from a newly created
+/// system, or right after [`System::reset`][]. A movie created by [`Movie::from_state`][] carries the
+/// savestate it starts from instead, which [`Movie::start`][] loads before playing.
+/// The keys are injected by [`System::set_button`][], so the [`Hardware`][crate::Hardware]
+/// must not report pressed keys, and must supply the same cartridge RAM in both runs.
+/// Enable [`Config::deterministic`][crate::Config::deterministic] for cartridges with a real-time clock.
+///
+/// ```rust,no_run
+/// # fn movie<'a, D: rgy::debug::Debugger + rgy::MaybeSend + 'a>(rom: &[u8], sys: &mut rgy::System<'a, D>) -> Result<(), rgy::Error> {
+/// let mut movie = rgy::Movie::new(rom);
+/// for _ in 0..60 {
+///     movie.record_frame(sys, &[rgy::Key::Start])?;
+/// }
+/// let data = movie.save();
+///
+/// let mut movie = rgy::Movie::load(&data)?;
+/// movie.start(sys)?;
+/// while movie.play_frame(sys)?.is_some() {}
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    rom: u64,
+    state: Option<Vec<u8>>,
+    inputs: Vec<u8>,
+    pos: usize,
+}
+
+impl Movie {
+    /// Create an empty movie for the ROM.
+    pub fn new(rom: &[u8]) -> Self {
+        Self {
+            rom: fnv1a(rom),
+            state: None,
+            inputs: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    /// Create an empty movie for the ROM starting from the current state of the system.
+    pub fn from_state<'a, D>(rom: &[u8], sys: &System<'a, D>) -> Self
+    where
+        D: Debugger + MaybeSend + 'a,
+    {
+        Self {
+            state: Some(sys.save_state()),
+            ..Self::new(rom)
+        }
+    }
+
+    /// Decode the movie saved by [`Movie::save`][].
+    pub fn load(data: &[u8]) -> Result<Self, Error> {
+        let invalid = |msg: &str| Error::InvalidMovie(msg.into());
+
+        if data.len() < HEADER_SIZE || &data[..4] != MAGIC {
+            return Err(invalid("not a movie"));
+        }
+        let version = data[4];
+        if version == 0 || version > VERSION {
+            return Err(Error::InvalidMovie(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+
+        let mut rom = [0; 8];
+        rom.copy_from_slice(&data[5..13]);
+        let mut frames = [0; 4];
+        frames.copy_from_slice(&data[13..17]);
+        let frames = u32::from_le_bytes(frames) as usize;
+
+        let mut pos = HEADER_SIZE;
+        let state = if version >= 2 {
+            let len = data.get(pos..pos + 4).ok_or_else(|| invalid("truncated"))?;
+            let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+            pos += 4;
+            let state = data
+                .get(pos..)
+                .filter(|rest| rest.len() >= len)
+                .ok_or_else(|| invalid("truncated"))?;
+            pos += len;
+            if len > 0 {
+                Some(state[..len].to_vec())
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+
+        // The keys are run-length encoded as pairs of the count and the keys. The number of
+        // the frames in the header is untrusted, so the keys are checked against it after decoding.
+        let runs = data[pos..].chunks_exact(2);
+        if !runs.remainder().is_empty() {
+            return Err(invalid("truncated"));
+        }
+        let mut inputs = Vec::new();
+        for run in runs {
+            inputs.resize(inputs.len() + run[0] as usize, run[1]);
+        }
+        if inputs.len() != frames {
+            return Err(Error::InvalidMovie(format!(
+                "{} frames while expecting {}",
+                inputs.len(),
+                frames
+            )));
+        }
+
+        Ok(Self {
+            rom: u64::from_le_bytes(rom),
+            state,
+            inputs,
+            pos: 0,
+        })
+    }
+
+    /// Encode the movie.
+    pub fn save(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        buf.extend_from_slice(MAGIC);
+        buf.push(VERSION);
+        buf.extend_from_slice(&self.rom.to_le_bytes());
+        buf.extend_from_slice(&(self.inputs.len() as u32).to_le_bytes());
+
+        let state = self.state.as_deref().unwrap_or(&[]);
+        buf.extend_from_slice(&(state.len() as u32).to_le_bytes());
+        buf.extend_from_slice(state);
+
+        let mut i = 0;
+        while i < self.inputs.len() {
+            let keys = self.inputs[i];
+            let count = self.inputs[i..]
+                .iter()
+                .take(0xff)
+                .take_while(|k| **k == keys)
+                .count();
+            buf.push(count as u8);
+            buf.push(keys);
+            i += count;
+        }
+
+        buf
+    }
+
+    /// Check if the movie is recorded with the ROM.
+    pub fn matches(&self, rom: &[u8]) -> bool {
+        self.rom == fnv1a(rom)
+    }
+
+    /// The number of frames in the movie.
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    /// Check if the movie has no frames.
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    /// The frame recorded or played next.
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// Move to the beginning of the movie, putting the system in the state the movie starts from.
+    ///
+    /// Loads the savestate of a movie created by [`Movie::from_state`][], or resets the system otherwise.
+    pub fn start<'a, D>(&mut self, sys: &mut System<'a, D>) -> Result<(), Error>
+    where
+        D: Debugger + MaybeSend + 'a,
+    {
+        match &self.state {
+            Some(state) => sys.load_state(state)?,
+            None => sys.reset(),
+        }
+        self.pos = 0;
+
+        Ok(())
+    }
+
+    /// The keys pressed in the frame.
+    pub fn keys(&self, frame: usize) -> Option<Vec<Key>> {
+        let keys = *self.inputs.get(frame)?;
+
+        Some(
+            KEYS.iter()
+                .enumerate()
+                .filter(|(i, _)| keys & 1 << i != 0)
+                .map(|(_, k)| k.clone())
+                .collect(),
+        )
+    }
+
+    /// Run a frame with the keys pressed, recording them.
+    ///
+    /// The frames after the current position are discarded, so recording can resume
+    /// from the middle of the movie after playing it up to there.
+    pub fn record_frame<'a, D>(
+        &mut self,
+        sys: &mut System<'a, D>,
+        pressed: &[Key],
+    ) -> Result<PollEvent, Error>
+    where
+        D: Debugger + MaybeSend + 'a,
+    {
+        let keys = key_bits(pressed);
+
+        self.inputs.truncate(self.pos);
+        self.inputs.push(keys);
+        self.pos += 1;
+
+        sys.set_keys(keys);
+        sys.run_frame()
+    }
+
+    /// Run the next frame of the movie with the recorded keys.
+    ///
+    /// Returns `None` without running the system at the end of the movie.
+    pub fn play_frame<'a, D>(&mut self, sys: &mut System<'a, D>) -> Result<Option<PollEvent>, Error>
+    where
+        D: Debugger + MaybeSend + 'a,
+    {
+        let keys = match self.inputs.get(self.pos) {
+            Some(keys) => *keys,
+            None => return Ok(None),
+        };
+        self.pos += 1;
+
+        sys.set_keys(keys);
+        sys.run_frame().map(Some)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::debug::NullDebugger;
+    use crate::hardware::NullHardware;
+    use crate::system::test::{rom, system};
+    use crate::system::Config;
+    use alloc::vec;
+
+    /// The program which keeps summing up the joypad lines into C000.
+    const PROGRAM: &[u8] = &[
+        0x3e, 0x10, // ld a,0x10
+        0xe0, 0x00, // ldh (0x00),a
+        0xf0, 0x00, // ldh a,(0x00)
+        0x47, // ld b,a
+        0xfa, 0x00, 0xc0, // ld a,(0xc000)
+        0x80, // add b
+        0xea, 0x00, 0xc0, // ld (0xc000),a
+        0x18, 0xf2, // jr -14
+    ];
+
+    #[test]
+    fn record_and_play() {
+        let rom = rom();
+        let mut sys = system(Config::new(), PROGRAM, NullHardware, NullDebugger);
+        let mut movie = Movie::new(&rom);
+
+        for i in 0..30 {
+            let pressed = if (5..15).contains(&i) {
+                vec![Key::A]
+            } else {
+                vec![]
+            };
+            movie.record_frame(&mut sys, &pressed).unwrap();
+        }
+        let recorded = sys.mmu_get8(0xc000);
+
+        let data = movie.save();
+        assert_eq!(data.len(), HEADER_SIZE + 4 + 6);
+
+        let mut movie = Movie::load(&data).unwrap();
+        assert!(movie.matches(&rom));
+        assert_eq!(movie.len(), 30);
+        assert_eq!(movie.keys(5), Some(vec![Key::A]));
+
+        let mut sys = system(Config::new(), PROGRAM, NullHardware, NullDebugger);
+        while movie.play_frame(&mut sys).unwrap().is_some() {}
+        assert_eq!(movie.pos(), 30);
+        assert_eq!(sys.mmu_get8(0xc000), recorded);
+
+        // The keys make a difference
+        let mut sys = system(Config::new(), PROGRAM, NullHardware, NullDebugger);
+        let mut silent = Movie::new(&rom);
+        for _ in 0..30 {
+            silent.record_frame(&mut sys, &[]).unwrap();
+        }
+        assert_ne!(sys.mmu_get8(0xc000), recorded);
+
+        assert!(Movie::load(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn from_state() {
+        let rom = rom();
+        let mut sys = system(Config::new(), PROGRAM, NullHardware, NullDebugger);
+        for _ in 0..10 {
+            sys.run_frame().unwrap();
+        }
+
+        let mut movie = Movie::from_state(&rom, &sys);
+        for _ in 0..10 {
+            movie.record_frame(&mut sys, &[Key::B]).unwrap();
+        }
+        let recorded = sys.mmu_get8(0xc000);
+
+        // Played from the embedded state on a system that ran differently
+        let mut movie = Movie::load(&movie.save()).unwrap();
+        let mut sys = system(Config::new(), PROGRAM, NullHardware, NullDebugger);
+        movie.start(&mut sys).unwrap();
+        while movie.play_frame(&mut sys).unwrap().is_some() {}
+        assert_eq!(sys.mmu_get8(0xc000), recorded);
+    }
+
+    #[test]
+    fn untrusted_header() {
+        let rom = rom();
+        let mut data = Movie::new(&rom).save();
+
+        // A huge number of frames isn't reserved up front
+        data[13..17].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            Movie::load(&data),
+            Err(Error::InvalidMovie(format!(
+                "0 frames while expecting {}",
+                u32::MAX
+            )))
+        );
+
+        // The savestate longer than the movie
+        let mut data = Movie::new(&rom).save();
+        data[17..21].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            Movie::load(&data),
+            Err(Error::InvalidMovie("truncated".into()))
+        );
+    }
+}
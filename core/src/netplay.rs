@@ -0,0 +1,507 @@
+use crate::debug::Debugger;
+use crate::error::Error;
+use crate::hardware::{key_bits, Key, MaybeSend};
+use crate::link::LocalLink;
+use crate::state::fnv1a;
+use crate::system::{PollEvent, System, CYCLES_PER_FRAME};
+use alloc::{format, vec::Vec};
+use log::*;
+
+/// The reliable transport which delivers the messages between the two peers in order.
+///
+/// The netplay only passes opaque messages through the transport, e.g. over TCP or WebRTC.
+pub trait Transport {
+    /// Send the message to the peer.
+    fn send(&mut self, msg: &[u8]);
+
+    /// Receive the next message from the peer if any has arrived, without blocking.
+    fn recv(&mut self) -> Option<Vec<u8>>;
+}
+
+/// The role of the peer in the netplay session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    /// Player 1, whose state wins when the instances diverge.
+    Host,
+    /// Player 2.
+    Guest,
+}
+
+impl Side {
+    fn index(self) -> usize {
+        match self {
+            Side::Host => 0,
+            Side::Guest => 1,
+        }
+    }
+}
+
+/// Event reported by [`Netplay::run_frame`][].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NetplayEvent {
+    /// The input of the peer for the frame hasn't arrived yet; call the function again.
+    Waiting,
+    /// Both instances completed the frame.
+    FrameReady,
+    /// The instances were found diverged from the peer and brought back in sync with the host,
+    /// then completed the frame.
+    Resynced,
+    /// Either instance exited.
+    Exit,
+}
+
+const MSG_INPUT: u8 = 0;
+const MSG_RESYNC: u8 = 1;
+
+/// The message exchanged between the peers.
+enum Message {
+    /// The keys of the sender for the frame, and the hash of the state before running the frame.
+    Input { frame: u32, keys: u8, hash: u64 },
+    /// The savestates of both instances of the host before running the frame.
+    Resync { frame: u32, states: [Vec<u8>; 2] },
+}
+
+impl Message {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        match self {
+            Message::Input { frame, keys, hash } => {
+                buf.push(MSG_INPUT);
+                buf.extend_from_slice(&frame.to_le_bytes());
+                buf.push(*keys);
+                buf.extend_from_slice(&hash.to_le_bytes());
+            }
+            Message::Resync { frame, states } => {
+                buf.push(MSG_RESYNC);
+                buf.extend_from_slice(&frame.to_le_bytes());
+                buf.extend_from_slice(&(states[0].len() as u32).to_le_bytes());
+                buf.extend_from_slice(&states[0]);
+                buf.extend_from_slice(&states[1]);
+            }
+        }
+
+        buf
+    }
+
+    fn decode(msg: &[u8]) -> Result<Self, Error> {
+        let invalid = || Error::InvalidMessage(format!("{:02x?}", &msg[..msg.len().min(16)]));
+
+        if msg.len() < 5 {
+            return Err(invalid());
+        }
+        let mut frame = [0; 4];
+        frame.copy_from_slice(&msg[1..5]);
+        let frame = u32::from_le_bytes(frame);
+
+        match msg[0] {
+            MSG_INPUT if msg.len() == 14 => {
+                let mut hash = [0; 8];
+                hash.copy_from_slice(&msg[6..14]);
+                Ok(Message::Input {
+                    frame,
+                    keys: msg[5],
+                    hash: u64::from_le_bytes(hash),
+                })
+            }
+            // The length of the savestate of player 1, followed by the savestates of both instances
+            MSG_RESYNC if msg.len() >= 9 => {
+                let mut len = [0; 4];
+                len.copy_from_slice(&msg[5..9]);
+                let len = u32::from_le_bytes(len) as usize;
+                if msg.len() - 9 < len {
+                    return Err(invalid());
+                }
+                let (p1, p2) = msg[9..].split_at(len);
+                Ok(Message::Resync {
+                    frame,
+                    states: [p1.to_vec(), p2.to_vec()],
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Two-player link play over the network.
+///
+/// Both peers run the same two instances, player 1 and player 2, connected by [`LocalLink`][],
+/// in lockstep frame by frame. Only the keys are exchanged over the [`Transport`][] every frame,
+/// so the serial transfers between the instances happen identically on both peers.
+/// Each input message carries the hash of the state of the instances;
+/// when the hashes differ, the host sends the savestates of its instances to bring the guest back in sync.
+///
+/// The instances must be created from the same ROM and configuration on both peers,
+/// and their [`Hardware`][crate::Hardware] must not report pressed keys, as the keys are injected
+/// by [`System::set_button`][].
+pub struct Netplay<'a, D, T> {
+    systems: [System<'a, D>; 2],
+    transport: T,
+    side: Side,
+    frame: u32,
+    /// The keys of this peer and the hash of the state sent for the frame.
+    local: Option<(u8, u64)>,
+    /// The keys and the hash received from the peer for the frame.
+    peer: Option<(u8, u64)>,
+    resync: Option<[Vec<u8>; 2]>,
+}
+
+impl<'a, D, T> Netplay<'a, D, T>
+where
+    D: Debugger + MaybeSend + 'a,
+    T: Transport,
+{
+    /// Start a session with the instances of player 1 and player 2, connecting them by a link cable.
+    pub fn new(side: Side, transport: T, mut p1: System<'a, D>, mut p2: System<'a, D>) -> Self {
+        let (l, r) = LocalLink::pair();
+        p1.connect_link(l);
+        p2.connect_link(r);
+
+        Self {
+            systems: [p1, p2],
+            transport,
+            side,
+            frame: 0,
+            local: None,
+            peer: None,
+            resync: None,
+        }
+    }
+
+    /// The instance of the player, 0 for player 1 and 1 for player 2.
+    pub fn system(&self, player: usize) -> &System<'a, D> {
+        &self.systems[player]
+    }
+
+    /// The instance of the player, 0 for player 1 and 1 for player 2.
+    pub fn system_mut(&mut self, player: usize) -> &mut System<'a, D> {
+        &mut self.systems[player]
+    }
+
+    /// The number of frames completed.
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// Run both instances for a frame with the keys pressed on this peer.
+    ///
+    /// Returns [`NetplayEvent::Waiting`][] without running the instances until the keys of the peer
+    /// for the frame arrive. The keys passed to the following calls are ignored until the frame completes.
+    pub fn run_frame(&mut self, pressed: &[Key]) -> Result<NetplayEvent, Error> {
+        let (local_keys, hash) = match self.local {
+            Some(local) => local,
+            None => {
+                let hash = self.state_hash();
+                let keys = key_bits(pressed);
+
+                self.systems[self.side.index()].set_keys(keys);
+                self.send(Message::Input {
+                    frame: self.frame,
+                    keys,
+                    hash,
+                });
+                self.local = Some((keys, hash));
+                (keys, hash)
+            }
+        };
+
+        self.receive(false)?;
+
+        let (keys, peer_hash) = match self.peer {
+            Some(peer) => peer,
+            None => return Ok(NetplayEvent::Waiting),
+        };
+
+        let resynced = peer_hash != hash;
+        if resynced {
+            match self.side {
+                Side::Host => {
+                    warn!("Desync at frame {}: sending the state", self.frame);
+                    let states = [self.systems[0].save_state(), self.systems[1].save_state()];
+                    self.send(Message::Resync {
+                        frame: self.frame,
+                        states,
+                    });
+                }
+                Side::Guest => match self.receive(true)?.resync.take() {
+                    Some([p1, p2]) => {
+                        warn!("Desync at frame {}: loading the state", self.frame);
+                        // Keep both instances as they are if either state is broken
+                        let backup = self.systems[0].save_state();
+                        self.systems[0].load_state(&p1)?;
+                        if let Err(e) = self.systems[1].load_state(&p2) {
+                            self.systems[0]
+                                .load_state(&backup)
+                                .expect("failed to restore the state");
+                            return Err(e);
+                        }
+                        // The keys are part of the state, so apply the ones of this frame again
+                        self.systems[self.side.index()].set_keys(local_keys);
+                    }
+                    None => return Ok(NetplayEvent::Waiting),
+                },
+            }
+        }
+
+        self.systems[1 - self.side.index()].set_keys(keys);
+        self.peer = None;
+        self.local = None;
+        self.frame += 1;
+
+        if !self.run_instances()? {
+            return Ok(NetplayEvent::Exit);
+        }
+
+        Ok(if resynced {
+            NetplayEvent::Resynced
+        } else {
+            NetplayEvent::FrameReady
+        })
+    }
+
+    fn send(&mut self, msg: Message) {
+        self.transport.send(&msg.encode());
+    }
+
+    /// Receive the messages until the keys of the peer for the frame arrive,
+    /// and the state from the host as well if `resync` is set.
+    fn receive(&mut self, resync: bool) -> Result<&mut Self, Error> {
+        while self.peer.is_none() || resync && self.resync.is_none() {
+            let msg = match self.transport.recv() {
+                Some(msg) => Message::decode(&msg)?,
+                None => break,
+            };
+
+            match msg {
+                Message::Input { frame, keys, hash } if frame == self.frame => {
+                    self.peer = Some((keys, hash));
+                }
+                Message::Resync { frame, states } if frame == self.frame => {
+                    self.resync = Some(states);
+                }
+                Message::Input { frame, .. } | Message::Resync { frame, .. } => {
+                    return Err(Error::InvalidMessage(format!(
+                        "frame {} while expecting {}",
+                        frame, self.frame
+                    )));
+                }
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// The hash of the whole state of both instances, so that a desync in any component is detected.
+    fn state_hash(&self) -> u64 {
+        let mut state = self.systems[0].save_state();
+        state.extend(self.systems[1].save_state());
+        fnv1a(&state)
+    }
+
+    /// Run both instances for a frame, interleaving them so that
+    /// the serial transfers happen at the right timing. Returns `false` on exit.
+    fn run_instances(&mut self) -> Result<bool, Error> {
+        let start = [self.systems[0].cycles(), self.systems[1].cycles()];
+        let mut done = [false; 2];
+
+        while !(done[0] && done[1]) {
+            // Step the instance behind
+            let i = if done[0] || !done[1] && self.systems[1].cycles() < self.systems[0].cycles() {
+                1
+            } else {
+                0
+            };
+
+            match self.systems[i].poll_event()? {
+                PollEvent::FrameReady => done[i] = true,
+                PollEvent::Exit => return Ok(false),
+                _ => {}
+            }
+
+            // The frame never completes while the LCD is off
+            if self.systems[i].cycles() - start[i] >= CYCLES_PER_FRAME {
+                done[i] = true;
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::debug::NullDebugger;
+    use crate::hardware::NullHardware;
+    use crate::system::Config;
+    use alloc::collections::VecDeque;
+    use alloc::rc::Rc;
+    use alloc::vec;
+    use core::cell::RefCell;
+
+    /// The in-memory transport delivering the messages to the other end.
+    struct Pipe {
+        tx: Rc<RefCell<VecDeque<Vec<u8>>>>,
+        rx: Rc<RefCell<VecDeque<Vec<u8>>>>,
+    }
+
+    impl Transport for Pipe {
+        fn send(&mut self, msg: &[u8]) {
+            self.tx.borrow_mut().push_back(msg.to_vec());
+        }
+
+        fn recv(&mut self) -> Option<Vec<u8>> {
+            self.rx.borrow_mut().pop_front()
+        }
+    }
+
+    fn peer(
+        side: Side,
+        tx: &Rc<RefCell<VecDeque<Vec<u8>>>>,
+        rx: &Rc<RefCell<VecDeque<Vec<u8>>>>,
+    ) -> Netplay<'static, NullDebugger, Pipe> {
+        let system = || {
+            let cfg = Config::new().native_speed(true).headless(true);
+            let rom = vec![0u8; 0x8000];
+            System::new(cfg, &rom, vec![0u8; 0x10000], NullHardware, NullDebugger).unwrap()
+        };
+        let pipe = Pipe {
+            tx: tx.clone(),
+            rx: rx.clone(),
+        };
+        Netplay::new(side, pipe, system(), system())
+    }
+
+    #[test]
+    fn lockstep_and_resync() {
+        let a = Rc::new(RefCell::new(VecDeque::new()));
+        let b = Rc::new(RefCell::new(VecDeque::new()));
+        let mut host = peer(Side::Host, &a, &b);
+        let mut guest = peer(Side::Guest, &b, &a);
+
+        // The host waits for the guest
+        assert_eq!(host.run_frame(&[Key::A]).unwrap(), NetplayEvent::Waiting);
+        assert_eq!(guest.run_frame(&[]).unwrap(), NetplayEvent::FrameReady);
+        assert_eq!(host.run_frame(&[]).unwrap(), NetplayEvent::FrameReady);
+        assert_eq!(host.frame(), 1);
+
+        // Diverge the guest
+        guest.system_mut(1).mmu_set8(0xc000, 0x12);
+        assert_eq!(host.run_frame(&[]).unwrap(), NetplayEvent::Waiting);
+        assert_eq!(guest.run_frame(&[]).unwrap(), NetplayEvent::Waiting);
+        assert_eq!(host.run_frame(&[]).unwrap(), NetplayEvent::Resynced);
+        assert_eq!(guest.run_frame(&[]).unwrap(), NetplayEvent::Resynced);
+        assert_eq!(guest.system(1).mmu_get8(0xc000), 0x00);
+
+        assert_eq!(host.run_frame(&[]).unwrap(), NetplayEvent::Waiting);
+        assert_eq!(guest.run_frame(&[]).unwrap(), NetplayEvent::FrameReady);
+        assert_eq!(host.run_frame(&[]).unwrap(), NetplayEvent::FrameReady);
+    }
+
+    #[test]
+    fn truncated_resync() {
+        let a = Rc::new(RefCell::new(VecDeque::new()));
+        let b = Rc::new(RefCell::new(VecDeque::new()));
+        let mut guest = peer(Side::Guest, &b, &a);
+
+        // The input with a different hash is followed by a short resync
+        let input = Message::Input {
+            frame: 0,
+            keys: 0,
+            hash: 0,
+        };
+        a.borrow_mut().push_back(input.encode());
+        a.borrow_mut()
+            .push_back(vec![MSG_RESYNC, 0, 0, 0, 0, 1, 2, 3]);
+
+        assert!(matches!(
+            guest.run_frame(&[]),
+            Err(Error::InvalidMessage(_))
+        ));
+
+        // The length of the first state beyond the message
+        let mut guest = peer(Side::Guest, &b, &a);
+        a.borrow_mut().push_back(input.encode());
+        a.borrow_mut()
+            .push_back(vec![MSG_RESYNC, 0, 0, 0, 0, 4, 0, 0, 0, 1, 2, 3]);
+
+        assert!(matches!(
+            guest.run_frame(&[]),
+            Err(Error::InvalidMessage(_))
+        ));
+    }
+
+    #[test]
+    fn broken_resync() {
+        let a = Rc::new(RefCell::new(VecDeque::new()));
+        let b = Rc::new(RefCell::new(VecDeque::new()));
+        let mut guest = peer(Side::Guest, &b, &a);
+        guest.system_mut(0).mmu_set8(0xc000, 0x12);
+
+        // The valid state of player 1 and the broken state of player 2
+        let host = peer(Side::Host, &a, &b);
+        let input = Message::Input {
+            frame: 0,
+            keys: 0,
+            hash: 0,
+        };
+        let resync = Message::Resync {
+            frame: 0,
+            states: [host.system(0).save_state(), vec![1, 2, 3]],
+        };
+        a.borrow_mut().push_back(input.encode());
+        a.borrow_mut().push_back(resync.encode());
+
+        assert!(matches!(guest.run_frame(&[]), Err(Error::InvalidState(_))));
+        // Neither instance is touched
+        assert_eq!(guest.system(0).mmu_get8(0xc000), 0x12);
+    }
+
+    #[test]
+    fn resync_peripherals() {
+        let a = Rc::new(RefCell::new(VecDeque::new()));
+        let b = Rc::new(RefCell::new(VecDeque::new()));
+        let mut host = peer(Side::Host, &a, &b);
+        let mut guest = peer(Side::Guest, &b, &a);
+
+        // Diverge the timer modulo, the scroll register and the high RAM of the guest
+        guest.system_mut(0).mmu_set8(0xff06, 0x80);
+        guest.system_mut(1).mmu_set8(0xff42, 0x34);
+        guest.system_mut(1).mmu_set8(0xff90, 0x56);
+
+        assert_eq!(host.run_frame(&[]).unwrap(), NetplayEvent::Waiting);
+        assert_eq!(guest.run_frame(&[]).unwrap(), NetplayEvent::Waiting);
+        assert_eq!(host.run_frame(&[]).unwrap(), NetplayEvent::Resynced);
+        assert_eq!(guest.run_frame(&[]).unwrap(), NetplayEvent::Resynced);
+        assert_eq!(guest.system(0).mmu_get8(0xff06), 0x00);
+        assert_eq!(guest.system(1).mmu_get8(0xff42), 0x00);
+        assert_eq!(guest.system(1).mmu_get8(0xff90), 0x00);
+
+        // Back in lockstep
+        assert_eq!(host.run_frame(&[]).unwrap(), NetplayEvent::Waiting);
+        assert_eq!(guest.run_frame(&[]).unwrap(), NetplayEvent::FrameReady);
+        assert_eq!(host.run_frame(&[]).unwrap(), NetplayEvent::FrameReady);
+        assert_eq!(host.state_hash(), guest.state_hash());
+    }
+
+    #[test]
+    fn resync_keeps_keys() {
+        let a = Rc::new(RefCell::new(VecDeque::new()));
+        let b = Rc::new(RefCell::new(VecDeque::new()));
+        let mut host = peer(Side::Host, &a, &b);
+        let mut guest = peer(Side::Guest, &b, &a);
+
+        // The guest presses the keys on the frame it gets resynced
+        guest.system_mut(1).mmu_set8(0xc000, 0x12);
+        assert_eq!(host.run_frame(&[]).unwrap(), NetplayEvent::Waiting);
+        assert_eq!(guest.run_frame(&[Key::A]).unwrap(), NetplayEvent::Waiting);
+        assert_eq!(host.run_frame(&[]).unwrap(), NetplayEvent::Resynced);
+        assert_eq!(guest.run_frame(&[Key::A]).unwrap(), NetplayEvent::Resynced);
+        assert_eq!(host.state_hash(), guest.state_hash());
+
+        assert_eq!(host.run_frame(&[Key::B]).unwrap(), NetplayEvent::Waiting);
+        assert_eq!(guest.run_frame(&[]).unwrap(), NetplayEvent::FrameReady);
+        assert_eq!(host.run_frame(&[]).unwrap(), NetplayEvent::FrameReady);
+        assert_eq!(host.state_hash(), guest.state_hash());
+    }
+}
@@ -0,0 +1,346 @@
+use crate::error::Error;
+use alloc::{format, vec::Vec};
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const BPS_MAGIC: &[u8] = b"BPS1";
+
+/// The size of the BPS footer: the CRC32 of the source, the target and the patch.
+const BPS_FOOTER_SIZE: usize = 12;
+
+/// Apply an IPS or BPS patch to the ROM, returning the patched ROM.
+///
+/// The format is detected from the magic bytes of the patch. BPS patches carry the CRC32 of
+/// the original and the patched ROM, so applying one to the wrong ROM fails instead of
+/// producing a broken image. Pass the result to [`System::new`][crate::System::new].
+///
+/// ```rust,no_run
+/// # fn load<H: rgy::Hardware>(rom: &[u8], ips: &[u8], hw: H) -> Result<(), rgy::Error> {
+/// let rom = rgy::apply_patch(rom, ips)?;
+/// let cfg = rgy::Config::new();
+/// let sys = rgy::System::new(cfg, &rom, vec![0; 0x10000], hw, rgy::debug::NullDebugger)?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn apply_patch(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, Error> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, &patch[IPS_MAGIC.len()..])
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else {
+        Err(invalid("unknown format"))
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::InvalidPatch(msg.into())
+}
+
+/// Reads the patch from the front, failing on truncation.
+struct Cursor<'p> {
+    data: &'p [u8],
+    pos: usize,
+}
+
+impl<'p> Cursor<'p> {
+    fn new(data: &'p [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'p [u8], Error> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| invalid("truncated"))?;
+        let b = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(b)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// Read a big-endian number of `len` bytes.
+    fn be(&mut self, len: usize) -> Result<usize, Error> {
+        Ok(self
+            .bytes(len)?
+            .iter()
+            .fold(0, |n, b| (n << 8) | *b as usize))
+    }
+
+    /// Read a variable-length number of BPS.
+    fn varint(&mut self) -> Result<usize, Error> {
+        let mut n = 0usize;
+        let mut shift = 1usize;
+
+        loop {
+            let b = self.u8()?;
+            n = (b as usize & 0x7f)
+                .checked_mul(shift)
+                .and_then(|v| n.checked_add(v))
+                .ok_or_else(|| invalid("number overflow"))?;
+            if b & 0x80 != 0 {
+                return Ok(n);
+            }
+            shift = shift
+                .checked_shl(7)
+                .filter(|s| *s != 0)
+                .ok_or_else(|| invalid("number overflow"))?;
+            n = n
+                .checked_add(shift)
+                .ok_or_else(|| invalid("number overflow"))?;
+        }
+    }
+}
+
+fn apply_ips(rom: &[u8], records: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut out = rom.to_vec();
+    let mut r = Cursor::new(records);
+
+    loop {
+        if r.data[r.pos..].starts_with(IPS_EOF) {
+            r.bytes(IPS_EOF.len())?;
+            break;
+        }
+
+        let offset = r.be(3)?;
+        let (len, fill) = match r.be(2)? {
+            // Run-length encoded record
+            0 => (r.be(2)?, Some(r.u8()?)),
+            len => (len, None),
+        };
+
+        if out.len() < offset + len {
+            out.resize(offset + len, 0);
+        }
+        match fill {
+            Some(b) => out[offset..offset + len].iter_mut().for_each(|o| *o = b),
+            None => out[offset..offset + len].copy_from_slice(r.bytes(len)?),
+        }
+    }
+
+    // The optional extension truncating the output
+    if let Ok(len) = r.be(3) {
+        out.truncate(len);
+    }
+
+    Ok(out)
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, Error> {
+    if patch.len() < BPS_MAGIC.len() + BPS_FOOTER_SIZE {
+        return Err(invalid("truncated"));
+    }
+
+    let (body, footer) = patch.split_at(patch.len() - BPS_FOOTER_SIZE);
+    let crc =
+        |i: usize| u32::from_le_bytes([footer[i], footer[i + 1], footer[i + 2], footer[i + 3]]);
+
+    if crc32(&patch[..patch.len() - 4]) != crc(8) {
+        return Err(invalid("patch checksum mismatch"));
+    }
+    if crc32(rom) != crc(0) {
+        return Err(invalid("the patch is for another ROM"));
+    }
+
+    let mut r = Cursor::new(body);
+    r.bytes(BPS_MAGIC.len())?;
+    let source_size = r.varint()?;
+    let target_size = r.varint()?;
+    let metadata_size = r.varint()?;
+    r.bytes(metadata_size)?;
+
+    if source_size != rom.len() {
+        return Err(invalid("the patch is for another ROM"));
+    }
+
+    // The size is untrusted until the output is checked, so don't reserve beyond what
+    // the patch can plausibly produce
+    let mut out = Vec::with_capacity(target_size.min(rom.len() + patch.len()));
+    let mut source_pos = 0usize;
+    let mut target_pos = 0usize;
+
+    // Move the relative pointer by the signed offset
+    let seek = |pos: usize, d: usize, limit: usize| {
+        let pos = if d & 1 != 0 {
+            pos.checked_sub(d >> 1)
+        } else {
+            pos.checked_add(d >> 1)
+        };
+        pos.filter(|p| *p < limit)
+            .ok_or_else(|| invalid("offset out of range"))
+    };
+
+    while r.pos < body.len() {
+        let action = r.varint()?;
+        let len = (action >> 2) + 1;
+
+        if out.len() + len > target_size {
+            return Err(invalid("output overflow"));
+        }
+
+        match action & 3 {
+            // Source read
+            0 => {
+                let src = rom
+                    .get(out.len()..out.len() + len)
+                    .ok_or_else(|| invalid("offset out of range"))?;
+                out.extend_from_slice(src);
+            }
+            // Target read
+            1 => out.extend_from_slice(r.bytes(len)?),
+            // Source copy
+            2 => {
+                source_pos = seek(source_pos, r.varint()?, rom.len())?;
+                let src = rom
+                    .get(source_pos..source_pos + len)
+                    .ok_or_else(|| invalid("offset out of range"))?;
+                out.extend_from_slice(src);
+                source_pos += len;
+            }
+            // Target copy, which can overlap the bytes being written
+            _ => {
+                target_pos = seek(target_pos, r.varint()?, out.len())?;
+                for _ in 0..len {
+                    out.push(out[target_pos]);
+                    target_pos += 1;
+                }
+            }
+        }
+    }
+
+    if out.len() != target_size {
+        return Err(Error::InvalidPatch(format!(
+            "output size {} doesn't match {}",
+            out.len(),
+            target_size
+        )));
+    }
+    if crc32(&out) != crc(4) {
+        return Err(invalid("output checksum mismatch"));
+    }
+
+    Ok(out)
+}
+
+/// CRC-32 (IEEE 802.3) computed bit by bit to avoid the table in flash.
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, b| {
+        (0..8).fold(crc ^ *b as u32, |c, _| {
+            if c & 1 != 0 {
+                (c >> 1) ^ 0xedb8_8320
+            } else {
+                c >> 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    fn varint(mut n: usize, out: &mut Vec<u8>) {
+        loop {
+            let b = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                out.push(b | 0x80);
+                return;
+            }
+            out.push(b);
+            n -= 1;
+        }
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn ips() {
+        let rom = vec![0u8; 8];
+
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xaa, 0xbb]);
+        // Run-length encoded record growing the ROM
+        patch.extend_from_slice(&[0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x03, 0xcc]);
+        patch.extend_from_slice(b"EOF");
+
+        let out = apply_patch(&rom, &patch).unwrap();
+        assert_eq!(out, [0, 0xaa, 0xbb, 0, 0, 0, 0, 0xcc, 0xcc, 0xcc]);
+
+        // Truncated record
+        assert_eq!(
+            apply_patch(&rom, &patch[..10]),
+            Err(Error::InvalidPatch("truncated".into()))
+        );
+        assert!(apply_patch(&rom, b"XXXX").is_err());
+    }
+
+    #[test]
+    fn bps() {
+        let rom = b"abcdefgh".to_vec();
+        let target = b"abcXYXYXgh".to_vec();
+
+        let mut patch = b"BPS1".to_vec();
+        varint(rom.len(), &mut patch);
+        varint(target.len(), &mut patch);
+        varint(0, &mut patch);
+        // Source read "abc"
+        varint((3 - 1) << 2, &mut patch);
+        // Target read "XY"
+        varint(((2 - 1) << 2) | 1, &mut patch);
+        patch.extend_from_slice(b"XY");
+        // Target copy "XYX" from 3
+        varint(((3 - 1) << 2) | 3, &mut patch);
+        varint(3 << 1, &mut patch);
+        // Source copy "gh" from 6
+        varint(((2 - 1) << 2) | 2, &mut patch);
+        varint(6 << 1, &mut patch);
+        patch.extend_from_slice(&crc32(&rom).to_le_bytes());
+        patch.extend_from_slice(&crc32(&target).to_le_bytes());
+        patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+
+        assert_eq!(apply_patch(&rom, &patch).unwrap(), target);
+
+        assert_eq!(
+            apply_patch(b"abcdefgX", &patch),
+            Err(Error::InvalidPatch("the patch is for another ROM".into()))
+        );
+
+        let mut broken = patch.clone();
+        broken[6] ^= 1;
+        assert_eq!(
+            apply_patch(&rom, &broken),
+            Err(Error::InvalidPatch("patch checksum mismatch".into()))
+        );
+    }
+
+    #[test]
+    fn bps_huge_target_size() {
+        let rom = b"abcdefgh".to_vec();
+
+        let mut patch = b"BPS1".to_vec();
+        varint(rom.len(), &mut patch);
+        varint(usize::MAX >> 1, &mut patch);
+        varint(0, &mut patch);
+        // Source read of the whole ROM
+        varint((rom.len() - 1) << 2, &mut patch);
+        patch.extend_from_slice(&crc32(&rom).to_le_bytes());
+        patch.extend_from_slice(&crc32(&rom).to_le_bytes());
+        patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+
+        assert_eq!(
+            apply_patch(&rom, &patch),
+            Err(Error::InvalidPatch(format!(
+                "output size 8 doesn't match {}",
+                usize::MAX >> 1
+            )))
+        );
+    }
+}
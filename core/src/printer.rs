@@ -0,0 +1,316 @@
+use crate::hardware::{MaybeSend, VRAM_WIDTH};
+use crate::link::LinkCable;
+use alloc::{vec, vec::Vec};
+use log::*;
+
+const CMD_INIT: u8 = 0x01;
+const CMD_PRINT: u8 = 0x02;
+const CMD_DATA: u8 = 0x04;
+const CMD_STATUS: u8 = 0x0f;
+
+const STATUS_CHECKSUM: u8 = 0x01;
+const STATUS_BUSY: u8 = 0x02;
+const STATUS_FULL: u8 = 0x04;
+const STATUS_UNPROCESSED: u8 = 0x08;
+
+/// The size of the image data the printer can hold, which is 9 packets of 2 tile rows.
+const BUFFER_SIZE: usize = 0x280 * 9;
+
+/// The number of status queries the printer stays busy for after printing.
+const BUSY_QUERIES: usize = 4;
+
+/// The position in the packet of the next byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LenLo,
+    LenHi,
+    Data,
+    ChecksumLo,
+    ChecksumHi,
+    Alive,
+    Status,
+}
+
+/// The Game Boy Printer, which is connected to the serial port as a link cable.
+///
+/// The printed images are passed to the callback as the shades from 0 (white) to 3 (black),
+/// [`VRAM_WIDTH`][crate::VRAM_WIDTH] pixels per line.
+///
+/// ```rust,no_run
+/// # fn connect<'a, D: rgy::debug::Debugger + rgy::MaybeSend + 'a>(sys: &mut rgy::System<'a, D>) {
+/// sys.connect_link(rgy::Printer::new(|image: &[u8]| {
+///     println!("Printed {} lines", image.len() / rgy::VRAM_WIDTH);
+/// }));
+/// # }
+/// ```
+pub struct Printer<F> {
+    print: F,
+    state: State,
+    cmd: u8,
+    compressed: bool,
+    len: usize,
+    packet: Vec<u8>,
+    sum: u16,
+    checksum: u16,
+    status: u8,
+    busy: usize,
+    image: Vec<u8>,
+}
+
+impl<F: FnMut(&[u8])> Printer<F> {
+    /// Create a printer which passes the printed images to `print`.
+    pub fn new(print: F) -> Self {
+        Self {
+            print,
+            state: State::Magic1,
+            cmd: 0,
+            compressed: false,
+            len: 0,
+            packet: Vec::new(),
+            sum: 0,
+            checksum: 0,
+            status: 0,
+            busy: 0,
+            image: Vec::new(),
+        }
+    }
+
+    /// Process a byte from the Game Boy, returning the response.
+    fn exchange(&mut self, data: u8) -> u8 {
+        let (state, resp) = match self.state {
+            State::Magic1 if data == 0x88 => (State::Magic2, 0x00),
+            State::Magic1 => (State::Magic1, 0x00),
+            State::Magic2 if data == 0x33 => (State::Command, 0x00),
+            State::Magic2 => (State::Magic1, 0x00),
+            State::Command => {
+                self.cmd = data;
+                self.sum = data as u16;
+                (State::Compression, 0x00)
+            }
+            State::Compression => {
+                self.compressed = data & 0x01 != 0;
+                self.sum = self.sum.wrapping_add(data as u16);
+                (State::LenLo, 0x00)
+            }
+            State::LenLo => {
+                self.len = data as usize;
+                self.sum = self.sum.wrapping_add(data as u16);
+                (State::LenHi, 0x00)
+            }
+            State::LenHi => {
+                self.len |= (data as usize) << 8;
+                self.sum = self.sum.wrapping_add(data as u16);
+                self.packet.clear();
+                if self.len == 0 {
+                    (State::ChecksumLo, 0x00)
+                } else {
+                    (State::Data, 0x00)
+                }
+            }
+            State::Data => {
+                self.packet.push(data);
+                self.sum = self.sum.wrapping_add(data as u16);
+                if self.packet.len() == self.len {
+                    (State::ChecksumLo, 0x00)
+                } else {
+                    (State::Data, 0x00)
+                }
+            }
+            State::ChecksumLo => {
+                self.checksum = data as u16;
+                (State::ChecksumHi, 0x00)
+            }
+            State::ChecksumHi => {
+                self.checksum |= (data as u16) << 8;
+                (State::Alive, 0x00)
+            }
+            State::Alive => {
+                if self.checksum == self.sum {
+                    self.status &= !STATUS_CHECKSUM;
+                    self.command();
+                } else {
+                    warn!(
+                        "Printer checksum mismatch: {:04x} != {:04x}",
+                        self.checksum, self.sum
+                    );
+                    self.status |= STATUS_CHECKSUM;
+                }
+                (State::Status, 0x81)
+            }
+            State::Status => (State::Magic1, self.status()),
+        };
+
+        self.state = state;
+        resp
+    }
+
+    /// Run the command of the received packet.
+    fn command(&mut self) {
+        match self.cmd {
+            CMD_INIT => {
+                debug!("Printer initialized");
+                self.image.clear();
+                self.status = 0;
+                self.busy = 0;
+            }
+            CMD_DATA => {
+                let data = if self.compressed {
+                    decompress(&self.packet)
+                } else {
+                    self.packet.clone()
+                };
+                let room = BUFFER_SIZE - self.image.len();
+                self.image.extend_from_slice(&data[..data.len().min(room)]);
+            }
+            CMD_PRINT if self.packet.len() >= 3 => {
+                self.print_image(self.packet[2]);
+                self.image.clear();
+                self.busy = BUSY_QUERIES;
+            }
+            CMD_STATUS => {}
+            cmd => warn!("Unsupported printer command: {:02x}", cmd),
+        }
+    }
+
+    fn status(&mut self) -> u8 {
+        let mut status = self.status;
+
+        if self.busy > 0 {
+            self.busy -= 1;
+            status |= STATUS_BUSY;
+        }
+        if !self.image.is_empty() {
+            status |= STATUS_UNPROCESSED;
+        }
+        if self.image.len() >= BUFFER_SIZE {
+            status |= STATUS_FULL;
+        }
+
+        status
+    }
+
+    /// Decode the tiles in the buffer, 20 tiles per row, into the image and pass it to the callback.
+    fn print_image(&mut self, palette: u8) {
+        // Zero means the default palette
+        let palette = if palette == 0 { 0xe4 } else { palette };
+        let tiles = VRAM_WIDTH / 8;
+        let height = self.image.len() / (tiles * 16) * 8;
+        let mut image = vec![0; VRAM_WIDTH * height];
+
+        for y in 0..height {
+            for x in 0..VRAM_WIDTH {
+                let tile = (y / 8) * tiles + x / 8;
+                let off = tile * 16 + (y % 8) * 2;
+                let bit = 7 - x % 8;
+                let lo = (self.image[off] >> bit) & 1;
+                let hi = (self.image[off + 1] >> bit) & 1;
+                let color = hi << 1 | lo;
+                image[y * VRAM_WIDTH + x] = (palette >> (color * 2)) & 0x3;
+            }
+        }
+
+        debug!("Printing {} lines", height);
+        (self.print)(&image);
+    }
+}
+
+/// Expand the run-length encoded data.
+///
+/// A control byte with the MSB set repeats the next byte `(n & 0x7f) + 2` times;
+/// otherwise `n + 1` bytes follow as they are.
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+
+    while i < data.len() {
+        let n = data[i] as usize;
+        i += 1;
+
+        if n & 0x80 != 0 {
+            if let Some(&b) = data.get(i) {
+                out.extend(core::iter::repeat_n(b, (n & 0x7f) + 2));
+            }
+            i += 1;
+        } else {
+            let end = (i + n + 1).min(data.len());
+            out.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+
+    out
+}
+
+impl<F: FnMut(&[u8]) + MaybeSend> LinkCable for Printer<F> {
+    fn send(&mut self, data: u8) -> Option<u8> {
+        Some(self.exchange(data))
+    }
+
+    fn recv(&mut self, _data: u8) -> Option<u8> {
+        // The printer never supplies the clock
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::sync::Arc;
+    use spin::Mutex;
+
+    fn packet(cmd: u8, compression: u8, data: &[u8]) -> Vec<u8> {
+        let len = data.len() as u16;
+        let mut body = vec![cmd, compression, len as u8, (len >> 8) as u8];
+        body.extend_from_slice(data);
+        let sum = body.iter().fold(0u16, |s, b| s.wrapping_add(*b as u16));
+
+        let mut p = vec![0x88, 0x33];
+        p.extend(body);
+        p.extend(&[sum as u8, (sum >> 8) as u8, 0x00, 0x00]);
+        p
+    }
+
+    fn send<F: FnMut(&[u8]) + MaybeSend>(printer: &mut Printer<F>, p: &[u8]) -> (u8, u8) {
+        let resp: Vec<u8> = p.iter().map(|b| printer.send(*b).unwrap()).collect();
+        (resp[resp.len() - 2], resp[resp.len() - 1])
+    }
+
+    #[test]
+    fn print_compressed() {
+        let printed = Arc::new(Mutex::new(Vec::new()));
+        let out = printed.clone();
+        let mut printer = Printer::new(move |image: &[u8]| out.lock().push(image.to_vec()));
+
+        assert_eq!(send(&mut printer, &packet(CMD_INIT, 0, &[])), (0x81, 0x00));
+
+        // A tile row: 20 tiles of black, compressed into runs of 128, 128 and 64 bytes
+        let data = [0xfe, 0xff, 0xfe, 0xff, 0xbe, 0xff];
+        assert_eq!(
+            send(&mut printer, &packet(CMD_DATA, 1, &data)),
+            (0x81, STATUS_UNPROCESSED)
+        );
+
+        // Checksum errors are reported
+        let mut bad = packet(CMD_STATUS, 0, &[]);
+        bad[6] ^= 0xff;
+        assert_eq!(
+            send(&mut printer, &bad).1,
+            STATUS_CHECKSUM | STATUS_UNPROCESSED
+        );
+
+        let print = [0x01, 0x00, 0xe4, 0x40];
+        assert_eq!(
+            send(&mut printer, &packet(CMD_PRINT, 0, &print)),
+            (0x81, STATUS_BUSY)
+        );
+
+        let printed = printed.lock();
+        assert_eq!(printed.len(), 1);
+        assert_eq!(printed[0].len(), VRAM_WIDTH * 8);
+        assert!(printed[0].iter().all(|p| *p == 3));
+    }
+}
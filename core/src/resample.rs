@@ -0,0 +1,159 @@
+use crate::hardware::Stream;
+
+/// The number of the samples mixed from the stream for each output sample.
+const OVERSAMPLE: u32 = 4;
+
+/// The pole of the high-pass filter removing the DC offset, in 1/256 units.
+const HIGH_PASS: i32 = 255;
+
+/// Converts a [`Stream`][] to ready-to-play samples at the sample rate of the host.
+///
+/// The stream is mixed at [`OVERSAMPLE`][] times the host rate and averaged down, which cuts
+/// the harmonics of the square waves above the host rate instead of aliasing them.
+/// The output is signed 16-bit centred on zero: the stream only has positive amplitudes,
+/// so the DC offset is removed by a high-pass filter as the capacitor on the real hardware does.
+///
+/// ```rust
+/// use rgy::{Resampler, Stream};
+///
+/// struct Square(bool);
+///
+/// impl Stream for Square {
+///     fn max(&self) -> u16 {
+///         1
+///     }
+///
+///     fn next(&mut self, _rate: u32) -> u16 {
+///         self.0 = !self.0;
+///         self.0 as u16
+///     }
+/// }
+///
+/// let mut r = Resampler::new(Square(false));
+/// let mut buf = [0i16; 4096];
+/// r.fill(48000, &mut buf);
+/// // The square wave is averaged to a constant level, which settles to zero.
+/// assert_eq!(buf[0], i16::MAX / 2);
+/// assert_eq!(buf[4095], 0);
+/// ```
+pub struct Resampler<S> {
+    stream: S,
+    last_in: i32,
+    last_out: i32,
+}
+
+impl<S: Stream> Resampler<S> {
+    /// Create a new resampler of the stream.
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            last_in: 0,
+            last_out: 0,
+        }
+    }
+
+    /// Return the next sample at the sample `rate` in Hz.
+    pub fn next(&mut self, rate: u32) -> i16 {
+        let fast = rate * OVERSAMPLE;
+        let sum: u64 = (0..OVERSAMPLE).map(|_| self.stream.next(fast) as u64).sum();
+        let max = self.stream.max().max(1) as u64 * OVERSAMPLE as u64;
+        let level = (sum.min(max) * i16::MAX as u64 / max) as i32;
+
+        let out = level - self.last_in + self.last_out * HIGH_PASS / 256;
+        let out = out.max(-(i16::MAX as i32)).min(i16::MAX as i32);
+        self.last_in = level;
+        self.last_out = out;
+
+        out as i16
+    }
+
+    /// Fill `out` with the mono samples at the sample `rate` in Hz.
+    pub fn fill(&mut self, rate: u32, out: &mut [i16]) {
+        for s in out {
+            *s = self.next(rate);
+        }
+    }
+
+    /// Fill `out` with the interleaved stereo samples at the sample `rate` in Hz,
+    /// where both the channels have the same sample.
+    pub fn fill_stereo(&mut self, rate: u32, out: &mut [i16]) {
+        for frame in out.chunks_mut(2) {
+            let s = self.next(rate);
+            for c in frame {
+                *c = s;
+            }
+        }
+    }
+
+    /// Return the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Counter(u16, u32);
+
+    impl Stream for Counter {
+        fn max(&self) -> u16 {
+            3
+        }
+
+        fn next(&mut self, rate: u32) -> u16 {
+            self.1 = rate;
+            self.0 = (self.0 + 1) % 4;
+            self.0
+        }
+    }
+
+    /// The loudest samples for the given number of the calls, then silence.
+    struct Loud(u32);
+
+    impl Stream for Loud {
+        fn max(&self) -> u16 {
+            u16::MAX
+        }
+
+        fn next(&mut self, _rate: u32) -> u16 {
+            if self.0 > 0 {
+                self.0 -= 1;
+                u16::MAX
+            } else {
+                0
+            }
+        }
+    }
+
+    #[test]
+    fn resample() {
+        let mut r = Resampler::new(Counter(3, 0));
+
+        // 0, 1, 2, 3 is averaged to the half
+        assert_eq!(r.next(44100), i16::MAX / 2);
+        assert_eq!(r.into_inner().1, 44100 * OVERSAMPLE);
+
+        let mut r = Resampler::new(Counter(3, 0));
+        let mut buf = [0i16; 6];
+        r.fill_stereo(48000, &mut buf);
+        assert_eq!(buf[0], buf[1]);
+        assert_eq!(buf[2], buf[3]);
+        assert!(buf[0] > buf[2] && buf[2] > buf[4] && buf[4] > 0);
+    }
+
+    #[test]
+    fn full_scale() {
+        let mut r = Resampler::new(Loud(4096 * OVERSAMPLE));
+
+        // The loudest samples don't overflow
+        assert_eq!(r.next(48000), i16::MAX);
+
+        // The constant level decays to zero, and the fall swings to the negative side
+        let mut buf = [0i16; 4095];
+        r.fill(48000, &mut buf);
+        assert_eq!(buf[4094], 0);
+        assert_eq!(r.next(48000), -i16::MAX);
+    }
+}
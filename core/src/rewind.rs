@@ -0,0 +1,263 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// The number of the snapshots stored as deltas after each full one.
+const KEYFRAME_INTERVAL: usize = 60;
+
+/// A savestate in the rewind buffer.
+struct Snapshot {
+    /// The frame number the savestate is taken at.
+    frame: u64,
+    /// The latest keyframe at the time the savestate is taken, shared by the deltas against it.
+    key: Arc<Vec<u8>>,
+    /// The savestate XORed with the keyframe and run-length encoded, or `None` for the keyframe itself.
+    delta: Option<Vec<u8>>,
+}
+
+impl Snapshot {
+    /// Restore the full savestate.
+    fn state(&self) -> Vec<u8> {
+        match &self.delta {
+            Some(delta) => decode(&self.key, delta),
+            None => self.key.to_vec(),
+        }
+    }
+}
+
+/// The ring buffer of the savestates taken periodically to rewind the emulation.
+///
+/// Most of the state, e.g. the ROM banks and the RAM, doesn't change between the frames,
+/// so only every [`KEYFRAME_INTERVAL`][]-th savestate is kept as is, and the others as the
+/// differences from the latest keyframe, which take a small fraction of the memory.
+pub(crate) struct Rewind {
+    /// The savestates from the oldest.
+    snapshots: VecDeque<Snapshot>,
+    capacity: usize,
+    interval: u64,
+    /// The number of the deltas taken against the latest keyframe.
+    deltas: usize,
+}
+
+impl Rewind {
+    /// Keep the savestates to rewind up to `frames` frames, taking one every `interval` frames.
+    pub fn new(frames: usize, interval: usize) -> Self {
+        let interval = interval.max(1);
+
+        Self {
+            snapshots: VecDeque::new(),
+            capacity: frames.div_ceil(interval),
+            interval: interval as u64,
+            deltas: 0,
+        }
+    }
+
+    /// Check if a savestate is to be taken at the frame.
+    pub fn wants(&self, frame: u64) -> bool {
+        self.capacity > 0
+            && frame.is_multiple_of(self.interval)
+            && self.snapshots.back().map(|s| s.frame) != Some(frame)
+    }
+
+    /// Add the savestate taken at the frame, dropping the oldest one if full.
+    ///
+    /// The deltas keep their keyframe alive after the keyframe itself is dropped.
+    pub fn push(&mut self, frame: u64, state: Vec<u8>) {
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+
+        let snapshot = match self.snapshots.back() {
+            Some(last) if self.deltas < KEYFRAME_INTERVAL => {
+                self.deltas += 1;
+                Snapshot {
+                    frame,
+                    key: last.key.clone(),
+                    delta: Some(encode(&last.key, &state)),
+                }
+            }
+            _ => {
+                self.deltas = 0;
+                Snapshot {
+                    frame,
+                    key: Arc::new(state),
+                    delta: None,
+                }
+            }
+        };
+        self.snapshots.push_back(snapshot);
+    }
+
+    /// Find the latest savestate taken at or before the frame, or the oldest one if none,
+    /// dropping the newer ones.
+    pub fn seek(&mut self, frame: u64) -> Option<Vec<u8>> {
+        while self.snapshots.len() > 1 && self.snapshots.back().is_some_and(|s| s.frame > frame) {
+            self.snapshots.pop_back();
+        }
+        // The count of the deltas is lost; start over with a keyframe
+        self.deltas = KEYFRAME_INTERVAL;
+
+        self.snapshots.back().map(Snapshot::state)
+    }
+
+    /// Drop all the savestates, e.g. as the emulation is reset.
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+
+    /// The bytes taken by the savestates, counting each keyframe once.
+    #[cfg(test)]
+    fn size(&self) -> usize {
+        let mut last: Option<&Arc<Vec<u8>>> = None;
+        let mut size = 0;
+
+        for s in &self.snapshots {
+            if !last.is_some_and(|k| Arc::ptr_eq(k, &s.key)) {
+                size += s.key.len();
+                last = Some(&s.key);
+            }
+            size += s.delta.as_ref().map_or(0, |d| d.len());
+        }
+
+        size
+    }
+}
+
+/// Encode the state as the runs of the bytes equal to the keyframe and the runs of the bytes
+/// XORed with it, each prefixed by the length. The length of the state comes first,
+/// as it can differ from the keyframe, e.g. with the queued events.
+fn encode(key: &[u8], state: &[u8]) -> Vec<u8> {
+    let xor = |i: usize| state[i] ^ key.get(i).copied().unwrap_or(0);
+    let mut out = Vec::new();
+    put_len(&mut out, state.len());
+
+    let mut i = 0;
+    while i < state.len() {
+        let same = (i..state.len()).take_while(|&j| xor(j) == 0).count();
+        put_len(&mut out, same);
+        i += same;
+
+        // A single equal byte is cheaper to keep in the literal run than to start a new run
+        let start = i;
+        while i < state.len() && (xor(i) != 0 || (i + 1 < state.len() && xor(i + 1) != 0)) {
+            i += 1;
+        }
+        put_len(&mut out, i - start);
+        out.extend((start..i).map(xor));
+    }
+
+    out
+}
+
+/// Restore the state encoded by [`encode`][] against the same keyframe.
+fn decode(key: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    let len = get_len(delta, &mut pos);
+    let mut out = Vec::with_capacity(len);
+    let key_at = |i: usize| key.get(i).copied().unwrap_or(0);
+
+    while out.len() < len {
+        let same = get_len(delta, &mut pos);
+        out.extend((out.len()..out.len() + same).map(key_at));
+
+        let diff = get_len(delta, &mut pos);
+        for b in &delta[pos..pos + diff] {
+            out.push(b ^ key_at(out.len()));
+        }
+        pos += diff;
+    }
+
+    out
+}
+
+/// Append the length in LEB128.
+fn put_len(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 0x80 {
+        out.push(len as u8 | 0x80);
+        len >>= 7;
+    }
+    out.push(len as u8);
+}
+
+fn get_len(data: &[u8], pos: &mut usize) -> usize {
+    let mut len = 0;
+    let mut shift = 0;
+
+    loop {
+        let b = data[*pos];
+        *pos += 1;
+        len |= (b as usize & 0x7f) << shift;
+        if b & 0x80 == 0 {
+            return len;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::debug::NullDebugger;
+    use crate::hardware::NullHardware;
+    use crate::system::test::system;
+    use crate::system::Config;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    /// The program which turns on the LCD and keeps counting up C000.
+    const PROGRAM: &[u8] = &[
+        0x3e, 0x91, // ld a,0x91
+        0xe0, 0x40, // ldh (0x40),a
+        0x21, 0x00, 0xc0, // ld hl,0xc000
+        0x34, // inc (hl)
+        0x18, 0xfd, // jr -3
+    ];
+
+    #[test]
+    fn delta() {
+        let mut rewind = Rewind::new(200, 1);
+        let mut states = Vec::new();
+        let mut state = vec![0u8; 0x10000];
+
+        for frame in 0..200u64 {
+            state[frame as usize * 7 % 0x10000] = frame as u8;
+            state[0x8000] = !frame as u8;
+            // The length can change, e.g. with the queued events
+            state.resize(0x10000 + frame as usize % 3, 0x55);
+            rewind.push(frame, state.clone());
+            states.push(state.clone());
+        }
+
+        // Much smaller than the full states
+        assert!(rewind.size() < 200 * 0x10000 / 20, "{}", rewind.size());
+        assert_eq!(
+            encode(&states[0], &states[0]),
+            [0x80, 0x80, 0x04, 0x80, 0x80, 0x04, 0x00]
+        );
+
+        for frame in (0..200).rev().step_by(13) {
+            assert!(rewind.seek(frame).unwrap() == states[frame as usize]);
+        }
+    }
+
+    #[test]
+    fn rewind_frames() {
+        let cfg = Config::new().rewind(8, 2);
+        let mut sys = system(cfg, PROGRAM, NullHardware, NullDebugger);
+
+        let mut counts = Vec::new();
+        for _ in 0..12 {
+            sys.run_frame().unwrap();
+            counts.push(sys.mmu_get8(0xc000));
+        }
+
+        // Back to the latest snapshot at or before 3 frames ago, taken at frame 8
+        assert_eq!(sys.rewind(3), 4);
+        assert_eq!(sys.mmu_get8(0xc000), counts[7]);
+
+        // Only 8 frames are kept, from frame 6
+        assert_eq!(sys.rewind(100), 2);
+        assert_eq!(sys.mmu_get8(0xc000), counts[5]);
+        assert_eq!(sys.rewind(1), 0);
+    }
+}
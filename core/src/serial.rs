@@ -1,27 +1,81 @@
 use crate::device::IoHandler;
-use crate::hardware::HardwareHandle;
+use crate::error::Error;
 use crate::ic::Irq;
+use crate::link::LinkCable;
 use crate::mmu::{MemRead, MemWrite, Mmu};
+use crate::state::{Reader, State, Writer};
+use alloc::boxed::Box;
 use log::*;
 
-pub struct Serial {
-    hw: HardwareHandle,
+pub struct Serial<'a> {
+    link: Box<dyn LinkCable + 'a>,
     irq: Irq,
     data: u8,
     recv: u8,
     ctrl: u8,
     clock: usize,
+    bits: usize,
+    sent: Option<u8>,
 }
 
-impl Serial {
-    pub fn new(hw: HardwareHandle, irq: Irq) -> Self {
+impl<'a> Serial<'a> {
+    pub fn new(link: Box<dyn LinkCable + 'a>, irq: Irq) -> Self {
         Self {
-            hw,
+            link,
             irq,
             data: 0,
             recv: 0,
             ctrl: 0,
             clock: 0,
+            bits: 0,
+            sent: None,
+        }
+    }
+
+    /// Reset the serial port, keeping the link cable connected.
+    pub fn reset(&mut self, irq: Irq) {
+        let link = core::mem::replace(&mut self.link, Box::new(Disconnected));
+        *self = Self::new(link, irq);
+    }
+
+    /// Connect the link cable, returning the previous one.
+    pub fn connect(&mut self, link: Box<dyn LinkCable + 'a>) -> Box<dyn LinkCable + 'a> {
+        core::mem::replace(&mut self.link, link)
+    }
+
+    /// Return the byte sent to the serial port since the last call, if any.
+    pub fn take_sent(&mut self) -> Option<u8> {
+        self.sent.take()
+    }
+
+    /// The clock cycles to shift one bit.
+    fn period(&self) -> usize {
+        if cfg!(feature = "color") && self.ctrl & 0x02 != 0 {
+            // Fast clock is 262144 Hz = 16 cpu clocks
+            16
+        } else {
+            // Internal clock is 8192 Hz = 512 cpu clocks
+            512
+        }
+    }
+
+    /// Start shifting the bits, with the byte exchanged with the peer at once.
+    fn start(&mut self, recv: u8) {
+        self.sent = Some(self.data);
+        self.recv = recv;
+        self.bits = 8;
+        self.clock = self.period();
+    }
+
+    /// Returns the clock cycles until the transfer completes, or `None` if no transfer.
+    pub fn next_event(&self) -> Option<usize> {
+        if self.ctrl & 0x80 == 0 {
+            None
+        } else if self.bits == 0 {
+            // Waiting for the clocks from the peer, which is polled on every step
+            Some(0)
+        } else {
+            Some(self.clock + (self.bits - 1) * self.period())
         }
     }
 
@@ -31,36 +85,80 @@ impl Serial {
             return;
         }
 
-        if self.ctrl & 0x01 != 0 {
-            if self.clock < time {
-                debug!("Serial transfer completed");
-                self.data = self.recv;
-
-                // End of transfer
-                self.ctrl &= !0x80;
-                self.irq.serial(true);
-            } else {
-                self.clock -= time;
+        if self.bits == 0 {
+            // With the external clock, the transfer starts when the peer supplies clocks.
+            match self.link.recv(self.data) {
+                Some(data) => {
+                    debug!("Serial transfer (External): {:02x}", data);
+                    self.start(data);
+                }
+                None => return,
             }
+        }
+
+        let mut time = time;
+
+        while self.bits > 0 && time >= self.clock {
+            time -= self.clock;
+            self.clock = self.period();
+
+            // Shift out the MSB, shifting in the bit from the peer
+            self.data = self.data << 1 | self.recv >> 7;
+            self.recv <<= 1;
+            self.bits -= 1;
+        }
+
+        if self.bits > 0 {
+            self.clock -= time;
         } else {
-            if let Some(data) = self.hw.get().borrow_mut().recv_byte() {
-                self.hw.get().borrow_mut().send_byte(self.data);
-                self.data = data;
+            debug!("Serial transfer completed");
 
-                // End of transfer
-                self.ctrl &= !0x80;
-                self.irq.serial(true);
-            }
+            // End of transfer
+            self.ctrl &= !0x80;
+            self.irq.serial(true);
         }
     }
 }
 
-impl IoHandler for Serial {
+/// The placeholder of the link cable with nothing connected.
+struct Disconnected;
+
+impl LinkCable for Disconnected {
+    fn send(&mut self, _data: u8) -> Option<u8> {
+        None
+    }
+
+    fn recv(&mut self, _data: u8) -> Option<u8> {
+        None
+    }
+}
+
+impl<'a> State for Serial<'a> {
+    fn save(&self, w: &mut Writer) {
+        self.data.save(w);
+        self.recv.save(w);
+        self.ctrl.save(w);
+        self.clock.save(w);
+        self.bits.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.data.load(r)?;
+        self.recv.load(r)?;
+        self.ctrl.load(r)?;
+        self.clock.load(r)?;
+        self.bits.load(r)?;
+        Ok(())
+    }
+}
+
+impl<'a> IoHandler for Serial<'a> {
     fn on_read(&mut self, _mmu: &Mmu, addr: u16) -> MemRead {
         if addr == 0xff01 {
             MemRead::Replace(self.data)
         } else if addr == 0xff02 {
-            MemRead::Replace(self.ctrl)
+            let unused = if cfg!(feature = "color") { 0x7c } else { 0x7e };
+            MemRead::Replace(self.ctrl | unused)
         } else {
             unreachable!("Read from serial: {:04x}", addr)
         }
@@ -71,21 +169,15 @@ impl IoHandler for Serial {
             self.data = value;
             MemWrite::Block
         } else if addr == 0xff02 {
-            self.ctrl = value;
-
-            if self.ctrl & 0x80 != 0 {
-                if self.ctrl & 0x01 != 0 {
-                    debug!("Serial transfer (Internal): {:02x}", self.data);
+            self.ctrl = value & 0x83;
+            self.bits = 0;
 
-                    // Internal clock is 8192 Hz = 512 cpu clocks
-                    self.clock = 512 * 8;
+            if self.ctrl & 0x81 == 0x81 {
+                debug!("Serial transfer (Internal): {:02x}", self.data);
 
-                    // Do transfer one byte at once
-                    self.hw.get().borrow_mut().send_byte(self.data);
-                    self.recv = self.hw.get().borrow_mut().recv_byte().unwrap_or(0xff);
-                } else {
-                    debug!("Serial transfer (External): {:02x}", self.data);
-                }
+                // Nothing is connected if the peer doesn't respond
+                let recv = self.link.send(self.data).unwrap_or(0xff);
+                self.start(recv);
             }
             MemWrite::Block
         } else {
@@ -93,3 +185,38 @@ impl IoHandler for Serial {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::Device;
+    use crate::ic::Ic;
+    use alloc::vec;
+
+    #[test]
+    fn internal_clock() {
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        let ic = Device::new(Ic::new());
+        let serial = Device::new(Serial::new(Box::new(Disconnected), ic.borrow().irq()));
+        mmu.add_handler((0xff0f, 0xff0f), ic.handler());
+        mmu.add_handler((0xff01, 0xff02), serial.handler());
+
+        mmu.set8(0xff01, 0x81);
+        mmu.set8(0xff02, 0x81);
+
+        // A bit is shifted every 512 clocks, receiving 1s from the disconnected port
+        serial.borrow_mut().step(511);
+        assert_eq!(mmu.get8(0xff01), 0x81);
+        serial.borrow_mut().step(1);
+        assert_eq!(mmu.get8(0xff01), 0x03);
+
+        serial.borrow_mut().step(512 * 6);
+        assert_eq!(mmu.get8(0xff02) & 0x80, 0x80);
+        assert_eq!(mmu.get8(0xff0f) & 0x08, 0x00);
+
+        serial.borrow_mut().step(512);
+        assert_eq!(mmu.get8(0xff01), 0xff);
+        assert_eq!(mmu.get8(0xff02) & 0x80, 0x00);
+        assert_eq!(mmu.get8(0xff0f) & 0x08, 0x08);
+    }
+}
@@ -0,0 +1,329 @@
+use crate::debug::Debugger;
+use crate::error::Error;
+use crate::hardware::MaybeSend;
+use crate::state::{Reader, State, Writer};
+use crate::system::System;
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+const MAGIC: &[u8; 4] = b"RGYT";
+const VERSION: u8 = 1;
+
+/// The key of a savestate slot, either numbered or named.
+///
+/// The numbered slots are sorted before the named ones.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Slot {
+    /// The numbered slot, e.g. the quick save slots bound to the number keys.
+    Number(u32),
+    /// The named slot.
+    Name(String),
+}
+
+impl From<u32> for Slot {
+    fn from(n: u32) -> Self {
+        Slot::Number(n)
+    }
+}
+
+impl From<&str> for Slot {
+    fn from(name: &str) -> Self {
+        Slot::Name(name.to_string())
+    }
+}
+
+/// The metadata of a savestate in a slot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlotInfo {
+    /// The slot the state is saved into.
+    pub slot: Slot,
+    /// The title of the cartridge the state is saved from.
+    pub title: String,
+    /// The global checksum of the cartridge the state is saved from.
+    pub global_checksum: u16,
+    /// The time of [`Clock::clock`][crate::Clock::clock] when the state is saved, in microseconds.
+    pub timestamp: u64,
+    /// The CPU clock cycles elapsed since the power on, i.e. the play time.
+    pub cycles: u64,
+}
+
+/// The savestates kept in numbered or named slots with their metadata.
+///
+/// Each state is bound to the cartridge it's saved from by the title and the global checksum,
+/// so loading a state of another game fails instead of crashing the emulation.
+/// The whole set is encoded into a single blob by [`Slots::encode`][] to be persisted by the frontend.
+///
+/// ```rust,no_run
+/// # fn slots<'a, D: rgy::debug::Debugger + rgy::MaybeSend + 'a>(sys: &mut rgy::System<'a, D>) -> Result<(), rgy::Error> {
+/// let mut slots = rgy::Slots::new();
+/// slots.save(1, sys);
+/// slots.save("before the boss", sys);
+/// for info in slots.iter() {
+///     println!("{:?}: {} at {}", info.slot, info.title, info.timestamp);
+/// }
+/// slots.load(1, sys)?;
+/// let data = slots.encode();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Slots {
+    /// The slots sorted by the key.
+    slots: Vec<(SlotInfo, Vec<u8>)>,
+}
+
+impl Slots {
+    /// Create an empty set of slots.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode the slots encoded by [`Slots::encode`][].
+    pub fn decode(data: &[u8]) -> Result<Self, Error> {
+        let mut r = Reader::new(data);
+
+        if r.take(MAGIC.len())? != MAGIC {
+            return Err(Error::InvalidState("not a set of slots".into()));
+        }
+        let mut version = 0u8;
+        version.load(&mut r)?;
+        if version != VERSION {
+            return Err(Error::InvalidState(format!(
+                "unsupported slots version {}",
+                version
+            )));
+        }
+
+        let mut count = 0u32;
+        count.load(&mut r)?;
+
+        let mut slots = Self::new();
+        for _ in 0..count {
+            let slot = match r.tag("slot", 2)? {
+                0 => {
+                    let mut n = 0u32;
+                    n.load(&mut r)?;
+                    Slot::Number(n)
+                }
+                _ => Slot::Name(read_str(&mut r)?),
+            };
+            let title = read_str(&mut r)?;
+            let mut info = SlotInfo {
+                slot,
+                title,
+                global_checksum: 0,
+                timestamp: 0,
+                cycles: 0,
+            };
+            info.global_checksum.load(&mut r)?;
+            info.timestamp.load(&mut r)?;
+            info.cycles.load(&mut r)?;
+            let state = read_bytes(&mut r)?.to_vec();
+
+            slots.insert(info, state);
+        }
+        r.finish()?;
+
+        Ok(slots)
+    }
+
+    /// Encode all the slots.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+
+        w.put(MAGIC);
+        VERSION.save(&mut w);
+        (self.slots.len() as u32).save(&mut w);
+
+        for (info, state) in &self.slots {
+            match &info.slot {
+                Slot::Number(n) => {
+                    0u8.save(&mut w);
+                    n.save(&mut w);
+                }
+                Slot::Name(name) => {
+                    1u8.save(&mut w);
+                    write_bytes(&mut w, name.as_bytes());
+                }
+            }
+            write_bytes(&mut w, info.title.as_bytes());
+            info.global_checksum.save(&mut w);
+            info.timestamp.save(&mut w);
+            info.cycles.save(&mut w);
+            write_bytes(&mut w, state);
+        }
+
+        w.finish()
+    }
+
+    /// Save the state of the system into the slot, replacing the state in it.
+    pub fn save<'a, D>(&mut self, slot: impl Into<Slot>, sys: &System<'a, D>) -> &SlotInfo
+    where
+        D: Debugger + MaybeSend + 'a,
+    {
+        let header = sys.header();
+        let info = SlotInfo {
+            slot: slot.into(),
+            title: header.title,
+            global_checksum: header.global_checksum,
+            timestamp: sys.now(),
+            cycles: sys.cycles(),
+        };
+
+        let i = self.insert(info, sys.save_state());
+        &self.slots[i].0
+    }
+
+    /// Restore the state in the slot.
+    ///
+    /// Fails if the slot is empty, or the state is saved from another cartridge.
+    pub fn load<'a, D>(&self, slot: impl Into<Slot>, sys: &mut System<'a, D>) -> Result<(), Error>
+    where
+        D: Debugger + MaybeSend + 'a,
+    {
+        let slot = slot.into();
+        let (info, state) = self
+            .find(&slot)
+            .map(|i| &self.slots[i])
+            .ok_or_else(|| Error::InvalidState(format!("empty slot {:?}", slot)))?;
+
+        let header = sys.header();
+        if info.title != header.title || info.global_checksum != header.global_checksum {
+            return Err(Error::InvalidState(format!(
+                "the state is for another cartridge: {}",
+                info.title
+            )));
+        }
+
+        sys.load_state(state)
+    }
+
+    /// Returns the metadata of the state in the slot.
+    pub fn get(&self, slot: impl Into<Slot>) -> Option<&SlotInfo> {
+        self.find(&slot.into()).map(|i| &self.slots[i].0)
+    }
+
+    /// Returns the raw state in the slot, as saved by [`System::save_state`][].
+    pub fn state(&self, slot: impl Into<Slot>) -> Option<&[u8]> {
+        self.find(&slot.into()).map(|i| &self.slots[i].1[..])
+    }
+
+    /// Delete the state in the slot, returning `true` if there is one.
+    pub fn remove(&mut self, slot: impl Into<Slot>) -> bool {
+        match self.find(&slot.into()) {
+            Some(i) => {
+                self.slots.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the metadata of all the slots in the order of the key.
+    pub fn iter(&self) -> impl Iterator<Item = &SlotInfo> {
+        self.slots.iter().map(|(info, _)| info)
+    }
+
+    /// The number of the slots with a state.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Check if no slot has a state.
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    fn find(&self, slot: &Slot) -> Option<usize> {
+        self.slots.binary_search_by(|(i, _)| i.slot.cmp(slot)).ok()
+    }
+
+    fn insert(&mut self, info: SlotInfo, state: Vec<u8>) -> usize {
+        match self.slots.binary_search_by(|(i, _)| i.slot.cmp(&info.slot)) {
+            Ok(i) => {
+                self.slots[i] = (info, state);
+                i
+            }
+            Err(i) => {
+                self.slots.insert(i, (info, state));
+                i
+            }
+        }
+    }
+}
+
+fn write_bytes(w: &mut Writer, data: &[u8]) {
+    (data.len() as u32).save(w);
+    w.put(data);
+}
+
+fn read_bytes<'r>(r: &mut Reader<'r>) -> Result<&'r [u8], Error> {
+    let mut len = 0u32;
+    len.load(r)?;
+    r.take(len as usize)
+}
+
+fn read_str(r: &mut Reader) -> Result<String, Error> {
+    let data = read_bytes(r)?;
+    core::str::from_utf8(data)
+        .map(|s| s.to_string())
+        .map_err(|_| Error::InvalidState("invalid string".into()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::debug::NullDebugger;
+    use crate::hardware::NullHardware;
+    use crate::system::Config;
+    use alloc::vec;
+
+    fn system(title: &[u8]) -> System<'static, NullDebugger> {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+
+        let cfg = Config::new().native_speed(true).headless(true);
+        System::new(cfg, &rom, vec![0u8; 0x10000], NullHardware, NullDebugger).unwrap()
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut sys = system(b"GAME");
+        let mut slots = Slots::new();
+
+        sys.run_frame().unwrap();
+        let info = slots.save("named", &sys).clone();
+        assert_eq!(info.title, "GAME");
+        assert_eq!(info.cycles, sys.cycles());
+        slots.save(2, &sys);
+        sys.run_frame().unwrap();
+        slots.save(1, &sys);
+
+        let keys: Vec<_> = slots.iter().map(|i| i.slot.clone()).collect();
+        assert_eq!(keys, [Slot::Number(1), Slot::Number(2), "named".into()]);
+
+        slots.load("named", &mut sys).unwrap();
+        assert_eq!(sys.cycles(), info.cycles);
+        assert!(slots.load(3, &mut sys).is_err());
+
+        // Round trip through the encoding
+        let slots = Slots::decode(&slots.encode()).unwrap();
+        assert_eq!(slots.len(), 3);
+        assert_eq!(slots.get("named"), Some(&info));
+        assert!(Slots::decode(&slots.encode()[1..]).is_err());
+
+        // The state of another game is refused
+        let mut other = system(b"OTHER");
+        let cycles = other.cycles();
+        assert!(slots.load(1, &mut other).is_err());
+        assert_eq!(other.cycles(), cycles);
+
+        let mut slots = slots;
+        assert!(slots.remove(1));
+        assert!(!slots.remove(1));
+        assert_eq!(slots.len(), 2);
+    }
+}
@@ -1,12 +1,16 @@
 use alloc::boxed::Box;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use log::*;
 use spin::Mutex;
 
 use crate::device::IoHandler;
-use crate::hardware::{HardwareHandle, Stream};
+use crate::error::Error;
+use crate::hardware::{HardwareHandle, MaybeSend, Stream};
 use crate::mmu::{MemRead, MemWrite, Mmu};
+use crate::resample::Resampler;
+use crate::state::{Reader, State, Writer};
 
 trait AtomicHelper {
     type Item;
@@ -613,11 +617,45 @@ impl Stream for NoiseStream {
     }
 }
 
+/// The sound channels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    /// Channel 1, the tone with the sweep.
+    Tone1,
+    /// Channel 2, the tone.
+    Tone2,
+    /// Channel 3, the wave pattern.
+    Wave,
+    /// Channel 4, the noise.
+    Noise,
+}
+
+/// Receives a copy of the samples pulled from the sound stream, e.g. to record a WAV file
+/// or to analyze the sound emulation.
+///
+/// Set with [`System::set_audio_sink`][crate::System::set_audio_sink].
+pub trait AudioSink: MaybeSend {
+    /// Called for each sample pulled from the stream at the sample `rate` in Hz, either by the stream
+    /// passed to [`Speaker::sound_play`][crate::Speaker::sound_play] or by
+    /// [`System::run_frame_av`][crate::System::run_frame_av], which pulls at 4 times the output rate.
+    ///
+    /// `mixed` is the sample played, and `channels` are the parts of it from each channel indexed by
+    /// [`Channel`][], which add up to `mixed`. Both are in the scale of the stream, whose maximum is
+    /// [`Stream::max`][crate::Stream::max]. The muted channels are silent as they are in the output.
+    fn write(&mut self, rate: u32, mixed: u16, channels: [u16; 4]);
+}
+
 struct Mixer {
     so1_volume: usize,
     so2_volume: usize,
     so_mask: usize,
     enable: bool,
+    /// Silence the output regardless of the registers.
+    muted: bool,
+    /// The channels silenced by the user, indexed by the channel number.
+    channel_muted: [bool; 4],
+    /// The only channel to be played if any.
+    solo: Option<Channel>,
     stream: MixerStream,
 }
 
@@ -628,14 +666,27 @@ impl Mixer {
             so2_volume: 0,
             so_mask: 0,
             enable: false,
+            muted: false,
+            channel_muted: [false; 4],
+            solo: None,
             stream: MixerStream::new(),
         }
     }
 
+    fn reset(&mut self) {
+        self.so1_volume = 0;
+        self.so2_volume = 0;
+        self.so_mask = 0;
+        self.enable = false;
+        self.stream.tone1.update(None);
+        self.stream.tone2.update(None);
+        self.stream.wave.update(None);
+        self.stream.noise.update(None);
+        self.update_volume();
+    }
+
     fn setup_stream(&self, hw: &HardwareHandle) {
-        hw.get()
-            .borrow_mut()
-            .sound_play(Box::new(self.stream.clone()))
+        hw.get().lock().sound_play(Box::new(self.stream.clone()))
     }
 
     fn on_read(&mut self, addr: u16) -> MemRead {
@@ -690,7 +741,7 @@ impl Mixer {
     }
 
     fn update_volume(&self) {
-        self.stream.enable.set(self.enable);
+        self.stream.enable.set(self.enable && !self.muted);
         self.stream.tone1.volume.set(self.get_volume(0));
         self.stream.tone2.volume.set(self.get_volume(1));
         self.stream.wave.volume.set(self.get_volume(2));
@@ -698,6 +749,10 @@ impl Mixer {
     }
 
     fn get_volume(&self, id: u8) -> usize {
+        if self.channel_muted[id as usize] || self.solo.is_some_and(|c| c as u8 != id) {
+            return 0;
+        }
+
         let mask = 1 << id;
         let v1 = if self.so_mask & mask != 0 {
             self.so1_volume
@@ -764,6 +819,7 @@ struct MixerStream {
     wave: Unit<WaveStream>,
     noise: Unit<NoiseStream>,
     enable: Arc<AtomicBool>,
+    sink: Arc<Mutex<Option<Box<dyn AudioSink>>>>,
 }
 
 impl MixerStream {
@@ -774,6 +830,7 @@ impl MixerStream {
             wave: Unit::new(),
             noise: Unit::new(),
             enable: Arc::new(AtomicBool::new(false)),
+            sink: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -792,24 +849,29 @@ impl Stream for MixerStream {
     }
 
     fn next(&mut self, rate: u32) -> u16 {
-        if self.enable.get() {
-            let mut vol = 0;
-
+        let channels = if self.enable.get() {
             let (t, v) = self.tone1.next(rate);
-            vol += self.volume(t, v);
+            let tone1 = self.volume(t, v);
             let (t, v) = self.tone2.next(rate);
-            vol += self.volume(t, v);
+            let tone2 = self.volume(t, v);
             let (t, v) = self.wave.next(rate);
-            vol += self.volume(t, v);
+            let wave = self.volume(t, v);
             let (t, v) = self.noise.next(rate);
-            vol += self.volume(t, v) / 2; // Soften the noise
+            let noise = self.volume(t, v) / 2; // Soften the noise
 
-            assert!(vol <= 840, "vol = {}", vol);
-
-            vol
+            [tone1, tone2, wave, noise]
         } else {
-            0
+            [0; 4]
+        };
+
+        let vol = channels.iter().sum();
+        assert!(vol <= 840, "vol = {}", vol);
+
+        if let Some(sink) = self.sink.lock().as_mut() {
+            sink.write(rate, vol, channels);
         }
+
+        vol
     }
 }
 
@@ -819,6 +881,7 @@ pub struct Sound {
     wave: Wave,
     noise: Noise,
     mixer: Mixer,
+    resampler: Resampler<MixerStream>,
 }
 
 impl Sound {
@@ -832,9 +895,189 @@ impl Sound {
             tone2: Tone::new(),
             wave: Wave::new(),
             noise: Noise::new(),
+            resampler: Resampler::new(mixer.stream.clone()),
             mixer,
         }
     }
+
+    /// Reset all the channels, keeping the stream passed to the hardware.
+    pub fn reset(&mut self) {
+        self.tone1 = Tone::new();
+        self.tone2 = Tone::new();
+        self.wave = Wave::new();
+        self.noise = Noise::new();
+        self.mixer.reset();
+    }
+
+    /// Silence the output without affecting the emulated registers, e.g. while fast-forwarding.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.mixer.muted = muted;
+        self.mixer.update_volume();
+    }
+
+    /// Silence the channel without affecting the emulated registers.
+    pub fn set_channel_muted(&mut self, ch: Channel, muted: bool) {
+        self.mixer.channel_muted[ch as usize] = muted;
+        self.mixer.update_volume();
+    }
+
+    /// Play only the channel, or all the channels not muted if `None`.
+    pub fn set_solo(&mut self, ch: Option<Channel>) {
+        self.mixer.solo = ch;
+        self.mixer.update_volume();
+    }
+
+    /// Tee the samples pulled from the stream into the sink, or stop if `None`.
+    pub fn set_sink(&mut self, sink: Option<Box<dyn AudioSink>>) {
+        *self.mixer.stream.sink.lock() = sink;
+    }
+
+    /// Mix `count` samples resampled to the sample `rate` in Hz, appending them to `out`.
+    ///
+    /// The samples are pulled from the same channels as the stream passed to
+    /// [`Speaker::sound_play`][crate::Speaker::sound_play], so only one of them should be consumed.
+    pub fn mix_into(&mut self, rate: u32, count: usize, out: &mut Vec<i16>) {
+        out.extend((0..count).map(|_| self.resampler.next(rate)));
+    }
+}
+
+impl State for Tone {
+    fn save(&self, w: &mut Writer) {
+        self.sweep_time.save(w);
+        self.sweep_sub.save(w);
+        self.sweep_shift.save(w);
+        self.sound_len.save(w);
+        self.wave_duty.save(w);
+        self.env_init.save(w);
+        self.env_inc.save(w);
+        self.env_count.save(w);
+        self.counter.save(w);
+        self.freq.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        // The fields are checked against the registers they come from,
+        // as the streams index and multiply by them on the audio thread
+        self.sweep_time = r.bounded("sweep time", 0x7)?;
+        self.sweep_sub.load(r)?;
+        self.sweep_shift = r.bounded("sweep shift", 0x7)?;
+        self.sound_len = r.bounded("sound length", 0x1f)?;
+        self.wave_duty = r.bounded("wave duty", 0x3)?;
+        self.env_init = r.bounded("envelope", 0xf)?;
+        self.env_inc.load(r)?;
+        self.env_count = r.bounded("envelope count", 0x7)?;
+        self.counter.load(r)?;
+        self.freq = r.bounded("frequency", 0x7ff)?;
+        Ok(())
+    }
+}
+
+impl State for Wave {
+    fn save(&self, w: &mut Writer) {
+        self.enable.save(w);
+        self.sound_len.save(w);
+        self.amp_shift.get().save(w);
+        self.counter.save(w);
+        self.freq.get().save(w);
+        self.wavebuf[..].save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.enable.load(r)?;
+        self.sound_len = r.bounded("sound length", 0xff)?;
+        let amp_shift = r.bounded("amplitude shift", 0x3)?;
+        self.counter.load(r)?;
+        let freq = r.bounded("frequency", 0x7ff)?;
+        self.wavebuf[..].load(r)?;
+
+        self.amp_shift.set(amp_shift);
+        self.freq.set(freq);
+        Ok(())
+    }
+}
+
+impl State for Noise {
+    fn save(&self, w: &mut Writer) {
+        self.sound_len.save(w);
+        self.env_init.save(w);
+        self.env_inc.save(w);
+        self.env_count.save(w);
+        self.shift_freq.save(w);
+        self.step.save(w);
+        self.div_freq.save(w);
+        self.counter.save(w);
+        self.freq.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.sound_len = r.bounded("sound length", 0x1f)?;
+        self.env_init = r.bounded("envelope", 0xf)?;
+        self.env_inc.load(r)?;
+        self.env_count = r.bounded("envelope count", 0x7)?;
+        self.shift_freq = r.bounded("shift frequency", 0xf)?;
+        self.step.load(r)?;
+        self.div_freq = r.bounded("dividing ratio", 0x7)?;
+        self.counter.load(r)?;
+        self.freq.load(r)?;
+        Ok(())
+    }
+}
+
+/// The registers of the channels and the mixer, and which channels are playing.
+///
+/// The streams are played at the pace of the host, so the playing channels are restarted
+/// from the beginning on load.
+impl State for Sound {
+    fn save(&self, w: &mut Writer) {
+        self.tone1.save(w);
+        self.tone2.save(w);
+        self.wave.save(w);
+        self.noise.save(w);
+
+        let m = &self.mixer;
+        m.so1_volume.save(w);
+        m.so2_volume.save(w);
+        m.so_mask.save(w);
+        m.enable.save(w);
+        m.stream.tone1.on().save(w);
+        m.stream.tone2.on().save(w);
+        m.stream.wave.on().save(w);
+        m.stream.noise.on().save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.tone1.load(r)?;
+        self.tone2.load(r)?;
+        self.wave.load(r)?;
+        self.noise.load(r)?;
+
+        let mut on = [false; 4];
+        let m = &mut self.mixer;
+        m.so1_volume = r.bounded("SO1 volume", 0x7)?;
+        m.so2_volume = r.bounded("SO2 volume", 0x7)?;
+        m.so_mask = r.bounded("output mask", 0xff)?;
+        m.enable.load(r)?;
+        on[..].load(r)?;
+        m.update_volume();
+
+        m.stream.tone1.update(None);
+        m.stream.tone2.update(None);
+        m.stream.wave.update(None);
+        m.stream.noise.update(None);
+        if on[0] {
+            m.restart_tone1(self.tone1.clone());
+        }
+        if on[1] {
+            m.restart_tone2(self.tone2.clone());
+        }
+        if on[2] {
+            m.restart_wave(self.wave.clone());
+        }
+        if on[3] {
+            m.restart_noise(self.noise.clone());
+        }
+        Ok(())
+    }
 }
 
 impl IoHandler for Sound {
@@ -882,3 +1125,47 @@ impl IoHandler for Sound {
         MemWrite::PassThrough
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::hardware::NullHardware;
+
+    fn reload(sound: &Sound) -> Result<(), Error> {
+        let mut w = Writer::new();
+        sound.save(&mut w);
+        let state = w.finish();
+
+        let mut r = Reader::new(&state);
+        Sound::new(HardwareHandle::new(NullHardware)).load(&mut r)
+    }
+
+    #[test]
+    fn load_out_of_range() {
+        let mut sound = Sound::new(HardwareHandle::new(NullHardware));
+        sound.mixer.so1_volume = 7;
+        sound.noise.env_init = 15;
+        assert_eq!(reload(&sound), Ok(()));
+
+        // The values the registers can't hold would overflow the mixer
+        sound.mixer.so1_volume = 8;
+        assert_eq!(
+            reload(&sound),
+            Err(Error::InvalidState("SO1 volume: 8".into()))
+        );
+
+        sound.mixer.so1_volume = 7;
+        sound.noise.env_init = 16;
+        assert_eq!(
+            reload(&sound),
+            Err(Error::InvalidState("envelope: 16".into()))
+        );
+
+        sound.noise.env_init = 15;
+        sound.wave.amp_shift.set(4);
+        assert_eq!(
+            reload(&sound),
+            Err(Error::InvalidState("amplitude shift: 4".into()))
+        );
+    }
+}
@@ -0,0 +1,340 @@
+use crate::error::Error;
+use alloc::collections::VecDeque;
+use alloc::{format, string::String, vec::Vec};
+
+/// The version of the savestate format, bumped whenever the encoding of any component changes.
+///
+/// Version 1 has no header and no sections; the components follow the magic bytes directly.
+/// Version 2 adds the header and wraps each component into a tagged section.
+/// Version 3 adds the pending start of the OAM DMA transfer.
+/// Version 4 adds the window wrapping around from WX=166.
+/// Version 5 adds the part of the line drawn before the register writes in mode 3.
+pub(crate) const STATE_VERSION: u16 = 5;
+
+/// The component of the emulator whose state is saved into savestates.
+///
+/// The state is encoded as the fields in order without any tags, so `load` has to read
+/// exactly what `save` writes.
+pub(crate) trait State {
+    /// Append the state to the writer.
+    fn save(&self, w: &mut Writer);
+
+    /// Restore the state from the reader.
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error>;
+}
+
+/// The FNV-1a hash of the bytes.
+pub(crate) fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |h, b| {
+        (h ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// The buffer the states are encoded into.
+pub(crate) struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    pub fn put(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Write the section of a component: the tag, the length, and what `f` writes.
+    pub fn section(&mut self, tag: &[u8; 4], f: impl FnOnce(&mut Writer)) {
+        self.put(tag);
+        let pos = self.buf.len();
+        self.put(&[0; 4]);
+        f(self);
+        let len = (self.buf.len() - pos - 4) as u32;
+        self.buf[pos..pos + 4].copy_from_slice(&len.to_le_bytes());
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// The buffer the states are decoded from.
+pub(crate) struct Reader<'a> {
+    buf: &'a [u8],
+    /// The format version of the savestate being read.
+    version: u16,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self {
+            buf,
+            version: STATE_VERSION,
+        }
+    }
+
+    /// Read the rest as the savestate of the older format version, e.g. to migrate it.
+    pub fn set_version(&mut self, version: u16) {
+        self.version = version;
+    }
+
+    /// The format version of the savestate being read, to skip the fields the older versions don't have.
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Read the section of a component written by [`Writer::section`][] with `f`,
+    /// which has to consume the whole section.
+    ///
+    /// The savestates of version 1 have no sections, so `f` reads the component right away.
+    pub fn section<T>(
+        &mut self,
+        tag: &[u8; 4],
+        f: impl FnOnce(&mut Reader<'a>) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        if self.version < 2 {
+            return f(self);
+        }
+
+        let name = |t: &[u8]| -> String { String::from_utf8_lossy(t).trim_end().into() };
+        let found = self.take(tag.len())?;
+        if found != tag {
+            return Err(Error::InvalidState(format!(
+                "expected section {} but found {}",
+                name(tag),
+                name(found)
+            )));
+        }
+
+        let mut len = 0u32;
+        len.load(self)?;
+        let mut r = Reader {
+            buf: self.take(len as usize)?,
+            version: self.version,
+        };
+
+        let in_section = |e| match e {
+            Error::InvalidState(msg) => Error::InvalidState(format!("{}: {}", name(tag), msg)),
+            e => e,
+        };
+        let v = f(&mut r).map_err(in_section)?;
+        r.finish().map_err(in_section)?;
+        Ok(v)
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.buf.len() < len {
+            return Err(Error::InvalidState("truncated".into()));
+        }
+
+        let (data, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(data)
+    }
+
+    /// Read a tag of an enum, failing if it's out of range.
+    pub fn tag(&mut self, what: &str, count: u8) -> Result<u8, Error> {
+        let mut tag = 0u8;
+        tag.load(self)?;
+
+        if tag < count {
+            Ok(tag)
+        } else {
+            Err(Error::InvalidState(format!("{}: {}", what, tag)))
+        }
+    }
+
+    /// Read a number, failing if it's above `max`, e.g. a field of a register used as an index or a shift.
+    pub fn bounded<T>(&mut self, what: &str, max: T) -> Result<T, Error>
+    where
+        T: State + Default + PartialOrd + core::fmt::Display,
+    {
+        let mut v = T::default();
+        v.load(self)?;
+
+        if v <= max {
+            Ok(v)
+        } else {
+            Err(Error::InvalidState(format!("{}: {}", what, v)))
+        }
+    }
+
+    /// Check that the whole buffer is consumed.
+    pub fn finish(&self) -> Result<(), Error> {
+        if self.buf.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidState(format!(
+                "{} trailing bytes",
+                self.buf.len()
+            )))
+        }
+    }
+}
+
+macro_rules! int_state {
+    ($($t:ty),*) => {
+        $(
+            impl State for $t {
+                fn save(&self, w: &mut Writer) {
+                    w.put(&self.to_le_bytes());
+                }
+
+                fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+                    let mut b = [0; core::mem::size_of::<$t>()];
+                    b.copy_from_slice(r.take(core::mem::size_of::<$t>())?);
+                    *self = <$t>::from_le_bytes(b);
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+int_state!(u8, u16, u32, u64, i16);
+
+impl State for usize {
+    fn save(&self, w: &mut Writer) {
+        (*self as u64).save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        let mut v = 0u64;
+        v.load(r)?;
+        *self = v as usize;
+        Ok(())
+    }
+}
+
+impl State for bool {
+    fn save(&self, w: &mut Writer) {
+        (*self as u8).save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        *self = r.tag("bool", 2)? != 0;
+        Ok(())
+    }
+}
+
+/// The memory, which has to have the same size as the saved one.
+impl State for Vec<u8> {
+    fn save(&self, w: &mut Writer) {
+        self.len().save(w);
+        w.put(self);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        let mut len = 0usize;
+        len.load(r)?;
+
+        if len != self.len() {
+            return Err(Error::InvalidState(format!(
+                "memory size {} while expecting {}",
+                len,
+                self.len()
+            )));
+        }
+
+        self.copy_from_slice(r.take(len)?);
+        Ok(())
+    }
+}
+
+/// The fixed number of elements, e.g. the banks of the memory.
+impl<T: State> State for [T] {
+    fn save(&self, w: &mut Writer) {
+        for v in self {
+            v.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        for v in self {
+            v.load(r)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: State + Default> State for VecDeque<T> {
+    fn save(&self, w: &mut Writer) {
+        self.len().save(w);
+        for v in self {
+            v.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        let mut len = 0usize;
+        len.load(r)?;
+
+        self.clear();
+        for _ in 0..len {
+            let mut v = T::default();
+            v.load(r)?;
+            self.push_back(v);
+        }
+        Ok(())
+    }
+}
+
+impl<T: State + Default> State for Option<T> {
+    fn save(&self, w: &mut Writer) {
+        self.is_some().save(w);
+        if let Some(v) = self {
+            v.save(w);
+        }
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        let mut some = false;
+        some.load(r)?;
+
+        *self = if some {
+            let mut v = T::default();
+            v.load(r)?;
+            Some(v)
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn round_trip() {
+        let mut w = Writer::new();
+        0x1234u16.save(&mut w);
+        true.save(&mut w);
+        Some(7usize).save(&mut w);
+        vec![1u8, 2, 3].save(&mut w);
+        [0x10u8, 0x20][..].save(&mut w);
+        let buf = w.finish();
+
+        let (mut a, mut b, mut c, mut d, mut e) =
+            (0u16, false, None::<usize>, vec![0u8; 3], [0u8; 2]);
+        let mut r = Reader::new(&buf);
+        a.load(&mut r).unwrap();
+        b.load(&mut r).unwrap();
+        c.load(&mut r).unwrap();
+        d.load(&mut r).unwrap();
+        e[..].load(&mut r).unwrap();
+        r.finish().unwrap();
+        assert_eq!(
+            (a, b, c, d, e),
+            (0x1234, true, Some(7), vec![1, 2, 3], [0x10, 0x20])
+        );
+
+        // The memory size has to match
+        let mut r = Reader::new(&buf[12..]);
+        assert!(vec![0u8; 2].load(&mut r).is_err());
+        assert!(Reader::new(&buf[..1]).take(2).is_err());
+    }
+}
@@ -1,22 +1,81 @@
+use crate::boot::Model;
+use crate::buffers::{Buf, Buffers};
+use crate::cart::{Header, Validation};
+#[cfg(feature = "cgb")]
 use crate::cgb::Cgb;
-use crate::cpu::Cpu;
-use crate::debug::Debugger;
-use crate::device::Device;
+use crate::cheat::{Cheat, Cheats, RamSearch};
+use crate::cpu::{Cpu, CpuState};
+#[cfg(feature = "debugger")]
+use crate::debug::{
+    Access, AccessLog, Breakpoints, CallStack, Condition, Coverage, Frame, MemAccess, ProfileEntry,
+    Profiler,
+};
+use crate::debug::{Break, Debugger, Events, Trace};
+use crate::device::{Device, Region};
+#[cfg(feature = "debugger")]
+use crate::disasm::inst_len;
 use crate::dma::Dma;
-use crate::fc::FreqControl;
-use crate::gpu::Gpu;
-use crate::hardware::{Hardware, HardwareHandle};
+use crate::error::Error;
+use crate::fc::{FreqControl, Pacing};
+use crate::gpu::{Gpu, Palettes, Sprite, TileMap};
+use crate::hardware::{
+    FrameSink, Hardware, HardwareHandle, Key, LineSink, MaybeSend, Pixel, PixelFormat, KEYS,
+    VRAM_HEIGHT, VRAM_WIDTH,
+};
 use crate::ic::Ic;
 use crate::joypad::Joypad;
-use crate::mbc::Mbc;
-use crate::mmu::Mmu;
+#[cfg(feature = "serial")]
+use crate::link::{HardwareLink, LinkCable};
+use crate::mbc::{Mapper, Mbc};
+use crate::mmu::{Builtin, Clock, MemHandler, Mmu};
+use crate::rewind::Rewind;
+#[cfg(feature = "serial")]
 use crate::serial::Serial;
-use crate::sound::Sound;
+#[cfg(feature = "sound")]
+use crate::sound::{AudioSink, Channel, Sound};
+use crate::state::{Reader, State, Writer, STATE_VERSION};
 use crate::timer::Timer;
 use log::*;
+use spin::Mutex;
 
-use alloc::vec::Vec;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
 use alloc::vec;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::time::Duration;
+
+/// CPU cycles taken by a frame.
+pub(crate) const CYCLES_PER_FRAME: u64 = 70224;
+
+/// The maximum clock cycles run at once in HALT or deferred by the peripherals, which is a line of the LCD.
+const IDLE_CYCLES_MAX: usize = 456;
+
+/// The magic bytes at the beginning of savestates, followed by the header.
+const STATE_MAGIC: &[u8] = b"RGY\0";
+
+/// The magic bytes at the beginning of savestates of version 1, which have no header.
+const STATE_MAGIC_V1: &[u8] = b"RGYS";
+
+/// CPU cycles taken by the CGB speed switch.
+#[cfg(feature = "color")]
+const SPEED_SWITCH_CYCLES: usize = 8200;
+
+/// Event reported by [`System::poll_event`][].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PollEvent {
+    /// The emulator is running.
+    Running,
+    /// A frame is completed, i.e. all the lines are passed to [`Screen::vram_update`][crate::Screen::vram_update].
+    FrameReady,
+    /// The game sent a byte to the serial port.
+    SerialByte(u8),
+    /// A breakpoint or a watchpoint is hit.
+    Break(Break),
+    /// [`Clock::sched`][crate::Clock::sched] requested to stop the emulation.
+    Exit,
+}
 
 /// Configuration of the emulator.
 pub struct Config {
@@ -28,6 +87,65 @@ pub struct Config {
     pub(crate) delay_unit: u64,
     /// Don't adjust CPU frequency.
     pub(crate) native_speed: bool,
+    /// How to keep the CPU frequency.
+    pub(crate) pacing: Pacing,
+    /// How the cartridge header is checked.
+    pub(crate) header_validation: Validation,
+    /// Run the boot ROM instead of starting from the state it leaves.
+    pub(crate) boot_rom: bool,
+    /// The model whose boot ROM state is set when the boot ROM is skipped.
+    pub(crate) model: Model,
+    /// Force MBC1 multicart wiring on or off instead of detecting it from the ROM.
+    pub(crate) mbc1_multicart: Option<bool>,
+    /// Lock up the CPU on invalid opcodes instead of returning an error.
+    pub(crate) lock_on_invalid_opcode: bool,
+    /// Call the debugger on every instruction with the CPU state.
+    pub(crate) trace: bool,
+    /// Pass the hardware events to the debugger.
+    pub(crate) events: bool,
+    /// Track subroutine calls in a shadow call stack.
+    #[cfg(feature = "debugger")]
+    pub(crate) call_stack: bool,
+    /// The number of entries kept in the memory access log.
+    #[cfg(feature = "debugger")]
+    pub(crate) access_log_size: usize,
+    /// Keep the bytes sent to the serial port for `System::serial_output`.
+    #[cfg(feature = "serial")]
+    pub(crate) serial_capture: bool,
+    /// Keep the whole frame in memory.
+    pub(crate) frame_buffer: bool,
+    /// Pixel format of the rendered lines.
+    pub(crate) pixel_format: PixelFormat,
+    /// Run the GPU timing without rendering the lines.
+    pub(crate) headless: bool,
+    /// Weight of the previous frame when blending frames, out of 256.
+    pub(crate) frame_blending: u8,
+    /// Colors of the DMG shades.
+    pub(crate) dmg_palette: [u32; 4],
+    /// Render the lines with the pixel FIFO pipeline.
+    pub(crate) pixel_fifo: bool,
+    /// The granularity in pixels of the register writes in the middle of a line.
+    pub(crate) mid_line_writes: usize,
+    /// Emulate the OAM corruption bug.
+    pub(crate) oam_bug: bool,
+    /// The number of frames which can be rewound.
+    pub(crate) rewind_frames: usize,
+    /// The interval in frames to take savestates for rewinding.
+    pub(crate) rewind_interval: usize,
+    /// Run the cartridge RTC on the emulated clock cycles.
+    pub(crate) deterministic: bool,
+    /// Skip the cycles in HALT at once up to the next event.
+    pub(crate) batch_halt: bool,
+    /// Run the peripherals only when their next events are due.
+    pub(crate) schedule: bool,
+    /// The number of frames to skip rendering after each rendered frame.
+    pub(crate) frame_skip: usize,
+    /// The number of frames to skip rendering out of every cycle while fast-forwarding.
+    pub(crate) fast_forward_skip: usize,
+    /// The length of the frame skipping cycle in frames while fast-forwarding.
+    pub(crate) fast_forward_cycle: usize,
+    /// Mute the sound while fast-forwarding.
+    pub(crate) fast_forward_mute: bool,
 }
 
 impl Config {
@@ -39,6 +157,37 @@ impl Config {
             sample: freq / 1000,
             delay_unit: 10,
             native_speed: false,
+            pacing: Pacing::DelayLoop,
+            header_validation: Validation::Warn,
+            boot_rom: true,
+            model: Model::default(),
+            mbc1_multicart: None,
+            lock_on_invalid_opcode: true,
+            trace: false,
+            events: false,
+            #[cfg(feature = "debugger")]
+            call_stack: false,
+            #[cfg(feature = "debugger")]
+            access_log_size: 0x1000,
+            #[cfg(feature = "serial")]
+            serial_capture: false,
+            frame_buffer: true,
+            pixel_format: PixelFormat::Rgb888,
+            headless: false,
+            frame_blending: 0,
+            dmg_palette: [0xdddddd, 0xaaaaaa, 0x888888, 0x555555],
+            pixel_fifo: false,
+            mid_line_writes: 1,
+            oam_bug: false,
+            rewind_frames: 0,
+            rewind_interval: 1,
+            deterministic: false,
+            batch_halt: true,
+            schedule: true,
+            frame_skip: 0,
+            fast_forward_skip: 0,
+            fast_forward_cycle: 1,
+            fast_forward_mute: true,
         }
     }
 
@@ -65,174 +214,2908 @@ impl Config {
         self.native_speed = native;
         self
     }
+
+    /// Set how to keep the CPU frequency unless running at native speed (default [`Pacing::DelayLoop`][]).
+    pub fn pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// Set how the cartridge header is checked on loading the ROM (default [`Validation::Warn`][]).
+    ///
+    /// With [`Validation::Reject`][], corrupt or truncated ROM files are refused
+    /// with [`Error::InvalidHeader`][] instead of crashing in the middle of the game.
+    pub fn header_validation(mut self, validation: Validation) -> Self {
+        self.header_validation = validation;
+        self
+    }
+
+    /// Run the built-in boot ROM on power-on (default `true`).
+    ///
+    /// If `false`, the game starts right at 0100 with the CPU registers and the I/O ports
+    /// set to the values the boot ROM of [`Config::model`][] leaves.
+    pub fn boot_rom(mut self, boot_rom: bool) -> Self {
+        self.boot_rom = boot_rom;
+        self
+    }
+
+    /// Set the model whose boot ROM state is set when [`Config::boot_rom`][] is disabled
+    /// (default [`Model::Cgb`][] with the `color` feature, otherwise [`Model::Dmg`][]).
+    ///
+    /// Only the initial state differs; the hardware emulated is still chosen by the `color` feature.
+    pub fn model(mut self, model: Model) -> Self {
+        self.model = model;
+        self
+    }
+
+    /// Force MBC1 multicart (MBC1M) wiring on or off.
+    ///
+    /// By default, multicarts are detected from the ROM image.
+    pub fn mbc1_multicart(mut self, multicart: bool) -> Self {
+        self.mbc1_multicart = Some(multicart);
+        self
+    }
+
+    /// Set the behaviour on invalid opcodes.
+    ///
+    /// If `true` (default), the CPU locks up as the hardware does.
+    /// If `false`, [`System::poll`][] returns [`Error::InvalidOpcode`][].
+    pub fn lock_on_invalid_opcode(mut self, lock: bool) -> Self {
+        self.lock_on_invalid_opcode = lock;
+        self
+    }
+
+    /// Pass the CPU state to [`Debugger::trace`][] before every instruction.
+    pub fn trace(mut self, trace: bool) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// Pass the hardware events, e.g. interrupts, LCD mode changes and bank switches,
+    /// to [`Debugger::on_event`][].
+    pub fn events(mut self, events: bool) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Accumulate the bytes sent to the serial port for [`System::serial_output`][],
+    /// e.g. the text printed by test ROMs, without implementing [`Link`][crate::Link].
+    #[cfg(feature = "serial")]
+    pub fn serial_capture(mut self, capture: bool) -> Self {
+        self.serial_capture = capture;
+        self
+    }
+
+    /// Keep the whole frame in memory for [`System::frame`][] (default `true`).
+    ///
+    /// If `false`, the lines are only streamed to [`Screen::vram_update`][crate::Screen::vram_update] as they are
+    /// rendered, which saves the frame buffers on memory-constrained targets.
+    pub fn frame_buffer(mut self, frame_buffer: bool) -> Self {
+        self.frame_buffer = frame_buffer;
+        self
+    }
+
+    /// Set the pixel format of the lines passed to [`Screen::vram_update`][crate::Screen::vram_update] and [`System::frame`][].
+    ///
+    /// The GPU encodes the pixels in this format as it renders, so no conversion pass is needed.
+    pub fn pixel_format(mut self, format: PixelFormat) -> Self {
+        self.pixel_format = format;
+        self
+    }
+
+    /// Skip rendering the lines, e.g. to run tests or bots faster.
+    ///
+    /// The GPU still keeps its timing and raises interrupts as usual,
+    /// but nothing is passed to [`Screen::vram_update`][crate::Screen::vram_update].
+    pub fn headless(mut self, headless: bool) -> Self {
+        self.headless = headless;
+        self
+    }
+
+    /// Blend each frame with the previous ones to simulate the slow response of the DMG LCD.
+    ///
+    /// Games rely on the ghosting for transparency effects such as flickering sprites.
+    /// `persistence` is the weight of the previous frame out of 256; `0` (default) disables blending.
+    pub fn frame_blending(mut self, persistence: u8) -> Self {
+        self.frame_blending = persistence;
+        self
+    }
+
+    /// Set the colors of the four DMG shades in `0xRRGGBB`, from the lightest to the darkest.
+    ///
+    /// The default is grayscale. Use e.g. `[0x9bbc0f, 0x8bac0f, 0x306230, 0x0f380f]` for the classic green.
+    pub fn dmg_palette(mut self, shades: [u32; 4]) -> Self {
+        self.dmg_palette = shades;
+        self
+    }
+
+    /// Render the lines pixel by pixel with the fetcher and FIFO pipeline of the hardware.
+    ///
+    /// The length of mode 3 varies with the fine scroll, the window and the sprites as on the hardware,
+    /// and register writes in the middle of a line take effect from the next pixel.
+    /// Slower than the default, which renders a whole line at once.
+    pub fn pixel_fifo(mut self, fifo: bool) -> Self {
+        self.pixel_fifo = fifo;
+        self
+    }
+
+    /// Apply the writes to LCDC, SCY, SCX, BGP, WX and the CGB background palettes in the middle of
+    /// a line from the pixel being drawn, rounded down to a multiple of `granularity` pixels
+    /// (default `1`), with the default renderer.
+    ///
+    /// This is enough for the wobble, split scroll and shading effects without the cost of
+    /// [`Config::pixel_fifo`][]. Zero latches the registers once at the end of mode 3 instead.
+    /// The sprites always use the values at the end of mode 3.
+    pub fn mid_line_writes(mut self, granularity: usize) -> Self {
+        self.mid_line_writes = granularity;
+        self
+    }
+
+    /// Emulate the DMG OAM corruption bug (default `false`).
+    ///
+    /// Incrementing or decrementing a 16-bit register pointing to `FE00-FEFF`, or accessing
+    /// `FE00-FEFF`, while the GPU scans OAM corrupts a row of OAM as the hardware does. CGB doesn't have the bug,
+    /// so the flag is ignored with the `color` feature.
+    pub fn oam_bug(mut self, oam_bug: bool) -> Self {
+        self.oam_bug = !cfg!(feature = "color") && oam_bug;
+        self
+    }
+
+    /// Track CALL, RST, RET and interrupts in a shadow call stack.
+    ///
+    /// The stack is available from [`System::call_stack`][] and reported to
+    /// [`Debugger::on_call`][] and [`Debugger::on_return`][].
+    #[cfg(feature = "debugger")]
+    pub fn call_stack(mut self, call_stack: bool) -> Self {
+        self.call_stack = call_stack;
+        self
+    }
+
+    /// Set the number of entries kept in the memory access log.
+    ///
+    /// Once the log is full, the oldest entries are dropped.
+    #[cfg(feature = "debugger")]
+    pub fn access_log_size(mut self, size: usize) -> Self {
+        self.access_log_size = size;
+        self
+    }
+
+    /// Keep savestates to rewind up to `frames` frames by [`System::rewind`][],
+    /// taking one every `interval` frames.
+    ///
+    /// Most savestates are kept as the differences from the latest full one, taking a few kilobytes each.
+    /// A larger interval saves memory at the cost of coarser rewinding. `0` frames disables rewinding.
+    pub fn rewind(mut self, frames: usize, interval: usize) -> Self {
+        self.rewind_frames = frames;
+        self.rewind_interval = interval;
+        self
+    }
+
+    /// Make the emulation depend only on the ROM and the inputs, so that it's reproduced bit-exactly.
+    ///
+    /// The real-time clock of the cartridge runs on the emulated clock cycles
+    /// instead of [`Clock::clock`][crate::Clock::clock], which is then only used to pace the emulation.
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    /// Run the cycles the CPU spends in HALT at once, up to the next event of the peripherals.
+    ///
+    /// The emulation goes exactly the same, only in fewer steps, and each batch is reported
+    /// to [`Clock::idle`][crate::Clock::idle] so the host can sleep. Enabled by default.
+    pub fn batch_halt(mut self, batch: bool) -> Self {
+        self.batch_halt = batch;
+        self
+    }
+
+    /// Defer running the GPU, the timer and the serial port until their next events are due,
+    /// instead of running them on every memory cycle.
+    ///
+    /// The peripherals catch up before their registers are accessed, so the emulation goes
+    /// exactly the same. Enabled by default.
+    pub fn schedule(mut self, schedule: bool) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    /// Skip rendering `n` out of every `n + 1` frames, e.g. to keep up on slow targets.
+    ///
+    /// The GPU still keeps its timing and raises interrupts in the skipped frames,
+    /// and [`PollEvent::FrameReady`][] is raised for every frame, leaving the last rendered frame on the screen.
+    pub fn frame_skip(mut self, n: usize) -> Self {
+        self.frame_skip = n;
+        self
+    }
+
+    /// Skip rendering `skip` out of every `cycle` frames while fast-forwarding by [`System::set_fast_forward`][].
+    ///
+    /// The skipped frames still run and raise [`PollEvent::FrameReady`][], leaving the last rendered frame on the screen.
+    pub fn fast_forward_skip(mut self, skip: usize, cycle: usize) -> Self {
+        self.fast_forward_skip = skip;
+        self.fast_forward_cycle = cycle.max(1);
+        self
+    }
+
+    /// Mute the sound while fast-forwarding by [`System::set_fast_forward`][]. Enabled by default.
+    pub fn fast_forward_mute(mut self, mute: bool) -> Self {
+        self.fast_forward_mute = mute;
+        self
+    }
 }
 
 /// Represents the entire emulator context.
-pub struct System<D> {
+///
+/// With the `threads` feature, the context is `Send` as the hardware and the debugger are,
+/// so the emulation can run on a dedicated thread while another thread owns the display.
+pub struct System<'a, D> {
     cfg: Config,
-    hw: HardwareHandle,
-    fc: FreqControl,
+    hw: HardwareHandle<'a>,
+    fc: FreqControl<'a>,
     cpu: Cpu,
-    mmu: Option<Mmu>,
+    cycles: Arc<Mutex<u64>>,
+    frames: u64,
+    fast_forward: bool,
+    rewind: Rewind,
+    mmu: Option<Mmu<'a>>,
+    events: VecDeque<PollEvent>,
+    frame: Buf<'a, u32>,
+    #[cfg(feature = "serial")]
+    serial_output: Vec<u8>,
     dbg: Device<D>,
+    #[cfg(feature = "debugger")]
+    breaks: Device<Breakpoints>,
+    #[cfg(feature = "debugger")]
+    calls: CallStack,
+    #[cfg(feature = "debugger")]
+    log: Option<Arc<Mutex<AccessLog>>>,
+    #[cfg(feature = "debugger")]
+    profiler: Option<Profiler>,
+    #[cfg(feature = "debugger")]
+    coverage: Option<Coverage>,
+    cheats: Device<Cheats>,
+    /// The memory handlers of the custom devices added by the user, registered again on reset.
+    mem_handlers: Vec<((u16, u16), Arc<dyn MemHandler + 'a>)>,
+    /// The hardware events reported by the peripherals, passed to the debugger after each step.
+    hooks: Events,
+    mbc: Device<Mbc<'a>>,
+    #[cfg(feature = "sound")]
+    sound: Device<Sound>,
+    /// The fraction of a sample carried over to the next frame by `run_frame_av`, in CPU cycles times the rate.
+    #[cfg(feature = "sound")]
+    sample_rem: u64,
     ic: Device<Ic>,
-    gpu: Device<Gpu>,
-    joypad: Device<Joypad>,
+    #[cfg(feature = "cgb")]
+    cgb: Device<Cgb<'a>>,
+    gpu: Device<Gpu<'a>>,
+    joypad: Device<Joypad<'a>>,
     timer: Device<Timer>,
-    serial: Device<Serial>,
+    #[cfg(feature = "serial")]
+    serial: Device<Serial<'a>>,
     dma: Device<Dma>,
+    clock: Arc<Mutex<Peripherals<'a>>>,
 }
 
-impl<D> System<D>
+impl<'a, D> System<'a, D>
 where
-    D: Debugger + 'static,
+    D: Debugger + MaybeSend + 'a,
 {
     /// Create a new emulator context.
-    pub fn new<T>(cfg: Config, rom: &[u8], ram: Vec<u8>, hw: T, dbg: D) -> Self
+    ///
+    /// The hardware and the debugger can be borrowed for the lifetime of the context,
+    /// e.g. `&mut hw`, so the caller gets them back when the context is dropped.
+    pub fn new<T>(cfg: Config, rom: &[u8], ram: Vec<u8>, hw: T, dbg: D) -> Result<Self, Error>
     where
-        T: Hardware + 'static,
+        T: Hardware + 'a,
     {
-        info!("Initializing...");
-
         let hw = HardwareHandle::new(hw);
+        let mbc = Mbc::new(hw.clone(), rom.to_vec().into(), &cfg)?;
+        let bufs = Buffers::alloc(ram, &cfg);
 
-        let mut fc = FreqControl::new(hw.clone(), &cfg);
-
-        let dbg = Device::mediate(dbg);
-        let cpu = Cpu::new();
-        let mut mmu = Mmu::new(ram);
-        let sound = Device::new(Sound::new(hw.clone()));
-        let ic = Device::new(Ic::new());
-        let irq = ic.borrow().irq().clone();
-        let gpu = Device::new(Gpu::new(hw.clone(), irq.clone()));
-        let joypad = Device::new(Joypad::new(hw.clone(), irq.clone()));
-        let timer = Device::new(Timer::new(irq.clone()));
-        let serial = Device::new(Serial::new(hw.clone(), irq.clone()));
-        let mbc = Device::new(Mbc::new(hw.clone(), rom.to_vec()));
-        let cgb = Device::new(Cgb::new());
-        let dma = Device::new(Dma::new());
+        Ok(Self::with_mbc(cfg, bufs, hw, dbg, mbc))
+    }
 
-        mmu.add_handler((0x0000, 0xffff), dbg.handler());
+    /// Create a new emulator context which borrows the ROM instead of copying it.
+    ///
+    /// Once created, the emulator doesn't allocate as long as [`Config::frame_buffer`][] is
+    /// disabled and the frames are rendered by [`System::run_frame_into`][], so it runs with
+    /// the ROM in flash and a small heap on microcontrollers.
+    pub fn borrow_rom<T>(
+        cfg: Config,
+        rom: &'a [u8],
+        ram: Vec<u8>,
+        hw: T,
+        dbg: D,
+    ) -> Result<Self, Error>
+    where
+        T: Hardware + 'a,
+    {
+        let hw = HardwareHandle::new(hw);
+        let mbc = Mbc::new(hw.clone(), rom.into(), &cfg)?;
+        let bufs = Buffers::alloc(ram, &cfg);
 
-        mmu.add_handler((0xc000, 0xdfff), cgb.handler());
-        mmu.add_handler((0xff4d, 0xff4d), cgb.handler());
-        mmu.add_handler((0xff56, 0xff56), cgb.handler());
-        mmu.add_handler((0xff70, 0xff70), cgb.handler());
+        Ok(Self::with_mbc(cfg, bufs, hw, dbg, mbc))
+    }
 
-        mmu.add_handler((0x0000, 0x7fff), mbc.handler());
-        mmu.add_handler((0xff50, 0xff50), mbc.handler());
-        mmu.add_handler((0xa000, 0xbfff), mbc.handler());
-        mmu.add_handler((0xff10, 0xff3f), sound.handler());
+    /// Create a new emulator context which borrows the ROM and runs on the memory provided by the caller.
+    ///
+    /// The frame buffer and the frame blending are enabled only if their buffers are given in `bufs`,
+    /// whatever [`Config::frame_buffer`][] and [`Config::frame_blending`][] say. Apart from the cartridge RAM,
+    /// the CGB work RAM if not given, and the bookkeeping allocated here, the emulator doesn't allocate
+    /// as long as the frames are rendered by [`System::run_frame_into`][], also across [`System::reset`][].
+    pub fn with_buffers<T>(
+        mut cfg: Config,
+        rom: &'a [u8],
+        mut bufs: Buffers<'a>,
+        hw: T,
+        dbg: D,
+    ) -> Result<Self, Error>
+    where
+        T: Hardware + 'a,
+    {
+        bufs.clear();
+        cfg.frame_buffer = !bufs.frame.is_empty();
+        if bufs.prev.is_empty() {
+            cfg.frame_blending = 0;
+        }
 
-        mmu.add_handler((0xff46, 0xff46), dma.handler());
+        let hw = HardwareHandle::new(hw);
+        let mbc = Mbc::new(hw.clone(), rom.into(), &cfg)?;
 
-        mmu.add_handler((0x8000, 0x9fff), gpu.handler());
-        mmu.add_handler((0xff40, 0xff55), gpu.handler());
-        mmu.add_handler((0xff68, 0xff6b), gpu.handler());
+        Ok(Self::with_mbc(cfg, bufs, hw, dbg, mbc))
+    }
 
-        mmu.add_handler((0xff0f, 0xff0f), ic.handler());
-        mmu.add_handler((0xffff, 0xffff), ic.handler());
-        mmu.add_handler((0xff00, 0xff00), joypad.handler());
-        mmu.add_handler((0xff04, 0xff07), timer.handler());
-        mmu.add_handler((0xff01, 0xff02), serial.handler());
+    /// Create a new emulator context which uses a custom memory bank controller.
+    ///
+    /// The ROM is only used to read the cartridge header;
+    /// all the cartridge memory accesses are handled by `mapper`.
+    pub fn with_mapper<T, M>(
+        cfg: Config,
+        rom: &[u8],
+        mapper: M,
+        ram: Vec<u8>,
+        hw: T,
+        dbg: D,
+    ) -> Result<Self, Error>
+    where
+        T: Hardware + 'a,
+        M: Mapper + 'a,
+    {
+        let hw = HardwareHandle::new(hw);
+        let mbc = Mbc::with_mapper(rom, Box::new(mapper), &cfg)?;
+        let bufs = Buffers::alloc(ram, &cfg);
 
-        dbg.borrow_mut().init(&mmu);
+        Ok(Self::with_mbc(cfg, bufs, hw, dbg, mbc))
+    }
 
-        info!("Starting...");
+    #[allow(unused_mut)]
+    fn with_mbc(
+        cfg: Config,
+        mut bufs: Buffers<'a>,
+        hw: HardwareHandle<'a>,
+        dbg: D,
+        mbc: Mbc<'a>,
+    ) -> Self {
+        info!("Initializing...");
 
-        fc.reset();
+        #[cfg(feature = "cgb")]
+        bufs.alloc_wram();
 
-        let mmu = Some(mmu);
+        let fc = FreqControl::new(hw.clone(), &cfg);
+        let ic = Device::new(Ic::new());
+        let irq = ic.borrow().irq().clone();
+        let gpu = Device::new(Gpu::with_buffers(
+            hw.clone(),
+            irq.clone(),
+            &cfg,
+            bufs.vram,
+            bufs.drawn,
+            bufs.prev,
+        ));
+        let timer = Device::new(Timer::new(irq.clone()));
+        #[cfg(feature = "serial")]
+        let serial = Device::new(Serial::new(Box::new(HardwareLink(hw.clone())), irq.clone()));
+        let dma = Device::mediate(Dma::new());
+        let clock = Peripherals::new(
+            &dma,
+            &gpu,
+            &timer,
+            #[cfg(feature = "serial")]
+            &serial,
+            cfg.schedule,
+        );
 
-        Self {
-            cfg,
-            hw,
+        let mut sys = Self {
+            hw: hw.clone(),
             fc,
-            cpu,
-            mmu,
-            dbg,
+            cpu: Cpu::new(),
+            cycles: Arc::new(Mutex::new(0)),
+            frames: 0,
+            fast_forward: false,
+            rewind: Rewind::new(cfg.rewind_frames, cfg.rewind_interval),
+            mmu: None,
+            events: VecDeque::new(),
+            frame: bufs.frame,
+            #[cfg(feature = "serial")]
+            serial_output: Vec::new(),
+            dbg: Device::mediate(dbg),
+            #[cfg(feature = "debugger")]
+            breaks: Device::mediate(Breakpoints::new()),
+            #[cfg(feature = "debugger")]
+            calls: CallStack::new(),
+            #[cfg(feature = "debugger")]
+            log: None,
+            #[cfg(feature = "debugger")]
+            profiler: None,
+            #[cfg(feature = "debugger")]
+            coverage: None,
+            cheats: Device::mediate(Cheats::new()),
+            mem_handlers: Vec::new(),
+            hooks: Events::new(cfg.events && debugging::<D>()),
+            mbc: Device::new(mbc),
+            #[cfg(feature = "sound")]
+            sound: Device::new(Sound::new(hw.clone())),
+            #[cfg(feature = "sound")]
+            sample_rem: 0,
             ic,
+            #[cfg(feature = "cgb")]
+            cgb: Device::new(Cgb::with_wram(hw.clone(), bufs.wram)),
             gpu,
-            joypad,
+            joypad: Device::new(Joypad::new(hw, irq)),
             timer,
+            #[cfg(feature = "serial")]
             serial,
             dma,
+            clock,
+            cfg,
+        };
+
+        if sys.cfg.deterministic {
+            sys.mbc.borrow_mut().set_clock(sys.cycles.clone());
+        }
+        sys.power_on(bufs.memory);
+        if !sys.cfg.boot_rom {
+            sys.skip_boot();
+        }
+        sys
+    }
+
+    /// Reset the emulator as if the power is cycled.
+    ///
+    /// The CPU, the memory and all the peripherals are reset and the boot ROM runs again.
+    /// The cartridge keeps its RAM, and the hardware, the debugger, breakpoints and cheats are kept.
+    /// The memory is reused, so the memory provided by [`System::with_buffers`][] stays in use.
+    pub fn reset(&mut self) {
+        info!("Resetting...");
+
+        let ic = Device::new(Ic::new());
+        let irq = ic.borrow().irq().clone();
+
+        let (vram, drawn, prev) = self.gpu.borrow_mut().take_buffers();
+        let mut bufs = Buffers {
+            memory: self.mmu.take().map_or_else(Buf::empty, Mmu::into_ram),
+            vram,
+            #[cfg(feature = "cgb")]
+            wram: self.cgb.borrow_mut().take_wram(),
+            drawn,
+            frame: Buf::empty(),
+            prev,
+        };
+        bufs.clear();
+
+        self.gpu = Device::new(Gpu::with_buffers(
+            self.hw.clone(),
+            irq.clone(),
+            &self.cfg,
+            bufs.vram,
+            bufs.drawn,
+            bufs.prev,
+        ));
+        self.joypad = Device::new(Joypad::new(self.hw.clone(), irq.clone()));
+        self.timer = Device::new(Timer::new(irq.clone()));
+        #[cfg(feature = "serial")]
+        self.serial.borrow_mut().reset(irq);
+        self.dma = Device::mediate(Dma::new());
+        self.clock = Peripherals::new(
+            &self.dma,
+            &self.gpu,
+            &self.timer,
+            #[cfg(feature = "serial")]
+            &self.serial,
+            self.cfg.schedule,
+        );
+        self.ic = ic;
+        #[cfg(feature = "cgb")]
+        {
+            self.cgb = Device::new(Cgb::with_wram(self.hw.clone(), bufs.wram));
+        }
+        #[cfg(feature = "sound")]
+        self.sound.borrow_mut().reset();
+        self.mbc.borrow_mut().reset();
+
+        self.cpu = Cpu::new();
+        *self.cycles.lock() = 0;
+        if self.cfg.deterministic {
+            self.mbc.borrow_mut().set_clock(self.cycles.clone());
         }
+        self.frames = 0;
+        self.rewind.clear();
+        #[cfg(feature = "debugger")]
+        {
+            self.calls = CallStack::new();
+        }
+        self.events.clear();
+
+        self.power_on(bufs.memory);
+        if !self.cfg.boot_rom {
+            self.skip_boot();
+        }
+    }
+
+    /// Unmap the boot ROM and set the state it leaves at 0100.
+    fn skip_boot(&mut self) {
+        let model = self.cfg.model;
+        let title: Vec<u8> = (0x134..0x144).map(|addr| self.mmu_get8(addr)).collect();
+        let state = model.cpu_state(&self.header(), &title);
+
+        self.mmu_set8(0xff50, 0x01);
+        for (addr, value) in model.io() {
+            self.mmu_set8(addr, value);
+        }
+        self.timer.borrow_mut().set_div(model.div());
+        self.cpu.set_state(&state);
     }
 
-    fn step(&mut self, mut mmu: Mmu, gpu_enabled: bool) -> Mmu {
+    /// Replace the cartridge with another ROM image while keeping the rest of the system running.
+    ///
+    /// The memory bank controller of the new cartridge starts from its initial state,
+    /// and its RAM is loaded by [`Persistence::load_ram`][crate::Persistence::load_ram]
+    /// after the RAM of the old one is saved. The hardware, the configuration, breakpoints and cheats are kept.
+    /// The rewind history is cleared as it belongs to the old cartridge.
+    ///
+    /// Call [`System::reset`][] afterwards to boot the new game from scratch. If the ROM is invalid,
+    /// the error is returned and the old cartridge stays.
+    pub fn swap_rom(&mut self, rom: &[u8]) -> Result<(), Error> {
+        info!("Swapping the cartridge...");
+
+        // Save the RAM of the old cartridge before the new one loads its RAM
+        self.mbc.borrow_mut().flush_ram();
+        let mbc = Mbc::new(self.hw.clone(), rom.to_vec().into(), &self.cfg)?;
+        self.mbc.borrow_mut().swap(mbc);
+        if self.cfg.deterministic {
+            self.mbc.borrow_mut().set_clock(self.cycles.clone());
+        }
+        self.rewind.clear();
+
+        Ok(())
+    }
+
+    fn power_on(&mut self, ram: Buf<'a, u8>) {
+        let mut mmu = Mmu::with_ram(ram);
+
+        self.ic.borrow().set_events(self.hooks.clone());
+        self.gpu.borrow_mut().set_events(self.hooks.clone());
+        self.dma.borrow_mut().set_events(self.hooks.clone());
+        self.mbc.borrow_mut().set_events(self.hooks.clone());
+
+        mmu.add_builtin((0x0000, 0x7fff), Builtin::Cheats(self.cheats.handler()));
+        if debugging::<D>() {
+            mmu.add_handler((0x0000, 0xffff), self.dbg.handler());
+        }
+        #[cfg(feature = "debugger")]
+        mmu.add_builtin((0x0000, 0xffff), Builtin::Breaks(self.breaks.handler()));
+        mmu.add_builtin((0x0000, 0xfe9f), Builtin::DmaBus(self.dma.handler()));
+        for (range, handler) in &self.mem_handlers {
+            mmu.add_device(*range, handler.clone());
+        }
+
+        #[cfg(feature = "cgb")]
         {
+            mmu.add_builtin((0xc000, 0xdfff), Builtin::Cgb(self.cgb.handler()));
+            mmu.add_builtin((0xff4d, 0xff4d), Builtin::Cgb(self.cgb.handler()));
+            mmu.add_builtin((0xff56, 0xff56), Builtin::Cgb(self.cgb.handler()));
+            mmu.add_builtin((0xff70, 0xff70), Builtin::Cgb(self.cgb.handler()));
+        }
+
+        mmu.add_builtin((0x0000, 0x7fff), Builtin::Mbc(self.mbc.handler()));
+        mmu.add_builtin((0xff50, 0xff50), Builtin::Mbc(self.mbc.handler()));
+        mmu.add_builtin((0xa000, 0xbfff), Builtin::Mbc(self.mbc.handler()));
+        #[cfg(feature = "sound")]
+        mmu.add_builtin((0xff10, 0xff3f), Builtin::Sound(self.sound.handler()));
+
+        mmu.add_builtin((0xff46, 0xff46), Builtin::Dma(self.dma.handler()));
+
+        mmu.add_builtin((0x8000, 0x9fff), Builtin::Gpu(self.gpu.handler()));
+        mmu.add_builtin((0xfe00, 0xfeff), Builtin::Gpu(self.gpu.handler()));
+        mmu.add_builtin((0xff40, 0xff55), Builtin::Gpu(self.gpu.handler()));
+        mmu.add_builtin((0xff68, 0xff6b), Builtin::Gpu(self.gpu.handler()));
+
+        mmu.add_builtin((0xff0f, 0xff0f), Builtin::Ic(self.ic.handler()));
+        mmu.add_builtin((0xffff, 0xffff), Builtin::Ic(self.ic.handler()));
+        mmu.add_builtin((0xff00, 0xff00), Builtin::Joypad(self.joypad.handler()));
+        mmu.add_builtin((0xff04, 0xff07), Builtin::Timer(self.timer.handler()));
+        #[cfg(feature = "serial")]
+        mmu.add_builtin((0xff01, 0xff02), Builtin::Serial(self.serial.handler()));
+
+        #[cfg(feature = "debugger")]
+        mmu.set_access_log(self.log.clone());
+        mmu.set_clock(Some(self.clock.clone()));
+
+        if debugging::<D>() {
+            self.dbg.borrow_mut().init(&mmu);
+        }
+
+        info!("Starting...");
+
+        self.fc.reset();
+
+        self.mmu = Some(mmu);
+    }
+
+    fn step(&mut self, mmu: &mut Mmu, sink: Option<&mut dyn LineSink>) -> Result<(), Error> {
+        if self.cpu.is_stopped() {
+            self.wait_stopped();
+            return Ok(());
+        }
+
+        #[cfg(feature = "debugger")]
+        if self.breaks.borrow_mut().check_pc(&self.cpu, mmu) {
+            return Ok(());
+        }
+
+        if debugging::<D>() {
             let mut dbg = self.dbg.borrow_mut();
             dbg.check_signal();
             dbg.take_cpu_snapshot(self.cpu.clone());
-            dbg.on_decode(&mmu);
+            dbg.on_decode(mmu);
+            dbg.write_back(&mut self.cpu, mmu);
+            if self.cfg.trace {
+                dbg.trace(&Trace::new(&self.cpu, mmu, self.cycles()));
+            }
         }
 
-        let mut time = self.cpu.execute(&mut mmu);
+        #[cfg(feature = "debugger")]
+        let prev = if self.cfg.call_stack {
+            Some((self.cpu.fetch(mmu).0, self.cpu.get_pc(), self.cpu.get_sp()))
+        } else {
+            None
+        };
 
-        time += self.cpu.check_interrupt(&mut mmu, &self.ic);
+        let oam_bug = self.cfg.oam_bug && oam_bug_trigger(self.cpu.fetch(mmu).0, &self.cpu);
 
-        self.dma.borrow_mut().step(&mut mmu);
-        if gpu_enabled {
-            self.gpu.borrow_mut().step(time, &mut mmu);
+        #[cfg(feature = "debugger")]
+        let profiled = self.profiler.as_ref().map(|_| {
+            let pc = self.cpu.get_pc();
+            (self.mbc.borrow().rom_bank(pc), pc)
+        });
+
+        #[cfg(feature = "debugger")]
+        if let Some(c) = &mut self.coverage {
+            let pc = self.cpu.get_pc();
+            // The CPU in HALT stays before the next instruction without executing it
+            if pc <= 0x7fff && !self.cpu.is_halted() {
+                let len = inst_len(self.cpu.fetch(mmu).0);
+                c.mark(self.mbc.borrow().rom_bank(0x4000), pc, len);
+            }
+        }
+
+        #[cfg(feature = "debugger")]
+        {
+            if let Some(log) = &self.log {
+                log.lock().begin(self.cpu.get_pc(), self.cycles());
+            }
+            self.breaks.borrow_mut().set_active(true);
+        }
+        let res = self.cpu.execute(mmu);
+        let mut ticked = mmu.take_ticked();
+        #[cfg(feature = "debugger")]
+        {
+            self.breaks.borrow_mut().set_active(false);
+            if let Some(log) = &self.log {
+                log.lock().end();
+            }
+        }
+
+        let mut time = match res {
+            Ok(time) => time,
+            Err(Error::InvalidOpcode { pc, code }) => {
+                if debugging::<D>() {
+                    self.dbg.borrow_mut().on_invalid_opcode(pc, code);
+                }
+
+                if !self.cfg.lock_on_invalid_opcode {
+                    return Err(Error::InvalidOpcode { pc, code });
+                }
+
+                self.cpu.lock();
+                4
+            }
+            Err(e) => return Err(e),
+        };
+
+        if self.cpu.take_stop() {
+            time += self.stop();
+        }
+
+        #[cfg(feature = "debugger")]
+        self.breaks.borrow_mut().check_watch(&self.cpu, mmu);
+
+        if oam_bug {
+            self.clock.lock().sync(true);
+            self.gpu.borrow().corrupt_oam(mmu);
+        }
+
+        #[cfg(feature = "debugger")]
+        if let Some((code, pc, sp)) = prev {
+            let mut dbg = self.dbg.borrow_mut();
+            self.calls.exec(code, pc, sp, &self.cpu, &mut *dbg);
+        }
+
+        #[cfg(feature = "debugger")]
+        let pc = self.cpu.get_pc();
+        let itime = self.cpu.check_interrupt(mmu, &self.ic);
+        #[cfg(feature = "debugger")]
+        if itime > 0 && self.cfg.call_stack {
+            let mut dbg = self.dbg.borrow_mut();
+            self.calls.interrupt(pc, &self.cpu, &mut *dbg);
+        }
+        time += itime;
+        ticked += mmu.take_ticked();
+
+        // Nothing happens in HALT until the next event of the peripherals
+        if self.cfg.batch_halt && self.cpu.is_halted() && !self.cpu.is_stopped() && ticked == 0 {
+            time = self.clock.lock().idle_cycles();
+            self.hw.get().lock().idle(time);
+        }
+        *self.cycles.lock() += time as u64;
+
+        #[cfg(feature = "debugger")]
+        if let (Some(p), Some((bank, pc))) = (&mut self.profiler, profiled) {
+            p.add(bank, pc, time as u64);
+        }
+
+        // The memory accesses have already run the peripherals for the cycles they spent.
+        if !self.cpu.is_stopped() {
+            self.clock.lock().tick(time.saturating_sub(ticked), mmu);
+        }
+
+        if debugging::<D>() && self.cfg.events {
+            let cycles = self.cycles();
+            let mut dbg = self.dbg.borrow_mut();
+            self.hooks.drain(|e| dbg.on_event(e, cycles));
+        }
+        // The peripherals only have something to report after they have run
+        let stepped = self.clock.lock().take_stepped();
+        if stepped {
+            self.gpu.borrow_mut().flush(sink);
+        }
+        if self.clock.lock().take_vblank() {
+            if self.cfg.frame_buffer {
+                self.frame.copy_from_slice(self.gpu.borrow().frame());
+            }
+            self.events.push_back(PollEvent::FrameReady);
+            self.frames += 1;
+            self.gpu.borrow_mut().set_skip(self.skip_frame());
+
+            for (addr, value) in self.cheats.borrow().writes() {
+                mmu.set8(addr, value);
+            }
+        }
+        if stepped {
+            #[cfg(feature = "serial")]
+            if let Some(b) = self.serial.borrow_mut().take_sent() {
+                if self.cfg.serial_capture {
+                    self.serial_output.push(b);
+                }
+                self.events.push_back(PollEvent::SerialByte(b));
+            }
+            self.joypad.borrow_mut().poll();
         }
-        self.timer.borrow_mut().step(time);
-        self.serial.borrow_mut().step(time);
-        self.joypad.borrow_mut().poll();
 
-        if !self.cfg.native_speed {
+        if !self.cfg.native_speed && !self.fast_forward {
             self.fc.adjust(time);
         }
 
-        mmu
+        Ok(())
+    }
+
+    /// Handle STOP executed by the CPU, returning the extra clock cycles it takes.
+    fn stop(&mut self) -> usize {
+        self.clock.lock().sync(true);
+        self.timer.borrow_mut().reset_div();
+
+        // STOP switches the CPU speed instead if armed through KEY1
+        #[cfg(feature = "color")]
+        if self.cgb.borrow_mut().try_switch_speed() {
+            let double = self.cgb.borrow().double_speed();
+            info!("Double speed: {}", double);
+            self.hooks.push(crate::debug::HwEvent::SpeedSwitch(double));
+            self.cpu.resume();
+            return SPEED_SWITCH_CYCLES;
+        }
+
+        info!("Entering STOP mode");
+
+        if self.gpu.borrow_mut().stop() {
+            let mut clock = self.clock.lock();
+            clock.vblank = true;
+            clock.stepped = true;
+        }
+        self.hw.get().lock().stop_mode(true);
+
+        0
+    }
+
+    /// Spend a cycle in the stop state, where the peripherals are stopped
+    /// until any of the selected keys is pressed.
+    fn wait_stopped(&mut self) {
+        if self.joypad.borrow().pressed() {
+            info!("Leaving STOP mode");
+            self.cpu.resume();
+            self.hw.get().lock().stop_mode(false);
+        }
+        self.joypad.borrow_mut().poll();
+
+        *self.cycles.lock() += 4;
+        if !self.cfg.native_speed && !self.fast_forward {
+            self.fc.adjust(4);
+        }
     }
 
     /// Run a single step of emulation.
-    /// This function needs to be called repeatedly until it returns `false`.
-    /// Returning `false` indicates the end of emulation, and the functions shouldn't be called again.
-    pub fn poll(&mut self, gpu_enabled: bool) -> bool {
-        if !self.hw.get().borrow_mut().sched() {
-            return false;
+    /// This function needs to be called repeatedly until it returns `Ok(false)`.
+    /// Returning `Ok(false)` indicates the end of emulation, and the functions shouldn't be called again.
+    /// Returning an error indicates the emulator cannot proceed, e.g. because the CPU hit an invalid opcode.
+    ///
+    /// Use [`System::poll_event`][] to get notified of frames, serial transfers and breakpoints.
+    pub fn poll(&mut self) -> Result<bool, Error> {
+        Ok(self.poll_event()? != PollEvent::Exit)
+    }
+
+    /// Run a single step of emulation and report what happened.
+    ///
+    /// If a step raises multiple events, the pending events are returned one by one
+    /// by the following calls before the emulation proceeds.
+    /// If a breakpoint or a watchpoint is hit, calling the function again resumes the emulation.
+    pub fn poll_event(&mut self) -> Result<PollEvent, Error> {
+        self.poll_into(None)
+    }
+
+    fn poll_into(&mut self, sink: Option<&mut dyn LineSink>) -> Result<PollEvent, Error> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+
+        if !self.hw.get().lock().sched() {
+            return Ok(PollEvent::Exit);
         }
 
-        let mmu = self.mmu.take().unwrap();
-        self.mmu = Some(self.step(mmu, gpu_enabled));
+        self.advance(sink)?;
 
-        true
+        Ok(self.events.pop_front().unwrap_or(PollEvent::Running))
     }
 
-    /// Read a byte from the given address in the MMU
-    pub fn mmu_get8(&self, addr: u16) -> u8 {
-        self.mmu.as_ref().expect("memory not initialized").get8(addr)
+    /// Run a single step of emulation, queueing the events it raises.
+    fn advance(&mut self, sink: Option<&mut dyn LineSink>) -> Result<(), Error> {
+        let mut mmu = self.mmu.take().unwrap();
+        let res = self.step(&mut mmu, sink);
+        self.mmu = Some(mmu);
+        res?;
+
+        if self.rewind.wants(self.frames) {
+            let state = self.save_state();
+            self.rewind.push(self.frames, state);
+        }
+
+        #[cfg(feature = "debugger")]
+        if let Some(b) = self.breaks.borrow_mut().take_hit() {
+            self.events.push_front(PollEvent::Break(b));
+        }
+
+        Ok(())
     }
 
-    /// Read a byte from the given address in the MMU
-    pub fn mmu_get16(&self, addr: u16) -> u16 {
-        self.mmu.as_ref().expect("memory not initialized").get16(addr)
+    /// Run the emulation for at least the given clock cycles at once, returning the cycles actually run.
+    ///
+    /// The batch overshoots by up to an instruction, or a batch of HALT cycles with [`Config::batch_halt`][].
+    /// [`Clock::sched`][crate::Clock::sched] is only checked once at the beginning, and the events other than
+    /// [`PollEvent::Break`][] are discarded. The batch stops early if a breakpoint or a watchpoint is hit,
+    /// or the hardware requests to stop, leaving the event to the next call of [`System::poll_event`][].
+    pub fn run_cycles(&mut self, cycles: u64) -> Result<u64, Error> {
+        let start = self.cycles();
+
+        self.events.retain(|e| matches!(e, PollEvent::Break(_)));
+        if !self.events.is_empty() {
+            return Ok(0);
+        }
+        if !self.hw.get().lock().sched() {
+            self.events.push_back(PollEvent::Exit);
+            return Ok(0);
+        }
+
+        while self.cycles() - start < cycles {
+            self.advance(None)?;
+
+            self.events.retain(|e| matches!(e, PollEvent::Break(_)));
+            if !self.events.is_empty() {
+                break;
+            }
+        }
+
+        Ok(self.cycles() - start)
     }
 
-    /// dump the array backing the memory
-    pub fn mmu_dump(&self) -> &[u8] {
-        self.mmu.as_ref().expect("memory not initialized").dump()
+    /// Run the emulation for the given time at the CPU frequency set by [`Config::freq`][],
+    /// returning the clock cycles actually run. See [`System::run_cycles`][].
+    pub fn run_for(&mut self, time: Duration) -> Result<u64, Error> {
+        let cycles = time.as_nanos() * self.cfg.freq as u128 / 1_000_000_000;
+
+        self.run_cycles(cycles as u64)
     }
-}
 
-/// Run the emulator with the given configuration.
-pub fn run<T: Hardware + 'static>(cfg: Config, rom: &[u8], hw: T) {
-    run_inner(cfg, rom, hw, Debugger::empty())
-}
+    /// Run the emulation until the GPU completes the next frame.
+    ///
+    /// Returns [`PollEvent::FrameReady`][] once the frame is available from [`System::frame`][].
+    /// While the LCD is off, the function returns after the time of a frame elapses.
+    /// Stops early returning [`PollEvent::Break`][] or [`PollEvent::Exit`][];
+    /// the other events are discarded.
+    pub fn run_frame(&mut self) -> Result<PollEvent, Error> {
+        self.run_frame_inner(None)
+    }
 
-/// Run the emulator with the given configuration and debugger.
-pub fn run_debug<T: Hardware + 'static, D: Debugger + 'static>(
-    cfg: Config,
-    rom: &[u8],
-    hw: T,
-    dbg: D,
-) {
-    run_inner(cfg, rom, hw, dbg)
-}
+    /// Run the emulation until the GPU completes the next frame, rendering the lines directly into `fb`.
+    ///
+    /// `fb` holds [`VRAM_WIDTH`][crate::VRAM_WIDTH] x [`VRAM_HEIGHT`][crate::VRAM_HEIGHT] pixels
+    /// in row-major order, encoded in [`Config::pixel_format`][]. The lines aren't passed to
+    /// [`Screen::vram_update`][crate::Screen::vram_update]. Otherwise the same as [`System::run_frame`][].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fb` is shorter than the screen.
+    pub fn run_frame_into<P: Pixel>(&mut self, fb: &mut [P]) -> Result<PollEvent, Error> {
+        assert!(
+            fb.len() >= VRAM_WIDTH * VRAM_HEIGHT,
+            "frame buffer too short: {}",
+            fb.len()
+        );
 
-fn run_inner<T: Hardware + 'static, D: Debugger + 'static>(cfg: Config, rom: &[u8], hw: T, dbg: D) {
-    let mut sys = System::new(cfg, rom, vec![0u8; 0x10000], hw, dbg);
-    while sys.poll(true) {}
+        self.run_frame_inner(Some(&mut FrameSink(fb)))
+    }
+
+    /// Run the emulation until the GPU completes the next frame, then await `sleep` to keep the CPU frequency.
+    ///
+    /// The future returned by `sleep` is awaited for the time the emulation is ahead of
+    /// [`Clock::clock`][crate::Clock::clock], instead of busy-waiting, to cooperate with async executors,
+    /// e.g. a timer of embassy or `setTimeout` in the browser. The future can also wait for
+    /// the audio output to drain. Use with [`Pacing::External`][], which leaves the pacing to this function.
+    /// Otherwise the same as [`System::run_frame`][].
+    pub async fn run_frame_async<F, W>(&mut self, mut sleep: F) -> Result<PollEvent, Error>
+    where
+        F: FnMut(Duration) -> W,
+        W: Future<Output = ()>,
+    {
+        let start = self.cycles();
+        let event = self.run_frame_inner(None)?;
+
+        if !self.cfg.native_speed && !self.fast_forward {
+            let lead = self.fc.lead(self.cycles() - start);
+            if lead > 0 {
+                sleep(Duration::from_micros(lead)).await;
+            }
+        }
+
+        Ok(event)
+    }
+
+    /// Run the emulation for a frame, rendering the pixels into `fb` and mixing the sound
+    /// for the cycles run into `samples` at the sample `rate` in Hz.
+    ///
+    /// Suited to hosts driving the emulator from their event loop, e.g. `requestAnimationFrame`
+    /// in the browser, with [`Pacing::External`][]: the samples are resampled by [`Resampler`][crate::Resampler]
+    /// and appended to `samples`, ready to play. The stream passed to
+    /// [`Speaker::sound_play`][crate::Speaker::sound_play] shouldn't be consumed at the same time. The fractions of a sample are carried over
+    /// to the next call, so the number of the samples matches the emulated time.
+    /// Otherwise the same as [`System::run_frame_into`][].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fb` is shorter than the screen.
+    #[cfg(feature = "sound")]
+    pub fn run_frame_av<P: Pixel>(
+        &mut self,
+        fb: &mut [P],
+        rate: u32,
+        samples: &mut Vec<i16>,
+    ) -> Result<PollEvent, Error> {
+        let start = self.cycles();
+        let event = self.run_frame_into(fb)?;
+
+        let total = (self.cycles() - start) * rate as u64 + self.sample_rem;
+        self.sample_rem = total % self.cfg.freq;
+        self.sound
+            .borrow_mut()
+            .mix_into(rate, (total / self.cfg.freq) as usize, samples);
+
+        Ok(event)
+    }
+
+    fn run_frame_inner(&mut self, mut sink: Option<&mut dyn LineSink>) -> Result<PollEvent, Error> {
+        let start = self.cycles();
+
+        loop {
+            match self.poll_into(sink.as_mut().map(|s| &mut **s as &mut dyn LineSink))? {
+                PollEvent::Running | PollEvent::SerialByte(_) => {}
+                e => return Ok(e),
+            }
+
+            if self.cycles() - start >= CYCLES_PER_FRAME {
+                return Ok(PollEvent::FrameReady);
+            }
+        }
+    }
+
+    /// Return the pixels of the last completed frame in row-major order,
+    /// which has [`VRAM_WIDTH`][crate::VRAM_WIDTH] x [`VRAM_HEIGHT`][crate::VRAM_HEIGHT] pixels.
+    ///
+    /// The frame is kept whichever way the lines are consumed, i.e. through
+    /// [`Screen::vram_update`][crate::Screen::vram_update] or [`System::run_frame_into`][],
+    /// and the lines of the frame in progress don't show up until it completes,
+    /// so it can be taken at any time for screenshots or the thumbnails of save states.
+    /// The frames skipped while fast-forwarding leave the last rendered one.
+    ///
+    /// Empty if [`Config::frame_buffer`][] is disabled.
+    pub fn frame(&self) -> &[u32] {
+        &self.frame
+    }
+
+    /// Return the bytes sent to the serial port since the start or [`System::clear_serial_output`][].
+    ///
+    /// Empty unless [`Config::serial_capture`][] is enabled.
+    #[cfg(feature = "serial")]
+    pub fn serial_output(&self) -> &[u8] {
+        &self.serial_output
+    }
+
+    /// Discard the bytes returned by [`System::serial_output`][].
+    #[cfg(feature = "serial")]
+    pub fn clear_serial_output(&mut self) {
+        self.serial_output.clear();
+    }
+
+    /// Change the colors of the four DMG shades in `0xRRGGBB`, from the lightest to the darkest.
+    ///
+    /// Takes effect from the next line rendered. See [`Config::dmg_palette`][].
+    pub fn set_dmg_palette(&mut self, shades: [u32; 4]) {
+        self.cfg.dmg_palette = shades;
+        self.gpu.borrow_mut().set_shades(shades);
+    }
+
+    /// Turn fast-forwarding on or off.
+    ///
+    /// While fast-forwarding, the emulation runs as fast as the host allows,
+    /// skipping frames and muting the sound as set by [`Config::fast_forward_skip`][]
+    /// and [`Config::fast_forward_mute`][].
+    pub fn set_fast_forward(&mut self, on: bool) {
+        if self.fast_forward == on {
+            return;
+        }
+
+        info!("Fast-forward: {}", on);
+
+        self.fast_forward = on;
+        #[cfg(feature = "sound")]
+        self.sound
+            .borrow_mut()
+            .set_muted(on && self.cfg.fast_forward_mute);
+        if !on {
+            self.gpu.borrow_mut().set_skip(false);
+            self.fc.reset();
+        }
+    }
+
+    /// Returns `true` if fast-forwarding.
+    pub fn fast_forward(&self) -> bool {
+        self.fast_forward
+    }
+
+    /// Mute or unmute the sound channel, e.g. to debug the sound or to listen to a part of the music.
+    ///
+    /// The channel keeps running, so the registers and the savestates aren't affected.
+    #[cfg(feature = "sound")]
+    pub fn set_channel_muted(&mut self, ch: Channel, muted: bool) {
+        self.sound.borrow_mut().set_channel_muted(ch, muted);
+    }
+
+    /// Play only the sound channel, or all the channels not muted by
+    /// [`System::set_channel_muted`][] if `None`.
+    #[cfg(feature = "sound")]
+    pub fn set_solo(&mut self, ch: Option<Channel>) {
+        self.sound.borrow_mut().set_solo(ch);
+    }
+
+    /// Tee the sound output into the sink, e.g. to record a WAV file, or stop if `None`.
+    ///
+    /// The sink receives the samples as they are pulled from the stream, mixed and per channel.
+    /// See [`AudioSink`][crate::AudioSink].
+    #[cfg(feature = "sound")]
+    pub fn set_audio_sink(&mut self, sink: Option<Box<dyn AudioSink>>) {
+        self.sound.borrow_mut().set_sink(sink);
+    }
+
+    /// Check if rendering of the frame which just started is to be skipped.
+    fn skip_frame(&self) -> bool {
+        let cfg = &self.cfg;
+
+        let skip = !self.frames.is_multiple_of(cfg.frame_skip as u64 + 1);
+        let fast_forward = self.fast_forward
+            && (self.frames % cfg.fast_forward_cycle as u64) < cfg.fast_forward_skip as u64;
+
+        skip || fast_forward
+    }
+
+    /// Connect the link cable to the serial port, e.g. one end of [`LocalLink`][crate::LocalLink].
+    ///
+    /// Replaces the default cable, which passes the bytes to [`Link::send_byte`][crate::Link::send_byte]
+    /// and [`Link::recv_byte`][crate::Link::recv_byte]. The cable is kept connected across [`System::reset`][].
+    #[cfg(feature = "serial")]
+    pub fn connect_link<L: LinkCable + 'a>(&mut self, link: L) {
+        self.serial.borrow_mut().connect(Box::new(link));
+    }
+
+    /// Disconnect the link cable connected by [`System::connect_link`][],
+    /// going back to the default one through [`Hardware`][].
+    #[cfg(feature = "serial")]
+    pub fn disconnect_link(&mut self) {
+        let link = Box::new(HardwareLink(self.hw.clone()));
+        self.serial.borrow_mut().connect(link);
+    }
+
+    /// Press or release the key.
+    ///
+    /// The key is treated as pressed while either this function or
+    /// [`Input::joypad_pressed`][crate::Input::joypad_pressed] reports it as pressed.
+    pub fn set_button(&mut self, key: Key, pressed: bool) {
+        self.joypad.borrow_mut().set_button(key, pressed);
+    }
+
+    /// Press the keys in the bits of the key set and release the others.
+    pub(crate) fn set_keys(&mut self, keys: u8) {
+        for (i, key) in KEYS.iter().enumerate() {
+            self.set_button(key.clone(), keys & 1 << i != 0);
+        }
+    }
+
+    /// Map a custom device into the address range (inclusive), e.g. a debug console port,
+    /// the registers of a flash cartridge or a mailbox to communicate with the host.
+    ///
+    /// The handler sees the accesses before the built-in peripherals and the cartridge, so it can
+    /// take them over by [`MemRead::Replace`][crate::mmu::MemRead::Replace] or
+    /// [`MemWrite::Block`][crate::mmu::MemWrite::Block], or pass them through.
+    /// The handlers added earlier come first. The handler is kept across [`System::reset`][].
+    pub fn add_mem_handler<T>(&mut self, range: (u16, u16), handler: T)
+    where
+        T: MemHandler + 'a,
+    {
+        let handler: Arc<dyn MemHandler + 'a> = Arc::new(handler);
+        self.mmu
+            .as_mut()
+            .expect("memory not initialized")
+            .add_device(range, handler.clone());
+        self.mem_handlers.push((range, handler));
+    }
+
+    /// Register a cheat code, which is enabled initially. Returns the id of the code.
+    pub fn add_cheat(&mut self, cheat: Cheat) -> usize {
+        self.cheats.borrow_mut().add(cheat)
+    }
+
+    /// Remove the cheat code.
+    pub fn remove_cheat(&mut self, id: usize) {
+        self.cheats.borrow_mut().remove(id);
+    }
+
+    /// Enable or disable the cheat code.
+    pub fn enable_cheat(&mut self, id: usize, enabled: bool) {
+        self.cheats.borrow_mut().enable(id, enabled);
+    }
+
+    /// Remove all the cheat codes.
+    pub fn clear_cheats(&mut self) {
+        self.cheats.borrow_mut().clear();
+    }
+
+    /// Start searching the work RAM for the addresses to cheat, taking the first snapshot.
+    pub fn ram_search(&self) -> RamSearch {
+        RamSearch::new(self)
+    }
+
+    /// Return the shadow call stack, the innermost frame last.
+    ///
+    /// The stack is empty unless [`Config::call_stack`][] is enabled.
+    #[cfg(feature = "debugger")]
+    pub fn call_stack(&self) -> &[Frame] {
+        self.calls.frames()
+    }
+
+    /// Record the memory accesses from the CPU to `range` (inclusive) in the access log.
+    #[cfg(feature = "debugger")]
+    pub fn log_accesses(&mut self, range: (u16, u16), access: Access) {
+        let size = self.cfg.access_log_size;
+        let log = self
+            .log
+            .get_or_insert_with(|| Arc::new(Mutex::new(AccessLog::new(size))));
+        log.lock().add_range(range, access);
+
+        self.mmu
+            .as_mut()
+            .expect("memory not initialized")
+            .set_access_log(Some(log.clone()));
+    }
+
+    /// Stop recording memory accesses and discard the log.
+    #[cfg(feature = "debugger")]
+    pub fn clear_access_log(&mut self) {
+        self.log = None;
+        self.mmu
+            .as_mut()
+            .expect("memory not initialized")
+            .set_access_log(None);
+    }
+
+    /// Take the memory accesses recorded since the last call, the oldest first.
+    #[cfg(feature = "debugger")]
+    pub fn drain_access_log(&mut self) -> Vec<MemAccess> {
+        match &self.log {
+            Some(log) => log.lock().drain(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Start attributing the executed cycles to the instruction addresses, bucketed per ROM bank.
+    ///
+    /// Profiling slows down the emulation, so it's off until started. See [`System::profile`][].
+    #[cfg(feature = "debugger")]
+    pub fn start_profiling(&mut self) {
+        self.profiler.get_or_insert_with(Profiler::new);
+    }
+
+    /// Stop profiling and discard the profile.
+    #[cfg(feature = "debugger")]
+    pub fn stop_profiling(&mut self) {
+        self.profiler = None;
+    }
+
+    /// Returns the cycles spent per instruction address since [`System::start_profiling`][], the hottest first.
+    ///
+    /// Re-sort the entries, e.g. by `(bank, pc)`, to view the profile in the address order.
+    #[cfg(feature = "debugger")]
+    pub fn profile(&self) -> Vec<ProfileEntry> {
+        self.profiler
+            .as_ref()
+            .map(|p| p.entries())
+            .unwrap_or_default()
+    }
+
+    /// Start recording the ROM bytes executed by the CPU. See [`System::coverage`][].
+    ///
+    /// The coverage accumulates until [`System::stop_coverage`][], also across resets and savestates.
+    #[cfg(feature = "debugger")]
+    pub fn start_coverage(&mut self) {
+        self.coverage.get_or_insert_with(Coverage::new);
+    }
+
+    /// Stop recording the executed ROM bytes and discard the coverage.
+    #[cfg(feature = "debugger")]
+    pub fn stop_coverage(&mut self) {
+        self.coverage = None;
+    }
+
+    /// Returns the ROM bytes executed since [`System::start_coverage`][], or `None` if not recording.
+    #[cfg(feature = "debugger")]
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Stop the emulation before the CPU executes the instruction at `pc`.
+    #[cfg(feature = "debugger")]
+    pub fn add_breakpoint(&mut self, pc: u16) {
+        self.breaks.borrow_mut().add_breakpoint(pc, None);
+    }
+
+    /// Stop the emulation before the CPU executes the instruction at `pc` if `cond` holds.
+    #[cfg(feature = "debugger")]
+    pub fn add_breakpoint_if(&mut self, pc: u16, cond: Condition) {
+        self.breaks.borrow_mut().add_breakpoint(pc, Some(cond));
+    }
+
+    /// Remove the breakpoint at `pc`.
+    #[cfg(feature = "debugger")]
+    pub fn remove_breakpoint(&mut self, pc: u16) {
+        self.breaks.borrow_mut().remove_breakpoint(pc);
+    }
+
+    /// Stop the emulation after the CPU accesses `addr`.
+    #[cfg(feature = "debugger")]
+    pub fn add_watchpoint(&mut self, addr: u16, access: Access) {
+        self.breaks.borrow_mut().add_watchpoint(addr, access, None);
+    }
+
+    /// Stop the emulation after the CPU accesses `addr` if `cond` holds after the access.
+    #[cfg(feature = "debugger")]
+    pub fn add_watchpoint_if(&mut self, addr: u16, access: Access, cond: Condition) {
+        self.breaks
+            .borrow_mut()
+            .add_watchpoint(addr, access, Some(cond));
+    }
+
+    /// Remove the watchpoint at `addr`.
+    #[cfg(feature = "debugger")]
+    pub fn remove_watchpoint(&mut self, addr: u16, access: Access) {
+        self.breaks.borrow_mut().remove_watchpoint(addr, access);
+    }
+
+    /// Remove all the breakpoints and the watchpoints.
+    #[cfg(feature = "debugger")]
+    pub fn clear_breakpoints(&mut self) {
+        self.breaks.borrow_mut().clear();
+    }
+
+    /// Read a byte from the given address in the MMU
+    pub fn mmu_get8(&self, addr: u16) -> u8 {
+        self.mmu
+            .as_ref()
+            .expect("memory not initialized")
+            .get8(addr)
+    }
+
+    /// Read a byte from the given address in the MMU
+    pub fn mmu_get16(&self, addr: u16) -> u16 {
+        self.mmu
+            .as_ref()
+            .expect("memory not initialized")
+            .get16(addr)
+    }
+
+    /// Write a byte to the given address in the MMU
+    pub fn mmu_set8(&mut self, addr: u16, v: u8) {
+        self.mmu
+            .as_mut()
+            .expect("memory not initialized")
+            .set8(addr, v)
+    }
+
+    /// Write two bytes to the given address in the MMU
+    pub fn mmu_set16(&mut self, addr: u16, v: u16) {
+        self.mmu
+            .as_mut()
+            .expect("memory not initialized")
+            .set16(addr, v)
+    }
+
+    /// Write the bytes to the MMU starting from the given address
+    pub fn mmu_write(&mut self, addr: u16, data: &[u8]) {
+        let mmu = self.mmu.as_mut().expect("memory not initialized");
+
+        for (i, v) in data.iter().enumerate() {
+            mmu.set8(addr.wrapping_add(i as u16), *v);
+        }
+    }
+
+    /// Returns the time of [`Clock::clock`][crate::Clock::clock] in microseconds.
+    pub(crate) fn now(&self) -> u64 {
+        self.hw.get().lock().clock()
+    }
+
+    /// Returns the CPU clock cycles (T-cycles) elapsed since the power on.
+    ///
+    /// The count is part of the savestates, so it goes back by [`System::load_state`][] and [`System::rewind`][].
+    pub fn cycles(&self) -> u64 {
+        *self.cycles.lock()
+    }
+
+    /// Returns the header of the cartridge, including the results of the validation.
+    pub fn header(&self) -> Header {
+        self.mbc.borrow().header().clone()
+    }
+
+    /// Check if the cartridge RAM may have changed since it was flushed by [`System::flush_cart_ram`][].
+    ///
+    /// Poll this periodically, e.g. every second, and flush the RAM only when it's set,
+    /// so the save data survives crashes without writing the file every frame.
+    /// The flag is also set by loading a savestate.
+    pub fn cart_ram_dirty(&self) -> bool {
+        self.mbc.borrow().ram_dirty()
+    }
+
+    /// Pass the cartridge RAM to [`Persistence::save_ram`][crate::Persistence::save_ram] now,
+    /// clearing the flag of [`System::cart_ram_dirty`][].
+    pub fn flush_cart_ram(&mut self) {
+        self.mbc.borrow_mut().flush_ram();
+    }
+
+    /// Returns the CPU registers.
+    pub fn cpu_state(&self) -> CpuState {
+        self.cpu.state()
+    }
+
+    /// Overwrite the CPU registers for debugging, e.g. to jump to a routine from a test harness.
+    ///
+    /// Takes effect from the next instruction.
+    pub fn set_cpu_state(&mut self, state: &CpuState) {
+        self.cpu.set_state(state);
+    }
+
+    /// Save the whole state of the emulation, which can be restored by [`System::load_state`][].
+    ///
+    /// The state covers the CPU, the memory, the peripherals and the cartridge, but not the hardware,
+    /// the debugger, breakpoints or cheats.
+    pub fn save_state(&self) -> Vec<u8> {
+        self.clock.lock().sync(false);
+
+        let mut w = Writer::new();
+
+        // The header: the magic, the format version and the crate version for diagnosis
+        w.put(STATE_MAGIC);
+        STATE_VERSION.save(&mut w);
+        let crate_version = env!("CARGO_PKG_VERSION").as_bytes();
+        (crate_version.len() as u8).save(&mut w);
+        w.put(crate_version);
+
+        w.section(b"SYS ", |w| {
+            self.frames.save(w);
+            self.cycles().save(w);
+        });
+        w.section(b"CPU ", |w| self.cpu.save(w));
+        w.section(b"MMU ", |w| {
+            self.mmu.as_ref().expect("memory not initialized").save(w)
+        });
+        w.section(b"IC  ", |w| self.ic.borrow().save(w));
+        #[cfg(feature = "cgb")]
+        w.section(b"CGB ", |w| self.cgb.borrow().save(w));
+        w.section(b"GPU ", |w| self.gpu.borrow().save(w));
+        w.section(b"JOYP", |w| self.joypad.borrow().save(w));
+        w.section(b"TIMR", |w| self.timer.borrow().save(w));
+        #[cfg(feature = "serial")]
+        w.section(b"SERL", |w| self.serial.borrow().save(w));
+        w.section(b"DMA ", |w| self.dma.borrow().save(w));
+        w.section(b"MBC ", |w| self.mbc.borrow().save(w));
+        #[cfg(feature = "sound")]
+        w.section(b"SND ", |w| self.sound.borrow().save(w));
+
+        w.finish()
+    }
+
+    /// Restore the state saved by [`System::save_state`][] for the same cartridge.
+    ///
+    /// If the state is broken, the error is returned and the emulation is left as is.
+    /// The states saved by the older versions of the crate are migrated, and the ones of
+    /// a newer format fail with [`Error::StateVersion`][].
+    /// The pending events and the call stack are dropped.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), Error> {
+        let backup = self.save_state();
+
+        if let Err(e) = self.load_state_inner(state) {
+            self.load_state_inner(&backup)
+                .expect("failed to restore the state");
+            return Err(e);
+        }
+
+        self.events.clear();
+        #[cfg(feature = "debugger")]
+        {
+            self.calls = CallStack::new();
+        }
+        self.fc.reset();
+        let mut clock = self.clock.lock();
+        clock.vblank = false;
+        clock.sync(true);
+
+        Ok(())
+    }
+
+    fn load_state_inner(&mut self, state: &[u8]) -> Result<(), Error> {
+        let mut r = Reader::new(state);
+
+        let magic = r.take(STATE_MAGIC.len())?;
+        if magic == STATE_MAGIC_V1 {
+            // The components of version 1 are encoded the same, only without the sections
+            r.set_version(1);
+        } else if magic == STATE_MAGIC {
+            let mut version = 0u16;
+            version.load(&mut r)?;
+            if !(2..=STATE_VERSION).contains(&version) {
+                return Err(Error::StateVersion(version));
+            }
+            r.set_version(version);
+            let mut len = 0u8;
+            len.load(&mut r)?;
+            r.take(len as usize)?;
+        } else {
+            return Err(Error::InvalidState("not a savestate".into()));
+        }
+
+        let mut cycles = 0u64;
+        r.section(b"SYS ", |r| {
+            self.frames.load(r)?;
+            cycles.load(r)
+        })?;
+        *self.cycles.lock() = cycles;
+        r.section(b"CPU ", |r| self.cpu.load(r))?;
+        r.section(b"MMU ", |r| {
+            self.mmu.as_mut().expect("memory not initialized").load(r)
+        })?;
+        r.section(b"IC  ", |r| self.ic.borrow_mut().load(r))?;
+        #[cfg(feature = "cgb")]
+        r.section(b"CGB ", |r| self.cgb.borrow_mut().load(r))?;
+        r.section(b"GPU ", |r| self.gpu.borrow_mut().load(r))?;
+        r.section(b"JOYP", |r| self.joypad.borrow_mut().load(r))?;
+        r.section(b"TIMR", |r| self.timer.borrow_mut().load(r))?;
+        #[cfg(feature = "serial")]
+        r.section(b"SERL", |r| self.serial.borrow_mut().load(r))?;
+        r.section(b"DMA ", |r| self.dma.borrow_mut().load(r))?;
+        r.section(b"MBC ", |r| self.mbc.borrow_mut().load(r))?;
+        #[cfg(feature = "sound")]
+        r.section(b"SND ", |r| self.sound.borrow_mut().load(r))?;
+
+        r.finish()
+    }
+
+    /// Rewind the emulation by about `frames` frames with the savestates enabled by [`Config::rewind`][].
+    ///
+    /// Goes back to the latest savestate at or before the frame, or the oldest one kept,
+    /// and returns the number of frames actually rewound; `0` if there's nothing to rewind to.
+    pub fn rewind(&mut self, frames: usize) -> usize {
+        let now = self.frames;
+        let state = match self.rewind.seek(now.saturating_sub(frames as u64)) {
+            Some(state) => state,
+            None => return 0,
+        };
+
+        self.load_state(&state)
+            .expect("failed to load the rewind state");
+
+        now.saturating_sub(self.frames) as usize
+    }
+
+    /// dump the array backing the memory
+    pub fn mmu_dump(&self) -> &[u8] {
+        self.mmu.as_ref().expect("memory not initialized").dump()
+    }
+
+    /// Borrow the VRAM bank of 8 KB, mapped at 0x8000-0x9fff.
+    ///
+    /// The bank 1 is used only by CGB.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bank` isn't 0 or 1.
+    pub fn vram(&self, bank: usize) -> Region<'_> {
+        assert!(bank < 2, "no VRAM bank {}", bank);
+        self.gpu.bank(bank)
+    }
+
+    /// Borrow the work RAM bank of 4 KB.
+    ///
+    /// The bank 0 is mapped at 0xc000-0xcfff, and the bank selected by SVBK at 0xd000-0xdfff,
+    /// which is the bank 1 on DMG.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bank` is out of range, i.e. 0-7 with the `cgb` feature and 0-1 without.
+    pub fn wram(&self, bank: usize) -> Region<'_> {
+        #[cfg(feature = "cgb")]
+        {
+            assert!(bank < 8, "no WRAM bank {}", bank);
+            self.cgb.bank(bank)
+        }
+        #[cfg(not(feature = "cgb"))]
+        {
+            assert!(bank < 2, "no WRAM bank {}", bank);
+            let start = 0xc000 + bank * 0x1000;
+            Region::ram(&self.mmu_dump()[start..start + 0x1000])
+        }
+    }
+
+    /// Borrow the 40 sprite entries of 4 bytes in OAM, mapped at 0xfe00-0xfe9f.
+    pub fn oam(&self) -> &[u8] {
+        &self.mmu_dump()[0xfe00..0xfea0]
+    }
+
+    /// Borrow the high RAM, mapped at 0xff80-0xfffe.
+    pub fn hram(&self) -> &[u8] {
+        &self.mmu_dump()[0xff80..0xffff]
+    }
+
+    /// Return the DMG palette registers and the colors of the palettes, e.g. for a palette viewer
+    /// or to pick the colors of the border matching the game.
+    pub fn palettes(&self) -> Palettes {
+        self.gpu.borrow().palettes()
+    }
+
+    /// Decode the 40 OAM entries for a sprite viewer, with the pixels as drawn on the screen.
+    ///
+    /// [`Sprite::visible`][crate::Sprite::visible] tells if the sprite was selected for
+    /// any line of the last frame. It's updated only for the frames rendered,
+    /// unless [`Config::pixel_fifo`][] is enabled.
+    pub fn debug_sprites(&self) -> Vec<Sprite> {
+        let mmu = self.mmu.as_ref().expect("memory not initialized");
+        self.gpu.borrow().debug_sprites(&mmu.ram()[0xfe00..0xfea0])
+    }
+
+    /// Decode all the tiles in VRAM for a tile viewer, in `0xRRGGBB` and row-major order.
+    ///
+    /// The buffer has [`TILES_WIDTH`][crate::TILES_WIDTH] x [`TILES_HEIGHT`][crate::TILES_HEIGHT] pixels,
+    /// where the 384 tiles of the VRAM bank 0 are on the left and the bank 1 of CGB is on the right,
+    /// 16 tiles per row. The tiles are colored with the BG palette 0.
+    pub fn debug_tiles(&self) -> Vec<u32> {
+        self.gpu.borrow().debug_tiles()
+    }
+
+    /// Decode the background map for a map viewer, in `0xRRGGBB` and row-major order.
+    ///
+    /// The buffer has [`MAP_SIZE`][crate::MAP_SIZE] x [`MAP_SIZE`][crate::MAP_SIZE] pixels.
+    /// The tiles are addressed as selected by LCDC, and the CGB attributes select
+    /// the bank, the flips and the palette of each tile.
+    pub fn debug_bg_map(&self, map: TileMap) -> Vec<u32> {
+        self.gpu.borrow().debug_bg_map(map)
+    }
+}
+
+/// Whether the hooks of the debugger are called, which they never are without the `debugger` feature.
+fn debugging<D: Debugger>() -> bool {
+    cfg!(feature = "debugger") && D::enabled()
+}
+
+/// The peripherals which run along with the CPU, clocked by every memory cycle.
+///
+/// The cycles which don't reach the next event of any peripheral are only counted,
+/// and run when an event is due or the registers of the peripherals are accessed.
+struct Peripherals<'a> {
+    dma: Device<Dma>,
+    gpu: Device<Gpu<'a>>,
+    timer: Device<Timer>,
+    #[cfg(feature = "serial")]
+    serial: Device<Serial<'a>>,
+    vblank: bool,
+    /// Defer the cycles short of the next event.
+    schedule: bool,
+    /// The cycles deferred since the peripherals last ran.
+    pending: usize,
+    /// The cycles which can be deferred, or `None` if the events have to be checked again.
+    budget: Option<usize>,
+    /// The peripherals have run since the last call of [`Peripherals::take_stepped`][].
+    stepped: bool,
+}
+
+impl<'a> Peripherals<'a> {
+    // Shared with the MMU as `Arc` either way, even if not `Send` without the `threads` feature
+    #[cfg_attr(not(feature = "threads"), allow(clippy::arc_with_non_send_sync))]
+    fn new(
+        dma: &Device<Dma>,
+        gpu: &Device<Gpu<'a>>,
+        timer: &Device<Timer>,
+        #[cfg(feature = "serial")] serial: &Device<Serial<'a>>,
+        schedule: bool,
+    ) -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self {
+            dma: dma.clone(),
+            gpu: gpu.clone(),
+            timer: timer.clone(),
+            #[cfg(feature = "serial")]
+            serial: serial.clone(),
+            vblank: false,
+            schedule,
+            pending: 0,
+            budget: None,
+            stepped: false,
+        }))
+    }
+
+    /// Returns the clock cycles until the next event of any peripheral,
+    /// in memory cycles, up to a line.
+    fn next_event(&self) -> usize {
+        let next = [
+            self.dma.borrow().next_event(),
+            self.gpu.borrow().next_event(),
+            self.timer.borrow().next_event(),
+            #[cfg(feature = "serial")]
+            self.serial.borrow().next_event(),
+        ];
+        let cycles = next
+            .iter()
+            .flatten()
+            .fold(IDLE_CYCLES_MAX, |m, c| m.min(*c));
+
+        // Stepping by memory cycles reaches the event in the last one
+        cycles & !3
+    }
+
+    /// Returns the clock cycles which can be run at once without missing any event.
+    fn idle_cycles(&mut self) -> usize {
+        self.sync(true);
+        self.next_event().max(4)
+    }
+
+    /// Returns the clock cycles which can be deferred from now without missing any event.
+    fn budget(&mut self) -> usize {
+        if !self.schedule {
+            return 0;
+        }
+        match self.budget {
+            Some(budget) => budget,
+            None => {
+                let budget = self.next_event();
+                self.budget = Some(budget);
+                budget
+            }
+        }
+    }
+
+    /// Returns `true` if the GPU has completed a frame since the last call.
+    fn take_vblank(&mut self) -> bool {
+        core::mem::replace(&mut self.vblank, false)
+    }
+
+    /// Returns `true` if the peripherals have run since the last call.
+    fn take_stepped(&mut self) -> bool {
+        core::mem::replace(&mut self.stepped, false)
+    }
+}
+
+impl<'a> Clock for Peripherals<'a> {
+    fn tick(&mut self, time: usize, mmu: &mut Mmu) {
+        if self.pending + time < self.budget() {
+            self.pending += time;
+            return;
+        }
+        self.sync(true);
+
+        self.dma.borrow_mut().step(time, mmu);
+        if self.gpu.borrow_mut().step(time, mmu) {
+            self.vblank = true;
+        }
+        self.timer.borrow_mut().step(time);
+        #[cfg(feature = "serial")]
+        self.serial.borrow_mut().step(time);
+        self.stepped = true;
+    }
+
+    fn sync(&mut self, write: bool) {
+        let time = core::mem::replace(&mut self.pending, 0);
+
+        // The deferred cycles don't reach any event, which the DMA doesn't have while idle
+        if time > 0 {
+            self.gpu.borrow_mut().advance(time);
+            self.timer.borrow_mut().step(time);
+            #[cfg(feature = "serial")]
+            self.serial.borrow_mut().step(time);
+        }
+
+        // Writes may bring the events forward, while the reads only spend the budget
+        self.budget = match self.budget {
+            Some(budget) if !write => Some(budget - time),
+            _ => None,
+        };
+    }
+}
+
+/// Check if the instruction triggers the OAM corruption bug,
+/// i.e. `inc rr` or `dec rr` with the register pointing to `FE00-FEFF`.
+fn oam_bug_trigger(code: u16, cpu: &Cpu) -> bool {
+    let v = match code {
+        0x03 | 0x0b => cpu.get_bc(),
+        0x13 | 0x1b => cpu.get_de(),
+        0x23 | 0x2b => cpu.get_hl(),
+        0x33 | 0x3b => cpu.get_sp(),
+        _ => return false,
+    };
+
+    (0xfe00..=0xfeff).contains(&v)
+}
+
+/// Run the emulator with the given configuration.
+pub fn run<T: Hardware>(cfg: Config, rom: &[u8], hw: T) -> Result<(), Error> {
+    run_inner(cfg, rom, hw, Debugger::empty())
+}
+
+/// Run the emulator with the given configuration and debugger.
+pub fn run_debug<T: Hardware, D: Debugger + MaybeSend>(
+    cfg: Config,
+    rom: &[u8],
+    hw: T,
+    dbg: D,
+) -> Result<(), Error> {
+    run_inner(cfg, rom, hw, dbg)
+}
+
+fn run_inner<T: Hardware, D: Debugger + MaybeSend>(
+    cfg: Config,
+    rom: &[u8],
+    hw: T,
+    dbg: D,
+) -> Result<(), Error> {
+    let mut sys = System::new(cfg, rom, vec![0u8; 0x10000], hw, dbg)?;
+    while sys.poll()? {}
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::buffers::{FRAME_SIZE, MEMORY_SIZE, VRAM_SIZE, WRAM_SIZE};
+    use crate::debug::{HwEvent, NullDebugger};
+    use crate::device::IoHandler;
+    use crate::hardware::{Input, Link, NullHardware, Persistence, Screen, Speaker};
+    use crate::mmu::{MemRead, MemWrite};
+
+    /// The program which unmaps the boot ROM and waits for the V-blank and timer interrupts in HALT.
+    const PROGRAM: &[u8] = &[
+        0xe0, 0x50, // ldh (0x50),a
+        0x31, 0xfe, 0xff, // ld sp,0xfffe
+        0x3e, 0x91, // ld a,0x91
+        0xe0, 0x40, // ldh (0x40),a
+        0x3e, 0x05, // ld a,0x05
+        0xe0, 0x07, // ldh (0x07),a
+        0xe0, 0xff, // ldh (0xff),a
+        0x21, 0x00, 0xc0, // ld hl,0xc000
+        0xfb, // ei
+        0x76, // halt
+        0x18, 0xfd, // jr -3
+    ];
+
+    /// The program which keeps reading LY and DIV while the interrupts come in.
+    const BUSY: &[u8] = &[
+        0xe0, 0x50, // ldh (0x50),a
+        0x31, 0xfe, 0xff, // ld sp,0xfffe
+        0x3e, 0x91, // ld a,0x91
+        0xe0, 0x40, // ldh (0x40),a
+        0x3e, 0x05, // ld a,0x05
+        0xe0, 0x07, // ldh (0x07),a
+        0xe0, 0xff, // ldh (0xff),a
+        0x21, 0x00, 0xc0, // ld hl,0xc000
+        0xfb, // ei
+        0xf0, 0x44, // ldh a,(0x44)
+        0x86, // add a,(hl)
+        0xe0, 0x80, // ldh (0x80),a
+        0xf0, 0x04, // ldh a,(0x04)
+        0xe0, 0x81, // ldh (0x81),a
+        0x18, 0xf5, // jr -11
+    ];
+
+    /// Debugger which counts the instructions and the memory reads, and records the hardware events.
+    #[derive(Default)]
+    struct Counter {
+        decodes: usize,
+        reads: usize,
+        events: Vec<(HwEvent, u64)>,
+    }
+
+    impl Debugger for Counter {
+        fn init(&mut self, _: &Mmu) {}
+
+        fn take_cpu_snapshot(&mut self, _: Cpu) {}
+
+        fn on_decode(&mut self, _: &Mmu) {
+            self.decodes += 1;
+        }
+
+        fn check_signal(&mut self) {}
+
+        fn on_event(&mut self, event: &HwEvent, cycles: u64) {
+            self.events.push((*event, cycles));
+        }
+    }
+
+    impl IoHandler for Counter {
+        fn on_read(&mut self, _: &Mmu, _: u16) -> MemRead {
+            self.reads += 1;
+            MemRead::PassThrough
+        }
+
+        fn on_write(&mut self, _: &Mmu, _: u16, _: u8) -> MemWrite {
+            MemWrite::PassThrough
+        }
+    }
+
+    /// Hardware which counts the idle cycles, and reads the clock if any.
+    #[derive(Default)]
+    struct Idle(usize, Option<Arc<Mutex<u64>>>);
+
+    impl Screen for Idle {}
+
+    impl Speaker for Idle {}
+
+    impl Input for Idle {}
+
+    impl crate::hardware::Clock for Idle {
+        fn clock(&mut self) -> u64 {
+            self.1.as_ref().map_or(0, |now| *now.lock())
+        }
+
+        fn idle(&mut self, cycles: usize) {
+            self.0 += cycles;
+        }
+    }
+
+    impl Persistence for Idle {}
+
+    impl Link for Idle {}
+
+    /// The ROM of the test systems, which has only the interrupt handlers.
+    pub(crate) fn rom() -> Vec<u8> {
+        let mut rom = vec![0u8; 0x8000];
+        // V-blank counts up C000, and timer counts up C
+        rom[0x40..0x42].copy_from_slice(&[0x34, 0xd9]);
+        rom[0x50..0x52].copy_from_slice(&[0x0c, 0xd9]);
+        rom
+    }
+
+    /// Create the system at native speed running the program from C100.
+    pub(crate) fn system<'a, T, D>(cfg: Config, program: &[u8], hw: T, dbg: D) -> System<'a, D>
+    where
+        T: Hardware + 'a,
+        D: Debugger + MaybeSend + 'a,
+    {
+        let cfg = cfg.native_speed(true);
+        let mut sys = System::new(cfg, &rom(), vec![0u8; 0x10000], hw, dbg).unwrap();
+
+        let mut cpu = sys.cpu_state();
+        cpu.pc = 0xc100;
+        sys.set_cpu_state(&cpu);
+        sys.mmu_write(0xc100, program);
+        sys
+    }
+
+    fn run(batch: bool) -> (Vec<u8>, u8, u8) {
+        let mut sys = system(
+            Config::new().batch_halt(batch),
+            PROGRAM,
+            NullHardware,
+            NullDebugger,
+        );
+
+        // Stop at the same cycle, which the time limit of run_frame doesn't
+        while sys.frames < 10 {
+            sys.poll().unwrap();
+        }
+        (sys.save_state(), sys.mmu_get8(0xc000), sys.cpu.get_c())
+    }
+
+    #[test]
+    #[cfg(feature = "threads")]
+    fn system_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<System<NullDebugger>>();
+    }
+
+    #[test]
+    #[cfg(not(feature = "threads"))]
+    fn non_send_hardware() {
+        use alloc::rc::Rc;
+        use core::cell::Cell;
+
+        struct Local(Rc<Cell<usize>>);
+
+        impl Screen for Local {}
+
+        impl Speaker for Local {}
+
+        impl Input for Local {}
+
+        impl crate::hardware::Clock for Local {
+            fn clock(&mut self) -> u64 {
+                0
+            }
+
+            fn idle(&mut self, cycles: usize) {
+                self.0.set(self.0.get() + cycles);
+            }
+        }
+
+        impl Persistence for Local {}
+
+        impl Link for Local {}
+
+        let idle = Rc::new(Cell::new(0));
+        let mut sys = system(Config::new(), PROGRAM, Local(idle.clone()), NullDebugger);
+        sys.run_frame().unwrap();
+        assert!(idle.get() > 0);
+    }
+
+    #[test]
+    #[cfg(all(feature = "debugger", not(feature = "threads")))]
+    fn non_send_debugger() {
+        use alloc::rc::Rc;
+        use core::cell::Cell;
+
+        struct Local(Rc<Cell<usize>>);
+
+        impl Debugger for Local {
+            fn init(&mut self, _: &Mmu) {}
+
+            fn take_cpu_snapshot(&mut self, _: Cpu) {}
+
+            fn on_decode(&mut self, _: &Mmu) {
+                self.0.set(self.0.get() + 1);
+            }
+
+            fn check_signal(&mut self) {}
+        }
+
+        impl IoHandler for Local {
+            fn on_read(&mut self, _: &Mmu, _: u16) -> MemRead {
+                MemRead::PassThrough
+            }
+
+            fn on_write(&mut self, _: &Mmu, _: u16, _: u8) -> MemWrite {
+                MemWrite::PassThrough
+            }
+        }
+
+        let decodes = Rc::new(Cell::new(0));
+        let mut sys = system(Config::new(), PROGRAM, NullHardware, Local(decodes.clone()));
+        sys.run_frame().unwrap();
+        assert!(decodes.get() > 0);
+    }
+
+    #[test]
+    fn batch_halt() {
+        let (batched, frames, timer) = run(true);
+        assert!(frames >= 9, "{}", frames);
+        assert!(timer > 100, "{}", timer);
+
+        // Exactly the same as running the halt cycles one by one
+        assert!(batched == run(false).0);
+    }
+
+    #[test]
+    fn schedule() {
+        let run = |schedule| {
+            let mut sys = system(
+                Config::new().schedule(schedule),
+                BUSY,
+                NullHardware,
+                NullDebugger,
+            );
+            while sys.frames < 10 {
+                sys.poll().unwrap();
+            }
+            assert!(sys.mmu_get8(0xc000) >= 9);
+            assert!(sys.cpu.get_c() > 100);
+            sys.save_state()
+        };
+
+        // Exactly the same as running the peripherals on every memory cycle
+        assert!(run(true) == run(false));
+    }
+
+    #[test]
+    fn run_cycles() {
+        let mut sys = system(Config::new(), PROGRAM, NullHardware, NullDebugger);
+
+        let ran = sys.run_cycles(100_000).unwrap();
+        assert!(
+            ran >= 100_000 && ran < 100_000 + IDLE_CYCLES_MAX as u64,
+            "{}",
+            ran
+        );
+        assert_eq!(sys.cycles(), ran);
+
+        let ran = sys.run_for(Duration::from_millis(10)).unwrap();
+        assert!(ran >= 41_943, "{}", ran);
+
+        // The breakpoint stops the batch and is reported by the next poll
+        #[cfg(feature = "debugger")]
+        {
+            sys.add_breakpoint(0xc114);
+            assert!(sys.run_cycles(100_000).unwrap() < 100_000);
+            assert_eq!(
+                sys.poll_event().unwrap(),
+                PollEvent::Break(Break::Breakpoint(0xc114))
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "debugger")]
+    fn debugger_hooks() {
+        let mut sys = system(Config::new(), PROGRAM, NullHardware, Counter::default());
+        sys.run_frame().unwrap();
+
+        let dbg = sys.dbg.borrow();
+        assert!(dbg.decodes > 0);
+        // The interrupt handlers read C000 and the stack
+        assert!(dbg.reads > 0);
+    }
+
+    #[test]
+    #[cfg(feature = "debugger")]
+    fn hw_events() {
+        let mut sys = system(Config::new(), PROGRAM, NullHardware, Counter::default());
+        sys.run_frame().unwrap();
+        assert!(sys.dbg.borrow().events.is_empty());
+
+        let cfg = Config::new().events(true);
+        let mut sys = system(cfg, PROGRAM, NullHardware, Counter::default());
+        sys.run_frame().unwrap();
+        sys.mmu_set8(0xff46, 0xc0);
+        sys.run_frame().unwrap();
+
+        let dbg = sys.dbg.borrow();
+        let has = |e: HwEvent| dbg.events.iter().any(|(x, _)| *x == e);
+        assert!(has(HwEvent::ModeChange { mode: 2, line: 0 }));
+        assert!(has(HwEvent::ModeChange { mode: 1, line: 144 }));
+        assert!(has(HwEvent::InterruptRaised(0x40)));
+        assert!(has(HwEvent::InterruptServiced(0x40)));
+        assert!(has(HwEvent::InterruptRaised(0x50)));
+        assert!(has(HwEvent::DmaStart(0xc000)));
+        assert!(has(HwEvent::DmaEnd));
+        assert!(dbg.events.windows(2).all(|e| e[0].1 <= e[1].1));
+    }
+
+    #[test]
+    fn borrow() {
+        let mut hw = Idle::default();
+        let mut dbg = Counter::default();
+
+        let mut sys = system(Config::new(), PROGRAM, &mut hw, &mut dbg);
+        sys.run_frame().unwrap();
+        drop(sys);
+
+        // The hardware and the debugger on the stack are back to the caller
+        assert!(hw.0 > 0);
+        assert_eq!(dbg.decodes > 0, cfg!(feature = "debugger"));
+    }
+
+    #[test]
+    fn borrow_rom() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x4000] = 0x42;
+
+        let cfg = Config::new().native_speed(true).frame_buffer(false);
+        let mut sys =
+            System::borrow_rom(cfg, &rom, vec![0u8; 0x10000], NullHardware, NullDebugger).unwrap();
+        assert_eq!(sys.mmu_get8(0x4000), 0x42);
+
+        let mut fb = vec![0u16; VRAM_WIDTH * VRAM_HEIGHT];
+        sys.run_frame_into(&mut fb).unwrap();
+        assert!(sys.frame().is_empty());
+    }
+
+    #[test]
+    fn buffers() {
+        let rom = crate::testing::rom(&[0x18, 0xfe]);
+        let mut memory = [0u8; MEMORY_SIZE];
+        let mut vram = [0u8; VRAM_SIZE];
+        let mut wram = [0u8; WRAM_SIZE];
+        let mut drawn = [0u32; FRAME_SIZE];
+        let mut frame = [0u32; FRAME_SIZE];
+
+        let bufs = Buffers::new(&mut memory, &mut vram)
+            .wram(&mut wram)
+            .frame_buffer(&mut drawn, &mut frame);
+        let cfg = Config::new().native_speed(true).frame_blending(128);
+        let mut sys = System::with_buffers(cfg, &rom, bufs, NullHardware, NullDebugger).unwrap();
+
+        // Without the buffer for blending
+        assert_eq!(sys.cfg.frame_blending, 0);
+
+        for _ in 0..60 {
+            sys.run_frame().unwrap();
+        }
+        sys.mmu_set8(0xff80, 0x42);
+        assert_eq!(sys.mmu_get8(0xff80), 0x42);
+
+        // Cleared on reset
+        sys.reset();
+        assert_eq!(sys.mmu_get8(0xff80), 0x00);
+        for _ in 0..60 {
+            sys.run_frame().unwrap();
+        }
+        sys.mmu_set8(0xff80, 0x42);
+        drop(sys);
+
+        assert_eq!(memory[0xff80], 0x42);
+        // The logo of the boot ROM
+        assert!(vram.iter().any(|b| *b != 0));
+        assert!(frame.iter().any(|p| *p != frame[0]));
+    }
+
+    #[test]
+    fn frame() {
+        let rom = crate::testing::rom(&[0x18, 0xfe]);
+        let cfg = Config::new().native_speed(true);
+        let mut sys =
+            System::new(cfg, &rom, vec![0u8; 0x10000], NullHardware, NullDebugger).unwrap();
+        let mut fb = vec![0u32; VRAM_WIDTH * VRAM_HEIGHT];
+
+        // Scroll the logo of the boot ROM into the screen
+        for _ in 0..60 {
+            while sys.poll_into(Some(&mut FrameSink(&mut fb))).unwrap() != PollEvent::FrameReady {}
+            assert!(sys.frame() == &fb[..]);
+        }
+        assert!(fb.iter().any(|p| *p != fb[0]));
+
+        // Not updated by the lines of the frame in progress
+        let start = sys.cycles();
+        while sys.cycles() - start < CYCLES_PER_FRAME / 2 {
+            sys.poll().unwrap();
+        }
+        assert!(sys.frame() == &fb[..]);
+    }
+
+    #[test]
+    #[cfg(not(feature = "color"))]
+    fn pixel_formats() {
+        fn render<P: Pixel + Default>(format: PixelFormat) -> Vec<P> {
+            let cfg = Config::new()
+                .pixel_format(format)
+                .dmg_palette([0xffffff, 0xaaaaaa, 0x555555, 0x5a9cde]);
+            let mut sys = system(cfg, PROGRAM, NullHardware, NullDebugger);
+            // Map all the colors of the background to the darkest shade
+            sys.mmu_set8(0xff47, 0xff);
+
+            let mut fb = vec![P::default(); VRAM_WIDTH * VRAM_HEIGHT];
+            for _ in 0..2 {
+                sys.run_frame_into(&mut fb).unwrap();
+            }
+            fb
+        }
+
+        // The pixels are truncated to the element size
+        let rgb888 = render::<u32>(PixelFormat::Rgb888);
+        assert!(rgb888.iter().all(|p| *p == 0x5a9cde));
+        let rgba8888 = render::<u32>(PixelFormat::Rgba8888);
+        assert!(rgba8888.iter().all(|p| *p == 0x5a9cdeff));
+        let rgb565 = render::<u16>(PixelFormat::Rgb565);
+        assert!(rgb565.iter().all(|p| *p == 0x5cfb));
+        let index = render::<u8>(PixelFormat::Index);
+        assert!(index.iter().all(|p| *p == 3));
+    }
+
+    #[test]
+    fn frame_skip() {
+        struct Lines(Arc<Mutex<usize>>);
+
+        impl Screen for Lines {
+            fn vram_update(&mut self, _: usize, _: &[u32]) {
+                *self.0.lock() += 1;
+            }
+        }
+
+        impl Speaker for Lines {}
+
+        impl Input for Lines {}
+
+        impl crate::hardware::Clock for Lines {
+            fn clock(&mut self) -> u64 {
+                0
+            }
+        }
+
+        impl Persistence for Lines {}
+
+        impl Link for Lines {}
+
+        let lines = Arc::new(Mutex::new(0));
+        let cfg = Config::new().frame_skip(2);
+        let mut sys = system(cfg, PROGRAM, Lines(lines.clone()), NullDebugger);
+
+        // Every frame is reported while only one out of three is rendered
+        let mut rendered = vec![];
+        for _ in 0..6 {
+            assert_eq!(sys.run_frame().unwrap(), PollEvent::FrameReady);
+            rendered.push(core::mem::take(&mut *lines.lock()) > 0);
+        }
+        assert_eq!(sys.frames, 6);
+        assert_eq!(rendered, [true, false, false, true, false, false]);
+    }
+
+    #[test]
+    #[cfg(feature = "serial")]
+    fn serial_output() {
+        let mut program = vec![];
+        for b in b"Hi" {
+            program.extend_from_slice(&[
+                0x3e, *b, // ld a,b
+                0xe0, 0x01, // ldh (0x01),a
+                0x3e, 0x81, // ld a,0x81
+                0xe0, 0x02, // ldh (0x02),a
+                0xf0, 0x02, // ldh a,(0x02)
+                0xcb, 0x7f, // bit 7,a
+                0x20, 0xfa, // jr nz,-6
+            ]);
+        }
+        program.extend_from_slice(&[0x18, 0xfe]);
+
+        let run = |cfg| {
+            let mut sys = system(cfg, &program, NullHardware, NullDebugger);
+            sys.run_frame().unwrap();
+            sys
+        };
+
+        assert!(run(Config::new()).serial_output().is_empty());
+
+        let mut sys = run(Config::new().serial_capture(true));
+        assert_eq!(sys.serial_output(), b"Hi");
+        sys.clear_serial_output();
+        assert!(sys.serial_output().is_empty());
+    }
+
+    #[test]
+    fn skip_boot() {
+        let rom = crate::testing::rom(&[0x18, 0xfe]);
+        let cfg = Config::new()
+            .native_speed(true)
+            .boot_rom(false)
+            .model(Model::Mgb);
+        let mut sys =
+            System::new(cfg, &rom, vec![0u8; 0x10000], NullHardware, NullDebugger).unwrap();
+
+        let check = |sys: &System<'_, NullDebugger>| {
+            let s = sys.cpu_state();
+            assert_eq!((s.af, s.bc, s.sp, s.pc), (0xffb0, 0x0013, 0xfffe, 0x0100));
+            assert_eq!(sys.mmu_get8(0x0000), 0x00);
+            assert_eq!(sys.mmu_get8(0xff40), 0x91);
+            assert_eq!(sys.mmu_get8(0xff47), 0xfc);
+            assert_eq!(sys.mmu_get8(0xff04), 0xab);
+        };
+        check(&sys);
+
+        // The game runs without the boot ROM
+        sys.run_frame().unwrap();
+        assert_eq!(sys.cpu_state().pc, 0x0150);
+
+        sys.reset();
+        check(&sys);
+    }
+
+    #[test]
+    fn mem_handler() {
+        use crate::mmu::{MemRead, MemWrite};
+
+        /// The console printing the bytes written to FF7F, which also patches the ROM at 4000.
+        #[derive(Default)]
+        struct Console(Mutex<Vec<u8>>);
+
+        impl MemHandler for Console {
+            fn on_read(&self, _: &Mmu, addr: u16) -> MemRead {
+                match addr {
+                    0x4000 => MemRead::Replace(0x42),
+                    _ => MemRead::PassThrough,
+                }
+            }
+
+            fn on_write(&self, _: &Mmu, addr: u16, value: u8) -> MemWrite {
+                match addr {
+                    0xff7f => {
+                        self.0.lock().push(value);
+                        MemWrite::Block
+                    }
+                    _ => MemWrite::PassThrough,
+                }
+            }
+        }
+
+        let mut sys = system(Config::new(), PROGRAM, NullHardware, NullDebugger);
+        let console = Arc::new(Console::default());
+        sys.add_mem_handler((0x4000, 0x4000), console.clone());
+        sys.add_mem_handler((0xff7f, 0xff7f), console.clone());
+
+        sys.mmu_set8(0xff7f, b'H');
+        sys.mmu_set8(0xff7f, b'i');
+        assert_eq!(*console.0.lock(), b"Hi");
+        assert_eq!(sys.mmu_get8(0xff7f), 0x00);
+        // Takes over the cartridge
+        assert_eq!(sys.mmu_get8(0x4000), 0x42);
+        assert_eq!(sys.mmu_get8(0x4001), 0x00);
+
+        sys.reset();
+        sys.mmu_set8(0xff7f, b'!');
+        assert_eq!(*console.0.lock(), b"Hi!");
+        assert_eq!(sys.mmu_get8(0x4000), 0x42);
+    }
+
+    #[test]
+    fn cheats() {
+        let mut sys = system(Config::new(), PROGRAM, NullHardware, NullDebugger);
+        sys.run_frame().unwrap();
+        let count = |sys: &mut System<_>| {
+            let before = sys.mmu_get8(0xc000);
+            sys.run_frame().unwrap();
+            sys.mmu_get8(0xc000).wrapping_sub(before)
+        };
+
+        // Game Genie turning `inc (hl)` of the V-blank handler into `nop`, unless the compare value differs
+        let mismatch = sys.add_cheat(Cheat::GameGenie {
+            addr: 0x0040,
+            value: 0x00,
+            compare: Some(0x35),
+        });
+        assert_eq!(count(&mut sys), 1);
+        sys.remove_cheat(mismatch);
+
+        sys.add_cheat(Cheat::GameGenie {
+            addr: 0x0040,
+            value: 0x00,
+            compare: Some(0x34),
+        });
+        assert_eq!(count(&mut sys), 0);
+        sys.clear_cheats();
+        assert_eq!(count(&mut sys), 1);
+
+        // GameShark writing the RAM every frame
+        let id = sys.add_cheat(Cheat::GameShark {
+            bank: 0x01,
+            addr: 0xc010,
+            value: 0x55,
+        });
+        sys.run_frame().unwrap();
+        assert_eq!(sys.mmu_get8(0xc010), 0x55);
+        sys.mmu_set8(0xc010, 0x00);
+        sys.run_frame().unwrap();
+        assert_eq!(sys.mmu_get8(0xc010), 0x55);
+
+        sys.enable_cheat(id, false);
+        sys.mmu_set8(0xc010, 0x00);
+        sys.run_frame().unwrap();
+        assert_eq!(sys.mmu_get8(0xc010), 0x00);
+    }
+
+    #[test]
+    fn header_validation() {
+        let rom = vec![0u8; 0x8000];
+
+        let cfg = Config::new().header_validation(Validation::Reject);
+        let err = System::new(cfg, &rom, vec![0u8; 0x10000], NullHardware, NullDebugger).err();
+        assert_eq!(
+            err,
+            Some(Error::InvalidHeader("Nintendo logo mismatch".into()))
+        );
+
+        let sys = System::new(
+            Config::new(),
+            &rom,
+            vec![0u8; 0x10000],
+            NullHardware,
+            NullDebugger,
+        );
+        assert!(!sys.unwrap().header().logo_valid);
+    }
+
+    #[test]
+    #[cfg(feature = "debugger")]
+    fn profile() {
+        let mut sys = system(Config::new(), PROGRAM, NullHardware, NullDebugger);
+        sys.run_frame().unwrap();
+        assert!(sys.profile().is_empty());
+
+        sys.start_profiling();
+        let start = sys.cycles();
+        for _ in 0..3 {
+            sys.run_frame().unwrap();
+        }
+        let profile = sys.profile();
+
+        // The CPU waits in HALT at C113, and the V-blank handler runs once per frame
+        assert_eq!(profile[0].pc, 0xc114);
+        assert!(profile.windows(2).all(|p| p[0].cycles >= p[1].cycles));
+        let handler = profile.iter().find(|e| e.pc == 0x40).unwrap();
+        assert_eq!((handler.bank, handler.count), (0, 3));
+        let total: u64 = profile.iter().map(|e| e.cycles).sum();
+        assert_eq!(total, sys.cycles() - start);
+
+        sys.stop_profiling();
+        assert!(sys.profile().is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "debugger")]
+    fn coverage() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x4000..0x4003].copy_from_slice(&[0xc3, 0x00, 0x40]); // jp 0x4000
+
+        let cfg = Config::new().native_speed(true).headless(true);
+        let mut sys =
+            System::new(cfg, &rom, vec![0u8; 0x10000], NullHardware, NullDebugger).unwrap();
+        assert!(sys.coverage().is_none());
+
+        sys.start_coverage();
+        let mut state = sys.cpu_state();
+        state.pc = 0x4000;
+        sys.set_cpu_state(&state);
+        sys.run_frame().unwrap();
+
+        let c = sys.coverage().unwrap();
+        assert_eq!(c.banks(), [1]);
+        assert_eq!(c.count(), 3);
+        assert!(c.executed(1, 0x0002));
+        assert!(!c.executed(1, 0x0003));
+
+        sys.stop_coverage();
+        assert!(sys.coverage().is_none());
+    }
+
+    #[test]
+    fn swap_rom() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x4000] = 0x42;
+
+        let cfg = Config::new().native_speed(true);
+        let mut sys =
+            System::new(cfg, &rom, vec![0u8; 0x10000], NullHardware, NullDebugger).unwrap();
+        sys.run_frame().unwrap();
+        let pc = sys.cpu_state().pc;
+
+        rom[0x4000] = 0x43;
+        sys.swap_rom(&rom).unwrap();
+        assert_eq!(sys.mmu_get8(0x4000), 0x43);
+        assert_eq!(sys.cpu_state().pc, pc);
+
+        // The broken ROM is rejected and the cartridge stays
+        assert!(sys.swap_rom(&[0u8; 0x100]).is_err());
+        assert_eq!(sys.mmu_get8(0x4000), 0x43);
+    }
+
+    #[test]
+    fn swap_rom_saves_before_load() {
+        #[derive(Default)]
+        struct Saves(Vec<(&'static str, u8)>);
+        impl Screen for Saves {}
+        impl Speaker for Saves {}
+        impl Input for Saves {}
+        impl Link for Saves {}
+        impl crate::hardware::Clock for Saves {
+            fn clock(&mut self) -> u64 {
+                0
+            }
+        }
+        impl Persistence for Saves {
+            fn load_ram(&mut self, size: usize) -> Vec<u8> {
+                self.0.push(("load", 0));
+                vec![0; size]
+            }
+            fn save_ram(&mut self, ram: &[u8]) {
+                self.0.push(("save", ram[0]));
+            }
+        }
+
+        // MBC1 with the battery and 8 KB RAM
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+
+        let mut saves = Saves::default();
+        let cfg = Config::new().native_speed(true).boot_rom(false);
+        let mut sys = System::new(cfg, &rom, vec![0u8; 0x10000], &mut saves, NullDebugger).unwrap();
+        sys.mmu_set8(0x0000, 0x0a);
+        sys.mmu_set8(0xa000, 0x42);
+
+        sys.swap_rom(&rom).unwrap();
+        drop(sys);
+
+        // Nothing is written since the swap, so the new cartridge isn't saved on drop
+        assert_eq!(saves.0, [("load", 0), ("save", 0x42), ("load", 0)]);
+    }
+
+    #[test]
+    #[cfg(feature = "sound")]
+    fn run_frame_av() {
+        let mut sys = system(Config::new(), PROGRAM, NullHardware, NullDebugger);
+        let mut fb = vec![0u32; VRAM_WIDTH * VRAM_HEIGHT];
+        let mut samples = vec![];
+
+        let mut cycles = 0;
+        for _ in 0..10 {
+            let start = sys.cycles();
+            assert_eq!(
+                sys.run_frame_av(&mut fb, 44100, &mut samples).unwrap(),
+                PollEvent::FrameReady
+            );
+            cycles += sys.cycles() - start;
+        }
+
+        // The fractions carried over add up to the samples of the whole time run
+        assert_eq!(samples.len() as u64, cycles * 44100 / 4194300);
+        assert!(samples.iter().all(|&s| s >= 0));
+    }
+
+    #[test]
+    fn memory_regions() {
+        let mut sys = system(Config::new(), PROGRAM, NullHardware, NullDebugger);
+        sys.mmu_set8(0x8001, 0x12);
+        sys.mmu_set8(0xc002, 0x34);
+        sys.mmu_set8(0xd003, 0x56);
+        sys.mmu_set8(0xfe04, 0x78);
+        sys.mmu_set8(0xff85, 0x9a);
+
+        assert_eq!(sys.vram(0).len(), 0x2000);
+        assert_eq!(sys.vram(0)[1], 0x12);
+        assert_eq!(sys.vram(1)[1], 0x00);
+        assert_eq!(sys.wram(0)[2], 0x34);
+        assert_eq!(sys.wram(1)[3], 0x56);
+        assert_eq!(sys.oam().len(), 0xa0);
+        assert_eq!(sys.oam()[4], 0x78);
+        assert_eq!(sys.hram().len(), 0x7f);
+        assert_eq!(sys.hram()[5], 0x9a);
+    }
+
+    #[test]
+    fn cpu_state() {
+        let mut sys = system(Config::new(), PROGRAM, NullHardware, NullDebugger);
+        let mut state = sys.cpu_state();
+        assert_eq!(state.pc, 0xc100);
+
+        // Jump back to the start after a step
+        sys.poll().unwrap();
+        assert_ne!(sys.cpu_state().pc, 0xc100);
+        state.bc = 0x1234;
+        sys.set_cpu_state(&state);
+        assert_eq!(sys.cpu_state(), state);
+    }
+
+    #[test]
+    fn save_and_load_state() {
+        let mut sys = system(Config::new(), PROGRAM, NullHardware, NullDebugger);
+        sys.run_frame().unwrap();
+
+        let state = sys.save_state();
+        let counted = sys.mmu_get8(0xc000);
+        for _ in 0..3 {
+            sys.run_frame().unwrap();
+        }
+        let after = sys.save_state();
+        assert_ne!(sys.mmu_get8(0xc000), counted);
+
+        // Loading the state reproduces the same run
+        sys.load_state(&state).unwrap();
+        assert_eq!(sys.mmu_get8(0xc000), counted);
+        for _ in 0..3 {
+            sys.run_frame().unwrap();
+        }
+        assert_eq!(sys.save_state(), after);
+
+        // A broken state leaves the system as is
+        assert!(sys.load_state(&state[..state.len() - 1]).is_err());
+        assert_eq!(sys.save_state(), after);
+    }
+
+    fn read_u32(b: &[u8]) -> usize {
+        u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize
+    }
+
+    #[test]
+    fn state_versions() {
+        let mut sys = system(Config::new(), PROGRAM, NullHardware, NullDebugger);
+        sys.run_frame().unwrap();
+        let state = sys.save_state();
+
+        // Make the same state in the older versions by stripping the bytes of the fields added since
+        // at the end of the sections; version 1 has no header and no sections either
+        let added: &[(&[u8], u16, usize)] = &[(b"DMA ", 3, 2), (b"GPU ", 4, 1), (b"GPU ", 5, 8)];
+        let header = 4 + 2 + 1 + state[6] as usize;
+        for version in 1..STATE_VERSION {
+            let mut old = if version == 1 {
+                b"RGYS".to_vec()
+            } else {
+                let mut h = state[..header].to_vec();
+                h[4..6].copy_from_slice(&version.to_le_bytes());
+                h
+            };
+
+            let mut pos = header;
+            while pos < state.len() {
+                let tag = &state[pos..pos + 4];
+                let len = read_u32(&state[pos + 4..]);
+                let strip: usize = added
+                    .iter()
+                    .filter(|(t, v, _)| *t == tag && version < *v)
+                    .map(|(_, _, len)| len)
+                    .sum();
+                let body = &state[pos + 8..pos + 8 + len - strip];
+                pos += 8 + len;
+
+                if version > 1 {
+                    old.extend_from_slice(tag);
+                    old.extend_from_slice(&(body.len() as u32).to_le_bytes());
+                }
+                old.extend_from_slice(body);
+            }
+
+            sys.run_frame().unwrap();
+            sys.load_state(&old).unwrap();
+            assert!(sys.save_state() == state, "version {}", version);
+        }
+
+        let mut newer = state.clone();
+        newer[4] = 99;
+        assert_eq!(sys.load_state(&newer), Err(Error::StateVersion(99)));
+
+        // The component failing to load is named: a byte too many in the CPU section
+        let mut broken = state.clone();
+        let cpu = 4 + 2 + 1 + state[6] as usize + 8 + 16;
+        assert_eq!(&state[cpu..cpu + 4], b"CPU ");
+        let len = read_u32(&state[cpu + 4..]);
+        broken[cpu + 4..cpu + 8].copy_from_slice(&(len as u32 + 1).to_le_bytes());
+        broken.insert(cpu + 8 + len, 0);
+        match sys.load_state(&broken) {
+            Err(Error::InvalidState(msg)) => assert!(msg.starts_with("CPU:"), "{}", msg),
+            e => panic!("{:?}", e),
+        }
+        assert!(sys.save_state() == state);
+    }
+
+    #[test]
+    fn corrupted_state() {
+        let mut sys = system(Config::new(), PROGRAM, NullHardware, NullDebugger);
+        sys.run_frame().unwrap();
+        let state = sys.save_state();
+
+        // The offset of the body of the section
+        let section = |tag: &[u8]| {
+            let mut pos = 4 + 2 + 1 + state[6] as usize;
+            while &state[pos..pos + 4] != tag {
+                pos += 8 + read_u32(&state[pos + 4..]);
+            }
+            pos + 8
+        };
+
+        // The indices out of range, which would panic at the next access instead
+        #[cfg_attr(not(feature = "cgb"), allow(unused_mut))]
+        let mut broken: Vec<(usize, &[u8], &str)> =
+            vec![(section(b"DMA ") + 2, &[0xa1, 0x00], "DMA: position: 161")];
+        #[cfg(feature = "cgb")]
+        broken.extend_from_slice(&[
+            (section(b"CGB ") + 3, &[0x00], "CGB: WRAM bank: 0"),
+            (section(b"CGB ") + 3, &[0x08], "CGB: WRAM bank: 8"),
+        ]);
+
+        for (pos, bytes, msg) in broken {
+            let mut corrupted = state.clone();
+            corrupted[pos..pos + bytes.len()].copy_from_slice(bytes);
+            match sys.load_state(&corrupted) {
+                Err(Error::InvalidState(m)) => assert_eq!(m, msg),
+                e => panic!("{:?}", e),
+            }
+            assert!(sys.save_state() == state);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "sound")]
+    fn channel_mute() {
+        let mut sys = system(Config::new(), PROGRAM, NullHardware, NullDebugger);
+        let mut fb = vec![0u32; VRAM_WIDTH * VRAM_HEIGHT];
+        let mut play = |sys: &mut System<_>| {
+            let mut samples = vec![];
+            sys.run_frame_av(&mut fb, 44100, &mut samples).unwrap();
+            samples.iter().any(|&s| s > 0)
+        };
+
+        // Play the channel 2 at the full volume on both the terminals
+        sys.mmu_set8(0xff26, 0x80);
+        sys.mmu_set8(0xff24, 0x77);
+        sys.mmu_set8(0xff25, 0x22);
+        sys.mmu_set8(0xff16, 0x80);
+        sys.mmu_set8(0xff17, 0xf0);
+        sys.mmu_set8(0xff18, 0x00);
+        sys.mmu_set8(0xff19, 0x87);
+        assert!(play(&mut sys));
+
+        sys.set_channel_muted(Channel::Tone2, true);
+        assert!(!play(&mut sys));
+        sys.set_channel_muted(Channel::Tone2, false);
+        assert!(play(&mut sys));
+
+        sys.set_solo(Some(Channel::Noise));
+        assert!(!play(&mut sys));
+        sys.set_solo(Some(Channel::Tone2));
+        assert!(play(&mut sys));
+        sys.set_solo(None);
+        assert!(play(&mut sys));
+    }
+
+    #[test]
+    #[cfg(feature = "sound")]
+    fn audio_sink() {
+        #[derive(Default)]
+        struct Recorder {
+            rates: Vec<u32>,
+            mixed: Vec<u16>,
+            tone2: Vec<u16>,
+        }
+
+        struct Sink(Arc<Mutex<Recorder>>);
+
+        impl AudioSink for Sink {
+            fn write(&mut self, rate: u32, mixed: u16, channels: [u16; 4]) {
+                assert_eq!(channels.iter().sum::<u16>(), mixed);
+                let mut r = self.0.lock();
+                r.rates.push(rate);
+                r.mixed.push(mixed);
+                r.tone2.push(channels[Channel::Tone2 as usize]);
+            }
+        }
+
+        let mut sys = system(Config::new(), PROGRAM, NullHardware, NullDebugger);
+        let mut fb = vec![0u32; VRAM_WIDTH * VRAM_HEIGHT];
+        let mut samples = vec![];
+        let rec = Arc::new(Mutex::new(Recorder::default()));
+        sys.set_audio_sink(Some(Box::new(Sink(rec.clone()))));
+
+        // Play the channel 2
+        sys.mmu_set8(0xff26, 0x80);
+        sys.mmu_set8(0xff24, 0x77);
+        sys.mmu_set8(0xff25, 0x22);
+        sys.mmu_set8(0xff16, 0x80);
+        sys.mmu_set8(0xff17, 0xf0);
+        sys.mmu_set8(0xff19, 0x87);
+        sys.run_frame_av(&mut fb, 44100, &mut samples).unwrap();
+
+        let r = core::mem::take(&mut *rec.lock());
+        assert_eq!(r.mixed.len(), samples.len() * 4);
+        assert!(r.rates.iter().all(|r| *r == 44100 * 4));
+        assert!(r.tone2.iter().any(|s| *s > 0));
+        assert_eq!(r.mixed, r.tone2);
+
+        sys.set_audio_sink(None);
+        sys.run_frame_av(&mut fb, 44100, &mut samples).unwrap();
+        assert!(rec.lock().mixed.is_empty());
+    }
+
+    #[test]
+    fn run_frame_async() {
+        use core::future::Future;
+        use core::pin::Pin;
+        use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+        fn block_on<F: Future>(mut f: F) -> F::Output {
+            fn raw() -> RawWaker {
+                fn clone(_: *const ()) -> RawWaker {
+                    raw()
+                }
+                fn noop(_: *const ()) {}
+                static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+                RawWaker::new(core::ptr::null(), &VTABLE)
+            }
+            let waker = unsafe { Waker::from_raw(raw()) };
+            let mut cx = Context::from_waker(&waker);
+            let mut f = unsafe { Pin::new_unchecked(&mut f) };
+            loop {
+                if let Poll::Ready(v) = f.as_mut().poll(&mut cx) {
+                    return v;
+                }
+            }
+        }
+
+        let now = Arc::new(Mutex::new(0u64));
+        let hw = Idle(0, Some(now.clone()));
+        let cfg = Config::new().pacing(Pacing::External);
+        let mut sys = system(cfg, PROGRAM, hw, NullDebugger);
+        sys.cfg.native_speed = false;
+        sys.run_frame().unwrap();
+
+        // The sleep advances the clock, so every frame sleeps for the time of a frame
+        for _ in 0..3 {
+            let start = sys.cycles();
+            let mut slept = 0;
+            let sleep = |d: Duration| {
+                slept = d.as_micros() as u64;
+                *now.lock() += slept;
+                async {}
+            };
+            assert_eq!(
+                block_on(sys.run_frame_async(sleep)).unwrap(),
+                PollEvent::FrameReady
+            );
+
+            let frame = (sys.cycles() - start) * 1_000_000 / 4194300;
+            assert!(
+                slept + 1 >= frame && slept <= frame + 1,
+                "{} {}",
+                slept,
+                frame
+            );
+        }
+    }
 }
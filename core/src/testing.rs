@@ -0,0 +1,258 @@
+use crate::debug::NullDebugger;
+use crate::error::Error;
+use crate::hardware::{Clock, Input, Link, Persistence, Screen, Speaker};
+use crate::system::{Config, PollEvent, System, CYCLES_PER_FRAME};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// The registers B, C, D, E, H and L of the Mooneye test ROMs on success.
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+/// The registers B, C, D, E, H and L of the Mooneye test ROMs on failure.
+const MOONEYE_FAIL: [u8; 6] = [0x42; 6];
+
+/// The signature of the Blargg test ROMs reporting the result in the cartridge RAM.
+const BLARGG_SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+
+/// The status of the Blargg test ROMs while the test is running.
+const BLARGG_RUNNING: u8 = 0x80;
+
+/// The outcome of a test ROM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// The test ROM reported success.
+    Passed,
+    /// The test ROM reported failure.
+    Failed,
+    /// The test ROM didn't report anything within the cycle limit.
+    Timeout,
+}
+
+/// The result of [`run_rom`][].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestResult {
+    /// The outcome of the test.
+    pub verdict: Verdict,
+    /// The text printed to the serial port, or to the cartridge RAM by the Blargg test ROMs.
+    pub output: String,
+    /// The clock cycles run until the verdict.
+    pub cycles: u64,
+    /// The last frame rendered, which is empty unless [`Config::frame_buffer`][] is enabled.
+    pub frame: Vec<u32>,
+}
+
+impl TestResult {
+    /// Check if the test ROM reported success.
+    pub fn passed(&self) -> bool {
+        self.verdict == Verdict::Passed
+    }
+}
+
+/// The hardware with nothing connected; the serial output is taken from the poll events.
+struct Headless;
+
+impl Screen for Headless {}
+impl Speaker for Headless {}
+impl Input for Headless {}
+impl Persistence for Headless {}
+impl Link for Headless {}
+
+impl Clock for Headless {
+    fn clock(&mut self) -> u64 {
+        0
+    }
+}
+
+/// Run a test ROM headless for up to `limit_cycles` clock cycles, and return its verdict.
+///
+/// The following conventions are detected:
+///
+/// * Mooneye: `LD B,B` executed with the Fibonacci numbers 3, 5, 8, 13, 21, 34 in B, C, D, E, H, L
+///   on success, or 0x42 in all of them on failure.
+/// * Blargg: "Passed" or "Failed" printed to the serial port, or the result code stored at A000
+///   after the signature `DE B0 61` at A001-A003, with the text from A004.
+///
+/// The emulation runs as fast as possible, without rendering, on the emulated clock.
+///
+/// ```rust,no_run
+/// let rom = std::fs::read("cpu_instrs.gb").unwrap();
+/// let res = rgy::testing::run_rom(&rom, 300_000_000).unwrap();
+/// assert!(res.passed(), "{}", res.output);
+/// ```
+pub fn run_rom(rom: &[u8], limit_cycles: u64) -> Result<TestResult, Error> {
+    let cfg = Config::new().frame_buffer(false).headless(true);
+
+    run_rom_with(cfg, rom, limit_cycles)
+}
+
+/// Run a test ROM like [`run_rom`][] with the given configuration,
+/// e.g. with [`Config::frame_buffer`][] enabled to check the screen.
+///
+/// The emulation always runs at native speed on the emulated clock.
+pub fn run_rom_with(cfg: Config, rom: &[u8], limit_cycles: u64) -> Result<TestResult, Error> {
+    let cfg = cfg.native_speed(true).deterministic(true);
+    let mut sys = System::new(cfg, rom, vec![0u8; 0x10000], Headless, NullDebugger)?;
+    let mut output = String::new();
+    let mut next_check = CYCLES_PER_FRAME;
+
+    let verdict = loop {
+        if sys.cycles() >= limit_cycles {
+            break Verdict::Timeout;
+        }
+
+        if let Some(v) = mooneye(&sys) {
+            break v;
+        }
+
+        match sys.poll_event()? {
+            PollEvent::SerialByte(b) => {
+                output.push(b as char);
+                if b == b'\n' {
+                    if let Some(v) = blargg_text(&output) {
+                        break v;
+                    }
+                }
+            }
+            PollEvent::Exit => break Verdict::Timeout,
+            _ => {}
+        }
+
+        if sys.cycles() >= next_check {
+            next_check += CYCLES_PER_FRAME;
+            if let Some((v, text)) = blargg_memory(&sys) {
+                output = text;
+                break v;
+            }
+        }
+    };
+
+    Ok(TestResult {
+        verdict,
+        output,
+        cycles: sys.cycles(),
+        frame: sys.frame().to_vec(),
+    })
+}
+
+/// Check the registers if the CPU is about to execute `LD B,B`.
+fn mooneye(sys: &System<'_, NullDebugger>) -> Option<Verdict> {
+    let s = sys.cpu_state();
+    if sys.mmu_get8(s.pc) != 0x40 {
+        return None;
+    }
+
+    let [b, c] = s.bc.to_be_bytes();
+    let [d, e] = s.de.to_be_bytes();
+    let [h, l] = s.hl.to_be_bytes();
+    let regs = [b, c, d, e, h, l];
+
+    if regs == MOONEYE_PASS {
+        Some(Verdict::Passed)
+    } else if regs == MOONEYE_FAIL {
+        Some(Verdict::Failed)
+    } else {
+        None
+    }
+}
+
+fn blargg_text(output: &str) -> Option<Verdict> {
+    if output.contains("Passed") {
+        Some(Verdict::Passed)
+    } else if output.contains("Failed") {
+        Some(Verdict::Failed)
+    } else {
+        None
+    }
+}
+
+fn blargg_memory(sys: &System<'_, NullDebugger>) -> Option<(Verdict, String)> {
+    let signature = [
+        sys.mmu_get8(0xa001),
+        sys.mmu_get8(0xa002),
+        sys.mmu_get8(0xa003),
+    ];
+    let status = sys.mmu_get8(0xa000);
+    if signature != BLARGG_SIGNATURE || status == BLARGG_RUNNING {
+        return None;
+    }
+
+    let text = (0xa004..=0xbfff)
+        .map(|addr| sys.mmu_get8(addr))
+        .take_while(|b| *b != 0)
+        .map(|b| b as char)
+        .collect();
+    let verdict = if status == 0 {
+        Verdict::Passed
+    } else {
+        Verdict::Failed
+    };
+
+    Some((verdict, text))
+}
+
+/// Build a ROM passing the boot ROM checks, which jumps to the program at 0150.
+#[cfg(test)]
+pub(crate) fn rom(program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+    rom[0x104..0x134].copy_from_slice(&crate::cart::LOGO);
+    rom[0x14d] = rom[0x134..0x14d]
+        .iter()
+        .fold(0u8, |x, b| x.wrapping_sub(*b).wrapping_sub(1));
+    rom[0x150..0x150 + program.len()].copy_from_slice(program);
+    rom
+}
+
+/// The program which passes the Mooneye convention.
+#[cfg(test)]
+pub(crate) const MOONEYE_PROGRAM: &[u8] = &[
+    0x06, 3, // ld b,3
+    0x0e, 5, // ld c,5
+    0x16, 8, // ld d,8
+    0x1e, 13, // ld e,13
+    0x26, 21, // ld h,21
+    0x2e, 34,   // ld l,34
+    0x40, // ld b,b
+    0x18, 0xfe, // jr -2
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mooneye() {
+        let rom = rom(MOONEYE_PROGRAM);
+        let res = run_rom(&rom, 100_000_000).unwrap();
+        assert_eq!(res.verdict, Verdict::Passed);
+        assert!(res.frame.is_empty());
+
+        let res = run_rom(&rom, 1000).unwrap();
+        assert_eq!(res.verdict, Verdict::Timeout);
+        assert!(res.cycles >= 1000);
+    }
+
+    #[test]
+    #[cfg(feature = "serial")]
+    fn blargg_serial() {
+        let mut program = vec![];
+        for b in b"Failed #2\n" {
+            program.extend_from_slice(&[
+                0x3e, *b, // ld a,b
+                0xe0, 0x01, // ldh (0x01),a
+                0x3e, 0x81, // ld a,0x81
+                0xe0, 0x02, // ldh (0x02),a
+                0xf0, 0x02, // ldh a,(0x02)
+                0xcb, 0x7f, // bit 7,a
+                0x20, 0xfa, // jr nz,-6
+            ]);
+        }
+        program.extend_from_slice(&[0x18, 0xfe]);
+
+        let res = run_rom(&rom(&program), 100_000_000).unwrap();
+        assert!(!res.passed());
+        assert_eq!(res.verdict, Verdict::Failed);
+        assert_eq!(res.output, "Failed #2\n");
+    }
+}
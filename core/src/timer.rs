@@ -1,16 +1,30 @@
 use crate::device::IoHandler;
+use crate::error::Error;
 use crate::ic::Irq;
 use crate::mmu::{MemRead, MemWrite, Mmu};
+use crate::state::{Reader, State, Writer};
 use log::*;
 
+/// The state of TIMA after it overflows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Reload {
+    /// TIMA is counting normally.
+    None,
+    /// TIMA overflowed and reads zero; TMA is loaded in the next memory cycle.
+    Pending,
+    /// TMA is being loaded into TIMA in this memory cycle, which ignores writes to TIMA.
+    Reloading,
+}
+
 pub struct Timer {
     irq: Irq,
-    div: u8,
-    div_clocks: usize,
+    /// The 16-bit internal divider, of which DIV is the upper byte.
+    div: u16,
+    clocks: usize,
     tim: u8,
-    tim_clocks: usize,
     tim_load: u8,
     ctrl: u8,
+    reload: Reload,
 }
 
 impl Timer {
@@ -18,73 +32,146 @@ impl Timer {
         Self {
             irq,
             div: 0,
-            div_clocks: 0,
+            clocks: 0,
             tim: 0,
-            tim_clocks: 0,
             tim_load: 0,
             ctrl: 0,
+            reload: Reload::None,
         }
     }
 
-    fn tim_clock_reset(&mut self) {
-        self.tim_clocks = match self.ctrl & 0x3 {
-            0x0 => 1024, // 4096Hz = 1024 cpu clocks
-            0x1 => 16,   // 262144Hz = 16 cpu clocks
-            0x2 => 64,   // 65536Hz = 64 cpu clocks
-            0x3 => 256,  // 16384Hz = 256 cpu clocks
+    /// The input of TIMA, which is the divider bit selected by TAC and gated by the enable bit.
+    fn signal(&self) -> bool {
+        let bit = match self.ctrl & 0x3 {
+            0x0 => 9, // 4096Hz = 1024 cpu clocks
+            0x1 => 3, // 262144Hz = 16 cpu clocks
+            0x2 => 5, // 65536Hz = 64 cpu clocks
+            0x3 => 7, // 16384Hz = 256 cpu clocks
             _ => unreachable!(),
         };
-    }
 
-    fn div_clock_reset(&mut self) {
-        self.div_clocks = 256; // 16384Hz = 256 cpu clocks
+        self.ctrl & 0x04 != 0 && self.div & (1 << bit) != 0
     }
 
-    pub fn step(&mut self, time: usize) {
-        if self.div_clocks < time {
-            self.div = self.div.wrapping_add(1);
-            let rem = time - self.div_clocks;
-            self.div_clock_reset();
-            self.div_clocks -= rem;
-        } else {
-            self.div_clocks -= time;
+    /// Increment TIMA if its input falls from the given old value.
+    fn detect_edge(&mut self, old: bool) {
+        if old && !self.signal() {
+            let (tim, of) = self.tim.overflowing_add(1);
+            self.tim = tim;
+            if of {
+                self.reload = Reload::Pending;
+            }
         }
+    }
+
+    /// Reset the divider, as writing to DIV or executing STOP does.
+    pub fn reset_div(&mut self) {
+        let old = self.signal();
+        self.div = 0;
+        // Clearing the selected bit may increment TIMA
+        self.detect_edge(old);
+    }
 
+    /// Set the internal divider, e.g. to the value the boot ROM leaves.
+    pub fn set_div(&mut self, div: u16) {
+        self.div = div;
+    }
+
+    /// Returns the clock cycles until TIMA overflows, or `None` if the timer is disabled.
+    pub fn next_event(&self) -> Option<usize> {
+        if self.reload != Reload::None {
+            return Some(4);
+        }
         if self.ctrl & 0x04 == 0 {
-            return;
+            return None;
         }
 
-        if self.tim_clocks < time {
-            let mut rem = time - self.tim_clocks;
+        let period = match self.ctrl & 0x3 {
+            0x0 => 1024,
+            0x1 => 16,
+            0x2 => 64,
+            _ => 256,
+        };
+        let first = period - self.div as usize % period;
 
-            loop {
-                let (tim, of) = self.tim.overflowing_add(1);
-                self.tim = tim;
-                if of {
-                    self.tim = self.tim_load;
-                    self.irq.timer(true);
-                }
-                self.tim_clock_reset();
-                if rem <= self.tim_clocks {
-                    self.tim_clocks -= rem;
-                    break;
-                }
-                rem -= self.tim_clocks;
-            }
-        } else {
-            self.tim_clocks -= time;
+        Some((first + (0xff - self.tim as usize) * period).saturating_sub(self.clocks))
+    }
+
+    pub fn step(&mut self, time: usize) {
+        self.clocks += time;
+
+        while self.clocks >= 4 {
+            self.clocks -= 4;
+            self.tick();
         }
     }
+
+    /// Run a memory cycle.
+    fn tick(&mut self) {
+        self.reload = match self.reload {
+            Reload::Pending => {
+                self.tim = self.tim_load;
+                self.irq.timer(true);
+                Reload::Reloading
+            }
+            _ => Reload::None,
+        };
+
+        let old = self.signal();
+        self.div = self.div.wrapping_add(4);
+        self.detect_edge(old);
+    }
+}
+
+impl State for Reload {
+    fn save(&self, w: &mut Writer) {
+        let tag: u8 = match self {
+            Reload::None => 0,
+            Reload::Pending => 1,
+            Reload::Reloading => 2,
+        };
+        tag.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        *self = match r.tag("timer reload", 3)? {
+            0 => Reload::None,
+            1 => Reload::Pending,
+            _ => Reload::Reloading,
+        };
+        Ok(())
+    }
+}
+
+impl State for Timer {
+    fn save(&self, w: &mut Writer) {
+        self.div.save(w);
+        self.clocks.save(w);
+        self.tim.save(w);
+        self.tim_load.save(w);
+        self.ctrl.save(w);
+        self.reload.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), Error> {
+        self.div.load(r)?;
+        self.clocks.load(r)?;
+        self.tim.load(r)?;
+        self.tim_load.load(r)?;
+        self.ctrl.load(r)?;
+        self.reload.load(r)?;
+        Ok(())
+    }
 }
 
 impl IoHandler for Timer {
     fn on_read(&mut self, _mmu: &Mmu, addr: u16) -> MemRead {
         info!("Timer read: {:04x}", addr);
         match addr {
-            0xff04 => MemRead::Replace(self.div),
+            0xff04 => MemRead::Replace((self.div >> 8) as u8),
             0xff05 => MemRead::Replace(self.tim),
             0xff06 => MemRead::Replace(self.tim_load),
-            0xff07 => MemRead::Replace(self.ctrl),
+            0xff07 => MemRead::Replace(self.ctrl | 0xf8),
             _ => MemRead::PassThrough,
         }
     }
@@ -92,20 +179,103 @@ impl IoHandler for Timer {
     fn on_write(&mut self, _mmu: &Mmu, addr: u16, value: u8) -> MemWrite {
         info!("Timer write: {:04x} {:02x}", addr, value);
         match addr {
-            0xff04 => self.div = 0,
-            0xff05 => self.tim = value,
-            0xff06 => self.tim_load = value,
-            0xff07 => {
-                let old_ctrl = self.ctrl;
-                self.ctrl = value;
-
-                if old_ctrl & 4 == 0 && value & 4 != 0 {
-                    debug!("Timer started");
-                    self.tim_clock_reset();
+            0xff04 => self.reset_div(),
+            0xff05 => match self.reload {
+                // Writing TIMA before the reload cancels it and the interrupt
+                Reload::Pending => {
+                    self.tim = value;
+                    self.reload = Reload::None;
+                }
+                Reload::Reloading => {}
+                Reload::None => self.tim = value,
+            },
+            0xff06 => {
+                self.tim_load = value;
+                // TMA written while reloading goes to TIMA as well
+                if self.reload == Reload::Reloading {
+                    self.tim = value;
                 }
             }
+            0xff07 => {
+                let old = self.signal();
+                self.ctrl = value & 0x07;
+                // Disabling the timer or switching the input may increment TIMA
+                self.detect_edge(old);
+            }
             _ => {}
         }
         MemWrite::PassThrough
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device::Device;
+    use crate::ic::Ic;
+    use alloc::vec;
+
+    fn setup() -> (Mmu<'static>, Device<Timer>) {
+        let mut mmu = Mmu::new(vec![0u8; 0x10000]);
+        let ic = Device::new(Ic::new());
+        let timer = Device::new(Timer::new(ic.borrow().irq()));
+        mmu.add_handler((0xff0f, 0xff0f), ic.handler());
+        mmu.add_handler((0xff04, 0xff07), timer.handler());
+        (mmu, timer)
+    }
+
+    #[test]
+    fn tima_reload_delay() {
+        let (mut mmu, timer) = setup();
+
+        mmu.set8(0xff06, 0x42);
+        mmu.set8(0xff05, 0xff);
+        mmu.set8(0xff07, 0x05);
+
+        // Overflows at the 4th memory cycle, reading zero for a cycle
+        timer.borrow_mut().step(16);
+        assert_eq!(mmu.get8(0xff05), 0x00);
+        assert_eq!(mmu.get8(0xff0f) & 0x04, 0x00);
+
+        timer.borrow_mut().step(4);
+        assert_eq!(mmu.get8(0xff05), 0x42);
+        assert_eq!(mmu.get8(0xff0f) & 0x04, 0x04);
+
+        // Writes to TIMA are ignored while reloading
+        mmu.set8(0xff05, 0x00);
+        assert_eq!(mmu.get8(0xff05), 0x42);
+
+        // Writing TIMA in the delay cancels the reload and the interrupt
+        timer.borrow_mut().step(4);
+        mmu.set8(0xff0f, 0x00);
+        mmu.set8(0xff05, 0xff);
+        timer.borrow_mut().step(8);
+        mmu.set8(0xff05, 0x10);
+        timer.borrow_mut().step(4);
+        assert_eq!(mmu.get8(0xff05), 0x10);
+        assert_eq!(mmu.get8(0xff0f) & 0x04, 0x00);
+    }
+
+    #[test]
+    fn falling_edge_glitches() {
+        let (mut mmu, timer) = setup();
+
+        mmu.set8(0xff07, 0x05);
+
+        // Bit 3 of the divider is set: resetting DIV is a falling edge
+        timer.borrow_mut().step(8);
+        assert_eq!(mmu.get8(0xff05), 0x00);
+        mmu.set8(0xff04, 0x00);
+        assert_eq!(mmu.get8(0xff05), 0x01);
+
+        // So is disabling the timer
+        timer.borrow_mut().step(8);
+        assert_eq!(mmu.get8(0xff05), 0x01);
+        mmu.set8(0xff07, 0x01);
+        assert_eq!(mmu.get8(0xff05), 0x02);
+
+        assert_eq!(mmu.get8(0xff04), 0x00);
+        timer.borrow_mut().step(256);
+        assert_eq!(mmu.get8(0xff04), 0x01);
+    }
+}
@@ -0,0 +1,615 @@
+//! The libretro core of the `rgy` emulator.
+//!
+//! Build the crate as a `cdylib` and load it into any libretro frontend, e.g. RetroArch.
+//! The core maps the [`System`][] API onto the libretro API:
+//!
+//! * `retro_run` runs a frame by [`System::run_frame_av`][], passing the pixels in `XRGB8888`
+//!   and the sound at [`SAMPLE_RATE`][] to the frontend, which paces the frames.
+//! * `retro_serialize` and `retro_unserialize` use [`System::save_state`][] and [`System::load_state`][].
+//! * The joypad of the first port is passed by [`System::set_button`][].
+//! * The battery-backed RAM is exposed as `RETRO_MEMORY_SAVE_RAM`.
+//! * The cheat codes are parsed by [`Cheat`][] and added by [`System::add_cheat`][].
+//!
+//! The emulator runs in the deterministic mode, so the savestates work with the rewind,
+//! the run-ahead and the netplay of the frontend.
+
+use std::ffi::{c_void, CStr};
+use std::os::raw::{c_char, c_uint};
+use std::ptr;
+use std::slice;
+use std::sync::{Arc, Mutex};
+
+use log::*;
+use rgy::cart::Header;
+use rgy::debug::NullDebugger;
+use rgy::{
+    Cheat, Clock, Config, Error, Input, Key, Link, Persistence, Screen, Speaker, System,
+    VRAM_HEIGHT, VRAM_WIDTH,
+};
+
+/// The sample rate of the sound passed to the frontend in Hz.
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// The CPU frequency in Hz.
+const FREQ: f64 = 4194304.0;
+
+/// The CPU cycles per frame.
+const CYCLES_PER_FRAME: f64 = 70224.0;
+
+const RETRO_API_VERSION: c_uint = 1;
+
+const RETRO_DEVICE_JOYPAD: c_uint = 1;
+
+const RETRO_DEVICE_ID_JOYPAD_B: c_uint = 0;
+const RETRO_DEVICE_ID_JOYPAD_SELECT: c_uint = 2;
+const RETRO_DEVICE_ID_JOYPAD_START: c_uint = 3;
+const RETRO_DEVICE_ID_JOYPAD_UP: c_uint = 4;
+const RETRO_DEVICE_ID_JOYPAD_DOWN: c_uint = 5;
+const RETRO_DEVICE_ID_JOYPAD_LEFT: c_uint = 6;
+const RETRO_DEVICE_ID_JOYPAD_RIGHT: c_uint = 7;
+const RETRO_DEVICE_ID_JOYPAD_A: c_uint = 8;
+
+const RETRO_MEMORY_SAVE_RAM: c_uint = 0;
+
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+
+const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+const RETRO_REGION_NTSC: c_uint = 0;
+
+/// The joypad buttons of libretro and the keys they press.
+const KEYS: [(c_uint, Key); 8] = [
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, Key::Right),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, Key::Left),
+    (RETRO_DEVICE_ID_JOYPAD_UP, Key::Up),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, Key::Down),
+    (RETRO_DEVICE_ID_JOYPAD_A, Key::A),
+    (RETRO_DEVICE_ID_JOYPAD_B, Key::B),
+    (RETRO_DEVICE_ID_JOYPAD_SELECT, Key::Select),
+    (RETRO_DEVICE_ID_JOYPAD_START, Key::Start),
+];
+
+#[repr(C)]
+pub struct RetroSystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct RetroGameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct RetroSystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct RetroSystemAvInfo {
+    pub geometry: RetroGameGeometry,
+    pub timing: RetroSystemTiming,
+}
+
+#[repr(C)]
+pub struct RetroGameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+pub type RetroEnvironment = extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+pub type RetroVideoRefresh =
+    extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type RetroAudioSample = extern "C" fn(left: i16, right: i16);
+pub type RetroAudioSampleBatch = extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type RetroInputPoll = extern "C" fn();
+pub type RetroInputState =
+    extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+/// The callbacks set by the frontend.
+#[derive(Clone, Copy)]
+struct Callbacks {
+    environment: Option<RetroEnvironment>,
+    video_refresh: Option<RetroVideoRefresh>,
+    audio_sample_batch: Option<RetroAudioSampleBatch>,
+    input_poll: Option<RetroInputPoll>,
+    input_state: Option<RetroInputState>,
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+fn callbacks() -> Callbacks {
+    *CALLBACKS.lock().unwrap()
+}
+
+fn with_core<R>(f: impl FnOnce(&mut Core) -> R) -> Option<R> {
+    CORE.lock().unwrap().as_mut().map(f)
+}
+
+/// The hardware passed to the emulator.
+///
+/// The screen, the sound and the joypad are handled by [`Core`][] around [`System::run_frame_av`][].
+/// Holds the battery-backed RAM shared with the core.
+struct Frontend(Arc<Mutex<Vec<u8>>>);
+
+impl Screen for Frontend {}
+
+impl Speaker for Frontend {}
+
+impl Input for Frontend {}
+
+impl Clock for Frontend {
+    fn clock(&mut self) -> u64 {
+        // The frontend paces the frames, and the deterministic mode runs the RTC on the CPU cycles.
+        0
+    }
+}
+
+impl Persistence for Frontend {
+    fn load_ram(&mut self, size: usize) -> Vec<u8> {
+        let mut ram = self.0.lock().unwrap().clone();
+        ram.resize(size, 0);
+        ram
+    }
+
+    fn save_ram(&mut self, ram: &[u8]) {
+        let mut sram = self.0.lock().unwrap();
+        let len = sram.len().min(ram.len());
+        // Keep the buffer in place, as the frontend holds the pointer to it.
+        sram[..len].copy_from_slice(&ram[..len]);
+    }
+}
+
+impl Link for Frontend {}
+
+/// The loaded game.
+struct Core {
+    rom: Vec<u8>,
+    /// The battery-backed RAM, which the frontend reads and writes through the pointer.
+    sram: Arc<Mutex<Vec<u8>>>,
+    /// The emulator, which is created on the first use after the frontend loads the battery-backed RAM.
+    sys: Option<System<'static, NullDebugger>>,
+    cheats: Vec<(usize, Vec<Cheat>)>,
+    fb: Vec<u32>,
+    samples: Vec<i16>,
+    audio: Vec<i16>,
+}
+
+impl Core {
+    fn load(rom: &[u8]) -> Result<Self, Error> {
+        let header = Header::parse(rom)?;
+        info!("Loading {}", header.title);
+
+        let sram = if header.has_battery() {
+            vec![0; header.ram_size]
+        } else {
+            vec![]
+        };
+
+        let core = Self {
+            rom: rom.to_vec(),
+            sram: Arc::new(Mutex::new(sram)),
+            sys: None,
+            cheats: vec![],
+            fb: vec![0; VRAM_WIDTH * VRAM_HEIGHT],
+            samples: vec![],
+            audio: vec![],
+        };
+
+        // Check the cartridge is supported before the frontend starts.
+        core.create()?;
+
+        Ok(core)
+    }
+
+    fn create(&self) -> Result<System<'static, NullDebugger>, Error> {
+        let cfg = Config::new()
+            .native_speed(true)
+            .frame_buffer(false)
+            .deterministic(true);
+
+        System::new(
+            cfg,
+            &self.rom,
+            vec![0; 0x10000],
+            Frontend(self.sram.clone()),
+            NullDebugger,
+        )
+    }
+
+    fn system(&mut self) -> &mut System<'static, NullDebugger> {
+        if self.sys.is_none() {
+            let mut sys = self.create().expect("the cartridge is checked on load");
+            for (_, cheats) in &self.cheats {
+                for cheat in cheats {
+                    sys.add_cheat(*cheat);
+                }
+            }
+            self.sys = Some(sys);
+        }
+
+        self.sys.as_mut().unwrap()
+    }
+
+    fn run(&mut self, cb: &Callbacks) {
+        if let Some(input_poll) = cb.input_poll {
+            input_poll();
+        }
+
+        let sys = self.system();
+
+        if let Some(input_state) = cb.input_state {
+            for (id, key) in KEYS.iter() {
+                sys.set_button(
+                    key.clone(),
+                    input_state(0, RETRO_DEVICE_JOYPAD, 0, *id) != 0,
+                );
+            }
+        }
+
+        self.samples.clear();
+        let sys = self.sys.as_mut().unwrap();
+        if let Err(e) = sys.run_frame_av(&mut self.fb, SAMPLE_RATE, &mut self.samples) {
+            error!("Emulation error: {}", e);
+        }
+
+        if let Some(video_refresh) = cb.video_refresh {
+            video_refresh(
+                self.fb.as_ptr() as *const c_void,
+                VRAM_WIDTH as c_uint,
+                VRAM_HEIGHT as c_uint,
+                VRAM_WIDTH * 4,
+            );
+        }
+
+        self.audio.clear();
+        for s in &self.samples {
+            self.audio.extend_from_slice(&[*s, *s]);
+        }
+
+        if let Some(audio_sample_batch) = cb.audio_sample_batch {
+            let mut frames = &self.audio[..];
+            while !frames.is_empty() {
+                let n = audio_sample_batch(frames.as_ptr(), frames.len() / 2);
+                if n == 0 {
+                    break;
+                }
+                frames = &frames[(n * 2).min(frames.len())..];
+            }
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *CORE.lock().unwrap() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(cb: Option<RetroEnvironment>) {
+    CALLBACKS.lock().unwrap().environment = cb;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(cb: Option<RetroVideoRefresh>) {
+    CALLBACKS.lock().unwrap().video_refresh = cb;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_cb: Option<RetroAudioSample>) {
+    // The samples are always passed in batch.
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(cb: Option<RetroAudioSampleBatch>) {
+    CALLBACKS.lock().unwrap().audio_sample_batch = cb;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(cb: Option<RetroInputPoll>) {
+    CALLBACKS.lock().unwrap().input_poll = cb;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(cb: Option<RetroInputState>) {
+    CALLBACKS.lock().unwrap().input_state = cb;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+/// # Safety
+///
+/// `info` must point to a valid `retro_system_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    *info = RetroSystemInfo {
+        library_name: b"rgy\0".as_ptr() as *const c_char,
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: b"gb|gbc\0".as_ptr() as *const c_char,
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+///
+/// `info` must point to a valid `retro_system_av_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    *info = RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: VRAM_WIDTH as c_uint,
+            base_height: VRAM_HEIGHT as c_uint,
+            max_width: VRAM_WIDTH as c_uint,
+            max_height: VRAM_HEIGHT as c_uint,
+            aspect_ratio: VRAM_WIDTH as f32 / VRAM_HEIGHT as f32,
+        },
+        timing: RetroSystemTiming {
+            fps: FREQ / CYCLES_PER_FRAME,
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+/// # Safety
+///
+/// `game` must be null or point to a valid `retro_game_info` with the content of the ROM.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+
+    if let Some(environment) = callbacks().environment {
+        let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+        if !environment(
+            RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+            &mut format as *mut c_uint as *mut c_void,
+        ) {
+            error!("XRGB8888 is not supported by the frontend");
+            return false;
+        }
+    }
+
+    let rom = slice::from_raw_parts((*game).data as *const u8, (*game).size);
+
+    match Core::load(rom) {
+        Ok(core) => {
+            *CORE.lock().unwrap() = Some(core);
+            true
+        }
+        Err(e) => {
+            error!("Couldn't load the game: {}", e);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const RetroGameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *CORE.lock().unwrap() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let cb = callbacks();
+    with_core(|core| core.run(&cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_core(|core| core.system().reset());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_core(|core| core.system().save_state().len()).unwrap_or(0)
+}
+
+/// # Safety
+///
+/// `data` must point to a writable buffer of `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    with_core(|core| {
+        let state = core.system().save_state();
+        if state.len() > size {
+            return false;
+        }
+        ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+        true
+    })
+    .unwrap_or(false)
+}
+
+/// # Safety
+///
+/// `data` must point to a readable buffer of `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let state = slice::from_raw_parts(data as *const u8, size);
+
+    with_core(|core| match core.system().load_state(state) {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Couldn't load the state: {}", e);
+            false
+        }
+    })
+    .unwrap_or(false)
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {
+    with_core(|core| {
+        core.cheats.clear();
+        if let Some(sys) = core.sys.as_mut() {
+            sys.clear_cheats();
+        }
+    });
+}
+
+/// # Safety
+///
+/// `code` must be null or a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn retro_cheat_set(index: c_uint, enabled: bool, code: *const c_char) {
+    if code.is_null() {
+        return;
+    }
+
+    let code = CStr::from_ptr(code).to_string_lossy();
+
+    // Multiple codes are joined by `+`.
+    let cheats: Result<Vec<Cheat>, Error> = code.split('+').map(|c| c.parse()).collect();
+    let cheats = match cheats {
+        Ok(cheats) => cheats,
+        Err(e) => {
+            warn!("Ignoring the cheat code: {}", e);
+            return;
+        }
+    };
+
+    with_core(|core| {
+        let index = index as usize;
+        core.cheats.retain(|(i, _)| *i != index);
+        if enabled {
+            core.cheats.push((index, cheats));
+        }
+
+        // Re-add all the codes, as the ids of the emulator don't follow the indices of the frontend.
+        let cheats = core.cheats.clone();
+        let sys = core.system();
+        sys.clear_cheats();
+        for (_, cheats) in cheats {
+            for cheat in cheats {
+                sys.add_cheat(cheat);
+            }
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    if id != RETRO_MEMORY_SAVE_RAM {
+        return ptr::null_mut();
+    }
+
+    with_core(|core| {
+        let mut sram = core.sram.lock().unwrap();
+        if sram.is_empty() {
+            ptr::null_mut()
+        } else {
+            sram.as_mut_ptr() as *mut c_void
+        }
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    if id != RETRO_MEMORY_SAVE_RAM {
+        return 0;
+    }
+
+    with_core(|core| core.sram.lock().unwrap().len()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static FRAMES: AtomicUsize = AtomicUsize::new(0);
+    static SAMPLES: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+        assert!(!data.is_null());
+        assert_eq!((width, height, pitch), (160, 144, 640));
+        FRAMES.fetch_add(1, Ordering::SeqCst);
+    }
+
+    extern "C" fn audio_sample_batch(_data: *const i16, frames: usize) -> usize {
+        SAMPLES.fetch_add(frames, Ordering::SeqCst);
+        frames
+    }
+
+    #[test]
+    fn run() {
+        // MBC1 with the battery and 8 KB RAM, looping at the entry point
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xfe]);
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+
+        let game = RetroGameInfo {
+            path: ptr::null(),
+            data: rom.as_ptr() as *const c_void,
+            size: rom.len(),
+            meta: ptr::null(),
+        };
+
+        retro_set_video_refresh(Some(video_refresh));
+        retro_set_audio_sample_batch(Some(audio_sample_batch));
+        assert!(unsafe { retro_load_game(&game) });
+        assert_eq!(retro_get_memory_size(RETRO_MEMORY_SAVE_RAM), 0x2000);
+        assert!(!retro_get_memory_data(RETRO_MEMORY_SAVE_RAM).is_null());
+
+        for _ in 0..60 {
+            retro_run();
+        }
+        assert_eq!(FRAMES.load(Ordering::SeqCst), 60);
+        // A second of the sound
+        let samples = SAMPLES.load(Ordering::SeqCst);
+        assert!(samples > 44000 && samples < 44200, "{}", samples);
+
+        let mut state = vec![0u8; retro_serialize_size()];
+        assert!(unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, state.len()) });
+        retro_run();
+        assert!(unsafe { retro_unserialize(state.as_ptr() as *const c_void, state.len()) });
+
+        retro_unload_game();
+        assert_eq!(retro_get_memory_size(RETRO_MEMORY_SAVE_RAM), 0);
+    }
+}